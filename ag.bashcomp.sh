@@ -66,6 +66,10 @@ _ag() {
     --vimgrep
     --word-regexp
     --workers
+    --zip-depth
+    --zip-max-prealloc
+    --zip-max-ratio
+    --zip-max-size
   '
   shtopt='
     -a -A -B -C -D
@@ -96,7 +100,8 @@ _ag() {
               COMPREPLY=( $(compgen -c -- "${cur}") )
               return 0;;
     --ackmate-dir-filter|--after|--before|--color-*|--context|--depth\
-    |--file-search-regex|--ignore|--max-count|--workers)
+    |--file-search-regex|--ignore|--max-count|--workers|--zip-depth\
+    |--zip-max-prealloc|--zip-max-ratio|--zip-max-size)
               return 0;;
   esac
 
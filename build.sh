@@ -19,7 +19,7 @@ cargo clean
 cargo build --release
 cd -
 mkdir lib
-cp rust-gzip/src/rgzip.h src/
+cp rust-gzip/src/rgzip.h rust-gzip/src/rgzip_stream.h rust-gzip/src/rgzip_members.h src/
 cp rust-gzip/target/release/librgzip-* lib/
 mv lib/librgzip-* lib/librgzip.a
 
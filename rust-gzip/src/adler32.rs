@@ -0,0 +1,48 @@
+#[doc="
+
+    Module: adler32
+
+    This module handles verifying the Adler-32 checksum that ends a
+    zlib stream, and names the preset dictionary one was made with.
+
+"]
+
+// the largest prime below 2^16
+const MOD_ADLER: u32 = 65521;
+// the most bytes that can be summed before b could overflow 32 bits
+const NMAX: usize = 5552;
+
+/// The Adler-32 checksum of data
+pub fn sum(data: &[u8]) -> u32 {
+    let mut a = 1;
+    let mut b = 0;
+    for chunk in data.chunks(NMAX) {
+        for &byte in chunk.iter() {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod adler32_tests {
+    use super::sum;
+    use std::iter::repeat;
+
+    #[test]
+    fn test_sum() {
+        assert_eq!(sum(b""), 1);
+        assert_eq!(sum(b"Wikipedia"), 0x11e60398);
+        assert_eq!(sum(b"hello, hello, hello zlib\n"), 0x740108b0);
+    }
+
+    #[test]
+    fn test_long_input() {
+        // enough 0xff bytes that b has to be reduced along the way
+        let data: Vec<u8> = repeat(0xff).take(100000).collect();
+        assert_eq!(sum(data.as_slice()), 0x149a302c);
+    }
+}
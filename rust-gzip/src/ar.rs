@@ -0,0 +1,292 @@
+#[doc="
+
+    Module: ar
+
+    This reads ar archives, the format of static libraries and of
+    Debian packages. After a magic string, each file is a 60 byte text
+    header followed by its contents, padded out to an even length.
+    Names longer than the header's 16 bytes are kept one of two ways:
+    GNU ar lists them all in a table member called // and names the
+    file /<offset into it>, while BSD ar names the file #1/<length>
+    and puts the name before its contents.
+
+"]
+use cvec::Buf;
+use error::{GzError, GzResult};
+
+// These constants are defined by the common ar format
+const MAGIC: &'static [u8] = b"!<arch>\n";
+const HEADER_LEN: usize = 60;
+const NAME_LEN: usize = 16;
+const SIZE_OFFSET: usize = 48;
+const SIZE_LEN: usize = 10;
+const HEADER_END_OFFSET: usize = 58;
+const HEADER_END: &'static [u8] = b"`\n";
+
+// GNU ar's symbol tables and table of long names
+const GNU_SYMBOL_TABLE: &'static [u8] = b"/";
+const GNU_SYMBOL_TABLE_64: &'static [u8] = b"/SYM64/";
+const GNU_NAME_TABLE: &'static [u8] = b"//";
+// BSD ar's symbol tables, which may go on to say "SORTED" or "_64"
+const BSD_SYMBOL_TABLE: &'static [u8] = b"__.SYMDEF";
+const BSD_LONG_NAME: &'static [u8] = b"#1/";
+
+/// A file in the archive
+#[derive(Show)]
+pub struct ArEntry {
+    /// name of the file, with a nul after it for C
+    name: Vec<u8>,
+    /// where its contents start, from the start of the buffer
+    offset: usize,
+    size: usize,
+}
+
+impl ArEntry {
+    /// The name of the file
+    pub fn name(&self) -> &[u8] {
+        &self.name[.. self.name.len() - 1]
+    }
+
+    /// The name of the file, followed by a nul
+    pub fn c_name(&self) -> &[u8] {
+        &self.name[..]
+    }
+}
+
+/// An ar archive, along with the files in it
+/// Symbol tables and the table of long names aren't listed, as they
+/// only describe the other files.
+pub struct ArArchive {
+    buffer: Buf,
+    entries: Vec<ArEntry>,
+}
+
+impl ArArchive {
+    /// Read every header of the archive in buffer
+    pub fn open(buffer: Buf) -> GzResult<ArArchive> {
+        if !is_ar(&buffer) {
+            return Err(GzError::BadMagic);
+        }
+        let mut entries = Vec::new();
+        // where the GNU table of long names is, once we've seen it
+        let mut name_table: Option<(usize, usize)> = None;
+        let mut pos = MAGIC.len();
+        while pos < buffer.len() {
+            if pos + HEADER_LEN > buffer.len() {
+                return Err(GzError::TruncatedHeader);
+            }
+            let header = &buffer.as_slice()[pos .. pos + HEADER_LEN];
+            if &header[HEADER_END_OFFSET ..] != HEADER_END {
+                return Err(GzError::BadArchive);
+            }
+            let size = try!(parse_number(&header[SIZE_OFFSET .. SIZE_OFFSET + SIZE_LEN]));
+            let start = pos + HEADER_LEN;
+            let end = match start.checked_add(size) {
+                Some(end) if end <= buffer.len() => end,
+                _ => { return Err(GzError::TruncatedData); }
+            };
+            let data = &buffer.as_slice()[start .. end];
+
+            let raw_name = trim_spaces(&header[.. NAME_LEN]);
+            if raw_name == GNU_NAME_TABLE {
+                name_table = Some((start, end));
+            } else if raw_name == GNU_SYMBOL_TABLE || raw_name == GNU_SYMBOL_TABLE_64 ||
+                      raw_name.starts_with(BSD_SYMBOL_TABLE) {
+                // nothing to search in a symbol table
+            } else if raw_name.starts_with(BSD_LONG_NAME) {
+                let name_len = try!(parse_number(&raw_name[BSD_LONG_NAME.len() ..]));
+                if name_len > size {
+                    return Err(GzError::BadArchive);
+                }
+                let name = until_nul(&data[.. name_len]);
+                // the symbol table may have a long name as well
+                if !name.starts_with(BSD_SYMBOL_TABLE) {
+                    entries.push(new_entry(name, start + name_len, size - name_len));
+                }
+            } else if raw_name.starts_with(b"/") {
+                let (table_start, table_end) = try_or!(name_table, GzError::BadArchive);
+                let name_offset = try!(parse_number(&raw_name[1 ..]));
+                let table = &buffer.as_slice()[table_start .. table_end];
+                if name_offset >= table.len() {
+                    return Err(GzError::BadArchive);
+                }
+                let name = &table[name_offset ..];
+                let name_len = name.iter().position(|&b| b == b'\n').unwrap_or(name.len());
+                entries.push(new_entry(strip_slash(&name[.. name_len]), start, size));
+            } else {
+                entries.push(new_entry(strip_slash(raw_name), start, size));
+            }
+            // contents are padded out to an even length
+            pos = end + (end & 1);
+        }
+        Ok(ArArchive {
+            buffer: buffer,
+            entries: entries
+        })
+    }
+
+    /// The files in the archive
+    pub fn entries(&self) -> &Vec<ArEntry> {
+        &self.entries
+    }
+
+    /// The contents of the file at index in entries, read in place
+    /// They must not be used once the archive is freed.
+    pub fn extract(&self, index: usize) -> GzResult<Buf> {
+        let entry = try_or!(self.entries.get(index), GzError::BadArchive);
+        Ok(try_or!(unsafe { self.buffer.view(entry.offset, entry.offset + entry.size) },
+                   GzError::AllocationFailed))
+    }
+}
+
+/// Whether buffer starts with the ar magic string
+pub fn is_ar(buffer: &Buf) -> bool {
+    buffer.len() >= MAGIC.len() && &buffer.as_slice()[.. MAGIC.len()] == MAGIC
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+fn new_entry(name: &[u8], offset: usize, size: usize) -> ArEntry {
+    let mut name = name.to_vec();
+    name.push(0);
+    ArEntry {
+        name: name,
+        offset: offset,
+        size: size
+    }
+}
+
+/// Parse a decimal header field, padded with spaces
+fn parse_number(field: &[u8]) -> GzResult<usize> {
+    let digits = trim_spaces(field);
+    if digits.len() == 0 {
+        return Err(GzError::BadArchive);
+    }
+    let mut value: usize = 0;
+    for &digit in digits.iter() {
+        if digit < b'0' || digit > b'9' {
+            return Err(GzError::BadArchive);
+        }
+        value = try_or!(value.checked_mul(10), GzError::BadArchive) + (digit - b'0') as usize;
+    }
+    Ok(value)
+}
+
+/// The field without the spaces padding it out
+fn trim_spaces(field: &[u8]) -> &[u8] {
+    let len = field.iter().rposition(|&b| b != b' ').map_or(0, |last| last + 1);
+    &field[.. len]
+}
+
+/// GNU ar ends each name with a slash, so that names can hold spaces
+fn strip_slash(name: &[u8]) -> &[u8] {
+    if name.ends_with(b"/") {
+        &name[.. name.len() - 1]
+    } else {
+        name
+    }
+}
+
+/// The part of field before the first nul
+fn until_nul(field: &[u8]) -> &[u8] {
+    match field.iter().position(|&b| b == 0) {
+        Some(len) => &field[.. len],
+        None => field
+    }
+}
+
+#[cfg(test)]
+mod ar_tests {
+    use super::{ArArchive, is_ar};
+    use cvec::{from_slice, Buf};
+    use error::GzError;
+
+    /// A header naming the member, followed by the padded contents
+    fn member(name: &str, contents: &[u8]) -> Vec<u8> {
+        let header = format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                             name, 0, 0, 0, 644, contents.len());
+        let mut raw = header.into_bytes();
+        raw.push_all(contents);
+        if raw.len() % 2 != 0 {
+            raw.push(b'\n');
+        }
+        raw
+    }
+
+    fn archive(members: &[Vec<u8>]) -> Buf {
+        let mut raw = b"!<arch>\n".to_vec();
+        for member in members.iter() {
+            raw.push_all(member.as_slice());
+        }
+        from_slice(raw.as_slice())
+    }
+
+    fn contents(ar: &ArArchive) -> Vec<(Vec<u8>, Vec<u8>)> {
+        (0 .. ar.entries().len()).map(|i| {
+            (ar.entries()[i].name().to_vec(),
+             ar.extract(i).unwrap().iter().map(|&b| b).collect())
+        }).collect()
+    }
+
+    #[test]
+    fn test_deb_layout() {
+        let buffer = archive(&[member("debian-binary", b"2.0\n"),
+                               member("control.tar.gz", b"odd"),
+                               member("data.tar.xz", b"")]);
+        assert!(is_ar(&buffer));
+        let ar = ArArchive::open(buffer).unwrap();
+        assert_eq!(contents(&ar), vec![(b"debian-binary".to_vec(), b"2.0\n".to_vec()),
+                                       (b"control.tar.gz".to_vec(), b"odd".to_vec()),
+                                       (b"data.tar.xz".to_vec(), Vec::new())]);
+        assert_eq!(ar.entries()[2].c_name(), &b"data.tar.xz\0"[..]);
+    }
+
+    #[test]
+    fn test_gnu_names() {
+        let table = b"a_rather_long_file_name.o/\nanother_long_file_name.o/\n";
+        let ar = ArArchive::open(archive(&[member("/", b"\0\0\0\0"),
+                                           member("//", table),
+                                           member("/27", b"second\n"),
+                                           member("short name.o/", b"short\n"),
+                                           member("/0", b"first\n")])).unwrap();
+        assert_eq!(contents(&ar),
+                   vec![(b"another_long_file_name.o".to_vec(), b"second\n".to_vec()),
+                        (b"short name.o".to_vec(), b"short\n".to_vec()),
+                        (b"a_rather_long_file_name.o".to_vec(), b"first\n".to_vec())]);
+
+        // a long name with no table, and one past the end of the table
+        assert_eq!(ArArchive::open(archive(&[member("/0", b"x")])).err(),
+                   Some(GzError::BadArchive));
+        assert_eq!(ArArchive::open(archive(&[member("//", table), member("/60", b"x")])).err(),
+                   Some(GzError::BadArchive));
+    }
+
+    #[test]
+    fn test_bsd_names() {
+        let ar = ArArchive::open(archive(&[member("#1/20", b"__.SYMDEF SORTED\0\0\0\0\0\0\0\0"),
+                                           member("#1/28", b"a_rather_long_file_name.o\0\0\0bsd\n"),
+                                           member("#1/3", b"abc")])).unwrap();
+        assert_eq!(contents(&ar), vec![(b"a_rather_long_file_name.o".to_vec(), b"bsd\n".to_vec()),
+                                       (b"abc".to_vec(), Vec::new())]);
+
+        assert_eq!(ArArchive::open(archive(&[member("#1/8", b"abc")])).err(),
+                   Some(GzError::BadArchive));
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        assert_eq!(ArArchive::open(from_slice(b"!<thin>\n")).err(), Some(GzError::BadMagic));
+
+        let mut bad_end = member("a", b"a\n");
+        bad_end[59] = b' ';
+        assert_eq!(ArArchive::open(archive(&[bad_end])).err(), Some(GzError::BadArchive));
+
+        let truncated = member("a", b"abcdef");
+        assert_eq!(ArArchive::open(archive(&[truncated[.. 40].to_vec()])).err(),
+                   Some(GzError::TruncatedHeader));
+        assert_eq!(ArArchive::open(archive(&[truncated[.. 62].to_vec()])).err(),
+                   Some(GzError::TruncatedData));
+    }
+}
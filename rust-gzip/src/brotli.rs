@@ -0,0 +1,959 @@
+#[doc="
+
+    Module: brotli
+
+    This decompresses Brotli streams (RFC 7932), which web servers keep
+    next to gzip versions of their assets. A stream is a run of
+    meta-blocks, each either stored, metadata to skip, or compressed.
+    Compressed ones switch between several prefix codes for literals,
+    commands and distances, chosen by block types and by context maps
+    over the last two bytes, and may copy words from a built-in static
+    dictionary, optionally transformed, rather than from earlier output.
+    Brotli has no magic number, so nothing can detect it: the caller
+    has to know from the file name.
+
+"]
+use std::cmp;
+use std::iter::repeat;
+use self::Transform::{Identity, OmitLast, OmitFirst, UppercaseFirst, UppercaseAll};
+use cvec::Buf;
+use error::{GzError, GzResult};
+use limits;
+use gz_reader::GzBitReader;
+use huffman::{HuffmanTable, HuffmanRange, build_huffman_table};
+
+// Brotli manages about 4:1 on the text it's meant for
+const EXPECTED_RATIO: usize = 4;
+// with only one block type there is never a switch, and no meta-block
+// holds more than 2^24 bytes
+const NO_SWITCH: u32 = 1 << 24;
+const LITERAL_ALPHABET: usize = 256;
+const COMMAND_ALPHABET: usize = 704;
+const BLOCK_COUNT_ALPHABET: usize = 26;
+const CODE_LENGTH_ALPHABET: usize = 18;
+// each literal block type has 64 contexts, each distance block type 4
+const LITERAL_CONTEXT_BITS: usize = 6;
+const DISTANCE_CONTEXT_BITS: usize = 2;
+// the code length code lengths are stored in this order
+const CODE_LENGTH_ORDER: [usize; 18] = [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+// and each with a small fixed code, looked up by its next 4 bits
+const CODE_LENGTH_CODE_BITS: [u32; 16] = [2, 2, 2, 3, 2, 2, 2, 4, 2, 2, 2, 3, 2, 2, 2, 4];
+const CODE_LENGTH_CODE_VALUES: [u32; 16] = [0, 4, 3, 2, 0, 4, 3, 1, 0, 4, 3, 2, 0, 4, 3, 5];
+// code lengths 16 and 17 repeat the previous non-zero length or zero
+const REPEAT_PREVIOUS: u32 = 16;
+const REPEAT_ZERO: u32 = 17;
+// the first non-zero length code 16 can repeat, before any is seen
+const INITIAL_REPEAT_LENGTH: u32 = 8;
+// the sum of 2^-length over a complete code, scaled up by 2^15
+const CODE_SPACE: u32 = 1 << 15;
+// the sum of 2^-length over a complete code length code, scaled by 2^5
+const CODE_LENGTH_SPACE: u32 = 1 << 5;
+// each block count symbol is a base and the number of extra bits after it
+const BLOCK_COUNTS: [(u32, u32); 26] = [
+    (1, 2), (5, 2), (9, 2), (13, 2), (17, 3), (25, 3), (33, 3), (41, 3), (49, 4), (65, 4),
+    (81, 4), (97, 4), (113, 5), (145, 5), (177, 5), (209, 5), (241, 6), (305, 6), (369, 7),
+    (497, 8), (753, 9), (1265, 10), (2289, 11), (4337, 12), (8433, 13), (16625, 24)];
+// and the same for insert and copy length codes
+const INSERT_LENGTHS: [(u32, u32); 24] = [
+    (0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0), (6, 1), (8, 1), (10, 2), (14, 2), (18, 3),
+    (26, 3), (34, 4), (50, 4), (66, 5), (98, 5), (130, 6), (194, 7), (322, 8), (578, 9),
+    (1090, 10), (2114, 12), (6210, 14), (22594, 24)];
+const COPY_LENGTHS: [(u32, u32); 24] = [
+    (2, 0), (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 1), (12, 1), (14, 2),
+    (18, 2), (22, 3), (30, 3), (38, 4), (54, 4), (70, 5), (102, 5), (134, 6), (198, 7),
+    (326, 8), (582, 9), (1094, 10), (2118, 24)];
+// each group of 64 commands starts at these insert and copy length codes
+const COMMAND_GROUPS: [(usize, usize); 11] = [
+    (0, 0), (0, 8), (0, 0), (0, 8), (8, 0), (8, 8), (0, 16), (16, 0), (8, 16), (16, 8), (16, 16)];
+// commands below this reuse the last distance without reading a code
+const IMPLICIT_DISTANCE_COMMANDS: u32 = 128;
+// the distance codes below 16 are one of the last four distances,
+// give or take a little
+const SHORT_DISTANCE_CODES: usize = 16;
+const SHORT_DISTANCES: [(usize, isize); 16] = [
+    (0, 0), (1, 0), (2, 0), (3, 0), (0, -1), (0, 1), (0, -2), (0, 2), (0, -3), (0, 3),
+    (1, -1), (1, 1), (1, -2), (1, 2), (1, -3), (1, 3)];
+const INITIAL_DISTANCES: [usize; 4] = [4, 11, 15, 16];
+
+// the static dictionary from RFC 7932 appendix A, and where the words
+// of each length start in it, and how many bits index them
+const DICTIONARY: &'static [u8] = include_bytes!("brotli_dictionary.bin");
+const DICTIONARY_OFFSETS: [usize; 25] = [
+    0, 0, 0, 0, 0, 4096, 9216, 21504, 35840, 44032, 53248, 63488, 74752, 87040, 93696, 100864,
+    104704, 106752, 108928, 113536, 115968, 118528, 119872, 121280, 122016];
+const DICTIONARY_SIZE_BITS: [usize; 25] = [
+    0, 0, 0, 0, 10, 10, 11, 11, 10, 10, 10, 10, 10, 9, 9, 8, 7, 7, 8, 7, 7, 6, 6, 5, 5];
+const MIN_WORD_LEN: usize = 4;
+const MAX_WORD_LEN: usize = 24;
+
+/// How a dictionary word is changed before it's copied out
+#[derive(Copy, Clone, Show, PartialEq)]
+enum Transform {
+    Identity,
+    OmitLast(usize),
+    OmitFirst(usize),
+    UppercaseFirst,
+    UppercaseAll,
+}
+
+// the transforms from RFC 7932 appendix B: a prefix, the change to the
+// word itself, and a suffix
+const TRANSFORMS: [(&'static [u8], Transform, &'static [u8]); 121] = [
+    (b"", Identity, b""),
+    (b"", Identity, b" "),
+    (b" ", Identity, b" "),
+    (b"", OmitFirst(1), b""),
+    (b"", UppercaseFirst, b" "),
+    (b"", Identity, b" the "),
+    (b" ", Identity, b""),
+    (b"s ", Identity, b" "),
+    (b"", Identity, b" of "),
+    (b"", UppercaseFirst, b""),
+    (b"", Identity, b" and "),
+    (b"", OmitFirst(2), b""),
+    (b"", OmitLast(1), b""),
+    (b", ", Identity, b" "),
+    (b"", Identity, b", "),
+    (b" ", UppercaseFirst, b" "),
+    (b"", Identity, b" in "),
+    (b"", Identity, b" to "),
+    (b"e ", Identity, b" "),
+    (b"", Identity, b"\""),
+    (b"", Identity, b"."),
+    (b"", Identity, b"\">"),
+    (b"", Identity, b"\n"),
+    (b"", OmitLast(3), b""),
+    (b"", Identity, b"]"),
+    (b"", Identity, b" for "),
+    (b"", OmitFirst(3), b""),
+    (b"", OmitLast(2), b""),
+    (b"", Identity, b" a "),
+    (b"", Identity, b" that "),
+    (b" ", UppercaseFirst, b""),
+    (b"", Identity, b". "),
+    (b".", Identity, b""),
+    (b" ", Identity, b", "),
+    (b"", OmitFirst(4), b""),
+    (b"", Identity, b" with "),
+    (b"", Identity, b"'"),
+    (b"", Identity, b" from "),
+    (b"", Identity, b" by "),
+    (b"", OmitFirst(5), b""),
+    (b"", OmitFirst(6), b""),
+    (b" the ", Identity, b""),
+    (b"", OmitLast(4), b""),
+    (b"", Identity, b". The "),
+    (b"", UppercaseAll, b""),
+    (b"", Identity, b" on "),
+    (b"", Identity, b" as "),
+    (b"", Identity, b" is "),
+    (b"", OmitLast(7), b""),
+    (b"", OmitLast(1), b"ing "),
+    (b"", Identity, b"\n\t"),
+    (b"", Identity, b":"),
+    (b" ", Identity, b". "),
+    (b"", Identity, b"ed "),
+    (b"", OmitFirst(9), b""),
+    (b"", OmitFirst(7), b""),
+    (b"", OmitLast(6), b""),
+    (b"", Identity, b"("),
+    (b"", UppercaseFirst, b", "),
+    (b"", OmitLast(8), b""),
+    (b"", Identity, b" at "),
+    (b"", Identity, b"ly "),
+    (b" the ", Identity, b" of "),
+    (b"", OmitLast(5), b""),
+    (b"", OmitLast(9), b""),
+    (b" ", UppercaseFirst, b", "),
+    (b"", UppercaseFirst, b"\""),
+    (b".", Identity, b"("),
+    (b"", UppercaseAll, b" "),
+    (b"", UppercaseFirst, b"\">"),
+    (b"", Identity, b"=\""),
+    (b" ", Identity, b"."),
+    (b".com/", Identity, b""),
+    (b" the ", Identity, b" of the "),
+    (b"", UppercaseFirst, b"'"),
+    (b"", Identity, b". This "),
+    (b"", Identity, b","),
+    (b".", Identity, b" "),
+    (b"", UppercaseFirst, b"("),
+    (b"", UppercaseFirst, b"."),
+    (b"", Identity, b" not "),
+    (b" ", Identity, b"=\""),
+    (b"", Identity, b"er "),
+    (b" ", UppercaseAll, b" "),
+    (b"", Identity, b"al "),
+    (b" ", UppercaseAll, b""),
+    (b"", Identity, b"='"),
+    (b"", UppercaseAll, b"\""),
+    (b"", UppercaseFirst, b". "),
+    (b" ", Identity, b"("),
+    (b"", Identity, b"ful "),
+    (b" ", UppercaseFirst, b". "),
+    (b"", Identity, b"ive "),
+    (b"", Identity, b"less "),
+    (b"", UppercaseAll, b"'"),
+    (b"", Identity, b"est "),
+    (b" ", UppercaseFirst, b"."),
+    (b"", UppercaseAll, b"\">"),
+    (b" ", Identity, b"='"),
+    (b"", UppercaseFirst, b","),
+    (b"", Identity, b"ize "),
+    (b"", UppercaseAll, b"."),
+    (b"\xc2\xa0", Identity, b""),
+    (b" ", Identity, b","),
+    (b"", UppercaseFirst, b"=\""),
+    (b"", UppercaseAll, b"=\""),
+    (b"", Identity, b"ous "),
+    (b"", UppercaseAll, b", "),
+    (b"", UppercaseFirst, b"='"),
+    (b" ", UppercaseFirst, b","),
+    (b" ", UppercaseAll, b"=\""),
+    (b" ", UppercaseAll, b", "),
+    (b"", UppercaseAll, b","),
+    (b"", UppercaseAll, b"("),
+    (b"", UppercaseAll, b". "),
+    (b" ", UppercaseAll, b"."),
+    (b"", UppercaseAll, b"='"),
+    (b" ", UppercaseAll, b". "),
+    (b" ", UppercaseFirst, b"=\""),
+    (b" ", UppercaseAll, b"='"),
+    (b" ", UppercaseFirst, b"='"),
+];
+
+// literal context modes 2 and 3 look the last two bytes up in these,
+// from RFC 7932 section 7.1
+const UTF8_LAST: [u8; 256] = [
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  4,  4,  0,  0,  4,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+     8, 12, 16, 12, 12, 20, 12, 16, 24, 28, 12, 12, 32, 12, 36, 12,
+    44, 44, 44, 44, 44, 44, 44, 44, 44, 44, 32, 32, 24, 40, 28, 12,
+    12, 48, 52, 52, 52, 48, 52, 52, 52, 48, 52, 52, 52, 52, 52, 48,
+    52, 52, 52, 52, 52, 48, 52, 52, 52, 52, 52, 24, 12, 28, 12, 12,
+    12, 56, 60, 60, 60, 56, 60, 60, 60, 56, 60, 60, 60, 60, 60, 56,
+    60, 60, 60, 60, 60, 56, 60, 60, 60, 60, 60, 24, 12, 28, 12,  0,
+     0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,
+     0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,
+     0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,
+     0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,  0,  1,
+     2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,
+     2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,
+     2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,
+     2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,  2,  3,
+];
+const UTF8_SECOND_LAST: [u8; 256] = [
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+     0,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,
+     2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  1,  1,  1,  1,  1,  1,
+     1,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,
+     2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  1,  1,  1,  1,  1,
+     1,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,
+     3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  1,  1,  1,  1,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+     2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,
+     2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,
+];
+const SIGNED: [u8; 256] = [
+     0,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,
+     2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,
+     2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,
+     2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  2,
+     3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,
+     3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,
+     3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,
+     3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,  3,
+     4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,
+     4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,
+     4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,
+     4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,
+     5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,
+     5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,
+     5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,  5,
+     6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  7,
+];
+
+/// A prefix code, which may have a single symbol that takes no bits
+enum PrefixCode {
+    Single(u32),
+    Table(HuffmanTable),
+}
+
+impl PrefixCode {
+    /// Decode the next symbol from the stream
+    fn read(&self, stream: &mut GzBitReader) -> GzResult<u32> {
+        match *self {
+            PrefixCode::Single(symbol) => Ok(symbol),
+            PrefixCode::Table(ref table) => table.read(stream)
+        }
+    }
+}
+
+/// The block types of one category (literals, commands or distances),
+/// and how many more symbols are left in the current block
+struct BlockSwitch {
+    types: usize,
+    /// the codes for the next block type and its count, if there is more
+    /// than one type
+    codes: Option<(PrefixCode, PrefixCode)>,
+    current: usize,
+    previous: usize,
+    remaining: u32,
+}
+
+impl BlockSwitch {
+    /// Read the number of block types of a category, their codes and the
+    /// length of the first block
+    fn read(stream: &mut GzBitReader) -> GzResult<BlockSwitch> {
+        let types = try!(read_var_len_u8(stream)) + 1;
+        if types == 1 {
+            return Ok(BlockSwitch { types: 1, codes: None, current: 0, previous: 1,
+                                    remaining: NO_SWITCH });
+        }
+        let type_code = try!(read_prefix_code(stream, types + 2));
+        let count_code = try!(read_prefix_code(stream, BLOCK_COUNT_ALPHABET));
+        let remaining = try!(read_block_count(stream, &count_code));
+        Ok(BlockSwitch {
+            types: types,
+            codes: Some((type_code, count_code)),
+            current: 0,
+            previous: 1,
+            remaining: remaining
+        })
+    }
+
+    /// Account for the next symbol of this category, switching to the
+    /// next block first if the current one is used up
+    fn advance(&mut self, stream: &mut GzBitReader) -> GzResult<()> {
+        if self.remaining == 0 {
+            let (block_type, count) = match self.codes {
+                Some((ref type_code, ref count_code)) => {
+                    let block_type = match try!(type_code.read(stream)) {
+                        0 => self.previous,
+                        1 => (self.current + 1) % self.types,
+                        code => code as usize - 2
+                    };
+                    (block_type, try!(read_block_count(stream, count_code)))
+                },
+                None => return Err(GzError::CorruptData)
+            };
+            self.previous = self.current;
+            self.current = block_type;
+            self.remaining = count;
+        }
+        self.remaining -= 1;
+        Ok(())
+    }
+}
+
+/// Decompress the given Brotli stream
+pub fn decompress_brotli(buffer: Buf) -> GzResult<Buf> {
+    let mut out_buf = try!(limits::output_buf(buffer.len(), buffer.len() * EXPECTED_RATIO));
+    {
+        let mut stream = try_or!(GzBitReader::new(buffer.iter()), GzError::TruncatedHeader);
+        let window = try!(read_window_size(&mut stream));
+        let mut distances = INITIAL_DISTANCES;
+        loop {
+            let is_last = try!(read_bits(&mut stream, 1)) == 1;
+            if is_last && try!(read_bits(&mut stream, 1)) == 1 {
+                // the last meta-block is empty
+                break;
+            }
+            let nibbles = match try!(read_bits(&mut stream, 2)) {
+                3 => 0,
+                n => n + 4
+            };
+            if nibbles == 0 {
+                try!(skip_metadata(&mut stream));
+            } else {
+                let len = try!(read_meta_block_len(&mut stream, nibbles));
+                let uncompressed = !is_last && try!(read_bits(&mut stream, 1)) == 1;
+                if uncompressed {
+                    try!(copy_uncompressed(&mut stream, len, &mut out_buf));
+                } else {
+                    try!(decompress_meta_block(&mut stream, len, window, &mut distances,
+                                               &mut out_buf));
+                }
+            }
+            // like gzip, ignore anything after the last meta-block
+            if is_last {
+                break;
+            }
+        }
+    }
+    Ok(out_buf)
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+/// Read count bits, failing if the input runs out
+fn read_bits(stream: &mut GzBitReader, count: u32) -> GzResult<u32> {
+    Ok(try_or!(stream.read_bits(count), GzError::TruncatedData))
+}
+
+/// Read the window size at the start of the stream, which limits how far
+/// back a distance may reach
+fn read_window_size(stream: &mut GzBitReader) -> GzResult<usize> {
+    let bits = if try!(read_bits(stream, 1)) == 0 {
+        16
+    } else {
+        match try!(read_bits(stream, 3)) {
+            0 => match try!(read_bits(stream, 3)) {
+                0 => 17,
+                // reserved, for the large window extension
+                1 => return Err(GzError::UnsupportedMethod),
+                n => n + 8
+            },
+            n => n + 17
+        }
+    };
+    Ok((1 << bits as usize) - 16)
+}
+
+/// Read a number from 0 to 255 in the variable length format used for
+/// counts of block types and trees
+fn read_var_len_u8(stream: &mut GzBitReader) -> GzResult<usize> {
+    if try!(read_bits(stream, 1)) == 0 {
+        return Ok(0);
+    }
+    let bits = try!(read_bits(stream, 3));
+    if bits == 0 {
+        return Ok(1);
+    }
+    Ok((1 << bits as usize) + try!(read_bits(stream, bits)) as usize)
+}
+
+/// Read the length of a meta-block, stored as nibbles - 1
+fn read_meta_block_len(stream: &mut GzBitReader, nibbles: u32) -> GzResult<usize> {
+    let len = try!(read_bits(stream, nibbles * 4));
+    // the length must be stored in as few nibbles as possible
+    if nibbles > 4 && len >> ((nibbles - 1) * 4) as usize == 0 {
+        return Err(GzError::CorruptData);
+    }
+    Ok(len as usize + 1)
+}
+
+/// Move on to the next byte boundary, whose padding bits must be zero
+fn align_to_byte(stream: &mut GzBitReader) -> GzResult<()> {
+    let partial = (stream.bit_position() % 8) as u32;
+    if partial != 0 && try!(read_bits(stream, 8 - partial)) != 0 {
+        return Err(GzError::CorruptData);
+    }
+    Ok(())
+}
+
+/// Skip a metadata meta-block, which holds nothing worth searching
+fn skip_metadata(stream: &mut GzBitReader) -> GzResult<()> {
+    if try!(read_bits(stream, 1)) != 0 {
+        return Err(GzError::CorruptData);
+    }
+    let len_bytes = try!(read_bits(stream, 2));
+    let mut len = 0;
+    for i in (0 .. len_bytes) {
+        let byte = try!(read_bits(stream, 8));
+        if i > 0 && i == len_bytes - 1 && byte == 0 {
+            return Err(GzError::CorruptData);
+        }
+        len |= byte << (8 * i) as usize;
+    }
+    if len_bytes > 0 {
+        len += 1;
+    }
+    try!(align_to_byte(stream));
+    for _ in (0 .. len) {
+        try!(read_bits(stream, 8));
+    }
+    Ok(())
+}
+
+/// Copy the len bytes of an uncompressed meta-block into out
+fn copy_uncompressed(stream: &mut GzBitReader, len: usize, out: &mut Buf) -> GzResult<()> {
+    try!(align_to_byte(stream));
+    for _ in (0 .. len) {
+        let byte = try!(read_bits(stream, 8));
+        try!(out.try_push(byte as u8));
+    }
+    Ok(())
+}
+
+/// Decompress a compressed meta-block of len bytes into out
+/// distances holds the last four distances, which carry over from one
+/// meta-block to the next
+fn decompress_meta_block(stream: &mut GzBitReader,
+                         len: usize,
+                         window: usize,
+                         distances: &mut [usize; 4],
+                         out: &mut Buf)
+        -> GzResult<()> {
+    let mut literal_blocks = try!(BlockSwitch::read(stream));
+    let mut command_blocks = try!(BlockSwitch::read(stream));
+    let mut distance_blocks = try!(BlockSwitch::read(stream));
+    let postfix_bits = try!(read_bits(stream, 2));
+    let direct_codes = (try!(read_bits(stream, 4)) << postfix_bits as usize) as usize;
+    let mut context_modes = Vec::with_capacity(literal_blocks.types);
+    for _ in (0 .. literal_blocks.types) {
+        context_modes.push(try!(read_bits(stream, 2)));
+    }
+    let literal_trees = try!(read_var_len_u8(stream)) + 1;
+    let literal_map = try!(read_context_map(stream, literal_trees,
+                                            literal_blocks.types << LITERAL_CONTEXT_BITS));
+    let distance_trees = try!(read_var_len_u8(stream)) + 1;
+    let distance_map = try!(read_context_map(stream, distance_trees,
+                                             distance_blocks.types << DISTANCE_CONTEXT_BITS));
+    let literal_codes = try!(read_prefix_codes(stream, literal_trees, LITERAL_ALPHABET));
+    let command_codes = try!(read_prefix_codes(stream, command_blocks.types, COMMAND_ALPHABET));
+    let distance_alphabet = SHORT_DISTANCE_CODES + direct_codes + (48 << postfix_bits as usize);
+    let distance_codes = try!(read_prefix_codes(stream, distance_trees, distance_alphabet));
+
+    let end = out.len() + len;
+    while out.len() < end {
+        try!(command_blocks.advance(stream));
+        let command = try!(command_codes[command_blocks.current].read(stream));
+        let (insert_len, copy_len) = try!(read_command_lengths(stream, command));
+        if insert_len > end - out.len() {
+            return Err(GzError::CorruptData);
+        }
+        for _ in (0 .. insert_len) {
+            try!(literal_blocks.advance(stream));
+            let context = literal_context(context_modes[literal_blocks.current], out);
+            let tree = literal_map[literal_blocks.current << LITERAL_CONTEXT_BITS | context];
+            let literal = try!(literal_codes[tree as usize].read(stream));
+            try!(out.try_push(literal as u8));
+        }
+        // the copy of the command that fills the meta-block is left out
+        if out.len() == end {
+            break;
+        }
+
+        let code = if command < IMPLICIT_DISTANCE_COMMANDS {
+            0
+        } else {
+            try!(distance_blocks.advance(stream));
+            let context = cmp::min(copy_len, 5) - 2;
+            let tree = distance_map[distance_blocks.current << DISTANCE_CONTEXT_BITS | context];
+            try!(distance_codes[tree as usize].read(stream)) as usize
+        };
+        let distance = try!(read_distance(stream, code, direct_codes, postfix_bits, distances));
+        let max_distance = cmp::min(window, out.len());
+        if distance > max_distance {
+            // past the start of the window, distances name dictionary words
+            try!(copy_dictionary_word(out, copy_len, distance - max_distance - 1, end));
+        } else {
+            if copy_len > end - out.len() {
+                return Err(GzError::CorruptData);
+            }
+            try!(out.copy_back_pointer(distance - 1, copy_len));
+            if code != 0 {
+                *distances = [distance, distances[0], distances[1], distances[2]];
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the insert and copy lengths of a command
+fn read_command_lengths(stream: &mut GzBitReader, command: u32) -> GzResult<(usize, usize)> {
+    let (first_insert, first_copy) = COMMAND_GROUPS[(command >> 6) as usize];
+    let (insert_base, insert_bits) = INSERT_LENGTHS[first_insert + (command >> 3 & 7) as usize];
+    let (copy_base, copy_bits) = COPY_LENGTHS[first_copy + (command & 7) as usize];
+    let insert_len = insert_base + try!(read_bits(stream, insert_bits));
+    let copy_len = copy_base + try!(read_bits(stream, copy_bits));
+    Ok((insert_len as usize, copy_len as usize))
+}
+
+/// Read the distance that code stands for, which may need extra bits
+fn read_distance(stream: &mut GzBitReader,
+                 code: usize,
+                 direct_codes: usize,
+                 postfix_bits: u32,
+                 distances: &[usize; 4])
+        -> GzResult<usize> {
+    if code < SHORT_DISTANCE_CODES {
+        let (index, delta) = SHORT_DISTANCES[code];
+        let distance = distances[index] as isize + delta;
+        if distance <= 0 {
+            return Err(GzError::CorruptData);
+        }
+        return Ok(distance as usize);
+    }
+    let code = code - SHORT_DISTANCE_CODES;
+    if code < direct_codes {
+        return Ok(code + 1);
+    }
+    let code = code - direct_codes;
+    let postfix_mask = (1 << postfix_bits as usize) - 1;
+    let extra_bits = 1 + (code >> (postfix_bits + 1) as usize);
+    let extra = try!(read_bits(stream, extra_bits as u32)) as usize;
+    let high = code >> postfix_bits as usize;
+    let low = code & postfix_mask;
+    let offset = ((2 + (high & 1)) << extra_bits) - 4;
+    Ok(((offset + extra) << postfix_bits as usize) + low + direct_codes + 1)
+}
+
+/// Which of the 64 contexts of its block type the next literal is in,
+/// going by the last two bytes of output
+fn literal_context(mode: u32, out: &Buf) -> usize {
+    let len = out.len();
+    let p1 = if len > 0 { out[len - 1] as usize } else { 0 };
+    let p2 = if len > 1 { out[len - 2] as usize } else { 0 };
+    match mode {
+        0 => p1 & 0x3f,
+        1 => p1 >> 2,
+        2 => (UTF8_LAST[p1] | UTF8_SECOND_LAST[p2]) as usize,
+        _ => (SIGNED[p1] << 3 | SIGNED[p2]) as usize
+    }
+}
+
+/// Copy out the dictionary word of length len with the given id, which
+/// picks both the word and the transform applied to it
+fn copy_dictionary_word(out: &mut Buf, len: usize, id: usize, end: usize) -> GzResult<()> {
+    if len < MIN_WORD_LEN || len > MAX_WORD_LEN {
+        return Err(GzError::CorruptData);
+    }
+    let bits = DICTIONARY_SIZE_BITS[len];
+    let (index, transform) = (id & ((1 << bits) - 1), id >> bits);
+    if transform >= TRANSFORMS.len() {
+        return Err(GzError::CorruptData);
+    }
+    let start = DICTIONARY_OFFSETS[len] + index * len;
+    let word = &DICTIONARY[start .. start + len];
+    let (prefix, change, suffix) = TRANSFORMS[transform];
+
+    let mut transformed = prefix.to_vec();
+    let word_start = transformed.len();
+    match change {
+        OmitFirst(n) => transformed.push_all(&word[cmp::min(n, len) ..]),
+        OmitLast(n) => transformed.push_all(&word[.. len - cmp::min(n, len)]),
+        _ => transformed.push_all(word)
+    }
+    match change {
+        UppercaseFirst => {
+            uppercase(&mut transformed[word_start ..]);
+        },
+        UppercaseAll => {
+            let mut i = word_start;
+            while i < transformed.len() {
+                i += uppercase(&mut transformed[i ..]);
+            }
+        },
+        _ => {}
+    }
+    transformed.push_all(suffix);
+
+    if transformed.len() > end - out.len() {
+        return Err(GzError::CorruptData);
+    }
+    for &byte in transformed.iter() {
+        try!(out.try_push(byte));
+    }
+    Ok(())
+}
+
+/// Uppercase the character at the start of word the way Brotli does,
+/// which only gets ASCII right. Returns the length of the character
+fn uppercase(word: &mut [u8]) -> usize {
+    if word[0] < 0xc0 {
+        if word[0] >= b'a' && word[0] <= b'z' {
+            word[0] ^= 0x20;
+        }
+        1
+    } else if word[0] < 0xe0 {
+        if word.len() > 1 {
+            word[1] ^= 0x20;
+        }
+        2
+    } else {
+        if word.len() > 2 {
+            word[2] ^= 5;
+        }
+        3
+    }
+}
+
+/// Read the length of the next block
+fn read_block_count(stream: &mut GzBitReader, count_code: &PrefixCode) -> GzResult<u32> {
+    let (base, extra_bits) = BLOCK_COUNTS[try!(count_code.read(stream)) as usize];
+    Ok(base + try!(read_bits(stream, extra_bits)))
+}
+
+/// Read a context map of size entries, which picks one of trees prefix
+/// codes for each context of each block type
+fn read_context_map(stream: &mut GzBitReader, trees: usize, size: usize) -> GzResult<Vec<u8>> {
+    if trees == 1 {
+        return Ok(repeat(0).take(size).collect());
+    }
+    // runs of up to 2^max_run_bits - 1 zeroes get codes of their own
+    let max_run_bits = if try!(read_bits(stream, 1)) == 1 {
+        try!(read_bits(stream, 4)) + 1
+    } else {
+        0
+    };
+    let code = try!(read_prefix_code(stream, trees + max_run_bits as usize));
+    let mut map = Vec::with_capacity(size);
+    while map.len() < size {
+        let symbol = try!(code.read(stream));
+        if symbol == 0 {
+            map.push(0);
+        } else if symbol <= max_run_bits {
+            let run = (1 << symbol as usize) + try!(read_bits(stream, symbol)) as usize;
+            if map.len() + run > size {
+                return Err(GzError::CorruptData);
+            }
+            map.extend(repeat(0).take(run));
+        } else {
+            map.push((symbol - max_run_bits) as u8);
+        }
+    }
+    if try!(read_bits(stream, 1)) == 1 {
+        inverse_move_to_front(map.as_mut_slice());
+    }
+    Ok(map)
+}
+
+/// Undo the move-to-front transform context maps may be stored with
+fn inverse_move_to_front(values: &mut [u8]) {
+    let mut order = [0u8; 256];
+    for i in (0 .. 256) {
+        order[i] = i as u8;
+    }
+    for value in values.iter_mut() {
+        let index = *value as usize;
+        let front = order[index];
+        for i in (0 .. index).rev() {
+            order[i + 1] = order[i];
+        }
+        order[0] = front;
+        *value = front;
+    }
+}
+
+/// Read count prefix codes over the same alphabet
+fn read_prefix_codes(stream: &mut GzBitReader, count: usize, alphabet: usize)
+        -> GzResult<Vec<PrefixCode>> {
+    let mut codes = Vec::with_capacity(count);
+    for _ in (0 .. count) {
+        codes.push(try!(read_prefix_code(stream, alphabet)));
+    }
+    Ok(codes)
+}
+
+/// Read a prefix code for an alphabet of the given size, which is either
+/// a simple one listing up to four symbols, or a complex one giving the
+/// length of every symbol's code
+fn read_prefix_code(stream: &mut GzBitReader, alphabet: usize) -> GzResult<PrefixCode> {
+    let skip = try!(read_bits(stream, 2));
+    if skip == 1 {
+        read_simple_prefix_code(stream, alphabet)
+    } else {
+        read_complex_prefix_code(stream, alphabet, skip as usize)
+    }
+}
+
+fn read_simple_prefix_code(stream: &mut GzBitReader, alphabet: usize) -> GzResult<PrefixCode> {
+    let count = try!(read_bits(stream, 2)) as usize + 1;
+    let mut symbol_bits = 0;
+    while (1 << symbol_bits as usize) < alphabet {
+        symbol_bits += 1;
+    }
+    let mut symbols = Vec::with_capacity(count);
+    for _ in (0 .. count) {
+        let symbol = try!(read_bits(stream, symbol_bits)) as usize;
+        if symbol >= alphabet || symbols.contains(&symbol) {
+            return Err(GzError::BadHuffmanTable);
+        }
+        symbols.push(symbol);
+    }
+    let code_lengths: &[u32] = match count {
+        1 => return Ok(PrefixCode::Single(symbols[0] as u32)),
+        2 => &[1, 1],
+        3 => &[1, 2, 2],
+        _ => if try!(read_bits(stream, 1)) == 0 { &[2, 2, 2, 2] } else { &[1, 2, 3, 3] }
+    };
+    let mut lengths: Vec<u32> = repeat(0).take(alphabet).collect();
+    for (&symbol, &length) in symbols.iter().zip(code_lengths.iter()) {
+        lengths[symbol] = length;
+    }
+    Ok(PrefixCode::Table(try!(build_table(lengths.as_slice()))))
+}
+
+fn read_complex_prefix_code(stream: &mut GzBitReader, alphabet: usize, skip: usize)
+        -> GzResult<PrefixCode> {
+    // first the code that the code lengths are written in
+    let mut code_length_lengths = [0u32; 18];
+    let mut space = CODE_LENGTH_SPACE as i32;
+    let mut used = 0;
+    for &symbol in CODE_LENGTH_ORDER[skip ..].iter() {
+        let (bits, _) = stream.peek_bits(4);
+        let length = CODE_LENGTH_CODE_VALUES[bits as usize];
+        try_or!(stream.consume_bits(CODE_LENGTH_CODE_BITS[bits as usize]), GzError::TruncatedData);
+        code_length_lengths[symbol] = length;
+        if length != 0 {
+            used += 1;
+            space -= (CODE_LENGTH_SPACE >> length as usize) as i32;
+            if space <= 0 {
+                break;
+            }
+        }
+    }
+    // a lone code length takes no bits, otherwise the code must be complete
+    if used != 1 && space != 0 {
+        return Err(GzError::BadHuffmanTable);
+    }
+    let code_length_code = if used == 1 {
+        let symbol = code_length_lengths.iter().position(|&length| length != 0).unwrap();
+        PrefixCode::Single(symbol as u32)
+    } else {
+        PrefixCode::Table(try!(build_table(&code_length_lengths)))
+    };
+
+    // then the length of each symbol's code
+    let mut lengths: Vec<u32> = repeat(0).take(alphabet).collect();
+    let mut symbol = 0;
+    let mut space = CODE_SPACE;
+    let mut previous_length = INITIAL_REPEAT_LENGTH;
+    let mut repeat_length = 0;
+    let mut repeated = 0;
+    while symbol < alphabet && space > 0 {
+        let code = try!(code_length_code.read(stream));
+        if code < REPEAT_PREVIOUS {
+            repeated = 0;
+            lengths[symbol] = code;
+            symbol += 1;
+            if code != 0 {
+                previous_length = code;
+                space = try!(take_space(space, CODE_SPACE >> code as usize));
+            }
+            continue;
+        }
+        // consecutive repeat codes of the same kind multiply together
+        let (extra_bits, length) = if code == REPEAT_PREVIOUS {
+            (2, previous_length)
+        } else {
+            (3, 0)
+        };
+        if length != repeat_length {
+            repeated = 0;
+            repeat_length = length;
+        }
+        let previous_repeated = repeated;
+        if repeated > 0 {
+            repeated = (repeated - 2) << extra_bits as usize;
+        }
+        repeated += try!(read_bits(stream, extra_bits)) as usize + 3;
+        let count = repeated - previous_repeated;
+        if symbol + count > alphabet {
+            return Err(GzError::BadHuffmanTable);
+        }
+        for _ in (0 .. count) {
+            lengths[symbol] = repeat_length;
+            symbol += 1;
+        }
+        if repeat_length != 0 {
+            space = try!(take_space(space, (count as u32) << (15 - repeat_length) as usize));
+        }
+    }
+    if space != 0 {
+        return Err(GzError::BadHuffmanTable);
+    }
+    Ok(PrefixCode::Table(try!(build_table(lengths.as_slice()))))
+}
+
+/// Take the space used by some codes from what's left, failing if they
+/// don't fit
+fn take_space(space: u32, used: u32) -> GzResult<u32> {
+    if used > space {
+        return Err(GzError::BadHuffmanTable);
+    }
+    Ok(space - used)
+}
+
+/// Build the decoding table for the code with the given lengths
+fn build_table(lengths: &[u32]) -> GzResult<HuffmanTable> {
+    let mut ranges = Vec::new();
+    let mut range = HuffmanRange::new();
+    for (i, &length) in lengths.iter().enumerate() {
+        if i > 0 && length != lengths[i - 1] {
+            ranges.push(range.clone());
+        }
+        range.end = i as u32;
+        range.bit_length = length;
+    }
+    ranges.push(range);
+    build_huffman_table(ranges.as_slice())
+}
+
+#[cfg(test)]
+mod brotli_tests {
+    use super::{decompress_brotli, inverse_move_to_front, uppercase};
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_brotli(from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    // a 64K window, an uncompressed meta-block holding "hello", and an
+    // empty last meta-block
+    const STORED: [u8; 9] = [0x40, 0x00, 0x10, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x03];
+
+    #[test]
+    fn test_compressed() {
+        // brotli -q 11, which copies most of the second half
+        let times = [0x1b, 0x33, 0x00, 0x30, 0x8d, 0x54, 0xb5, 0x7f, 0x84, 0x4a, 0xef, 0xb4,
+                     0x37, 0xb9, 0x3b, 0xa1, 0x00, 0x0e, 0x39, 0x60, 0xff, 0x76, 0x49, 0x29,
+                     0x0c, 0xb0, 0xe8, 0xeb, 0x82, 0x14, 0x35, 0x3a, 0xf5, 0x0e, 0x32, 0x30,
+                     0xe8, 0xe0, 0x6f, 0x04];
+        assert_eq!(decompress(&times),
+                   Ok(b"It was the best of times, it was the worst of times\n".to_vec()));
+        // and this one is mostly transformed dictionary words
+        let words = [0x1b, 0x3e, 0x00, 0xf8, 0x1d, 0xa9, 0x57, 0x9f, 0x34, 0x74, 0x49, 0xa5,
+                     0x10, 0xd6, 0x45, 0x48, 0x68, 0x6e, 0x0a, 0x84, 0x0c, 0x36, 0xe0, 0xc0,
+                     0x29, 0xc9, 0x38, 0xa0, 0xc1, 0x6e, 0x6a, 0x18, 0x32, 0x09, 0x29, 0x2f,
+                     0x3d, 0x6d, 0x2e, 0x75, 0xd8, 0x8f, 0x4c, 0x50, 0x0d];
+        assert_eq!(decompress(&words),
+                   Ok(b"The Government of the United States of America, and THE PEOPLE\n".to_vec()));
+    }
+
+    #[test]
+    fn test_uncompressed_and_empty() {
+        assert_eq!(decompress(&STORED), Ok(b"hello".to_vec()));
+        assert_eq!(decompress(&[0x3b]), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(&STORED[.. 6]), Err(GzError::TruncatedData));
+        // padding before the stored bytes must be zero
+        let mut bad_padding = STORED.to_vec();
+        bad_padding[2] = 0x30;
+        assert_eq!(decompress(bad_padding.as_slice()), Err(GzError::CorruptData));
+        // the window size reserved for large windows
+        assert_eq!(decompress(&[0x11, 0x00]), Err(GzError::UnsupportedMethod));
+    }
+
+    #[test]
+    fn test_uppercase() {
+        let mut word = b"hello".to_vec();
+        assert_eq!(uppercase(word.as_mut_slice()), 1);
+        assert_eq!(word.as_slice(), b"Hello");
+        // only the second byte of a two byte character changes
+        let mut word = b"\xc3\xa9t\xc3\xa9".to_vec();
+        assert_eq!(uppercase(word.as_mut_slice()), 2);
+        assert_eq!(word.as_slice(), b"\xc3\x89t\xc3\xa9");
+    }
+
+    #[test]
+    fn test_inverse_move_to_front() {
+        let mut values = [1, 1, 0, 2, 0];
+        inverse_move_to_front(&mut values);
+        assert_eq!(values, [1, 0, 0, 2, 2]);
+    }
+}
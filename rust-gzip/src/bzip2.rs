@@ -0,0 +1,548 @@
+#[doc="
+
+    Module: bzip2
+
+    This decompresses bzip2 files. Each block of up to 900k is undone
+    in the reverse of the order bzip2 applied its stages: Huffman
+    coding with up to six tables that take turns every 50 symbols,
+    then the run length coding of zeros and the move to front
+    transform, then the Burrows-Wheeler transform, and finally the
+    run length coding of the original bytes. Blocks and whole streams
+    both carry a CRC, and a file may hold several streams back to back.
+
+"]
+use std::iter::repeat;
+use cvec::Buf;
+use error::{GzError, GzResult};
+use limits;
+
+// These constants are defined by the bzip2 format
+const STREAM_MAGIC: &'static [u8] = b"BZh";
+const STREAM_HEADER_LEN: usize = 4;
+const BLOCK_MAGIC: u64 = 0x314159265359;
+const END_OF_STREAM_MAGIC: u64 = 0x177245385090;
+const BLOCK_SIZE_UNIT: usize = 100000;
+const MIN_TABLES: usize = 2;
+const MAX_TABLES: usize = 6;
+// each table is used for this many symbols before the next selector
+const GROUP_LEN: usize = 50;
+// bzip2 1.0.8 reads past this many selectors, but ignores the rest
+const MAX_SELECTORS: usize = 18002;
+const MAX_CODE_LENGTH: u32 = 20;
+const RUN_A: usize = 0;
+const RUN_B: usize = 1;
+// after this many copies of a byte, the next byte counts further copies
+const RUN_THRESHOLD: usize = 4;
+// bzip2 usually manages better than 4:1 on text, so this is a rough
+// first allocation
+const EXPECTED_RATIO: usize = 4;
+const CRC_POLYNOMIAL: u32 = 0x04c11db7;
+
+/// Decompress the given bzip2 file
+/// Like bzip2, this reads every stream in the file and ignores anything
+/// after the last one.
+pub fn decompress_bzip2(buffer: Buf) -> GzResult<Buf> {
+    if buffer.len() < STREAM_HEADER_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    if !is_stream_start(&buffer, 0) {
+        return Err(GzError::BadMagic);
+    }
+    let mut out_buf = try!(limits::output_buf(buffer.len(), buffer.len() * EXPECTED_RATIO));
+    let crc_table = make_crc_table();
+    let mut reader = BitReader::new(&buffer);
+    loop {
+        try!(decompress_stream(&mut reader, &crc_table, &mut out_buf));
+        reader.align_to_byte();
+        if !is_stream_start(&buffer, reader.byte_index()) {
+            break;
+        }
+    }
+    Ok(out_buf)
+}
+
+/// Whether a bzip2 stream begins at index start of the buffer
+fn is_stream_start(buffer: &Buf, start: usize) -> bool {
+    if start + STREAM_HEADER_LEN > buffer.len() {
+        return false;
+    }
+    let level = buffer[start + 3];
+    buffer.limit_iter(start, start + 3).zip(STREAM_MAGIC.iter()).all(|(a, b)| a == b)
+        && level >= b'1' && level <= b'9'
+}
+
+/// Decompress one stream, from its "BZh" header to the CRC at its end
+fn decompress_stream(reader: &mut BitReader, crc_table: &[u32; 256], out_buf: &mut Buf)
+        -> GzResult<()> {
+    for _ in (0 .. 3) {
+        try!(reader.read_bits(8));
+    }
+    let max_block_len = (try!(reader.read_bits(8)) - b'0' as u32) as usize * BLOCK_SIZE_UNIT;
+    // the block is first laid out here, one byte to an entry, and then
+    // the inverse BWT links each entry to the next in its high bits
+    let mut links: Vec<u32> = repeat(0).take(max_block_len).collect();
+    let mut stream_crc: u32 = 0;
+    loop {
+        let magic = ((try!(reader.read_bits(24)) as u64) << 24) | try!(reader.read_bits(24)) as u64;
+        let expected_crc = try!(reader.read_bits(32));
+        if magic == END_OF_STREAM_MAGIC {
+            if stream_crc != expected_crc {
+                return Err(GzError::CrcMismatch);
+            }
+            return Ok(());
+        }
+        if magic != BLOCK_MAGIC {
+            return Err(GzError::CorruptData);
+        }
+        let (block_len, start) = try!(read_block(reader, &mut links));
+        let crc = try!(write_block(&links, block_len, start, crc_table, out_buf));
+        if crc != expected_crc {
+            return Err(GzError::CrcMismatch);
+        }
+        stream_crc = ((stream_crc << 1) | (stream_crc >> 31)) ^ crc;
+    }
+}
+
+/// Read a block and undo everything up to the Burrows-Wheeler transform,
+/// which leaves each entry of links pointing at the one after it
+/// Returns the number of bytes in the block, and the entry to start at.
+fn read_block(reader: &mut BitReader, links: &mut Vec<u32>) -> GzResult<(usize, usize)> {
+    // blocks could be randomised to dodge slow sorting until bzip2 0.9.5,
+    // and nothing has written them since
+    if try!(reader.read_bits(1)) != 0 {
+        return Err(GzError::UnsupportedMethod);
+    }
+    let origin = try!(reader.read_bits(24)) as usize;
+
+    // which byte values appear in the block, in sixteen ranges of sixteen
+    let mut symbols: Vec<u8> = Vec::new();
+    let used_ranges = try!(reader.read_bits(16));
+    for i in (0 .. 16) {
+        if used_ranges & (0x8000 >> i) != 0 {
+            let used = try!(reader.read_bits(16));
+            for j in (0 .. 16) {
+                if used & (0x8000 >> j) != 0 {
+                    symbols.push((i * 16 + j) as u8);
+                }
+            }
+        }
+    }
+    if symbols.len() == 0 {
+        return Err(GzError::CorruptData);
+    }
+    // RUN_A, RUN_B, every byte but the first in the move to front list,
+    // and the end of the block
+    let alphabet_len = symbols.len() + 2;
+    let end_of_block = alphabet_len - 1;
+
+    let table_count = try!(reader.read_bits(3)) as usize;
+    if table_count < MIN_TABLES || table_count > MAX_TABLES {
+        return Err(GzError::CorruptData);
+    }
+    let selectors = try!(read_selectors(reader, table_count));
+    let mut tables = Vec::new();
+    for _ in (0 .. table_count) {
+        tables.push(try!(read_table(reader, alphabet_len)));
+    }
+
+    let mut mtf: Vec<u8> = (0 .. 256).map(|i| i as u8).collect();
+    let mut block_len = 0;
+    // a run of the first byte in the list is written in bijective base 2,
+    // with RUN_A and RUN_B as the digits 1 and 2, least significant first
+    let mut run_len = 0;
+    let mut run_digit = 1;
+    let mut selector = 0;
+    let mut group_left = 0;
+    loop {
+        if group_left == 0 {
+            if selector >= selectors.len() {
+                return Err(GzError::CorruptData);
+            }
+            group_left = GROUP_LEN;
+            selector += 1;
+        }
+        group_left -= 1;
+        let symbol = try!(tables[selectors[selector - 1] as usize].decode(reader));
+
+        if symbol == RUN_A || symbol == RUN_B {
+            run_len += run_digit << symbol;
+            run_digit <<= 1;
+            if run_len > links.len() {
+                return Err(GzError::CorruptData);
+            }
+            continue;
+        }
+        if run_len > 0 {
+            if block_len + run_len > links.len() || mtf[0] as usize >= symbols.len() {
+                return Err(GzError::CorruptData);
+            }
+            let byte = symbols[mtf[0] as usize] as u32;
+            for _ in (0 .. run_len) {
+                links[block_len] = byte;
+                block_len += 1;
+            }
+            run_len = 0;
+            run_digit = 1;
+        }
+        if symbol == end_of_block {
+            break;
+        }
+
+        let index = mtf.remove(symbol - 1);
+        mtf.insert(0, index);
+        if block_len >= links.len() || index as usize >= symbols.len() {
+            return Err(GzError::CorruptData);
+        }
+        links[block_len] = symbols[index as usize] as u32;
+        block_len += 1;
+    }
+    if origin >= block_len {
+        return Err(GzError::CorruptData);
+    }
+
+    // the inverse BWT: entry i of the sorted block follows the entry that
+    // sorts to the same place when the block is sorted by its bytes
+    let mut starts = [0usize; 256];
+    for i in (0 .. block_len) {
+        starts[(links[i] & 0xff) as usize] += 1;
+    }
+    let mut total = 0;
+    for start in starts.iter_mut() {
+        let count = *start;
+        *start = total;
+        total += count;
+    }
+    for i in (0 .. block_len) {
+        let byte = (links[i] & 0xff) as usize;
+        links[starts[byte]] |= (i as u32) << 8;
+        starts[byte] += 1;
+    }
+    // where the chain for the original order starts
+    Ok((block_len, (links[origin] >> 8) as usize))
+}
+
+/// Follow the links from read_block to write out the original bytes,
+/// undoing the first run length coding
+/// Returns the block's CRC.
+fn write_block(links: &Vec<u32>, block_len: usize, start: usize, crc_table: &[u32; 256],
+               out_buf: &mut Buf) -> GzResult<u32> {
+    let mut pos = start;
+    let mut crc: u32 = 0xffffffff;
+    let mut last: u8 = 0;
+    let mut run = 0;
+    for _ in (0 .. block_len) {
+        let link = links[pos];
+        let byte = (link & 0xff) as u8;
+        pos = (link >> 8) as usize;
+        let copies = if run == RUN_THRESHOLD {
+            run = 0;
+            byte as usize
+        } else {
+            if run > 0 && byte == last {
+                run += 1;
+            } else {
+                run = 1;
+                last = byte;
+            }
+            1
+        };
+        for _ in (0 .. copies) {
+            try!(out_buf.try_push(last));
+            crc = (crc << 8) ^ crc_table[((crc >> 24) as u8 ^ last) as usize];
+        }
+    }
+    Ok(!crc)
+}
+
+/// Read which table each group of symbols uses
+/// These are move to front coded, each index written in unary.
+fn read_selectors(reader: &mut BitReader, table_count: usize) -> GzResult<Vec<u8>> {
+    let selector_count = try!(reader.read_bits(15)) as usize;
+    if selector_count == 0 {
+        return Err(GzError::CorruptData);
+    }
+    let mut mtf: Vec<u8> = (0 .. table_count).map(|i| i as u8).collect();
+    let mut selectors = Vec::new();
+    for _ in (0 .. selector_count) {
+        let mut index = 0;
+        while try!(reader.read_bits(1)) == 1 {
+            index += 1;
+            if index >= table_count {
+                return Err(GzError::CorruptData);
+            }
+        }
+        let table = mtf.remove(index);
+        mtf.insert(0, table);
+        if selectors.len() < MAX_SELECTORS {
+            selectors.push(table);
+        }
+    }
+    Ok(selectors)
+}
+
+/// Read the code lengths of a Huffman table, each given as a change
+/// from the one before
+fn read_table(reader: &mut BitReader, alphabet_len: usize) -> GzResult<HuffmanCodes> {
+    let mut lengths = Vec::with_capacity(alphabet_len);
+    let mut length = try!(reader.read_bits(5));
+    for _ in (0 .. alphabet_len) {
+        loop {
+            if length < 1 || length > MAX_CODE_LENGTH {
+                return Err(GzError::BadHuffmanTable);
+            }
+            if try!(reader.read_bits(1)) == 0 {
+                break;
+            }
+            if try!(reader.read_bits(1)) == 0 {
+                length += 1;
+            } else {
+                length -= 1;
+            }
+        }
+        lengths.push(length);
+    }
+    HuffmanCodes::new(&lengths[..])
+}
+
+/// The CRC bzip2 uses is the same as gzip's, but most significant bit
+/// first
+fn make_crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    for i in (0 .. 256) {
+        let mut value = (i as u32) << 24;
+        for _ in (0 .. 8) {
+            value = if value & 0x80000000 != 0 {
+                (value << 1) ^ CRC_POLYNOMIAL
+            } else {
+                value << 1
+            };
+        }
+        table[i] = value;
+    }
+    table
+}
+
+/////////////////////////////////////////////////////////////////////
+//                         Huffman codes                           //
+/////////////////////////////////////////////////////////////////////
+
+/// A canonical Huffman code, decoded a length at a time
+/// bzip2 codes can be up to 20 bits long, too long for the deflate
+/// tables in the huffman module.
+struct HuffmanCodes {
+    min_length: u32,
+    max_length: u32,
+    /// the first code of each length
+    first_code: [u32; 21],
+    /// how many codes there are of each length
+    counts: [u32; 21],
+    /// where the symbols of each length start in symbols
+    first_index: [u32; 21],
+    /// the symbols, shortest codes first
+    symbols: Vec<u16>,
+}
+
+impl HuffmanCodes {
+    fn new(lengths: &[u32]) -> GzResult<HuffmanCodes> {
+        let mut codes = HuffmanCodes {
+            min_length: MAX_CODE_LENGTH,
+            max_length: 0,
+            first_code: [0; 21],
+            counts: [0; 21],
+            first_index: [0; 21],
+            symbols: Vec::with_capacity(lengths.len())
+        };
+        for &length in lengths.iter() {
+            codes.counts[length as usize] += 1;
+            if length < codes.min_length {
+                codes.min_length = length;
+            }
+            if length > codes.max_length {
+                codes.max_length = length;
+            }
+        }
+        let mut code = 0;
+        for length in (1 .. MAX_CODE_LENGTH as usize + 1) {
+            codes.first_code[length] = code;
+            codes.first_index[length] = codes.symbols.len() as u32;
+            for (symbol, &symbol_length) in lengths.iter().enumerate() {
+                if symbol_length as usize == length {
+                    codes.symbols.push(symbol as u16);
+                }
+            }
+            code = (code + codes.counts[length]) << 1;
+        }
+        Ok(codes)
+    }
+
+    /// Read the next symbol
+    fn decode(&self, reader: &mut BitReader) -> GzResult<usize> {
+        let bits = reader.peek_bits(self.max_length);
+        for length in (self.min_length .. self.max_length + 1) {
+            let code = bits >> (self.max_length - length) as usize;
+            let first_code = self.first_code[length as usize];
+            if code >= first_code && code - first_code < self.counts[length as usize] {
+                let offset = code - first_code;
+                try!(reader.consume_bits(length));
+                let index = self.first_index[length as usize] + offset;
+                return Ok(self.symbols[index as usize] as usize);
+            }
+        }
+        Err(GzError::InvalidCode)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+//                           Bit reader                            //
+/////////////////////////////////////////////////////////////////////
+
+/// Reads bits most significant first, unlike GzBitReader
+/// The next bit to read is always the top bit of bits.
+struct BitReader<'a> {
+    buffer: &'a Buf,
+    /// the next byte to load
+    byte_pos: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(buffer: &'a Buf) -> BitReader<'a> {
+        BitReader {
+            buffer: buffer,
+            byte_pos: 0,
+            bits: 0,
+            count: 0
+        }
+    }
+
+    /// Load whole bytes until at least 57 bits are loaded, or the input
+    /// runs out
+    #[inline]
+    fn refill(&mut self) {
+        while self.count <= 56 {
+            match self.buffer.get(self.byte_pos) {
+                Some(&byte) => {
+                    self.bits |= (byte as u64) << (56 - self.count) as usize;
+                    self.count += 8;
+                    self.byte_pos += 1;
+                },
+                None => { break; }
+            }
+        }
+    }
+
+    /// Look at the next count bits without reading them, with zeros
+    /// past the end of the input
+    #[inline]
+    fn peek_bits(&mut self, count: u32) -> u32 {
+        if self.count < count {
+            self.refill();
+        }
+        if count == 0 {
+            0
+        } else {
+            (self.bits >> (64 - count) as usize) as u32
+        }
+    }
+
+    #[inline]
+    fn consume_bits(&mut self, count: u32) -> GzResult<()> {
+        if self.count < count {
+            self.refill();
+            if self.count < count {
+                return Err(GzError::TruncatedData);
+            }
+        }
+        self.bits <<= count as usize;
+        self.count -= count;
+        Ok(())
+    }
+
+    fn read_bits(&mut self, count: u32) -> GzResult<u32> {
+        assert!(count <= 32);
+        let value = self.peek_bits(count);
+        try!(self.consume_bits(count));
+        Ok(value)
+    }
+
+    /// Skip to the start of the next byte
+    fn align_to_byte(&mut self) {
+        let extra = self.count % 8;
+        self.bits <<= extra as usize;
+        self.count -= extra;
+    }
+
+    /// The index of the byte holding the next bit
+    fn byte_index(&self) -> usize {
+        self.byte_pos - (self.count / 8) as usize
+    }
+}
+
+#[cfg(test)]
+mod bzip2_tests {
+    use super::decompress_bzip2;
+    use cvec::from_slice;
+    use error::{GzError, GzResult};
+
+    // printf 'hello\n' | bzip2
+    static HELLO: &'static [u8] = &[
+        0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0xc1, 0xc0,
+        0x80, 0xe2, 0x00, 0x00, 0x01, 0x41, 0x00, 0x00, 0x10, 0x02, 0x44, 0xa0,
+        0x00, 0x30, 0xcd, 0x00, 0xc3, 0x46, 0x29, 0x97, 0x17, 0x72, 0x45, 0x38,
+        0x50, 0x90, 0xc1, 0xc0, 0x80, 0xe2];
+
+    // printf 'world\n' | bzip2
+    static WORLD: &'static [u8] = &[
+        0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x0a, 0x58,
+        0x06, 0x95, 0x00, 0x00, 0x02, 0xc1, 0x80, 0x00, 0x10, 0x04, 0x04, 0x90,
+        0x80, 0x20, 0x00, 0x22, 0x18, 0x68, 0x30, 0x04, 0xe8, 0x18, 0x5d, 0xc9,
+        0x14, 0xe1, 0x42, 0x40, 0x29, 0x60, 0x1a, 0x54];
+
+    // 1000 'a's, 7 'b's and "banana\n", which is all runs
+    static RUNS: &'static [u8] = &[
+        0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x11, 0x76,
+        0x3c, 0xe2, 0x00, 0x00, 0x03, 0x41, 0x01, 0x84, 0x10, 0x30, 0x01, 0x00,
+        0x80, 0x00, 0x08, 0x20, 0x00, 0x21, 0x24, 0x69, 0x90, 0x86, 0x01, 0xbe,
+        0xaa, 0x30, 0x1c, 0xd0, 0x8a, 0xe8, 0xbc, 0x5d, 0xc9, 0x14, 0xe1, 0x42,
+        0x40, 0x45, 0xd8, 0xf3, 0x88];
+
+    fn decompress_bytes(raw: &[u8]) -> GzResult<Vec<u8>> {
+        let out = try!(decompress_bzip2(from_slice(raw)));
+        Ok(out.iter().map(|&b| b).collect())
+    }
+
+    #[test]
+    fn test_single_block() {
+        assert_eq!(decompress_bytes(HELLO), Ok(b"hello\n".to_vec()));
+    }
+
+    #[test]
+    fn test_runs() {
+        let mut expected: Vec<u8> = ::std::iter::repeat(b'a').take(1000).collect();
+        expected.push_all(b"bbbbbbbbanana\n");
+        assert_eq!(decompress_bytes(RUNS), Ok(expected));
+    }
+
+    #[test]
+    fn test_multiple_streams() {
+        let mut both = HELLO.to_vec();
+        both.push_all(WORLD);
+        both.push_all(&[0; 16]);
+        assert_eq!(decompress_bytes(both.as_slice()), Ok(b"hello\nworld\n".to_vec()));
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let mut corrupt = HELLO.to_vec();
+        corrupt[10] ^= 0x01;
+        assert_eq!(decompress_bytes(corrupt.as_slice()), Err(GzError::CrcMismatch));
+    }
+
+    #[test]
+    fn test_bad_header() {
+        assert_eq!(decompress_bytes(b"BZ"), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress_bytes(b"BZh0"), Err(GzError::BadMagic));
+        assert_eq!(decompress_bytes(&HELLO[.. 30]), Err(GzError::TruncatedData));
+    }
+}
@@ -0,0 +1,425 @@
+#[doc="
+
+    Module: cpio
+
+    This reads cpio archives, which RPM packages and Linux initramfs
+    images hold. Each file is a header, its name and then its
+    contents, and a file named TRAILER!!! ends the archive. There are
+    three kinds of header: the newc one of hex fields, written with or
+    without a sum of the file's bytes, the older odc one of octal
+    fields, and the original binary one of 16 bit words, in the byte
+    order of the machine that wrote it.
+
+"]
+use cvec::Buf;
+use error::{GzError, GzResult};
+
+// These constants are defined by POSIX (odc) and the SVR4 cpio (newc)
+const NEWC_MAGIC: &'static [u8] = b"070701";
+const NEWC_CRC_MAGIC: &'static [u8] = b"070702";
+const ODC_MAGIC: &'static [u8] = b"070707";
+// the binary magic is the same number, written as a 16 bit word
+const BINARY_MAGIC: u16 = 0o070707;
+const TRAILER: &'static [u8] = b"TRAILER!!!";
+
+const NEWC_HEADER_LEN: usize = 110;
+const NEWC_MODE_OFFSET: usize = 14;
+const NEWC_SIZE_OFFSET: usize = 54;
+const NEWC_NAME_LEN_OFFSET: usize = 94;
+const NEWC_CHECK_OFFSET: usize = 102;
+const NEWC_FIELD_LEN: usize = 8;
+// names and contents both start on a multiple of this
+const NEWC_ALIGNMENT: usize = 4;
+
+const ODC_HEADER_LEN: usize = 76;
+const ODC_MODE_OFFSET: usize = 18;
+const ODC_NAME_LEN_OFFSET: usize = 59;
+const ODC_SIZE_OFFSET: usize = 65;
+const ODC_FIELD_LEN: usize = 6;
+const ODC_SIZE_LEN: usize = 11;
+
+const BINARY_HEADER_LEN: usize = 26;
+const BINARY_MODE_OFFSET: usize = 6;
+const BINARY_NAME_LEN_OFFSET: usize = 20;
+// the size is two words, the high one first whatever the byte order
+const BINARY_SIZE_OFFSET: usize = 22;
+const BINARY_ALIGNMENT: usize = 2;
+
+const FILE_TYPE_MASK: usize = 0o170000;
+const REGULAR_FILE: usize = 0o100000;
+
+/// A file in the archive
+#[derive(Show)]
+pub struct CpioEntry {
+    /// path of the file within the archive, with a nul after it for C
+    name: Vec<u8>,
+    /// where its contents start, from the start of the buffer
+    offset: usize,
+    size: usize,
+    /// the sum of its bytes, for newc archives that record it
+    check: Option<u32>,
+}
+
+impl CpioEntry {
+    /// The path of the file within the archive
+    pub fn name(&self) -> &[u8] {
+        &self.name[.. self.name.len() - 1]
+    }
+
+    /// The path of the file, followed by a nul
+    pub fn c_name(&self) -> &[u8] {
+        &self.name[..]
+    }
+}
+
+/// A cpio archive, along with the files in it
+/// Only regular files are listed. Of a set of hard links, newc keeps
+/// the contents with the last one, so the others are empty.
+pub struct CpioArchive {
+    buffer: Buf,
+    entries: Vec<CpioEntry>,
+}
+
+/// What a header says, whichever kind it is
+struct Header {
+    len: usize,
+    mode: usize,
+    name_len: usize,
+    size: usize,
+    check: Option<u32>,
+    alignment: usize,
+}
+
+impl CpioArchive {
+    /// Read every header of the archive in buffer
+    pub fn open(buffer: Buf) -> GzResult<CpioArchive> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        loop {
+            let data = buffer.as_slice();
+            let header = try!(read_header(data, pos));
+            let name_start = pos + header.len;
+            let name_end = match name_start.checked_add(header.name_len) {
+                Some(end) if end <= data.len() => end,
+                _ => { return Err(GzError::TruncatedHeader); }
+            };
+            let name = until_nul(&data[name_start .. name_end]);
+            let start = align(name_end, header.alignment);
+            let end = match start.checked_add(header.size) {
+                Some(end) if end <= data.len() => end,
+                _ => { return Err(GzError::TruncatedData); }
+            };
+            if name == TRAILER {
+                break;
+            }
+            if header.mode & FILE_TYPE_MASK == REGULAR_FILE {
+                let mut name = name.to_vec();
+                name.push(0);
+                entries.push(CpioEntry {
+                    name: name,
+                    offset: start,
+                    size: header.size,
+                    check: header.check
+                });
+            }
+            pos = align(end, header.alignment);
+        }
+        Ok(CpioArchive {
+            buffer: buffer,
+            entries: entries
+        })
+    }
+
+    /// The files in the archive
+    pub fn entries(&self) -> &Vec<CpioEntry> {
+        &self.entries
+    }
+
+    /// The contents of the file at index in entries, read in place
+    /// They must not be used once the archive is freed.
+    pub fn extract(&self, index: usize) -> GzResult<Buf> {
+        let entry = try_or!(self.entries.get(index), GzError::BadArchive);
+        let out = try_or!(unsafe { self.buffer.view(entry.offset, entry.offset + entry.size) },
+                          GzError::AllocationFailed);
+        let sum = out.iter().fold(0u32, |sum, &byte| sum + byte as u32);
+        match entry.check {
+            Some(check) if check != sum => Err(GzError::CrcMismatch),
+            _ => Ok(out)
+        }
+    }
+}
+
+/// Whether buffer starts with any kind of cpio header
+pub fn is_cpio(buffer: &Buf) -> bool {
+    let data = buffer.as_slice();
+    match binary_byte_order(data) {
+        Some(_) => data.len() >= BINARY_HEADER_LEN,
+        None => [NEWC_MAGIC, NEWC_CRC_MAGIC, ODC_MAGIC].iter().any(|&magic| data.starts_with(magic))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+/// Read whichever kind of header starts at pos
+fn read_header(data: &[u8], pos: usize) -> GzResult<Header> {
+    let rest = &data[pos ..];
+    match binary_byte_order(rest) {
+        Some(big_endian) => {
+            if rest.len() < BINARY_HEADER_LEN {
+                return Err(GzError::TruncatedHeader);
+            }
+            return Ok(Header {
+                len: BINARY_HEADER_LEN,
+                mode: binary_word(rest, BINARY_MODE_OFFSET, big_endian),
+                name_len: binary_word(rest, BINARY_NAME_LEN_OFFSET, big_endian),
+                size: binary_word(rest, BINARY_SIZE_OFFSET, big_endian) << 16 |
+                      binary_word(rest, BINARY_SIZE_OFFSET + 2, big_endian),
+                check: None,
+                alignment: BINARY_ALIGNMENT
+            });
+        },
+        None => {}
+    }
+
+    if rest.len() < NEWC_MAGIC.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    let has_check = rest.starts_with(NEWC_CRC_MAGIC);
+    if rest.starts_with(NEWC_MAGIC) || has_check {
+        if rest.len() < NEWC_HEADER_LEN {
+            return Err(GzError::TruncatedHeader);
+        }
+        let check = try!(parse_number(&rest[NEWC_CHECK_OFFSET ..], NEWC_FIELD_LEN, 16));
+        Ok(Header {
+            len: NEWC_HEADER_LEN,
+            mode: try!(parse_number(&rest[NEWC_MODE_OFFSET ..], NEWC_FIELD_LEN, 16)),
+            name_len: try!(parse_number(&rest[NEWC_NAME_LEN_OFFSET ..], NEWC_FIELD_LEN, 16)),
+            size: try!(parse_number(&rest[NEWC_SIZE_OFFSET ..], NEWC_FIELD_LEN, 16)),
+            check: if has_check { Some(check as u32) } else { None },
+            alignment: NEWC_ALIGNMENT
+        })
+    } else if rest.starts_with(ODC_MAGIC) {
+        if rest.len() < ODC_HEADER_LEN {
+            return Err(GzError::TruncatedHeader);
+        }
+        Ok(Header {
+            len: ODC_HEADER_LEN,
+            mode: try!(parse_number(&rest[ODC_MODE_OFFSET ..], ODC_FIELD_LEN, 8)),
+            name_len: try!(parse_number(&rest[ODC_NAME_LEN_OFFSET ..], ODC_FIELD_LEN, 8)),
+            size: try!(parse_number(&rest[ODC_SIZE_OFFSET ..], ODC_SIZE_LEN, 8)),
+            check: None,
+            alignment: 1
+        })
+    } else {
+        Err(GzError::BadArchive)
+    }
+}
+
+/// Whether data starts with a binary header, and if so whether its
+/// words are big endian
+fn binary_byte_order(data: &[u8]) -> Option<bool> {
+    if data.len() < 2 {
+        None
+    } else if (data[0] as u16) << 8 | data[1] as u16 == BINARY_MAGIC {
+        Some(true)
+    } else if (data[1] as u16) << 8 | data[0] as u16 == BINARY_MAGIC {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// The 16 bit word at offset in a binary header
+fn binary_word(header: &[u8], offset: usize, big_endian: bool) -> usize {
+    let (high, low) = if big_endian { (0, 1) } else { (1, 0) };
+    (header[offset + high] as usize) << 8 | header[offset + low] as usize
+}
+
+/// Parse the numeric header field of len characters at the start of
+/// data, every one of which must be a digit
+fn parse_number(data: &[u8], len: usize, radix: usize) -> GzResult<usize> {
+    let mut value: usize = 0;
+    for &byte in data[.. len].iter() {
+        let digit = match byte {
+            b'0' ... b'9' => (byte - b'0') as usize,
+            b'a' ... b'f' => (byte - b'a') as usize + 10,
+            b'A' ... b'F' => (byte - b'A') as usize + 10,
+            _ => { return Err(GzError::BadArchive); }
+        };
+        if digit >= radix {
+            return Err(GzError::BadArchive);
+        }
+        value = try_or!(value.checked_mul(radix), GzError::BadArchive) + digit;
+    }
+    Ok(value)
+}
+
+/// pos rounded up to a multiple of alignment
+fn align(pos: usize, alignment: usize) -> usize {
+    (pos + alignment - 1) / alignment * alignment
+}
+
+/// The part of field before the first nul
+fn until_nul(field: &[u8]) -> &[u8] {
+    match field.iter().position(|&b| b == 0) {
+        Some(len) => &field[.. len],
+        None => field
+    }
+}
+
+#[cfg(test)]
+mod cpio_tests {
+    use std::iter::repeat;
+    use super::{CpioArchive, is_cpio};
+    use cvec::{from_slice, Buf};
+    use error::GzError;
+
+    const REGULAR: usize = 0o100644;
+    const DIRECTORY: usize = 0o040755;
+
+    /// A newc header, name and contents, each padded to four bytes
+    fn newc(name: &str, mode: usize, contents: &[u8], with_check: bool) -> Vec<u8> {
+        let (magic, check) = if with_check {
+            ("070702", contents.iter().fold(0, |sum, &b| sum + b as usize))
+        } else {
+            ("070701", 0)
+        };
+        let fields = [0, mode, 0, 0, 1, 0, contents.len(), 0, 0, 0, 0, name.len() + 1, check];
+        let mut raw = magic.as_bytes().to_vec();
+        for &field in fields.iter() {
+            raw.push_all(format!("{:08X}", field).as_bytes());
+        }
+        raw.push_all(name.as_bytes());
+        raw.push(0);
+        while raw.len() % 4 != 0 {
+            raw.push(0);
+        }
+        raw.push_all(contents);
+        while raw.len() % 4 != 0 {
+            raw.push(0);
+        }
+        raw
+    }
+
+    /// An odc header, name and contents, with no padding
+    fn odc(name: &str, mode: usize, contents: &[u8]) -> Vec<u8> {
+        let header = format!("070707{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:011o}{:06o}{:011o}",
+                             0, 0, mode, 0, 0, 1, 0, 0, name.len() + 1, contents.len());
+        let mut raw = header.into_bytes();
+        raw.push_all(name.as_bytes());
+        raw.push(0);
+        raw.push_all(contents);
+        raw
+    }
+
+    /// A binary header, name and contents, padded to two bytes
+    fn binary(name: &str, mode: usize, contents: &[u8], big_endian: bool) -> Vec<u8> {
+        let size = contents.len();
+        let words = [0o070707, 0, 0, mode, 0, 0, 1, 0, 0, 0, name.len() + 1,
+                     size >> 16, size & 0xffff];
+        let mut raw = Vec::new();
+        for &word in words.iter() {
+            let bytes = [(word >> 8) as u8, word as u8];
+            if big_endian {
+                raw.push_all(&bytes);
+            } else {
+                raw.push_all(&[bytes[1], bytes[0]]);
+            }
+        }
+        raw.push_all(name.as_bytes());
+        raw.push(0);
+        if raw.len() % 2 != 0 {
+            raw.push(0);
+        }
+        raw.push_all(contents);
+        if raw.len() % 2 != 0 {
+            raw.push(0);
+        }
+        raw
+    }
+
+    fn archive(entries: &[Vec<u8>]) -> Buf {
+        let mut raw = Vec::new();
+        for entry in entries.iter() {
+            raw.push_all(entry.as_slice());
+        }
+        from_slice(raw.as_slice())
+    }
+
+    fn contents(cpio: &CpioArchive) -> Vec<(Vec<u8>, Vec<u8>)> {
+        (0 .. cpio.entries().len()).map(|i| {
+            (cpio.entries()[i].name().to_vec(),
+             cpio.extract(i).unwrap().iter().map(|&b| b).collect())
+        }).collect()
+    }
+
+    fn expected() -> Vec<(Vec<u8>, Vec<u8>)> {
+        vec![(b"./etc/foo.conf".to_vec(), b"key = value\n".to_vec()),
+             (b"./empty".to_vec(), Vec::new())]
+    }
+
+    #[test]
+    fn test_newc() {
+        for &with_check in [false, true].iter() {
+            let buffer = archive(&[newc("./etc", DIRECTORY, b"", with_check),
+                                   newc("./etc/foo.conf", REGULAR, b"key = value\n", with_check),
+                                   newc("./empty", REGULAR, b"", with_check),
+                                   newc("TRAILER!!!", 0, b"", with_check),
+                                   repeat(0).take(512).collect()]);
+            assert!(is_cpio(&buffer));
+            let cpio = CpioArchive::open(buffer).unwrap();
+            assert_eq!(contents(&cpio), expected());
+            assert_eq!(cpio.entries()[1].c_name(), &b"./empty\0"[..]);
+        }
+    }
+
+    #[test]
+    fn test_newc_check() {
+        let mut raw = newc("a", REGULAR, b"abc", true);
+        raw[113] = b'x';
+        raw.push_all(newc("TRAILER!!!", 0, b"", true).as_slice());
+        let cpio = CpioArchive::open(from_slice(raw.as_slice())).unwrap();
+        assert_eq!(cpio.extract(0).err(), Some(GzError::CrcMismatch));
+    }
+
+    #[test]
+    fn test_odc() {
+        let buffer = archive(&[odc("./etc", DIRECTORY, b""),
+                               odc("./etc/foo.conf", REGULAR, b"key = value\n"),
+                               odc("./empty", REGULAR, b""),
+                               odc("TRAILER!!!", 0, b"")]);
+        assert!(is_cpio(&buffer));
+        assert_eq!(contents(&CpioArchive::open(buffer).unwrap()), expected());
+    }
+
+    #[test]
+    fn test_binary() {
+        for &big_endian in [false, true].iter() {
+            let buffer = archive(&[binary("./etc", DIRECTORY, b"", big_endian),
+                                   binary("./etc/foo.conf", REGULAR, b"key = value\n", big_endian),
+                                   binary("./empty", REGULAR, b"", big_endian),
+                                   binary("TRAILER!!!", 0, b"", big_endian)]);
+            assert!(is_cpio(&buffer));
+            assert_eq!(contents(&CpioArchive::open(buffer).unwrap()), expected());
+        }
+    }
+
+    #[test]
+    fn test_bad_archives() {
+        assert!(!is_cpio(&from_slice(b"070703")));
+
+        // no trailer
+        let raw = newc("a", REGULAR, b"abc", false);
+        assert_eq!(CpioArchive::open(from_slice(raw.as_slice())).err(),
+                   Some(GzError::TruncatedHeader));
+        assert_eq!(CpioArchive::open(from_slice(&raw[.. 114])).err(),
+                   Some(GzError::TruncatedData));
+
+        let mut bad_field = odc("a", REGULAR, b"abc");
+        bad_field[20] = b'9';
+        assert_eq!(CpioArchive::open(from_slice(bad_field.as_slice())).err(),
+                   Some(GzError::BadArchive));
+    }
+}
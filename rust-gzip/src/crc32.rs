@@ -10,14 +10,14 @@ use cvec;
 const IEEE: u32 = 0xedb88320;
 
 /// Cyclic Redundancy Check
-struct Crc32 {
+pub struct Crc32 {
     table: [u32; 256],
     value: u32
 }
 
 impl Crc32 {
     /// Setup the CRC
-    fn new() -> Crc32 {
+    pub fn new() -> Crc32 {
         let mut c = Crc32 { table: [0; 256], value: 0xffffffff };
         for i in 0 .. 256 {
             let mut v = i as u32;
@@ -34,7 +34,9 @@ impl Crc32 {
     }
 
     /// Create the CRC for the given buffer
-    fn sum(&mut self, mut buf: cvec::Iter<u8>) -> u32 {
+    /// The running value is kept, so calling this again with the data
+    /// that follows gives the CRC of everything seen so far
+    pub fn sum(&mut self, buf: cvec::Iter<u8>) -> u32 {
         for &i in buf {
             self.value = self.table[((self.value ^ (i as u32)) & 0xFF) as usize] ^
                 (self.value >> 8);
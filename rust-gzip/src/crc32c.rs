@@ -0,0 +1,69 @@
+#[doc="
+
+    Module: crc32c
+
+    This module handles verifying the CRC32C (Castagnoli) that Snappy
+    framed files keep for each chunk
+
+"]
+use cvec;
+
+const CASTAGNOLI: u32 = 0x82f63b78;
+
+/// Cyclic Redundancy Check, with the Castagnoli polynomial
+pub struct Crc32c {
+    table: [u32; 256],
+    value: u32
+}
+
+impl Crc32c {
+    /// Setup the CRC
+    pub fn new() -> Crc32c {
+        let mut c = Crc32c { table: [0; 256], value: 0xffffffff };
+        for i in 0 .. 256 {
+            let mut v = i as u32;
+            for _ in 0 .. 8 {
+                v = if v & 1 != 0 {
+                    CASTAGNOLI ^ (v >> 1)
+                } else {
+                    v >> 1
+                }
+            }
+            c.table[i] = v;
+        }
+        c
+    }
+
+    /// Create the CRC for the given buffer
+    /// The running value is kept, so calling this again with the data
+    /// that follows gives the CRC of everything seen so far
+    pub fn sum(&mut self, buf: cvec::Iter<u8>) -> u32 {
+        for &i in buf {
+            self.value = self.table[((self.value ^ (i as u32)) & 0xFF) as usize] ^
+                (self.value >> 8);
+        }
+        self.value ^ 0xffffffff
+    }
+}
+
+/// Public interface for using the CRC
+pub fn sum(buf: cvec::Iter<u8>) -> u32 {
+    let mut c = Crc32c::new();
+    c.sum(buf)
+}
+
+#[cfg(test)]
+mod crc32c_tests {
+    use super::sum;
+    use cvec::CVec;
+
+    #[test]
+    fn test_check_value() {
+        let mut buffer = CVec::with_capacity(9).unwrap();
+        for &byte in b"123456789".iter() {
+            buffer.push(byte);
+        }
+        assert_eq!(sum(buffer.iter()), 0xe3069283);
+        assert_eq!(sum(buffer.limit_iter(0, 0)), 0);
+    }
+}
@@ -0,0 +1,53 @@
+#[doc="
+
+    Module: crc64
+
+    This module handles verifying the CRC64 that xz files usually
+    keep for each block
+
+"]
+use cvec;
+
+const ECMA_182: u64 = 0xc96c5795d7870f42;
+
+/// Cyclic Redundancy Check, 64 bits wide
+pub struct Crc64 {
+    table: [u64; 256],
+    value: u64
+}
+
+impl Crc64 {
+    /// Setup the CRC
+    pub fn new() -> Crc64 {
+        let mut c = Crc64 { table: [0; 256], value: 0xffffffffffffffff };
+        for i in 0 .. 256 {
+            let mut v = i as u64;
+            for _ in 0 .. 8 {
+                v = if v & 1 != 0 {
+                    ECMA_182 ^ (v >> 1)
+                } else {
+                    v >> 1
+                }
+            }
+            c.table[i] = v;
+        }
+        c
+    }
+
+    /// Create the CRC for the given buffer
+    /// The running value is kept, so calling this again with the data
+    /// that follows gives the CRC of everything seen so far
+    pub fn sum(&mut self, buf: cvec::Iter<u8>) -> u64 {
+        for &i in buf {
+            self.value = self.table[((self.value ^ (i as u64)) & 0xFF) as usize] ^
+                (self.value >> 8);
+        }
+        self.value ^ 0xffffffffffffffff
+    }
+}
+
+/// Public interface for using the CRC
+pub fn sum(buf: cvec::Iter<u8>) -> u64 {
+    let mut c = Crc64::new();
+    c.sum(buf)
+}
@@ -15,13 +15,16 @@ extern crate libc;
 extern crate core;
 
 use std::ops::Index;
+use std::{cmp, usize};
 use libc::{c_void, size_t};
 use libc::funcs::c95::stdlib::{malloc, realloc, free};
 use std::mem;
 use self::core::raw::Slice as RawSlice;
 use self::core::num::Int;
 use std::ptr;
+use std::slice;
 use std::fmt;
+use error::{GzError, GzResult};
 
 const DEFAULT_CVEC_CAPACITY: usize = 8;
 
@@ -31,6 +34,8 @@ pub struct CVec<T> {
     ptr: *mut T,
     len: usize,
     cap: usize,
+    /// how long the CVec may grow, see set_max_len
+    max_len: usize,
     mutable: bool,
 }
 
@@ -66,6 +71,7 @@ impl<T> CVec<T> {
                 ptr: ptr,
                 len: 0,
                 cap: capacity,
+                max_len: usize::MAX,
                 mutable: true
             })
         }
@@ -82,11 +88,24 @@ impl<T> CVec<T> {
                 ptr: ptr as *mut T,
                 len: buf_size,
                 cap: buf_size,
+                max_len: buf_size,
                 mutable: false
             })
         }
     }
 
+    /// Constructs a CVec around elements start to end of this one, without
+    /// copying. It CANNOT be modified, and must not be used once this one
+    /// is freed. An empty range gets an empty CVec of its own.
+    pub unsafe fn view(&self, start: usize, end: usize) -> Option<CVec<T>> {
+        assert!(start <= end && end <= self.len);
+        if start == end {
+            CVec::with_capacity(0)
+        } else {
+            CVec::from_raw_buf(self.ptr.offset(start as isize) as *const T, end - start)
+        }
+    }
+
     /// Converts this CVec to a raw pointer. The CVec cannot be used after this
     /// is called. The raw pointer must be freed by the caller.
     pub fn into_raw_buf(self) -> (*mut T, usize) {
@@ -100,16 +119,19 @@ impl<T> CVec<T> {
         self.len
     }
 
-    /// Effect: doubles the CVec's capacity
+    /// Stop the CVec from growing past max_len elements, so that
+    /// decompressed output can't grow without bound
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = cmp::max(max_len, self.len);
+    }
+
+    /// Effect: doubles the CVec's capacity, or grows it to its maximum
+    /// length if that is nearer
     /// returns None if the allocation failed
     pub fn double_capacity(&mut self) -> Option<()> {
         assert!(self.mutable);
-        let old_size = self.cap * mem::size_of::<T>();
-        let size = old_size * 2;
-        if old_size > size {
-            mem::drop(self);
-            return None;
-        }
+        let cap = cmp::min(try_opt!(self.cap.checked_mul(2)), cmp::max(self.max_len, self.cap));
+        let size = try_opt!(cap.checked_mul(mem::size_of::<T>()));
         unsafe {
             let new_ptr = realloc(self.ptr as *mut c_void, size as size_t);
             if new_ptr.is_null() {
@@ -117,14 +139,18 @@ impl<T> CVec<T> {
             }
             self.ptr = new_ptr as *mut T;
         }
-        self.cap = self.cap * 2;
+        self.cap = cap;
         Some(())
     }
 
     /// Add a new element to the CVec
-    /// returns None if we had to reallocate and it failed
+    /// returns None if we had to reallocate and it failed, or if the CVec
+    /// is already at its maximum length
     pub fn push(&mut self, value: T) -> Option<()> {
         assert!(self.mutable);
+        if self.len == self.max_len {
+            return None;
+        }
         if self.len == self.cap {
             try_opt!(self.double_capacity());
         }
@@ -137,6 +163,17 @@ impl<T> CVec<T> {
         Some(())
     }
 
+    /// Add a new element to the CVec, for decompressed output
+    /// fails with LimitExceeded if the CVec is already at its maximum
+    /// length, or AllocationFailed if it couldn't grow
+    pub fn try_push(&mut self, value: T) -> GzResult<()> {
+        if self.len == self.max_len {
+            return Err(GzError::LimitExceeded);
+        }
+        try_or!(self.push(value), GzError::AllocationFailed);
+        Ok(())
+    }
+
     /// Remove and return the last element of the CVec
     #[allow(dead_code)]
     pub fn pop(&mut self) -> Option<T> {
@@ -179,6 +216,12 @@ impl<T> CVec<T> {
         }
     }
 
+    /// The contents as a mutable slice, for a CVec that can be modified
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        assert!(self.mutable);
+        unsafe { slice::from_raw_mut_buf(&self.ptr, self.len) }
+    }
+
     /// Return an iterator over the CVec's contents
     pub fn iter(&self) -> Iter<T> {
         Iter::new(self)
@@ -198,6 +241,23 @@ impl<T> CVec<T> {
         }
     }
 
+    /// Remove the first n elements of the CVec, moving the rest down
+    /// to the front
+    pub fn discard_front(&mut self, n: usize) {
+        assert!(self.mutable);
+        assert!(n <= self.len);
+        unsafe {
+            for i in 0 .. n {
+                ptr::read(self.get_unchecked(i));
+            }
+            for i in 0 .. self.len - n {
+                let moved = ptr::read(self.get_unchecked(i + n));
+                ptr::write(self.ptr.offset(i as isize), moved);
+            }
+        }
+        self.len -= n;
+    }
+
     /// Clear the contents of the CVec
     pub fn clear(&mut self) {
         unsafe {
@@ -211,16 +271,22 @@ impl<T> CVec<T> {
 
 impl<T: Clone> CVec<T> {
     /// Add to the CVec length bytes from distance bytes from the end
-    pub fn copy_back_pointer(&mut self, distance: usize, length: usize) {
+    /// fails with LimitExceeded, before copying anything, if that would
+    /// take the CVec past its maximum length
+    pub fn copy_back_pointer(&mut self, distance: usize, length: usize) -> GzResult<()> {
+        if length > self.max_len - self.len {
+            return Err(GzError::LimitExceeded);
+        }
         let mut back_ptr  = self.len - distance - 1;
         let mut length = length;
         let mut c;
         while length > 0 {
             c = self[back_ptr].clone();
-            self.push(c);
+            try_or!(self.push(c), GzError::AllocationFailed);
             back_ptr += 1;
             length -= 1;
         }
+        Ok(())
     }
 }
 
@@ -337,10 +403,21 @@ impl<'a, T> Iterator for Iter<'a, T> {
     }
 }
 
+/// A Buf holding a copy of raw, for tests to read from
+#[cfg(test)]
+pub fn from_slice(raw: &[u8]) -> Buf {
+    let mut buffer = CVec::with_capacity(raw.len()).unwrap();
+    for &byte in raw.iter() {
+        buffer.push(byte);
+    }
+    buffer
+}
+
 
 #[cfg(test)]
 mod cvec_tests {
     use super::CVec;
+    use error::GzError;
 
     fn setup() -> CVec<u8> {
         let mut v: CVec<u8> = CVec::new().unwrap();
@@ -385,6 +462,18 @@ mod cvec_tests {
         assert_eq!(v.pop().unwrap(), 5);
     }
 
+    #[test]
+    fn test_discard_front() {
+        let mut v = setup();
+        v.discard_front(3);
+        assert_eq!(v.len(), 6);
+        assert_eq!(v[0], 4);
+        assert_eq!(v[5], 9);
+        v.push(10);
+        v.discard_front(7);
+        assert_eq!(v.len(), 0);
+    }
+
     #[test]
     fn test_index() {
         let mut v = setup();
@@ -394,6 +483,25 @@ mod cvec_tests {
         v.push(42);
         assert_eq!(v[v.len() - 1], 42);
     }
+
+    #[test]
+    fn test_max_len() {
+        let mut v = setup();
+        v.set_max_len(12);
+        assert_eq!(v.try_push(10), Ok(()));
+        assert_eq!(v.copy_back_pointer(0, 3), Err(GzError::LimitExceeded));
+        assert_eq!(v.len(), 10);
+        assert_eq!(v.copy_back_pointer(0, 2), Ok(()));
+        assert_eq!(v[11], 10);
+        assert_eq!(v.push(13), None);
+        assert_eq!(v.try_push(13), Err(GzError::LimitExceeded));
+
+        // it can't be set below what the CVec already holds
+        v.set_max_len(0);
+        assert_eq!(v.len(), 12);
+        assert_eq!(v.pop(), Some(10));
+        assert_eq!(v.try_push(11), Ok(()));
+    }
 }
 
 
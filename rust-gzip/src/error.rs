@@ -0,0 +1,114 @@
+#[doc="
+
+    Module: error
+
+    This module lists the ways decompression can fail, so that the
+    C side can tell the user why a file was skipped instead of just
+    getting a null pointer back.
+
+"]
+use libc::c_int;
+
+/// Everything that can go wrong while decompressing
+/// The discriminants are the codes handed to C, 0 meaning no error
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum GzError {
+    BadMagic = 1,
+    UnsupportedMethod,
+    TruncatedHeader,
+    TruncatedData,
+    BadBlockType,
+    BadStoredLength,
+    BadHuffmanTable,
+    InvalidCode,
+    InvalidDistance,
+    CrcMismatch,
+    SizeMismatch,
+    AllocationFailed,
+    BadArchive,
+    Encrypted,
+    CorruptData,
+    MissingDictionary,
+    LimitExceeded,
+}
+
+pub type GzResult<T> = Result<T, GzError>;
+
+impl GzError {
+    /// The error with the given code, if there is one
+    pub fn from_code(code: c_int) -> Option<GzError> {
+        match code {
+            1 => Some(GzError::BadMagic),
+            2 => Some(GzError::UnsupportedMethod),
+            3 => Some(GzError::TruncatedHeader),
+            4 => Some(GzError::TruncatedData),
+            5 => Some(GzError::BadBlockType),
+            6 => Some(GzError::BadStoredLength),
+            7 => Some(GzError::BadHuffmanTable),
+            8 => Some(GzError::InvalidCode),
+            9 => Some(GzError::InvalidDistance),
+            10 => Some(GzError::CrcMismatch),
+            11 => Some(GzError::SizeMismatch),
+            12 => Some(GzError::AllocationFailed),
+            13 => Some(GzError::BadArchive),
+            14 => Some(GzError::Encrypted),
+            15 => Some(GzError::CorruptData),
+            16 => Some(GzError::MissingDictionary),
+            17 => Some(GzError::LimitExceeded),
+            _ => None
+        }
+    }
+
+    /// A nul-terminated description of the error, fit for C
+    pub fn message(&self) -> &'static [u8] {
+        match *self {
+            GzError::BadMagic => b"not a recognised compressed file (bad magic number)\0",
+            GzError::UnsupportedMethod => b"unsupported compression method\0",
+            GzError::TruncatedHeader => b"truncated header\0",
+            GzError::TruncatedData => b"unexpected end of compressed data\0",
+            GzError::BadBlockType => b"invalid deflate block type\0",
+            GzError::BadStoredLength => b"stored block length does not match its complement\0",
+            GzError::BadHuffmanTable => b"invalid Huffman table\0",
+            GzError::InvalidCode => b"invalid literal/length or distance code\0",
+            GzError::InvalidDistance => b"back reference distance too far back\0",
+            GzError::CrcMismatch => b"CRC32 does not match the data\0",
+            GzError::SizeMismatch => b"uncompressed size does not match the recorded size\0",
+            GzError::AllocationFailed => b"out of memory\0",
+            GzError::BadArchive => b"corrupt archive directory\0",
+            GzError::Encrypted => b"encrypted entries are not supported\0",
+            GzError::CorruptData => b"corrupt compressed data\0",
+            GzError::MissingDictionary => b"needs a preset dictionary that wasn't supplied\0",
+            GzError::LimitExceeded => b"decompresses to more than the size or ratio limit\0",
+        }
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::GzError;
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(GzError::BadMagic as i32, 1);
+        assert_eq!(GzError::AllocationFailed as i32, 12);
+    }
+
+    #[test]
+    fn test_from_code() {
+        assert_eq!(GzError::from_code(0), None);
+        assert_eq!(GzError::from_code(GzError::CrcMismatch as i32), Some(GzError::CrcMismatch));
+        assert_eq!(GzError::from_code(GzError::CorruptData as i32), Some(GzError::CorruptData));
+        assert_eq!(GzError::from_code(GzError::MissingDictionary as i32),
+                   Some(GzError::MissingDictionary));
+        assert_eq!(GzError::from_code(17), Some(GzError::LimitExceeded));
+        assert_eq!(GzError::from_code(18), None);
+    }
+
+    #[test]
+    fn test_messages_are_terminated() {
+        for err in [GzError::BadMagic, GzError::CrcMismatch, GzError::AllocationFailed].iter() {
+            let message = err.message();
+            assert_eq!(message[message.len() - 1], 0);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+#[doc="
+
+    Module: filter
+
+    This undoes the filters xz can run data through before compressing
+    it, to make it compress better. The delta filter stores each byte as
+    its difference from the one a fixed distance back, which suits
+    samples and images. The branch converters are for machine code:
+    the targets of calls and jumps are stored as absolute addresses
+    instead of relative ones, so that calls to the same function look
+    the same wherever they are. Each knows the instructions of one
+    architecture: x86, PowerPC, IA-64, ARM, ARM-Thumb, SPARC or ARM64.
+    RISC-V's converter isn't read. A converter can be told where in
+    memory the data would start, which changes the addresses.
+
+"]
+use error::{GzError, GzResult};
+
+// These constants are the filter ids of the .xz file format
+const FILTER_DELTA: u64 = 0x03;
+const FILTER_X86: u64 = 0x04;
+const FILTER_POWERPC: u64 = 0x05;
+const FILTER_IA64: u64 = 0x06;
+const FILTER_ARM: u64 = 0x07;
+const FILTER_ARMTHUMB: u64 = 0x08;
+const FILTER_SPARC: u64 = 0x09;
+const FILTER_ARM64: u64 = 0x0a;
+const DELTA_PROPS_LEN: usize = 1;
+// a branch converter's only property is its start offset, which can be
+// left out when it's 0
+const START_OFFSET_LEN: usize = 4;
+
+// which states of the last few bytes let an x86 call or jump be
+// converted, and which byte of its address to look at next in each
+const X86_ALLOWED: [bool; 8] = [true, true, true, false, true, false, false, false];
+const X86_BYTE_INDEX: [u32; 8] = [0, 1, 2, 2, 3, 3, 3, 3];
+const X86_INSTRUCTION_LEN: usize = 5;
+// which of the three slots of an IA-64 bundle hold branches, for each
+// bundle template
+const IA64_BRANCH_SLOTS: [u32; 32] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    4, 4, 6, 6, 0, 0, 7, 7, 4, 4, 0, 0, 4, 4, 0, 0];
+const IA64_BUNDLE_LEN: usize = 16;
+
+/// A filter, with what undoing it needs to know
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum Filter {
+    /// the distance each byte's difference was taken at
+    Delta(usize),
+    /// the branch converters, with the offset the data starts at
+    X86(u32),
+    PowerPc(u32),
+    Ia64(u32),
+    Arm(u32),
+    ArmThumb(u32),
+    Sparc(u32),
+    Arm64(u32),
+}
+
+impl Filter {
+    /// The filter with the given id and properties in an xz block header
+    pub fn from_xz(id: u64, props: &[u8]) -> GzResult<Filter> {
+        if id == FILTER_DELTA {
+            if props.len() != DELTA_PROPS_LEN {
+                return Err(GzError::CorruptData);
+            }
+            return Ok(Filter::Delta(props[0] as usize + 1));
+        }
+        let start = match props.len() {
+            0 => 0,
+            START_OFFSET_LEN => (0 .. START_OFFSET_LEN).fold(0u32, |value, i| {
+                value | (props[i] as u32) << (8 * i)
+            }),
+            _ => { return Err(GzError::CorruptData); }
+        };
+        match id {
+            FILTER_X86 => Ok(Filter::X86(start)),
+            FILTER_POWERPC => Ok(Filter::PowerPc(start)),
+            FILTER_IA64 => Ok(Filter::Ia64(start)),
+            FILTER_ARM => Ok(Filter::Arm(start)),
+            FILTER_ARMTHUMB => Ok(Filter::ArmThumb(start)),
+            FILTER_SPARC => Ok(Filter::Sparc(start)),
+            FILTER_ARM64 => Ok(Filter::Arm64(start)),
+            _ => Err(GzError::UnsupportedMethod)
+        }
+    }
+
+    /// Undo the filter on all of data, in place
+    pub fn unfilter(&self, data: &mut [u8]) {
+        match *self {
+            Filter::Delta(distance) => undo_delta(data, distance),
+            Filter::X86(start) => undo_x86(data, start),
+            Filter::PowerPc(start) => undo_powerpc(data, start),
+            Filter::Ia64(start) => undo_ia64(data, start),
+            Filter::Arm(start) => undo_arm(data, start),
+            Filter::ArmThumb(start) => undo_armthumb(data, start),
+            Filter::Sparc(start) => undo_sparc(data, start),
+            Filter::Arm64(start) => undo_arm64(data, start)
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+fn undo_delta(data: &mut [u8], distance: usize) {
+    for i in (distance .. data.len()) {
+        data[i] = data[i] + data[i - distance];
+    }
+}
+
+/// The high byte of an x86 address that is worth converting, as near
+/// calls and jumps rarely go more than 16M either way
+fn is_x86_near(byte: u8) -> bool {
+    byte == 0x00 || byte == 0xff
+}
+
+/// x86 calls (E8) and jumps (E9) are followed by a 32 bit offset. The
+/// opcode bytes turn up in other instructions too, so the encoder keeps
+/// track of the last few bytes to guess which are real, and the decoder
+/// has to follow the same guesses.
+fn undo_x86(data: &mut [u8], start: u32) {
+    if data.len() < X86_INSTRUCTION_LEN {
+        return;
+    }
+    // bits for the last few bytes: set if each was skipped, along with
+    // 0x10 if the one that ended an instruction looked like an address
+    let mut prev_mask: u32 = 0;
+    let mut prev_pos = start - X86_INSTRUCTION_LEN as u32;
+    let mut i = 0;
+    while i <= data.len() - X86_INSTRUCTION_LEN {
+        if data[i] != 0xe8 && data[i] != 0xe9 {
+            i += 1;
+            continue;
+        }
+        let pos = start + i as u32;
+        let offset = pos - prev_pos;
+        prev_pos = pos;
+        if offset > X86_INSTRUCTION_LEN as u32 {
+            prev_mask = 0;
+        } else {
+            for _ in (0 .. offset) {
+                prev_mask &= 0x77;
+                prev_mask <<= 1;
+            }
+        }
+        let high = data[i + 4];
+        if is_x86_near(high) && X86_ALLOWED[((prev_mask >> 1) & 7) as usize] &&
+           (prev_mask >> 1) < 0x10 {
+            let mut src = read_le32(data, i + 1);
+            let mut dest;
+            loop {
+                dest = src - (pos + X86_INSTRUCTION_LEN as u32);
+                if prev_mask == 0 {
+                    break;
+                }
+                let index = X86_BYTE_INDEX[(prev_mask >> 1) as usize];
+                if !is_x86_near((dest >> (24 - index * 8) as usize) as u8) {
+                    break;
+                }
+                src = dest ^ ((1 << (32 - index * 8) as usize) - 1);
+            }
+            data[i + 4] = if (dest >> 24) & 1 == 1 { 0xff } else { 0x00 };
+            data[i + 3] = (dest >> 16) as u8;
+            data[i + 2] = (dest >> 8) as u8;
+            data[i + 1] = dest as u8;
+            i += X86_INSTRUCTION_LEN;
+            prev_mask = 0;
+        } else {
+            i += 1;
+            prev_mask |= 1;
+            if is_x86_near(high) {
+                prev_mask |= 0x10;
+            }
+        }
+    }
+}
+
+/// PowerPC branches with the link bit set (bl), stored big endian
+fn undo_powerpc(data: &mut [u8], start: u32) {
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        if data[i] >> 2 == 0x12 && data[i + 3] & 3 == 1 {
+            let src = read_be32(data, i) & 0x03fffffc;
+            let dest = src - (start + i as u32);
+            data[i] = 0x48 | ((dest >> 24) & 0x03) as u8;
+            data[i + 1] = (dest >> 16) as u8;
+            data[i + 2] = (dest >> 8) as u8;
+            data[i + 3] = (data[i + 3] & 0x03) | (dest as u8 & 0xfc);
+        }
+        i += 4;
+    }
+}
+
+/// IA-64 code comes in 16 byte bundles of three 41 bit instructions,
+/// after a 5 bit template that says which slots may hold branches
+fn undo_ia64(data: &mut [u8], start: u32) {
+    let mut i = 0;
+    while i + IA64_BUNDLE_LEN <= data.len() {
+        let slots = IA64_BRANCH_SLOTS[(data[i] & 0x1f) as usize];
+        for slot in (0 .. 3) {
+            if (slots >> slot) & 1 == 0 {
+                continue;
+            }
+            let bit_pos = 5 + 41 * slot;
+            let byte_pos = i + bit_pos / 8;
+            let bit_res = bit_pos % 8;
+            let mut instruction = (0 .. 6).fold(0u64, |value, j| {
+                value | (data[byte_pos + j] as u64) << (8 * j)
+            });
+            let mut norm = instruction >> bit_res;
+            if (norm >> 37) & 0xf == 0x5 && (norm >> 9) & 0x7 == 0 {
+                let mut src = ((norm >> 13) & 0xfffff) as u32;
+                src |= (((norm >> 36) & 1) as u32) << 20;
+                src <<= 4;
+                let dest = (src - (start + i as u32)) >> 4;
+                norm &= !(0x8fffffu64 << 13);
+                norm |= ((dest & 0xfffff) as u64) << 13;
+                norm |= ((dest & 0x100000) as u64) << (36 - 20);
+                instruction &= (1u64 << bit_res) - 1;
+                instruction |= norm << bit_res;
+                for j in (0 .. 6) {
+                    data[byte_pos + j] = (instruction >> (8 * j)) as u8;
+                }
+            }
+        }
+        i += IA64_BUNDLE_LEN;
+    }
+}
+
+/// ARM branch with link (BL) instructions, stored little endian
+fn undo_arm(data: &mut [u8], start: u32) {
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        if data[i + 3] == 0xeb {
+            let src = (read_le32(data, i) & 0x00ffffff) << 2;
+            let dest = (src - (start + i as u32 + 8)) >> 2;
+            data[i + 2] = (dest >> 16) as u8;
+            data[i + 1] = (dest >> 8) as u8;
+            data[i] = dest as u8;
+        }
+        i += 4;
+    }
+}
+
+/// Thumb BL instructions, a pair of 16 bit halves
+fn undo_armthumb(data: &mut [u8], start: u32) {
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        if data[i + 1] & 0xf8 == 0xf0 && data[i + 3] & 0xf8 == 0xf8 {
+            let src = ((data[i + 1] as u32 & 7) << 19 | (data[i] as u32) << 11 |
+                       (data[i + 3] as u32 & 7) << 8 | data[i + 2] as u32) << 1;
+            let dest = (src - (start + i as u32 + 4)) >> 1;
+            data[i + 1] = 0xf0 | ((dest >> 19) & 7) as u8;
+            data[i] = (dest >> 11) as u8;
+            data[i + 3] = 0xf8 | ((dest >> 8) & 7) as u8;
+            data[i + 2] = dest as u8;
+            i += 4;
+        } else {
+            i += 2;
+        }
+    }
+}
+
+/// SPARC call instructions, stored big endian
+fn undo_sparc(data: &mut [u8], start: u32) {
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        if (data[i] == 0x40 && data[i + 1] & 0xc0 == 0x00) ||
+           (data[i] == 0x7f && data[i + 1] & 0xc0 == 0xc0) {
+            let src = read_be32(data, i) << 2;
+            let mut dest = (src - (start + i as u32)) >> 2;
+            dest = ((0 - ((dest >> 22) & 1)) << 22 & 0x3fffffff) | (dest & 0x3fffff) | 0x40000000;
+            data[i] = (dest >> 24) as u8;
+            data[i + 1] = (dest >> 16) as u8;
+            data[i + 2] = (dest >> 8) as u8;
+            data[i + 3] = dest as u8;
+        }
+        i += 4;
+    }
+}
+
+/// ARM64 BL instructions, and ADRP ones within 512M, stored little endian
+fn undo_arm64(data: &mut [u8], start: u32) {
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        let pc = start + i as u32;
+        let mut instruction = read_le32(data, i);
+        if instruction >> 26 == 0x25 {
+            let src = instruction;
+            instruction = 0x94000000 | ((src - (pc >> 2)) & 0x03ffffff);
+            write_le32(data, i, instruction);
+        } else if instruction & 0x9f000000 == 0x90000000 {
+            let src = ((instruction >> 29) & 3) | ((instruction >> 3) & 0x001ffffc);
+            if (src + 0x00020000) & 0x001c0000 == 0 {
+                let dest = src - (pc >> 12);
+                instruction &= 0x9000001f;
+                instruction |= (dest & 3) << 29;
+                instruction |= (dest & 0x0003fffc) << 3;
+                instruction |= (0 - (dest & 0x00020000)) & 0x00e00000;
+                write_le32(data, i, instruction);
+            }
+        }
+        i += 4;
+    }
+}
+
+fn read_le32(data: &[u8], pos: usize) -> u32 {
+    (0 .. 4).fold(0, |value, i| value | (data[pos + i] as u32) << (8 * i))
+}
+
+fn read_be32(data: &[u8], pos: usize) -> u32 {
+    (0 .. 4).fold(0, |value, i| value << 8 | data[pos + i] as u32)
+}
+
+fn write_le32(data: &mut [u8], pos: usize, value: u32) {
+    for i in (0 .. 4) {
+        data[pos + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::Filter;
+    use error::GzError;
+
+    fn unfilter(filter: Filter, data: &[u8]) -> Vec<u8> {
+        let mut out = data.to_vec();
+        filter.unfilter(out.as_mut_slice());
+        out
+    }
+
+    #[test]
+    fn test_from_xz() {
+        assert_eq!(Filter::from_xz(0x03, &[3]), Ok(Filter::Delta(4)));
+        assert_eq!(Filter::from_xz(0x04, &[]), Ok(Filter::X86(0)));
+        assert_eq!(Filter::from_xz(0x07, &[0x00, 0x10, 0x00, 0x00]), Ok(Filter::Arm(0x1000)));
+        assert_eq!(Filter::from_xz(0x03, &[]), Err(GzError::CorruptData));
+        assert_eq!(Filter::from_xz(0x04, &[0]), Err(GzError::CorruptData));
+        // RISC-V
+        assert_eq!(Filter::from_xz(0x0b, &[]), Err(GzError::UnsupportedMethod));
+    }
+
+    #[test]
+    fn test_delta() {
+        assert_eq!(unfilter(Filter::Delta(2), &[1, 2, 1, 1, 0xff, 0]), vec![1, 2, 2, 3, 1, 3]);
+    }
+
+    #[test]
+    fn test_x86() {
+        // a call 0x10 bytes on, at offset 8, stored as the absolute 0x1d
+        let filtered = [0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90,
+                        0xe8, 0x1d, 0x00, 0x00, 0x00, 0x90];
+        let text = [0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90,
+                    0xe8, 0x10, 0x00, 0x00, 0x00, 0x90];
+        assert_eq!(unfilter(Filter::X86(0), &filtered), text.to_vec());
+        // a call too far away to have been converted is left alone
+        let far = [0xe8, 0x00, 0x00, 0x00, 0x12];
+        assert_eq!(unfilter(Filter::X86(0), &far), far.to_vec());
+    }
+
+    #[test]
+    fn test_arm() {
+        // bl at offset 4 to 0x100 bytes past the pc, from a start of 0x1000
+        let filtered = [0, 0, 0, 0, 0x43, 0x04, 0x00, 0xeb];
+        assert_eq!(unfilter(Filter::Arm(0x1000), &filtered),
+                   vec![0, 0, 0, 0, 0x40, 0x00, 0x00, 0xeb]);
+    }
+}
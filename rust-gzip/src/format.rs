@@ -0,0 +1,175 @@
+#[doc="
+
+    Module: format
+
+    This works out what a buffer holds from the magic number at its
+    start, so that it can be handed to the module that reads it.
+
+"]
+use cvec::Buf;
+use error::{GzError, GzResult};
+use ar;
+use bzip2;
+use cpio;
+use gz;
+use lz4;
+use lzma;
+use lzip;
+use lzop;
+use lzw;
+use rpm;
+use snappy;
+use tar;
+use xz;
+use zlib;
+use zstd;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const COMPRESS_MAGIC: [u8; 2] = [0x1f, 0x9d];
+const BZIP2_MAGIC: [u8; 3] = [0x42, 0x5a, 0x68];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+const LZ4_LEGACY_MAGIC: [u8; 4] = [0x02, 0x21, 0x4c, 0x18];
+const XZ_MAGIC: [u8; 6] = [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00];
+// .lzma files have no magic number, but nearly all of them start with
+// the default settings and a dictionary size that's a multiple of 64K
+const LZMA_MAGIC: [u8; 3] = [0x5d, 0x00, 0x00];
+const LZIP_MAGIC: [u8; 4] = [0x4c, 0x5a, 0x49, 0x50];
+const LZOP_MAGIC: [u8; 9] = [0x89, 0x4c, 0x5a, 0x4f, 0x00, 0x0d, 0x0a, 0x1a, 0x0a];
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+const SEVENZIP_MAGIC: [u8; 6] = [0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c];
+// the stream identifier chunk that starts every Snappy framed file
+const SNAPPY_MAGIC: [u8; 10] = [0xff, 0x06, 0x00, 0x00, 0x73, 0x4e, 0x61, 0x50, 0x70, 0x59];
+
+/// Everything we know how to read
+/// The values are the RGZIP_FORMAT codes in rgzip.h, so don't renumber them.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum Format {
+    Gzip = 1,
+    Compress = 2,
+    Bzip2 = 3,
+    Zstd = 4,
+    Lz4 = 5,
+    Xz = 6,
+    Lzma = 7,
+    Snappy = 8,
+    Lzop = 9,
+    Lzip = 10,
+    Zlib = 11,
+    Zip = 12,
+    SevenZip = 13,
+    Tar = 14,
+    Ar = 15,
+    Cpio = 16,
+    Rpm = 17,
+}
+
+impl Format {
+    /// Whether this is an archive of many files, rather than a single
+    /// compressed stream
+    pub fn is_archive(&self) -> bool {
+        match *self {
+            Format::Zip | Format::SevenZip | Format::Tar | Format::Ar |
+            Format::Cpio | Format::Rpm => true,
+            _ => false
+        }
+    }
+}
+
+/// What buffer holds, if it's anything we can read
+pub fn detect(buffer: &Buf) -> Option<Format> {
+    if starts_with(buffer, &GZIP_MAGIC) {
+        Some(Format::Gzip)
+    } else if starts_with(buffer, &COMPRESS_MAGIC) {
+        Some(Format::Compress)
+    } else if starts_with(buffer, &BZIP2_MAGIC) {
+        Some(Format::Bzip2)
+    } else if starts_with(buffer, &ZSTD_MAGIC) {
+        Some(Format::Zstd)
+    } else if starts_with(buffer, &LZ4_MAGIC) || starts_with(buffer, &LZ4_LEGACY_MAGIC) {
+        Some(Format::Lz4)
+    } else if starts_with(buffer, &XZ_MAGIC) {
+        Some(Format::Xz)
+    } else if starts_with(buffer, &LZMA_MAGIC) {
+        Some(Format::Lzma)
+    } else if starts_with(buffer, &SNAPPY_MAGIC) {
+        Some(Format::Snappy)
+    } else if starts_with(buffer, &LZOP_MAGIC) {
+        Some(Format::Lzop)
+    } else if starts_with(buffer, &LZIP_MAGIC) {
+        Some(Format::Lzip)
+    } else if starts_with(buffer, &ZIP_MAGIC) {
+        Some(Format::Zip)
+    } else if starts_with(buffer, &SEVENZIP_MAGIC) {
+        Some(Format::SevenZip)
+    } else if tar::is_tar(buffer) {
+        Some(Format::Tar)
+    } else if ar::is_ar(buffer) {
+        Some(Format::Ar)
+    } else if rpm::is_rpm(buffer) {
+        Some(Format::Rpm)
+    } else if cpio::is_cpio(buffer) {
+        // before zlib, as binary cpio's magic is only two bytes too
+        Some(Format::Cpio)
+    } else if zlib::is_zlib(buffer) {
+        // last, as two bytes are easily matched by chance
+        Some(Format::Zlib)
+    } else {
+        None
+    }
+}
+
+/// Decompress buffer, which holds a single stream of the given format
+pub fn decompress(format: Format, buffer: Buf) -> GzResult<Buf> {
+    match format {
+        Format::Gzip => gz::decompress_gz(buffer),
+        Format::Compress => lzw::decompress_lzw(buffer),
+        Format::Bzip2 => bzip2::decompress_bzip2(buffer),
+        Format::Zstd => zstd::decompress_zstd(buffer),
+        Format::Lz4 => lz4::decompress_lz4(buffer),
+        Format::Xz => xz::decompress_xz(buffer),
+        Format::Lzma => lzma::decompress_lzma(buffer),
+        Format::Snappy => snappy::decompress_snappy(buffer),
+        Format::Lzop => lzop::decompress_lzop(buffer),
+        Format::Lzip => lzip::decompress_lzip(buffer),
+        Format::Zlib => zlib::decompress_zlib(buffer),
+        Format::Zip | Format::SevenZip | Format::Tar | Format::Ar |
+        Format::Cpio | Format::Rpm => {
+            Err(GzError::UnsupportedMethod)
+        }
+    }
+}
+
+/// Whether buffer starts with the given magic number
+fn starts_with(buffer: &Buf, magic: &[u8]) -> bool {
+    buffer.len() >= magic.len() && buffer.iter().zip(magic.iter()).all(|(a, b)| a == b)
+}
+
+#[cfg(test)]
+mod format_tests {
+    use super::{Format, detect};
+    use cvec::from_slice;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(&from_slice(&[0x1f, 0x8b, 0x08, 0x00])), Some(Format::Gzip));
+        assert_eq!(detect(&from_slice(&[0x1f, 0x9d, 0x90])), Some(Format::Compress));
+        assert_eq!(detect(&from_slice(b"BZh91AY&SY")), Some(Format::Bzip2));
+        assert_eq!(detect(&from_slice(&[0x28, 0xb5, 0x2f, 0xfd, 0x04])), Some(Format::Zstd));
+        assert_eq!(detect(&from_slice(&[0x04, 0x22, 0x4d, 0x18, 0x64])), Some(Format::Lz4));
+        assert_eq!(detect(&from_slice(&[0x02, 0x21, 0x4c, 0x18, 0x45])), Some(Format::Lz4));
+        assert_eq!(detect(&from_slice(b"\xfd7zXZ\0\0\x04")), Some(Format::Xz));
+        assert_eq!(detect(&from_slice(&[0x5d, 0x00, 0x00, 0x80, 0x00])), Some(Format::Lzma));
+        assert_eq!(detect(&from_slice(b"\xff\x06\0\0sNaPpY")), Some(Format::Snappy));
+        assert_eq!(detect(&from_slice(b"\x89LZO\0\r\n\x1a\n\x10")), Some(Format::Lzop));
+        assert_eq!(detect(&from_slice(b"LZIP\x01\x17\x00")), Some(Format::Lzip));
+        assert_eq!(detect(&from_slice(b"PK\x03\x04")), Some(Format::Zip));
+        assert_eq!(detect(&from_slice(b"7z\xbc\xaf\x27\x1c\x00\x04")), Some(Format::SevenZip));
+        assert_eq!(detect(&from_slice(b"!<arch>\ndebian-binary")), Some(Format::Ar));
+        assert_eq!(detect(&from_slice(&[0xed, 0xab, 0xee, 0xdb, 0x03, 0x00])), Some(Format::Rpm));
+        assert_eq!(detect(&from_slice(b"07070100000001")), Some(Format::Cpio));
+        assert_eq!(detect(&from_slice(&[0x78, 0x9c, 0xcb, 0x48])), Some(Format::Zlib));
+        assert_eq!(detect(&from_slice(b"just some text")), None);
+        assert_eq!(detect(&from_slice(&[0x1f])), None);
+    }
+}
@@ -7,70 +7,102 @@
     code to interface with C.
 
 "]
-use cvec::{CVec, Buf, Iter};
+extern crate core;
+
+use cvec::Buf;
 use libc::c_uint;
+use self::core::num::Int;
 
 use header;
 use crc32;
+use error::{GzError, GzResult};
+use limits;
 use gz_reader::GzBitReader;
 use inflate::inflate;
 
-// every gzip file is at least 10 bytes, if not, it's invalid
-const GZIP_MIN_LEN: usize = 40;
+// every gzip file has at least a 10 byte header and an 8 byte footer,
+// if not, it's invalid
+const GZIP_MIN_LEN: usize = 18;
+const GZIP_CRC_OFFSET: usize = 0;
 const GZIP_FILESIZE_OFFSET: usize = 4;
-const GZIP_CRC_OFFSET: usize = 8;
 const GZIP_FOOTER_LEN: usize = 8;
 
 /// Decompress the given compressed buffer
-pub fn decompress_gz(buffer: Buf) -> Option<Buf> {
+/// A gzip file may hold several members back to back (cat a.gz b.gz,
+/// pigz, logrotate), each with its own header and CRC32/ISIZE trailer.
+/// Their contents are concatenated, as RFC 1952 requires.
+pub fn decompress_gz(buffer: Buf) -> GzResult<Buf> {
     if buffer.len() < GZIP_MIN_LEN {
-        return None;
-    }
-    let out_len = get_uncompressed_len(&buffer);
-    let crc = get_crc(&buffer);
-    let header = try_opt!(header::parse_header(&buffer));
-    let mut out_buf = try_opt!(CVec::with_capacity(out_len));
-    decompress_raw(buffer.limit_iter(header.header_len, buffer.len() - GZIP_FOOTER_LEN),
-                   &mut out_buf);
-    if check_crc(&out_buf, crc) {
-        Some(out_buf)
-    } else {
-        None
+        return Err(GzError::TruncatedHeader);
+    }
+    // ISIZE of the last member is only a hint for the first allocation,
+    // and an untrusted one, so the limits cap it
+    let out_len = get_uncompressed_len(&buffer, buffer.len() - GZIP_FOOTER_LEN);
+    let mut out_buf = try!(limits::output_buf(buffer.len(), out_len));
+    let mut start = 0;
+    loop {
+        start = try!(decompress_member(&buffer, start, &mut out_buf));
+        // like gzip, ignore any padding or garbage after the last member
+        if !is_member_start(&buffer, start) {
+            break;
+        }
     }
+    Ok(out_buf)
 }
 
 /////////////////////////////////////////////////////////////////////
 //                       Helper functions                          //
 /////////////////////////////////////////////////////////////////////
 
-/// Decompress the buffer into out_buf
-/// Helper function for decompress
-fn decompress_raw(buffer: Iter<u8>, out_buf: &mut Buf) {
-    let mut gz_reader = match GzBitReader::new(buffer) {
-        Some(g) => g,
-        None => { return; }
-    };
-    match inflate(&mut gz_reader, out_buf) {
-        Some(()) => {},
-        None => { out_buf.clear(); }
+/// Decompress the member starting at index start of buffer, appending
+/// its contents to out_buf and checking them against the member trailer
+/// Returns the index just past the end of the member
+fn decompress_member(buffer: &Buf, start: usize, out_buf: &mut Buf) -> GzResult<usize> {
+    let header = try!(header::parse_header_at(buffer, start));
+    let out_start = out_buf.len();
+    let mut gz_reader = try_or!(GzBitReader::new(
+            buffer.limit_iter(start + header.header_len, buffer.len())), GzError::TruncatedData);
+    try!(inflate(&mut gz_reader, out_buf));
+    gz_reader.align_to_byte();
+
+    let footer = gz_reader.byte_index();
+    if footer + GZIP_FOOTER_LEN > buffer.len() {
+        return Err(GzError::TruncatedData);
     }
+    let crc = get_crc(buffer, footer);
+    let size = get_uncompressed_len(buffer, footer);
+    if !check_crc(out_buf, out_start, crc) {
+        return Err(GzError::CrcMismatch);
+    }
+    // ISIZE is the member length modulo 2^32
+    if (out_buf.len() - out_start) as u32 as usize != size {
+        return Err(GzError::SizeMismatch);
+    }
+    Ok(footer + GZIP_FOOTER_LEN)
 }
 
-/// Get the length of the uncompressed file
-fn get_uncompressed_len(buffer: &Buf) -> usize {
-    assert!(buffer.len() > GZIP_MIN_LEN);
-    buffer.get_wide::<c_uint>(buffer.len() - GZIP_FILESIZE_OFFSET).unwrap() as usize
+/// Whether another gzip member begins at index start of the buffer
+fn is_member_start(buffer: &Buf, start: usize) -> bool {
+    match (buffer.get(start), buffer.get(start + 1)) {
+        (Some(&0x1f), Some(&0x8b)) => true,
+        _ => false
+    }
 }
 
-/// Get the CRC of the uncompressed file
-fn get_crc(buffer: &Buf) -> c_uint {
-    assert!(buffer.len() > GZIP_MIN_LEN);
-    buffer.get_wide::<c_uint>(buffer.len() - GZIP_CRC_OFFSET).unwrap()
+/// Get the length of the uncompressed member whose trailer is at footer
+fn get_uncompressed_len(buffer: &Buf, footer: usize) -> usize {
+    Int::from_le(buffer.get_wide::<c_uint>(footer + GZIP_FILESIZE_OFFSET).unwrap()) as usize
 }
 
-/// Verify that the CRC matches what we expect
-fn check_crc(buffer: &Buf, crc: c_uint) -> bool {
-    crc32::sum(buffer.iter()) == crc
+/// Get the CRC of the uncompressed member whose trailer is at footer
+fn get_crc(buffer: &Buf, footer: usize) -> c_uint {
+    Int::from_le(buffer.get_wide::<c_uint>(footer + GZIP_CRC_OFFSET).unwrap())
+}
+
+/// Verify that the CRC of everything in buffer from start on matches
+/// what we expect
+fn check_crc(buffer: &Buf, start: usize, crc: c_uint) -> bool {
+    crc32::sum(buffer.limit_iter(start, buffer.len())) == crc
 }
 
 #[cfg(test)]
@@ -94,12 +126,120 @@ mod get_tests {
     #[test]
     fn test_get_crc() {
         let buf: Buf = setup();
-        assert_eq!(get_crc(&buf), 0x03020100);
+        assert_eq!(get_crc(&buf, 40), 0x03020100);
     }
 
     #[test]
     fn test_get_uncompressed_len() {
         let buf: Buf = setup();
-        assert_eq!(get_uncompressed_len(&buf), 0x07060504);
+        assert_eq!(get_uncompressed_len(&buf, 40), 0x07060504);
+    }
+}
+
+#[cfg(test)]
+mod decompress_tests {
+    use std::io::File;
+    use std::io::fs;
+    use test::Bencher;
+    use super::decompress_gz;
+    use cvec::from_slice;
+    use error::{GzError, GzResult};
+
+    // cargo runs tests from the crate root, next to the sample files
+    const CORPUS_DIR: &'static str = "../gzip-test";
+
+    // printf 'hello\n' | gzip -n; printf 'world\n' | gzip -n
+    static TWO_MEMBERS: &'static [u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48,
+        0xcd, 0xc9, 0xc9, 0xe7, 0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00,
+        0x00, 0x00, 0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+        0x2b, 0xcf, 0x2f, 0xca, 0x49, 0xe1, 0x02, 0x00, 0xa8, 0x61, 0x38, 0xdd,
+        0x06, 0x00, 0x00, 0x00];
+
+    fn read_corpus(name: &str) -> Vec<u8> {
+        File::open(&Path::new(CORPUS_DIR).join(name)).read_to_end().unwrap()
+    }
+
+    fn decompress_bytes(raw: &[u8]) -> GzResult<Vec<u8>> {
+        let out = try!(decompress_gz(from_slice(raw)));
+        Ok(out.iter().map(|&b| b).collect())
+    }
+
+    #[test]
+    fn test_multiple_members() {
+        assert_eq!(decompress_bytes(TWO_MEMBERS), Ok(b"hello\nworld\n".to_vec()));
+    }
+
+    #[test]
+    fn test_trailing_padding() {
+        let mut padded = TWO_MEMBERS.to_vec();
+        for _ in 0..16 {
+            padded.push(0);
+        }
+        assert_eq!(decompress_bytes(padded.as_slice()), Ok(b"hello\nworld\n".to_vec()));
+    }
+
+    #[test]
+    fn test_second_member_bad_crc() {
+        let mut corrupt = TWO_MEMBERS.to_vec();
+        corrupt[44] ^= 0xff;
+        assert_eq!(decompress_bytes(corrupt.as_slice()), Err(GzError::CrcMismatch));
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let mut corrupt = TWO_MEMBERS.to_vec();
+        corrupt[22] += 1;
+        assert_eq!(decompress_bytes(corrupt.as_slice()), Err(GzError::SizeMismatch));
+    }
+
+    #[test]
+    fn test_huge_recorded_size() {
+        // ISIZE only sizes the first allocation, which the limits cap
+        let mut corrupt = TWO_MEMBERS.to_vec();
+        for i in 48 .. 52 {
+            corrupt[i] = 0xff;
+        }
+        assert_eq!(decompress_bytes(corrupt.as_slice()), Err(GzError::SizeMismatch));
+    }
+
+    #[test]
+    fn test_truncated_member() {
+        assert_eq!(decompress_bytes(&TWO_MEMBERS[..48]), Err(GzError::TruncatedData));
+    }
+
+    #[test]
+    fn test_single_small_member() {
+        assert_eq!(decompress_bytes(&TWO_MEMBERS[..26]), Ok(b"hello\n".to_vec()));
+    }
+
+    #[test]
+    fn test_corpus() {
+        // every member's trailer has the CRC32 and length of what went in,
+        // so decompressing without an error means the output is identical
+        let mut count = 0;
+        for path in fs::readdir(&Path::new(CORPUS_DIR)).unwrap().iter() {
+            if path.extension_str() != Some("gz") {
+                continue;
+            }
+            let raw = File::open(path).read_to_end().unwrap();
+            match decompress_bytes(raw.as_slice()) {
+                Ok(_) => { count += 1; },
+                Err(err) => panic!("{} failed: {:?}", path.display(), err)
+            }
+        }
+        assert_eq!(count, 4);
+    }
+
+    #[bench]
+    fn bench_moby_dick(b: &mut Bencher) {
+        let raw = read_corpus("moby10b.txt.gz");
+        b.iter(|| decompress_gz(from_slice(raw.as_slice())).unwrap());
+    }
+
+    #[bench]
+    fn bench_shakespeare(b: &mut Bencher) {
+        let raw = read_corpus("shakespeare.txt.gz");
+        b.iter(|| decompress_gz(from_slice(raw.as_slice())).unwrap());
     }
 }
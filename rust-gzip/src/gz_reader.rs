@@ -5,57 +5,151 @@
     of a gzip-compressed buffer.
 
 "]
-use cvec::Iter;
+use std::cmp;
+use cvec::{Iter, Buf};
+
+// bytes are loaded while the accumulator holds no more than this many
+// bits, so the next byte always fits
+const REFILL_BITS: u32 = 56;
 
 #[derive(Show)]
 pub struct GzBitReader<'a> {
     iter: Iter<'a, u8>,
-    buf: u8,
-    mask: u8
+    /// index in the underlying buffer of the next byte to load
+    byte_pos: usize,
+    /// bits loaded from the buffer but not read yet, the next one lowest
+    bits: u64,
+    /// how many bits are loaded
+    count: u32,
+    exhausted: bool
 }
 
-/// Read the GZIP data bit by bit
+/// Read the GZIP data bit by bit, loading it several bytes at a time
 impl<'a> GzBitReader<'a> {
-    pub fn new(mut iter: Iter<'a, u8>) -> Option<GzBitReader<'a>> {
-        let starting_buf = try_opt!(iter.next());
-        Some(GzBitReader {
+    pub fn new(iter: Iter<'a, u8>) -> Option<GzBitReader<'a>> {
+        let mut reader = GzBitReader {
+            byte_pos: iter.index(),
             iter: iter,
-            buf: *starting_buf,
-            mask: 0x01
-        })
+            bits: 0,
+            count: 0,
+            exhausted: false
+        };
+        reader.refill();
+        if_opt!(reader.count > 0, reader)
+    }
+
+    /// Start reading buffer at the given bit, as returned by bit_position
+    pub fn at_bit(buffer: &'a Buf, bit: usize) -> Option<GzBitReader<'a>> {
+        let mut reader = try_opt!(GzBitReader::new(buffer.limit_iter(bit / 8, buffer.len())));
+        try_opt!(reader.consume_bits((bit % 8) as u32));
+        Some(reader)
+    }
+
+    /// Load whole bytes until there are more than REFILL_BITS bits, or the
+    /// input runs out
+    #[inline]
+    fn refill(&mut self) {
+        while self.count <= REFILL_BITS {
+            match self.iter.next() {
+                Some(&byte) => {
+                    self.bits |= (byte as u64) << self.count as usize;
+                    self.count += 8;
+                    self.byte_pos += 1;
+                },
+                None => { break; }
+            }
+        }
+    }
+
+    /// Make sure count bits are loaded, flagging the reader as exhausted
+    /// if the input doesn't have that many
+    #[inline]
+    fn ensure(&mut self, count: u32) -> Option<()> {
+        if self.count < count {
+            self.refill();
+            if self.count < count {
+                self.exhausted = true;
+                return None;
+            }
+        }
+        Some(())
     }
 
     #[inline]
     /// Get the next bit from the "stream"
     pub fn next_bit(&mut self) -> Option<u32> {
-        if self.mask == 0 {
-            self.buf = *try_opt!(self.iter.next());
-            self.mask = 0x01;
-        }
-        let bit = if (self.buf & self.mask) > 0 { 1 } else { 0 };
-        self.mask <<= 1;
+        try_opt!(self.ensure(1));
+        let bit = (self.bits & 1) as u32;
+        self.bits >>= 1;
+        self.count -= 1;
         Some(bit)
     }
 
+    /// Discard whatever is left of the current byte, so the next read
+    /// starts on a byte boundary (stored blocks begin this way)
+    pub fn align_to_byte(&mut self) {
+        let partial = self.count % 8;
+        self.bits >>= partial as usize;
+        self.count -= partial;
+    }
+
+    /// Index into the underlying buffer of the next byte that hasn't been
+    /// read from at all. Only exact once the reader is byte aligned.
+    pub fn byte_index(&self) -> usize {
+        self.byte_pos - (self.count / 8) as usize
+    }
+
+    /// Position of the next unread bit, counted from the start of the
+    /// underlying buffer
+    pub fn bit_position(&self) -> usize {
+        self.byte_pos * 8 - self.count as usize
+    }
+
+    /// Whether a read has failed because the input ran out, as opposed to
+    /// the input being invalid
+    pub fn exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    /// Look at up to count (at most 32) of the next bits without reading
+    /// them, least significant first. Returns the bits and how many of
+    /// them there really are, which is less than count near the end.
+    #[inline]
+    pub fn peek_bits(&mut self, count: u32) -> (u32, u32) {
+        assert!(count <= 32);
+        if self.count < count {
+            self.refill();
+        }
+        let available = cmp::min(count, self.count);
+        ((self.bits & ((1u64 << count as usize) - 1)) as u32, available)
+    }
+
+    /// Skip over count bits, usually ones looked at with peek_bits
+    #[inline]
+    pub fn consume_bits(&mut self, count: u32) -> Option<()> {
+        try_opt!(self.ensure(count));
+        self.bits >>= count as usize;
+        self.count -= count;
+        Some(())
+    }
+
     /// reads bits in least to most significant order
+    #[inline]
     pub fn read_bits(&mut self, count: u32) -> Option<u32> {
-        let mut bit: u32;
-        let mut value: u32 = 0;
-        for i in (0 .. count) {
-            bit = try_opt!(self.next_bit());
-            value |= bit << i;
-        }
+        assert!(count <= 32);
+        try_opt!(self.ensure(count));
+        let value = (self.bits & ((1u64 << count as usize) - 1)) as u32;
+        self.bits >>= count as usize;
+        self.count -= count;
         Some(value)
     }
 
     /// reads bits in most to least significant order
     pub fn read_bits_rev(&mut self, count: u32) -> Option<u32> {
-        let mut bit: u32;
+        let bits = try_opt!(self.read_bits(count));
         let mut value: u32 = 0;
-        for _ in (0 .. count) {
-            value <<= 1;
-            bit = try_opt!(self.next_bit());
-            value |= bit;
+        for i in (0 .. count) {
+            value = (value << 1) | ((bits >> i as usize) & 1);
         }
         Some(value)
     }
@@ -116,4 +210,84 @@ mod gz_reader_tests {
         }
         assert_eq!(reader.next_bit(), None);
     }
+
+    #[test]
+    fn test_align_to_byte() {
+        let bytes = setup();
+        let mut reader = GzBitReader::new(bytes.iter()).unwrap();
+        // already on a boundary, nothing is skipped
+        reader.align_to_byte();
+        assert_eq!(reader.read_bits(3), Some(1));
+        reader.align_to_byte();
+        assert_eq!(reader.read_bits(8), Some(2));
+        // a full byte was just read, so we stay where we are
+        reader.align_to_byte();
+        assert_eq!(reader.read_bits(8), Some(3));
+        assert_eq!(reader.read_bits(1), Some(0));
+        reader.align_to_byte();
+        assert_eq!(reader.read_bits(1), None);
+    }
+
+    #[test]
+    fn test_byte_index() {
+        let bytes = setup();
+        let mut reader = GzBitReader::new(bytes.limit_iter(1, 4)).unwrap();
+        assert_eq!(reader.byte_index(), 1);
+        assert_eq!(reader.read_bits(2), Some(2));
+        reader.align_to_byte();
+        assert_eq!(reader.byte_index(), 2);
+        assert_eq!(reader.read_bits(8), Some(3));
+        assert_eq!(reader.byte_index(), 3);
+    }
+
+    #[test]
+    fn test_peek_and_consume() {
+        let bytes = setup();
+        let mut reader = GzBitReader::new(bytes.iter()).unwrap();
+        assert_eq!(reader.peek_bits(9), (1, 9));
+        assert_eq!(reader.consume_bits(9), Some(()));
+        assert_eq!(reader.peek_bits(9), (385, 9));
+        assert_eq!(reader.consume_bits(3), Some(()));
+        assert_eq!(reader.read_bits(6), Some(48));
+        // only 14 bits are left
+        assert_eq!(reader.peek_bits(20), (256, 14));
+        assert_eq!(reader.consume_bits(14), Some(()));
+        assert_eq!(reader.peek_bits(1), (0, 0));
+        assert!(!reader.exhausted());
+        assert_eq!(reader.consume_bits(1), None);
+        assert!(reader.exhausted());
+    }
+
+    #[test]
+    fn test_reads_across_refills() {
+        let mut bytes: CVec<u8> = CVec::with_capacity(24).unwrap();
+        for i in 0 .. 24 {
+            bytes.push(i as u8);
+        }
+        let mut reader = GzBitReader::new(bytes.iter()).unwrap();
+        // 16 twelve bit reads cover the 24 bytes, each pair of reads
+        // being three bytes
+        for i in 0 .. 8 {
+            let low = reader.read_bits(12).unwrap();
+            let high = reader.read_bits(12).unwrap();
+            assert_eq!(low | (high << 12), (3 * i) | ((3 * i + 1) << 8) | ((3 * i + 2) << 16));
+        }
+        assert_eq!(reader.bit_position(), 24 * 8);
+        assert_eq!(reader.next_bit(), None);
+    }
+
+    #[test]
+    fn test_bit_position() {
+        let bytes = setup();
+        let mut reader = GzBitReader::new(bytes.iter()).unwrap();
+        assert_eq!(reader.read_bits(9), Some(1));
+        let position = reader.bit_position();
+        assert_eq!(position, 9);
+        assert_eq!(reader.read_bits(9), Some(385));
+        let mut resumed = GzBitReader::at_bit(&bytes, position).unwrap();
+        assert_eq!(resumed.read_bits(9), Some(385));
+        assert!(!resumed.exhausted());
+        assert_eq!(resumed.read_bits(16), None);
+        assert!(resumed.exhausted());
+    }
 }
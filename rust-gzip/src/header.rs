@@ -11,6 +11,7 @@ extern crate core;
 
 use cvec;
 use cvec::{Iter, Buf};
+use error::{GzError, GzResult};
 use self::core::num::Int;
 
 const GZ_MAGIC_BYTES: [u8; 2] = [0x1f, 0x8b];
@@ -68,8 +69,14 @@ struct GZHeader {
 
 /// Return a GZIP header structure representing the information
 /// contained in the beginning of the given Buf
-pub fn parse_header(buffer: &cvec::Buf) -> Option<GZHeader> {
-    let mut iter = buffer.iter();
+pub fn parse_header(buffer: &cvec::Buf) -> GzResult<GZHeader> {
+    parse_header_at(buffer, 0)
+}
+
+/// Return the GZIP header of the member starting at index start of the
+/// given Buf. header_len is relative to start.
+pub fn parse_header_at(buffer: &cvec::Buf, start: usize) -> GzResult<GZHeader> {
+    let mut iter = buffer.limit_iter(start, buffer.len());
 
     // Header fields
     let mut comp_method: u8;
@@ -79,25 +86,29 @@ pub fn parse_header(buffer: &cvec::Buf) -> Option<GZHeader> {
     let mut os: u8;
 
     // Check that the magic number is right
-    if *try_opt!(iter.next()) == GZ_MAGIC_BYTES[0]
-        && *try_opt!(iter.next()) == GZ_MAGIC_BYTES[1] {
-        comp_method = *try_opt!(iter.next());
+    if *try_or!(iter.next(), GzError::TruncatedHeader) == GZ_MAGIC_BYTES[0]
+        && *try_or!(iter.next(), GzError::TruncatedHeader) == GZ_MAGIC_BYTES[1] {
+        comp_method = *try_or!(iter.next(), GzError::TruncatedHeader);
         // We don't know how to decompress anything other than 8
-        if comp_method != 8 { return None; }
-        flags = Flags::new(*try_opt!(iter.next()));
+        if comp_method != 8 { return Err(GzError::UnsupportedMethod); }
+        flags = Flags::new(*try_or!(iter.next(), GzError::TruncatedHeader));
         // We need to shift mtime because it's 4 bytes
-        mtime = Int::from_le(try_opt!(iter.next_wide::<u32>()));
-        extra_flags = *try_opt!(iter.next());
-        os = *try_opt!(iter.next());
+        mtime = Int::from_le(try_or!(iter.next_wide::<u32>(), GzError::TruncatedHeader));
+        extra_flags = *try_or!(iter.next(), GzError::TruncatedHeader);
+        os = *try_or!(iter.next(), GzError::TruncatedHeader);
 
         // Optional stuff
         let extra = get_extra(&flags, &mut iter);
         let name = get_string(flags.FNAME, &mut iter);
         let comment = get_string(flags.FCOMMENT, &mut iter);
         let crc = get_crc(&flags, &mut iter);
+        // the iterator moves past the end when a field is cut short
+        if iter.index() > buffer.len() {
+            return Err(GzError::TruncatedHeader);
+        }
 
-        Some(GZHeader {
-            header_len: iter.index(),
+        Ok(GZHeader {
+            header_len: iter.index() - start,
             compression_method: comp_method,
             flags: flags,
             mtime: mtime,
@@ -109,28 +120,33 @@ pub fn parse_header(buffer: &cvec::Buf) -> Option<GZHeader> {
             crc: crc
         })
     } else {
-        None
+        Err(GzError::BadMagic)
     }
 }
 
-/// Get the values contained in the FEXTRA field of the header buffer
+/// Get the values contained in the FEXTRA field of the header buffer:
+/// the id and data of its first subfield
 fn get_extra(flags: &Flags, iter: &mut cvec::Iter<u8>) -> Option<(String, Vec<u8>)> {
     if_opt!(flags.FEXTRA, {
-        let mut id_bytes = Vec::with_capacity(2);
-        id_bytes.push(*try_opt!(iter.next()));
-        id_bytes.push(*try_opt!(iter.next()));
-        let id = match String::from_utf8(id_bytes) {
-            Ok(string) => string,
-            Err(..) => return None
-        };
-        let mut len: u16 = (*try_opt!(iter.next()) as u16) << 8;
-        len += *try_opt!(iter.next()) as u16;
-        let mut data = Vec::with_capacity(len as usize);
+        // XLEN, the length of the whole field, little endian
+        let mut len: u16 = *try_opt!(iter.next()) as u16;
+        len += (*try_opt!(iter.next()) as u16) << 8;
+        let mut field = Vec::with_capacity(len as usize);
         for _ in 0..(len as usize) {
             let byte: u8 = *try_opt!(iter.next());
-            data.push(byte);
+            field.push(byte);
         }
-        (id, data)
+        // each subfield is a two byte id and a two byte length, then data
+        if field.len() < 4 {
+            return None;
+        }
+        let id = match String::from_utf8(field[.. 2].to_vec()) {
+            Ok(string) => string,
+            Err(..) => return None
+        };
+        let sub_len = field[2] as usize + ((field[3] as usize) << 8);
+        let end = if 4 + sub_len < field.len() { 4 + sub_len } else { field.len() };
+        (id, field[4 .. end].to_vec())
     })
 }
 
@@ -165,16 +181,9 @@ fn get_crc(flags: &Flags, iter: &mut cvec::Iter<u8>) -> Option<u16> {
 
 #[cfg(test)]
 mod parse_header_tests {
-    use super::{parse_header, Flags};
-    use cvec;
-
-    fn create_buf(raw: &[u8]) -> cvec::Buf {
-        let mut buffer = cvec::CVec::with_capacity(raw.len()).unwrap();
-        for &byte in raw.iter() {
-            buffer.push(byte);
-        }
-        buffer
-    }
+    use super::{parse_header, parse_header_at, Flags};
+    use error::GzError;
+    use cvec::from_slice;
 
     #[test]
     fn test_basic_header() {
@@ -182,7 +191,7 @@ mod parse_header_tests {
               0x1f, 0x8b, 0x08, 0x00, 0x12, 0x34, 0x56, 0x78,
               0x00, 0x07];
 
-        let buffer = create_buf(HEADER_BYTES);
+        let buffer = from_slice(HEADER_BYTES);
         let results = parse_header(&buffer).unwrap();
         assert_eq!(results.compression_method, 8);
         assert_eq!(results.flags, Flags {
@@ -211,8 +220,8 @@ mod parse_header_tests {
             0x00,
             // OS
             0x07,
-            // extra id + length + extra
-            0x41, 0x70, 0x00, 0x04, 0x12, 0x34, 0x56, 0x78,
+            // extra length + subfield id + subfield length + data
+            0x08, 0x00, 0x41, 0x70, 0x04, 0x00, 0x12, 0x34, 0x56, 0x78,
             // name
             0x41, 0x42, 0x43, 0x44, 0x45, 0x00,
             // comment
@@ -220,7 +229,7 @@ mod parse_header_tests {
             // CRC
             0x00, 0x01];
 
-        let buffer = create_buf(HEADER_BYTES);
+        let buffer = from_slice(HEADER_BYTES);
         let results = parse_header(&buffer).unwrap();
         assert_eq!(results.compression_method, 8);
         assert_eq!(results.flags, Flags {
@@ -234,7 +243,7 @@ mod parse_header_tests {
         assert_eq!(results.fname, Some("ABCDE".to_string()));
         assert_eq!(results.comment, Some("AAAAAA".to_string()));
         assert_eq!(results.crc, Some(1));
-        assert_eq!(results.header_len, 33);
+        assert_eq!(results.header_len, 35);
     }
 
     #[test]
@@ -259,7 +268,7 @@ mod parse_header_tests {
             // CRC
             0x00, 0x01];
 
-        let buffer = create_buf(HEADER_BYTES);
+        let buffer = from_slice(HEADER_BYTES);
         let results = parse_header(&buffer).unwrap();
         assert_eq!(results.compression_method, 8);
         assert_eq!(results.flags, Flags {
@@ -276,20 +285,45 @@ mod parse_header_tests {
         assert_eq!(results.header_len, 25);
     }
 
+    #[test]
+    fn test_header_at_offset() {
+        static HEADER_BYTES: &'static [u8] = &[
+              // tail end of a previous member
+              0x00, 0x00, 0x00,
+              0x1f, 0x8b, 0x08, 0x08, 0x12, 0x34, 0x56, 0x78,
+              0x00, 0x03, 0x41, 0x42, 0x00];
+
+        let buffer = from_slice(HEADER_BYTES);
+        assert_eq!(parse_header(&buffer), Err(GzError::BadMagic));
+        let results = parse_header_at(&buffer, 3).unwrap();
+        assert_eq!(results.fname, Some("AB".to_string()));
+        assert_eq!(results.os, 3);
+        assert_eq!(results.header_len, 13);
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        static HEADER_BYTES: &'static [u8] = &[
+              0x1f, 0x8b, 0x08, 0x08, 0x12, 0x34, 0x56, 0x78,
+              0x00, 0x03, 0x41, 0x42];
+        let buffer = from_slice(HEADER_BYTES);
+        assert_eq!(parse_header(&buffer), Err(GzError::TruncatedHeader));
+    }
+
     #[test]
     fn test_invalid_header() {
         // Magic bytes are wrong
         static HEADER_BYTES: &'static [u8] = &[
               0x1f, 0x8c, 0x08, 0x00, 0x12, 0x34, 0x56, 0x78,
               0x00, 0x07];
-        let buffer = create_buf(HEADER_BYTES);
-        assert_eq!(parse_header(&buffer), None);
+        let buffer = from_slice(HEADER_BYTES);
+        assert_eq!(parse_header(&buffer), Err(GzError::BadMagic));
         // Wrong compression type
         static HEADER_BYTES2: &'static [u8] = &[
               0x1f, 0x8b, 0x07, 0x00, 0x12, 0x34, 0x56, 0x78,
               0x00, 0x07];
-        let buffer = create_buf(HEADER_BYTES2);
-        assert_eq!(parse_header(&buffer), None);
+        let buffer = from_slice(HEADER_BYTES2);
+        assert_eq!(parse_header(&buffer), Err(GzError::UnsupportedMethod));
     }
 
 }
@@ -2,15 +2,23 @@
 
     Module: huffman
 
-    This modules contains code to create huffman trees from ranges
-    as defined in the gzip specification, and read values from a
-    bitstream as interpreted by a huffman tree.
+    This modules contains code to create huffman decoding tables from
+    ranges as defined in the gzip specification, and read values from
+    a bitstream as interpreted by such a table.
 
 "]
 use std;
-use self::HuffmanNode::{Node, Leaf};
+use std::cmp;
+use self::TableEntry::{Invalid, Symbol, Subtable};
+use error::{GzError, GzResult};
 use gz_reader::GzBitReader;
 
+// how many bits the first lookup resolves; longer codes continue in a
+// subtable. 9 bits covers every literal of the fixed code.
+const ROOT_BITS: u32 = 9;
+// no deflate code is longer than this
+const MAX_CODE_LENGTH: u32 = 15;
+
 /////////////////////////////////////////////////////////////////////
 //                        Structs                                  //
 /////////////////////////////////////////////////////////////////////
@@ -34,24 +42,54 @@ pub struct TreeNode {
     pub label: usize
 }
 
+#[derive(Copy, Clone, Show, PartialEq)]
+enum TableEntry {
+    /// no code starts with these bits
+    Invalid,
+    /// the symbol these bits decode to, and the length of its code
+    Symbol(u16, u8),
+    /// the code is longer than the root table resolves, and continues
+    /// in the subtable at this offset, indexed by this many more bits
+    Subtable(u16, u8),
+}
+
+/// A Huffman code laid out for decoding with table lookups
+/// The stream holds codes most significant bit first, but we read it
+/// least significant bit first, so tables are indexed by the reversed
+/// code. Every index that starts with a code points to its symbol.
 #[derive(Show, PartialEq)]
-pub enum HuffmanNode {
-    Node(Option<Box<HuffmanNode>>, Option<Box<HuffmanNode>>),
-    Leaf(u32)
+pub struct HuffmanTable {
+    /// how many bits index the root table
+    root_bits: u32,
+    /// the root table, followed by all of the subtables
+    entries: Vec<TableEntry>,
 }
 
-impl HuffmanNode {
-    /// Traverse the Huffman Tree by reading sequential bytes
-    pub fn read(&self, stream: &mut GzBitReader) -> Option<u32> {
-        match self {
-            &Leaf(v) => Some(v),
-            &Node(ref left, ref right) => {
-                let target = match try_opt!(stream.next_bit()) {
-                    0 => try_ref_opt!(left),
-                    1 => try_ref_opt!(right),
-                    _ => { panic!("Bit greater than one, no bueno."); }
-                };
-                target.read(stream)
+impl HuffmanTable {
+    /// Decode the next symbol from the stream
+    pub fn read(&self, stream: &mut GzBitReader) -> GzResult<u32> {
+        let (bits, _) = stream.peek_bits(self.root_bits);
+        let mut lookup_bits = self.root_bits;
+        let mut entry = self.entries[bits as usize];
+        match entry {
+            Subtable(offset, sub_bits) => {
+                lookup_bits += sub_bits as u32;
+                let (bits, _) = stream.peek_bits(lookup_bits);
+                entry = self.entries[offset as usize + (bits >> self.root_bits as usize) as usize];
+            },
+            _ => {}
+        }
+        match entry {
+            Symbol(symbol, len) => {
+                try_or!(stream.consume_bits(len as u32), GzError::TruncatedData);
+                Ok(symbol as u32)
+            },
+            _ => {
+                // the bits past the end of the input read as zeroes, so
+                // a valid code may only look invalid for lack of input
+                try_or!(stream.consume_bits(lookup_bits), GzError::TruncatedData);
+                // an incomplete code leaves some bit sequences unused
+                Err(GzError::BadHuffmanTable)
             }
         }
     }
@@ -61,16 +99,19 @@ impl HuffmanNode {
 //                     Building the tree                           //
 /////////////////////////////////////////////////////////////////////
 
-/// Build the Huffman Tree from a set of Huffman Ranges
-pub fn build_huffman_tree(ranges: &[HuffmanRange]) -> Option<HuffmanNode> {
-    let max_bit_length: usize = try_opt!(ranges.iter()
-                                         .map(|x| x.bit_length)
-                                         .max()) as usize;
+/// Build the Huffman decoding table from a set of Huffman Ranges
+pub fn build_huffman_table(ranges: &[HuffmanRange]) -> GzResult<HuffmanTable> {
+    let max_bit_length: usize = try_or!(ranges.iter()
+                                        .map(|x| x.bit_length)
+                                        .max(), GzError::BadHuffmanTable) as usize;
+    if max_bit_length > MAX_CODE_LENGTH as usize {
+        return Err(GzError::BadHuffmanTable);
+    }
     let bl_count = count_bitlengths(ranges, max_bit_length);
+    try!(check_lengths(&bl_count));
     let mut next_code = compute_first_codes(&bl_count);
-    let table: Vec<TreeNode> = compute_code_table(&mut next_code, ranges);
-    let tree: HuffmanNode = build_tree(&table);
-    Some(tree)
+    let codes: Vec<TreeNode> = compute_code_table(&mut next_code, ranges);
+    Ok(build_table(&codes, cmp::max(cmp::min(max_bit_length as u32, ROOT_BITS), 1)))
 }
 
 /// determine number of codes of each bit-length
@@ -233,77 +274,171 @@ mod compute_code_table_tests {
     }
 }
 
-/// Create the Huffman tree from the code table
-fn build_tree(code_table: &Vec<TreeNode>) -> HuffmanNode {
-    let mut root = Node(None, None);
-    for t_node in code_table.iter() {
-        let bits = t_node.bits;
-        let len = (t_node.len - 1) as isize;
-        let label = t_node.label;
-        make_tree(&mut root, bits, len, label);
+/// Make sure the bit lengths don't describe more codes than fit,
+/// which would give some of them the same bits
+fn check_lengths(bl_count: &Vec<u32>) -> GzResult<()> {
+    // how many codes of the current length are still free
+    let mut left: u32 = 1;
+    for &count in bl_count.iter() {
+        left <<= 1;
+        if count > left {
+            return Err(GzError::BadHuffmanTable);
+        }
+        left -= count;
     }
-    root
+    Ok(())
 }
 
 #[cfg(test)]
-mod build_tree_tests {
-    use super::{build_tree, TreeNode};
-    use super::HuffmanNode::{Node, Leaf};
+mod check_lengths_tests {
+    use super::check_lengths;
+    use error::GzError;
 
     #[test]
-    fn test_build_tree() {
-        let input = vec![TreeNode {
-            len: 4,
-            bits: 5, // 0101
-            label: 0
-        }];
-        assert_eq!(build_tree(&input), Node(
-            Some(box Node(
-                    None,
-                    Some(box Node(
-                            Some(box Node(None,
-                                      Some(box Leaf(0)))),
-                            None)))),
-            None));
+    fn test_complete_and_incomplete() {
+        assert_eq!(check_lengths(&vec![0, 0, 0, 7, 8, 12]), Ok(()));
+        assert_eq!(check_lengths(&vec![1]), Ok(()));
+        assert_eq!(check_lengths(&vec![1, 2]), Ok(()));
+    }
 
+    #[test]
+    fn test_oversubscribed() {
+        assert_eq!(check_lengths(&vec![3]), Err(GzError::BadHuffmanTable));
+        assert_eq!(check_lengths(&vec![1, 3]), Err(GzError::BadHuffmanTable));
     }
 }
 
-/// Helper function for build_tree
-fn make_tree(tree: &mut HuffmanNode, bits: usize, len: isize, label: usize) {
-    match tree {
-        &mut Leaf(_) => {
-            panic!("This shouldn't have happened.");
-        },
-        &mut Node(ref mut left, ref mut right) => {
-            match get_bit(bits, len as usize) {
-                0 => { make_tree_side(left, bits, len - 1, label); },
-                1 => { make_tree_side(right, bits, len - 1, label); },
-                _ => { panic!("A bit was greater than 1, this is bad."); }
+/// Lay out the codes from the code table for lookups root_bits at a time
+fn build_table(code_table: &Vec<TreeNode>, root_bits: u32) -> HuffmanTable {
+    let root_size = 1 << root_bits as usize;
+    let mut entries: Vec<TableEntry> = std::iter::repeat(Invalid).take(root_size).collect();
+
+    // codes longer than root_bits are grouped by their first root_bits,
+    // each group getting a subtable wide enough for its longest code
+    let mut sub_bits: Vec<u32> = std::iter::repeat(0).take(root_size).collect();
+    for t_node in code_table.iter() {
+        if t_node.len as u32 > root_bits {
+            let prefix = reverse_bits(t_node.bits, t_node.len) & (root_size - 1);
+            sub_bits[prefix] = cmp::max(sub_bits[prefix], t_node.len as u32 - root_bits);
+        }
+    }
+    for prefix in (0 .. root_size) {
+        if sub_bits[prefix] > 0 {
+            entries[prefix] = Subtable(entries.len() as u16, sub_bits[prefix] as u8);
+            let sub_size = 1 << sub_bits[prefix] as usize;
+            entries.extend(std::iter::repeat(Invalid).take(sub_size));
+        }
+    }
+
+    for t_node in code_table.iter() {
+        let symbol = Symbol(t_node.label as u16, t_node.len as u8);
+        let code = reverse_bits(t_node.bits, t_node.len);
+        let (start, table_size, len) = if t_node.len as u32 <= root_bits {
+            (code, root_size, t_node.len)
+        } else {
+            match entries[code & (root_size - 1)] {
+                Subtable(offset, bits) => {
+                    (offset as usize + (code >> root_bits as usize),
+                     offset as usize + (1 << bits as usize),
+                     t_node.len - root_bits as usize)
+                },
+                _ => { panic!("Subtables were made for every long code."); }
             }
+        };
+        // every index that starts with the code, whatever bits follow
+        let mut index = start;
+        while index < table_size {
+            entries[index] = symbol;
+            index += 1 << len;
         }
     }
-}
 
-/// Make one side of the tree
-fn make_tree_side(t_side: &mut Option<Box<HuffmanNode>>, bits: usize, len: isize, value: usize) {
-    match t_side {
-        &mut None => { *t_side = Some(box make_new_tree(bits, len, value)); },
-        &mut Some(ref mut t) => { make_tree(&mut **t, bits, len, value); },
-    };
+    HuffmanTable {
+        root_bits: root_bits,
+        entries: entries
+    }
 }
 
-/// Create a new HuffmanNode based on the next set of bits to read
-fn make_new_tree(bits: usize, len: isize, value: usize) -> HuffmanNode {
-    if len < 0 {
-        Leaf(value as u32)
-    } else {
-        match get_bit(bits, len as usize) {
-            0 => Node(Some(box make_new_tree(bits, len - 1, value)), None),
-            1 => Node(None, Some(box make_new_tree(bits, len - 1, value))),
-            _ => { panic!("A bit was greater than 1, this is bad."); }
+#[cfg(test)]
+mod build_table_tests {
+    use super::{build_table, build_huffman_table, HuffmanRange, TreeNode};
+    use super::TableEntry::{Invalid, Symbol, Subtable};
+    use gz_reader::GzBitReader;
+    use cvec::CVec;
+    use error::GzError;
+
+    #[test]
+    fn test_build_table() {
+        // 0 -> A, 10 -> B, 11 -> C
+        let input = vec![TreeNode { len: 1, bits: 0, label: 0 },
+                         TreeNode { len: 2, bits: 2, label: 1 },
+                         TreeNode { len: 2, bits: 3, label: 2 }];
+        let table = build_table(&input, 2);
+        // indexed by the bits as they come out of the stream
+        assert_eq!(table.entries, vec![Symbol(0, 1), Symbol(1, 2),
+                                       Symbol(0, 1), Symbol(2, 2)]);
+    }
+
+    #[test]
+    fn test_subtable() {
+        // 0 -> A, 10 -> B, 110 -> C, 111 -> D, looked up one bit at a time
+        let input = vec![TreeNode { len: 1, bits: 0, label: 0 },
+                         TreeNode { len: 2, bits: 2, label: 1 },
+                         TreeNode { len: 3, bits: 6, label: 2 },
+                         TreeNode { len: 3, bits: 7, label: 3 }];
+        let table = build_table(&input, 1);
+        assert_eq!(table.entries, vec![Symbol(0, 1), Subtable(2, 2),
+                                       Symbol(1, 2), Symbol(2, 3),
+                                       Symbol(1, 2), Symbol(3, 3)]);
+    }
+
+    #[test]
+    fn test_incomplete() {
+        // a single one bit code, as deflate allows for distances
+        let input = vec![TreeNode { len: 1, bits: 0, label: 5 }];
+        let table = build_table(&input, 1);
+        assert_eq!(table.entries, vec![Symbol(5, 1), Invalid]);
+    }
+
+    #[test]
+    fn test_read() {
+        // A -> 0, B -> 10, C -> 110, D -> 1110, E -> 1111
+        let ranges = vec![HuffmanRange { end: 0, bit_length: 1 },
+                          HuffmanRange { end: 1, bit_length: 2 },
+                          HuffmanRange { end: 2, bit_length: 3 },
+                          HuffmanRange { end: 4, bit_length: 4 }];
+        let table = build_huffman_table(ranges.as_slice()).unwrap();
+        // E D C B A, then A cut short
+        let mut bytes = CVec::with_capacity(2).unwrap();
+        bytes.push(0x7f);
+        bytes.push(0x0b);
+        let mut reader = GzBitReader::new(bytes.iter()).unwrap();
+        for &symbol in [4, 3, 2, 1, 0, 0, 0].iter() {
+            assert_eq!(table.read(&mut reader), Ok(symbol));
         }
+        assert_eq!(table.read(&mut reader), Err(GzError::TruncatedData));
+        assert!(reader.exhausted());
+    }
+
+    #[test]
+    fn test_unused_code() {
+        let ranges = vec![HuffmanRange { end: 0, bit_length: 2 }];
+        let table = build_huffman_table(ranges.as_slice()).unwrap();
+        let mut bytes = CVec::with_capacity(1).unwrap();
+        bytes.push(0x01);
+        let mut reader = GzBitReader::new(bytes.iter()).unwrap();
+        assert_eq!(table.read(&mut reader), Err(GzError::BadHuffmanTable));
+        assert!(!reader.exhausted());
+    }
+}
+
+/// Reverse the order of the lowest len bits of code
+fn reverse_bits(code: usize, len: usize) -> usize {
+    let mut reversed = 0;
+    for i in (0 .. len) {
+        reversed |= get_bit(code, i) << (len - 1 - i);
     }
+    reversed
 }
 
 /// gets 'index' bit of input
@@ -10,8 +10,9 @@
 "]
 use gz_reader::GzBitReader;
 use cvec::Buf;
-use huffman::{HuffmanNode, HuffmanRange};
-use huffman::build_huffman_tree;
+use error::{GzError, GzResult};
+use huffman::{HuffmanTable, HuffmanRange};
+use huffman::build_huffman_table;
 
 // These constants are defined by the GZIP standard
 static CODE_LENGTH_OFFSETS: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
@@ -34,13 +35,13 @@ static FIXED_TREE_RANGES: [HuffmanRange; 4] = [
 /// Builds the first tree from a gzip block header, used to encode
 /// the following literals and distance tree
 fn build_code_length_tree(stream: &mut GzBitReader, hclen: u32)
-    -> Option<HuffmanNode>
+    -> GzResult<HuffmanTable>
 {
     let mut code_length_ranges = Vec::new();
     let mut code_lengths = [0u32; 19];
 
     for i in 0 .. (hclen + 4) as usize {
-        code_lengths[CODE_LENGTH_OFFSETS[i]] = try_opt!(stream.read_bits(3));
+        code_lengths[CODE_LENGTH_OFFSETS[i]] = try_or!(stream.read_bits(3), GzError::TruncatedData);
     }
 
     // make these ranges for the huffman tree routine
@@ -53,37 +54,37 @@ fn build_code_length_tree(stream: &mut GzBitReader, hclen: u32)
         range.bit_length = code_lengths[i];
     }
     code_length_ranges.push(range.clone());
-    build_huffman_tree(code_length_ranges.as_slice())
+    build_huffman_table(code_length_ranges.as_slice())
 }
 
 /// Reads a huffman tree from a GzBitReader and returns two trees:
 /// the first is the literals tree, and the second is the distances tree
-fn read_huffman_tree(stream: &mut GzBitReader) -> Option<(HuffmanNode, HuffmanNode)> {
-    let hlit = try_opt!(stream.read_bits(5));
-    let hdist = try_opt!(stream.read_bits(5));
-    let hclen = try_opt!(stream.read_bits(4)); // max of 15
+fn read_huffman_tree(stream: &mut GzBitReader) -> GzResult<(HuffmanTable, HuffmanTable)> {
+    let hlit = try_or!(stream.read_bits(5), GzError::TruncatedData);
+    let hdist = try_or!(stream.read_bits(5), GzError::TruncatedData);
+    let hclen = try_or!(stream.read_bits(4), GzError::TruncatedData); // max of 15
 
-    let code_lengths_root = try_opt!(build_code_length_tree(stream, hclen));
+    let code_lengths_root = try!(build_code_length_tree(stream, hclen));
 
     // now we read the literal/length alphabet, encoded with the huffman tree
     // we just built
     let mut i = 0;
     let mut alphabet: Vec<u32> = Vec::new();
     while i < (hlit + hdist + 258) {
-        let code = try_opt!(code_lengths_root.read(stream));
+        let code = try!(code_lengths_root.read(stream));
         if code > 15 {
             let mut repeat_length = {
                 if code == 16 {
-                    try_opt!(stream.read_bits(2)) + 3
+                    try_or!(stream.read_bits(2), GzError::TruncatedData) + 3
                 } else if code == 17 {
-                    try_opt!(stream.read_bits(3)) + 3
+                    try_or!(stream.read_bits(3), GzError::TruncatedData) + 3
                 } else if code == 18 {
-                    try_opt!(stream.read_bits(7)) + 11
-                } else { panic!("invalid code"); }
+                    try_or!(stream.read_bits(7), GzError::TruncatedData) + 11
+                } else { return Err(GzError::BadHuffmanTable); }
             } as i32;
             while repeat_length > 0 {
                 if code == 16 {
-                    let prev = *try_opt!(alphabet.get((i-1) as usize));
+                    let prev = *try_or!(alphabet.get((i-1) as usize), GzError::BadHuffmanTable);
                     alphabet.push(prev);
                 } else {
                     alphabet.push(0);
@@ -121,96 +122,240 @@ fn read_huffman_tree(stream: &mut GzBitReader) -> Option<(HuffmanNode, HuffmanNo
     }
     distances_ranges.push(range);
 
-    let literals_root = try_opt!(build_huffman_tree(literals_ranges.as_slice()));
-    let distances_root = try_opt!(build_huffman_tree(distances_ranges.as_slice()));
-    Some((literals_root, distances_root))
+    let literals_root = try!(build_huffman_table(literals_ranges.as_slice()));
+    let distances_root = try!(build_huffman_table(distances_ranges.as_slice()));
+    Ok((literals_root, distances_root))
 }
 
-/// Create the fixed HuffmanTree (per the spec)
-fn build_fixed_huffman_tree() -> Option<HuffmanNode> {
-    build_huffman_tree(&FIXED_TREE_RANGES)
+/// Create the fixed Huffman table (per the spec)
+pub fn build_fixed_huffman_table() -> GzResult<HuffmanTable> {
+    build_huffman_table(&FIXED_TREE_RANGES)
 }
 
 /////////////////////////////////////////////////////////////////////
 //                    Inflating the data                           //
 /////////////////////////////////////////////////////////////////////
 
+/// A deflate block, as described by its block header
+pub enum Block {
+    /// uncompressed block, with this many bytes still to copy
+    Stored(u32),
+    /// block encoded with the fixed Huffman tree
+    Fixed,
+    /// block encoded with the literals and distances trees that were
+    /// sent along with it
+    Dynamic(HuffmanTable, HuffmanTable),
+}
+
+/// Read the header of the next block: whether it is the last one, and
+/// everything needed to decode it
+pub fn read_block_header(stream: &mut GzBitReader) -> GzResult<(bool, Block)> {
+    let last_block = try_or!(stream.next_bit(), GzError::TruncatedData) == 1;
+    let block = match try_or!(stream.read_bits(2), GzError::TruncatedData) {
+        0x00 => {
+            // uncompressed block
+            Block::Stored(try!(read_stored_len(stream)))
+        },
+        0x01 => {
+            // fixed tree
+            Block::Fixed
+        },
+        0x02 => {
+            // dynamic tree
+            let (literals_tree, distances_tree) = try!(read_huffman_tree(stream));
+            Block::Dynamic(literals_tree, distances_tree)
+        },
+        _ => {
+            // reserved block type
+            return Err(GzError::BadBlockType);
+        }
+    };
+    Ok((last_block, block))
+}
+
+/// A stored block starts at the next byte boundary with LEN and its
+/// one's complement NLEN, and is followed by LEN raw bytes
+fn read_stored_len(stream: &mut GzBitReader) -> GzResult<u32> {
+    stream.align_to_byte();
+    let len = try_or!(stream.read_bits(16), GzError::TruncatedData);
+    let nlen = try_or!(stream.read_bits(16), GzError::TruncatedData);
+    if len != !nlen & 0xffff {
+        return Err(GzError::BadStoredLength);
+    }
+    Ok(len)
+}
+
+/// Decode a single literal or length/distance pair into out
+/// Nothing is written to out unless the whole symbol could be read
+/// Returns Ok(true) once the end of block code has been read
+pub fn inflate_symbol(stream: &mut GzBitReader,
+                      literals_root: &HuffmanTable,
+                      distances_root: Option<&HuffmanTable>,
+                      out: &mut Buf)
+        -> GzResult<bool> {
+    let code = try!(literals_root.read(stream));
+    if code >= 286 {
+        return Err(GzError::InvalidCode);
+    }
+    if code < 256 {
+        try!(out.try_push(code as u8));
+        return Ok(false);
+    } else if code == 256 { //stop code
+        return Ok(true);
+    }
+
+    let length = if code < 265 {
+        code - 254
+    } else {
+        if code < 285 {
+            let extra_bits = try_or!(stream.read_bits((code - 261) / 4), GzError::TruncatedData);
+            extra_bits + EXTRA_LENGTH_ADDEND[((code - 266) + 1) as usize] as u32
+        } else { 258 }
+    };
+
+    // now, the length is followed by the distance back
+    let mut dist = match distances_root {
+        None => {
+            try_or!(stream.read_bits_rev(5), GzError::TruncatedData) // hardcoded distance
+        },
+        Some(distance_tree) => {
+            try!(distance_tree.read(stream))
+        }
+    };
+
+    if dist > 29 {
+        return Err(GzError::InvalidCode);
+    }
+    if dist > 3 {
+        let extra_dist = try_or!(stream.read_bits((dist - 2) / 2), GzError::TruncatedData);
+        dist = extra_dist + EXTRA_DIST_ADDEND[(dist - 4) as usize] as u32;
+    }
+    // dist is one less than the real distance, and can't reach back
+    // past the start of the output
+    if dist as usize >= out.len() {
+        return Err(GzError::InvalidDistance);
+    }
+    try!(out.copy_back_pointer(dist as usize, length as usize));
+    Ok(false)
+}
+
 /// Inflate the data segment based on the given Huffman Trees
 /// Effect: the output will be stored in out
-/// Success on a Some(()) result, failure on a None result
+/// Success on an Ok(()) result, the reason for failure otherwise
 fn inflate_huffman_codes(stream: &mut GzBitReader,
-                         literals_root: &HuffmanNode,
-                         distances_root: Option<&HuffmanNode>,
+                         literals_root: &HuffmanTable,
+                         distances_root: Option<&HuffmanTable>,
                          out: &mut Buf)
-        -> Option<()> {
-    while let Some(code) = literals_root.read(stream) {
-        if code >= 286 {
-            return None;
-        }
-        if code < 256 {
-            out.push(code as u8);
-        } else if code == 256 { //stop code
-            break;
-        } else if code > 256 {
-            let length = if code < 265 {
-                code - 254
-            } else {
-                if code < 285 {
-                    let extra_bits = try_opt!(stream.read_bits((code - 261) / 4));
-                    extra_bits + EXTRA_LENGTH_ADDEND[((code - 266) + 1) as usize] as u32
-                } else { 258 }
-            };
-
-            // now, the length is followed by the distance back
-            let mut dist = match distances_root {
-                None => {
-                    try_opt!(stream.read_bits_rev(5)) // hardcoded distance
-                },
-                Some(distance_tree) => {
-                    try_opt!(distance_tree.read(stream))
-                }
-            };
-
-            if dist > 3 {
-                let extra_dist = try_opt!(stream.read_bits((dist - 2) / 2));
-                dist = extra_dist + EXTRA_DIST_ADDEND[(dist - 4) as usize] as u32;
+        -> GzResult<()> {
+    while !try!(inflate_symbol(stream, literals_root, distances_root, out)) {}
+    Ok(())
+}
 
-            }
-            out.copy_back_pointer(dist as usize, length as usize);
-        }
+/// Copy the len bytes of a stored block into the out buffer
+fn inflate_stored(stream: &mut GzBitReader, len: u32, out: &mut Buf) -> GzResult<()> {
+    for _ in (0 .. len) {
+        let byte = try_or!(stream.read_bits(8), GzError::TruncatedData);
+        try!(out.try_push(byte as u8));
     }
-    Some(())
+    Ok(())
 }
 
 /// Inflate the given compressed stream into the out buffer
 /// inflate() should be called with a GzBitReader starting at the head
 /// of the first block
-pub fn inflate(stream: &mut GzBitReader, out: &mut Buf) -> Option<()> {
-    let fixed_tree = try_opt!(build_fixed_huffman_tree());
-    let mut last_block = 0;
-    while { last_block == 0 } {
-        last_block = try_opt!(stream.next_bit());
-        let block_format = try_opt!(stream.read_bits(2));
-        match block_format {
-            0x00 => {
-                // uncompressed block type, not supported
-                return None;
+pub fn inflate(stream: &mut GzBitReader, out: &mut Buf) -> GzResult<()> {
+    let fixed_tree = try!(build_fixed_huffman_table());
+    loop {
+        let (last_block, block) = try!(read_block_header(stream));
+        match block {
+            Block::Stored(len) => {
+                try!(inflate_stored(stream, len, out));
             },
-            0x01 => {
-                // fixed tree
-                try_opt!(inflate_huffman_codes(stream, &fixed_tree, None, out));
+            Block::Fixed => {
+                try!(inflate_huffman_codes(stream, &fixed_tree, None, out));
             },
-            0x02 => {
-                // dynamic tree
-                let (literals_tree, distances_tree) = try_opt!(read_huffman_tree(stream));
-                try_opt!(inflate_huffman_codes(stream, &literals_tree, Some(&distances_tree), out));
-            }
-            _ => {
-                println!("unsupported block");
-                // unsupported block type
-                return None;
+            Block::Dynamic(ref literals_tree, ref distances_tree) => {
+                try!(inflate_huffman_codes(stream, literals_tree, Some(distances_tree), out));
             }
         }
+        if last_block {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod inflate_tests {
+    use super::inflate;
+    use gz_reader::GzBitReader;
+    use cvec::{from_slice, CVec};
+    use error::{GzError, GzResult};
+
+    fn inflate_bytes(raw: &[u8]) -> GzResult<Vec<u8>> {
+        let input = from_slice(raw);
+        let mut out = CVec::with_capacity(raw.len()).unwrap();
+        let mut reader = GzBitReader::new(input.iter()).unwrap();
+        try!(inflate(&mut reader, &mut out));
+        Ok(out.iter().map(|&b| b).collect())
+    }
+
+    #[test]
+    fn test_stored_block() {
+        static STORED: &'static [u8] = &[
+            // final stored block, LEN = 6, NLEN = !6
+            0x01, 0x06, 0x00, 0xf9, 0xff,
+            0x73, 0x74, 0x6f, 0x72, 0x65, 0x64];
+        assert_eq!(inflate_bytes(STORED), Ok(b"stored".to_vec()));
+    }
+
+    #[test]
+    fn test_stored_block_after_fixed() {
+        static MIXED: &'static [u8] = &[
+            // fixed block with "abcabcabc", then an empty stored block
+            // that starts in the middle of a byte
+            0x4a, 0x4c, 0x4a, 0x4e, 0x04, 0x23, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff,
+            // final stored block with "hello"
+            0x01, 0x05, 0x00, 0xfa, 0xff, 0x68, 0x65, 0x6c, 0x6c, 0x6f];
+        assert_eq!(inflate_bytes(MIXED), Ok(b"abcabcabchello".to_vec()));
+    }
+
+    #[test]
+    fn test_stored_bad_complement() {
+        static BAD: &'static [u8] = &[
+            0x01, 0x06, 0x00, 0xf8, 0xff,
+            0x73, 0x74, 0x6f, 0x72, 0x65, 0x64];
+        assert_eq!(inflate_bytes(BAD), Err(GzError::BadStoredLength));
+    }
+
+    #[test]
+    fn test_reserved_block_type() {
+        // final block with BTYPE = 11
+        assert_eq!(inflate_bytes(&[0x07, 0x00]), Err(GzError::BadBlockType));
+    }
+
+    #[test]
+    fn test_distance_before_start() {
+        // fixed block that opens with a length/distance pair
+        assert_eq!(inflate_bytes(&[0x03, 0x02, 0x00]), Err(GzError::InvalidDistance));
+    }
+
+    #[test]
+    fn test_output_limit() {
+        // printf 'a%.0s' {1..1000} | raw deflate
+        static RUN: &'static [u8] = &[
+            0x4b, 0x4c, 0x1c, 0x05, 0xa3, 0x60, 0x14, 0x0c, 0x77, 0x00, 0x00];
+        let input = from_slice(RUN);
+        let mut out = CVec::with_capacity(0).unwrap();
+        out.set_max_len(999);
+        let mut reader = GzBitReader::new(input.iter()).unwrap();
+        assert_eq!(inflate(&mut reader, &mut out), Err(GzError::LimitExceeded));
+        assert!(out.len() < 999);
+
+        out.clear();
+        out.set_max_len(1000);
+        let mut reader = GzBitReader::new(input.iter()).unwrap();
+        assert_eq!(inflate(&mut reader, &mut out), Ok(()));
+        assert_eq!(out.len(), 1000);
     }
-    Some(())
 }
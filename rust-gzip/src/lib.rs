@@ -17,20 +17,114 @@
 "]
 
 extern crate libc;
+#[cfg(test)]
+extern crate test;
 
-use libc::{c_int, c_uchar, c_void};
-use std::ptr::null;
-use cvec::CVec;
+use libc::{c_char, c_int, c_uchar, c_void, size_t};
+use std::cell::Cell;
+use std::ptr::{null, null_mut};
+use std::{mem, slice};
+use cvec::{CVec, Buf};
+use error::{GzError, GzResult};
+use stream::GzStream;
+use members::Members;
 
 #[macro_use]
 mod macros;
 mod cvec;
+mod error;
+mod limits;
 mod gz;
 mod header;
 mod crc32;
 mod inflate;
 mod huffman;
 mod gz_reader;
+mod stream;
+mod zip;
+mod members;
+mod lzw;
+mod tar;
+mod bzip2;
+mod format;
+mod xxhash;
+mod zstd;
+mod lz4;
+mod crc64;
+mod lzma;
+mod filter;
+mod xz;
+mod adler32;
+mod zlib;
+mod brotli;
+mod crc32c;
+mod snappy;
+mod lzo;
+mod lzop;
+mod lzip;
+mod sevenzip;
+mod ar;
+mod cpio;
+mod rpm;
+
+/////////////////////////////////////////////////////////////////////
+//                          Errors                                 //
+/////////////////////////////////////////////////////////////////////
+
+// the reason the last call on this thread failed, 0 if it didn't
+thread_local!(static LAST_ERROR: Cell<c_int> = Cell::new(0));
+
+fn set_last_error(err: GzError) {
+    LAST_ERROR.with(|last| last.set(err as c_int));
+}
+
+fn clear_last_error() {
+    LAST_ERROR.with(|last| last.set(0));
+}
+
+/// Why the last decompression call on this thread failed
+/// return 0 if it succeeded, otherwise one of the codes in rgzip.h
+#[no_mangle]
+pub extern "C" fn decompress_error_code() -> c_int {
+    LAST_ERROR.with(|last| last.get())
+}
+
+/// A description of an error code, for printing
+/// The string is static and must not be freed
+#[no_mangle]
+pub extern "C" fn decompress_error_message(code: c_int) -> *const c_char {
+    let message: &'static [u8] = match GzError::from_code(code) {
+        Some(err) => err.message(),
+        None if code == 0 => b"no error\0",
+        None => b"unknown error\0"
+    };
+    message.as_ptr() as *const c_char
+}
+
+/// Limit how much any one buffer may decompress to, for every thread:
+/// max_output bytes in all, max_ratio times the compressed size once
+/// past the first 16 MiB, and max_preallocation bytes allocated before
+/// any output is produced. 0 means no limit. Decompression that would
+/// go past them fails with RGZIP_LIMIT_EXCEEDED.
+#[no_mangle]
+pub extern "C" fn decompress_set_limits(max_output: size_t,
+                                        max_ratio: size_t,
+                                        max_preallocation: size_t) {
+    limits::set_limits(max_output as usize, max_ratio as usize, max_preallocation as usize);
+}
+
+/// What the buffer holds, as one of the RGZIP_FORMAT codes in rgzip.h
+/// return 0 (RGZIP_FORMAT_NONE) if it's nothing we can read
+#[no_mangle]
+pub extern "C" fn decompress_detect_format(buf: *const c_void, buf_len: size_t) -> c_int {
+    match unsafe { CVec::from_raw_buf(buf as *const c_uchar, buf_len as usize) } {
+        Some(buffer) => match format::detect(&buffer) {
+            Some(format) => format as c_int,
+            None => 0
+        },
+        None => 0
+    }
+}
 
 /////////////////////////////////////////////////////////////////////
 //                   Decompression interface                       //
@@ -38,18 +132,333 @@ mod gz_reader;
 
 /// The main decompression function
 /// return a null pointer on failure, let the caller clean up
+/// decompress_error_code() tells why it failed
 
 #[no_mangle]
 pub extern "C" fn decompress_gzip_to_heap(buf: *const c_void,
-                                          buf_len: c_int,
-                                          decompressed_len: *mut c_int)
+                                          buf_len: size_t,
+                                          decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, gz::decompress_gz)
+}
+
+/// Decompress a file made by UNIX compress (.Z) the same way
+#[no_mangle]
+pub extern "C" fn decompress_lzw_to_heap(buf: *const c_void,
+                                         buf_len: size_t,
+                                         decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, lzw::decompress_lzw)
+}
+
+/// Decompress a bzip2 file the same way
+#[no_mangle]
+pub extern "C" fn decompress_bzip2_to_heap(buf: *const c_void,
+                                           buf_len: size_t,
+                                           decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, bzip2::decompress_bzip2)
+}
+
+/// Decompress a zstd file the same way
+#[no_mangle]
+pub extern "C" fn decompress_zstd_to_heap(buf: *const c_void,
+                                          buf_len: size_t,
+                                          decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, zstd::decompress_zstd)
+}
+
+/// Decompress an LZ4 file the same way
+#[no_mangle]
+pub extern "C" fn decompress_lz4_to_heap(buf: *const c_void,
+                                         buf_len: size_t,
+                                         decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, lz4::decompress_lz4)
+}
+
+/// Decompress an xz file the same way
+#[no_mangle]
+pub extern "C" fn decompress_xz_to_heap(buf: *const c_void,
+                                        buf_len: size_t,
+                                        decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, xz::decompress_xz)
+}
+
+/// Decompress a .lzma file the same way
+#[no_mangle]
+pub extern "C" fn decompress_lzma_to_heap(buf: *const c_void,
+                                          buf_len: size_t,
+                                          decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, lzma::decompress_lzma)
+}
+
+/// Decompress a zlib stream the same way
+#[no_mangle]
+pub extern "C" fn decompress_zlib_to_heap(buf: *const c_void,
+                                          buf_len: size_t,
+                                          decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, zlib::decompress_zlib)
+}
+
+/// Decompress a zlib stream made with the preset dictionary dict
+/// dict is only used if the stream's header asks for it
+#[no_mangle]
+pub extern "C" fn decompress_zlib_dict_to_heap(buf: *const c_void,
+                                               buf_len: size_t,
+                                               dict: *const c_void,
+                                               dict_len: size_t,
+                                               decompressed_len: *mut size_t)
         -> *mut c_void {
-    let in_vec = try_bail!(unsafe { CVec::from_raw_buf(buf as *const c_uchar, buf_len as usize)});
-    let out_vec = try_bail!(gz::decompress_gz(in_vec));
+    clear_last_error();
+    let in_vec = try_bail!(unsafe { CVec::from_raw_buf(buf as *const c_uchar, buf_len as usize)}
+                           .ok_or(GzError::TruncatedHeader));
+    let dict_ptr = dict as *const u8;
+    let dictionary: &[u8] = if dict.is_null() || dict_len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_buf(&dict_ptr, dict_len as usize) }
+    };
+    let out_vec = try_bail!(zlib::decompress_zlib_with_dictionary(in_vec, dictionary));
     unsafe {
         let (out_ptr, out_size) = out_vec.into_raw_buf();
-        *decompressed_len = out_size as c_int;
+        *decompressed_len = out_size as size_t;
         out_ptr as *mut c_void
     }
 }
 
+/// Decompress a Brotli stream the same way
+/// Brotli has no magic number, so the caller has to know what it holds
+#[no_mangle]
+pub extern "C" fn decompress_brotli_to_heap(buf: *const c_void,
+                                            buf_len: size_t,
+                                            decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, brotli::decompress_brotli)
+}
+
+/// Decompress a Snappy framed file the same way
+#[no_mangle]
+pub extern "C" fn decompress_snappy_to_heap(buf: *const c_void,
+                                            buf_len: size_t,
+                                            decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, snappy::decompress_snappy)
+}
+
+/// Decompress an lzop file the same way, every file in it one after
+/// another
+#[no_mangle]
+pub extern "C" fn decompress_lzop_to_heap(buf: *const c_void,
+                                          buf_len: size_t,
+                                          decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, lzop::decompress_lzop)
+}
+
+/// Decompress an lzip file the same way
+#[no_mangle]
+pub extern "C" fn decompress_lzip_to_heap(buf: *const c_void,
+                                          buf_len: size_t,
+                                          decompressed_len: *mut size_t)
+        -> *mut c_void {
+    decompress_to_heap(buf, buf_len, decompressed_len, lzip::decompress_lzip)
+}
+
+/// Run one of the decompressors on a C buffer, handing back a malloc'd one
+fn decompress_to_heap(buf: *const c_void,
+                      buf_len: size_t,
+                      decompressed_len: *mut size_t,
+                      decompress: fn(Buf) -> GzResult<Buf>)
+        -> *mut c_void {
+    clear_last_error();
+    // an empty buffer doesn't even hold a header
+    let in_vec = try_bail!(unsafe { CVec::from_raw_buf(buf as *const c_uchar, buf_len as usize)}
+                           .ok_or(GzError::TruncatedHeader));
+    let out_vec = try_bail!(decompress(in_vec));
+    unsafe {
+        let (out_ptr, out_size) = out_vec.into_raw_buf();
+        *decompressed_len = out_size as size_t;
+        out_ptr as *mut c_void
+    }
+}
+
+
+/////////////////////////////////////////////////////////////////////
+//                Incremental decompression interface              //
+/////////////////////////////////////////////////////////////////////
+
+/// Create a decoder for gzip data that arrives in pieces
+/// return a null pointer on failure
+#[no_mangle]
+pub extern "C" fn gzip_stream_new() -> *mut GzStream {
+    clear_last_error();
+    match GzStream::new() {
+        Ok(stream) => unsafe { mem::transmute(box stream) },
+        Err(err) => {
+            set_last_error(err);
+            null_mut()
+        }
+    }
+}
+
+/// Hand the decoder the next buf_len bytes of compressed input
+/// return 0 on success, -1 on failure
+#[no_mangle]
+pub extern "C" fn gzip_stream_feed(stream: *mut GzStream,
+                                   buf: *const c_void,
+                                   buf_len: c_int)
+        -> c_int {
+    clear_last_error();
+    if stream.is_null() || buf.is_null() || buf_len < 0 {
+        return -1;
+    }
+    let buf_ptr = buf as *const u8;
+    let input = unsafe { slice::from_raw_buf(&buf_ptr, buf_len as usize) };
+    match unsafe { (*stream).feed(input) } {
+        Ok(()) => 0,
+        Err(err) => {
+            set_last_error(err);
+            -1
+        }
+    }
+}
+
+/// Decompress up to out_len bytes into out
+/// return the number of bytes written, 0 once everything fed so far has
+/// been decompressed, or -1 if the input is corrupt (see
+/// decompress_error_code for why)
+#[no_mangle]
+pub extern "C" fn gzip_stream_read(stream: *mut GzStream,
+                                   out: *mut c_void,
+                                   out_len: c_int)
+        -> c_int {
+    clear_last_error();
+    if stream.is_null() || out.is_null() || out_len < 0 {
+        return -1;
+    }
+    let out_ptr = out as *mut u8;
+    let output = unsafe { slice::from_raw_mut_buf(&out_ptr, out_len as usize) };
+    match unsafe { (*stream).read(output) } {
+        Ok(count) => count as c_int,
+        Err(err) => {
+            set_last_error(err);
+            -1
+        }
+    }
+}
+
+/// To be called once all input has been fed and read
+/// return 0 if the input ended cleanly after a complete gzip member,
+/// -1 if it was cut short or corrupt
+#[no_mangle]
+pub extern "C" fn gzip_stream_finish(stream: *const GzStream) -> c_int {
+    clear_last_error();
+    if stream.is_null() {
+        return -1;
+    }
+    match unsafe { (*stream).finish() } {
+        Ok(()) => 0,
+        Err(err) => {
+            set_last_error(err);
+            -1
+        }
+    }
+}
+
+/// Free a decoder created by gzip_stream_new
+#[no_mangle]
+pub extern "C" fn gzip_stream_free(stream: *mut GzStream) {
+    if !stream.is_null() {
+        let _: Box<GzStream> = unsafe { mem::transmute(stream) };
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+//                    Archive member interface                     //
+/////////////////////////////////////////////////////////////////////
+
+/// Get ready to read the members of the gzip file or zip archive in buf,
+/// opening members that are archives or compressed files themselves up
+/// to max_depth inside one another
+/// buf must stay valid until the members are freed
+/// return a null pointer on failure
+#[no_mangle]
+pub extern "C" fn archive_members_open(buf: *const c_void, buf_len: size_t, max_depth: size_t)
+        -> *mut Members {
+    clear_last_error();
+    let buffer = match unsafe { CVec::from_raw_buf(buf as *const c_uchar, buf_len as usize) } {
+        Some(buffer) => buffer,
+        None => {
+            set_last_error(GzError::TruncatedHeader);
+            return null_mut();
+        }
+    };
+    match Members::open(buffer, max_depth as usize) {
+        Ok(members) => unsafe { mem::transmute(box members) },
+        Err(err) => {
+            set_last_error(err);
+            null_mut()
+        }
+    }
+}
+
+/// Move on to the next member
+/// return 1 if there is one, 0 once all have been read, or -1 if it
+/// can't be decompressed. Its name is still set then, and the members
+/// after it can still be read.
+#[no_mangle]
+pub extern "C" fn archive_members_next(members: *mut Members) -> c_int {
+    clear_last_error();
+    if members.is_null() {
+        return 0;
+    }
+    match unsafe { (*members).next() } {
+        Some(Ok(())) => 1,
+        Some(Err(err)) => {
+            set_last_error(err);
+            -1
+        },
+        None => 0
+    }
+}
+
+/// The nul-terminated name of the current member, which is empty if it
+/// has none, valid until the next call to archive_members_next
+#[no_mangle]
+pub extern "C" fn archive_member_name(members: *const Members) -> *const c_char {
+    if members.is_null() {
+        return null();
+    }
+    unsafe { (*members).c_name().as_ptr() as *const c_char }
+}
+
+/// The decompressed contents of the current member, valid until the next
+/// call to archive_members_next
+/// return a null pointer if it couldn't be decompressed
+#[no_mangle]
+pub extern "C" fn archive_member_data(members: *const Members, data_len: *mut size_t)
+        -> *const c_void {
+    if members.is_null() {
+        return null();
+    }
+    match unsafe { (*members).data() } {
+        Some(data) => unsafe {
+            *data_len = data.len() as size_t;
+            data.as_slice().as_ptr() as *const c_void
+        },
+        None => null()
+    }
+}
+
+/// Free the members opened by archive_members_open, leaving buf alone
+#[no_mangle]
+pub extern "C" fn archive_members_free(members: *mut Members) {
+    if !members.is_null() {
+        let _: Box<Members> = unsafe { mem::transmute(members) };
+    }
+}
@@ -0,0 +1,188 @@
+#[doc="
+
+    Module: limits
+
+    This bounds how much a single buffer may decompress to. The sizes
+    that headers and trailers record (gzip's ISIZE, a zip entry's
+    uncompressed size) can say anything, and a run of back references
+    can repeat the same bytes over and over, so without limits a file
+    of a few dozen bytes can have every worker thread trying to
+    allocate gigabytes. There are three limits, shared by all threads:
+    on the size of the output, on its size relative to the input, and
+    on how much is allocated up front on the word of a header.
+
+"]
+use std::cmp;
+use std::usize;
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use cvec::{CVec, Buf};
+use error::{GzError, GzResult};
+
+pub const DEFAULT_MAX_OUTPUT: usize = 1 << 30;
+// deflate can't do much better than 1032:1, so gzip, zip and zlib files
+// rarely come near this. xz, lzma, zstd, bzip2 and brotli go far past it
+// on very repetitive input, and files like that are only read while their
+// output stays under RATIO_GRACE, or if the caller raises the limit. A
+// limit per format wouldn't help: it would have to allow what the best of
+// them manage, which lets through any bomb made with those.
+pub const DEFAULT_MAX_RATIO: usize = 1000;
+pub const DEFAULT_MAX_PREALLOCATION: usize = 1 << 26;
+
+// output up to this size is allowed whatever the ratio, so that small
+// files of very repetitive text still decompress
+const RATIO_GRACE: usize = 1 << 24;
+
+// 0 until set_limits is called, and the defaults apply
+static MAX_OUTPUT: AtomicUsize = ATOMIC_USIZE_INIT;
+static MAX_RATIO: AtomicUsize = ATOMIC_USIZE_INIT;
+static MAX_PREALLOCATION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The limits decompression runs under
+#[derive(Copy, Clone, PartialEq, Show)]
+pub struct Limits {
+    /// most bytes one buffer may decompress to
+    pub max_output: usize,
+    /// most bytes of output per byte of input, past RATIO_GRACE
+    pub max_ratio: usize,
+    /// most bytes allocated before any output is produced
+    pub max_preallocation: usize,
+}
+
+impl Limits {
+    /// The limits set by set_limits, or the defaults
+    pub fn current() -> Limits {
+        Limits {
+            max_output: load(&MAX_OUTPUT, DEFAULT_MAX_OUTPUT),
+            max_ratio: load(&MAX_RATIO, DEFAULT_MAX_RATIO),
+            max_preallocation: load(&MAX_PREALLOCATION, DEFAULT_MAX_PREALLOCATION)
+        }
+    }
+
+    /// How long the output of decompressing input_len bytes may get
+    pub fn max_output_len(&self, input_len: usize) -> usize {
+        let by_ratio = input_len.checked_mul(self.max_ratio).unwrap_or(usize::MAX);
+        cmp::min(self.max_output, cmp::max(by_ratio, RATIO_GRACE))
+    }
+
+    /// An empty buffer for the output of decompressing input_len bytes,
+    /// with room for the expected length if the limits allow it
+    /// Pushing past the limits fails with LimitExceeded.
+    pub fn output_buf(&self, input_len: usize, expected_len: usize) -> GzResult<Buf> {
+        let max_len = self.max_output_len(input_len);
+        let capacity = cmp::min(expected_len, cmp::min(max_len, self.max_preallocation));
+        let mut buf = try_or!(CVec::with_capacity(capacity), GzError::AllocationFailed);
+        buf.set_max_len(max_len);
+        Ok(buf)
+    }
+}
+
+/// Set the limits for every thread, 0 meaning no limit
+pub fn set_limits(max_output: usize, max_ratio: usize, max_preallocation: usize) {
+    MAX_OUTPUT.store(unlimited_if_zero(max_output), Ordering::Relaxed);
+    MAX_RATIO.store(unlimited_if_zero(max_ratio), Ordering::Relaxed);
+    MAX_PREALLOCATION.store(unlimited_if_zero(max_preallocation), Ordering::Relaxed);
+}
+
+/// An empty buffer for the output of decompressing input_len bytes,
+/// under the current limits
+pub fn output_buf(input_len: usize, expected_len: usize) -> GzResult<Buf> {
+    Limits::current().output_buf(input_len, expected_len)
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+fn load(limit: &AtomicUsize, default: usize) -> usize {
+    match limit.load(Ordering::Relaxed) {
+        0 => default,
+        value => value
+    }
+}
+
+fn unlimited_if_zero(limit: usize) -> usize {
+    if limit == 0 { usize::MAX } else { limit }
+}
+
+#[cfg(test)]
+mod limits_tests {
+    use std::iter::repeat;
+    use std::usize;
+    use super::{Limits, DEFAULT_MAX_RATIO};
+    use cvec::from_slice;
+    use error::GzError;
+    use xz::decompress_xz;
+    use zstd::decompress_zstd;
+
+    // 1 MiB of a's, from xz and from zstd -19
+    const XZ_AS: [u8; 284] = [
+        0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46, 0x02,
+        0x00, 0x21, 0x01, 0x16, 0x00, 0x00, 0x00, 0x74, 0x2f, 0xe5, 0xa3, 0xef, 0xff,
+        0xff, 0x00, 0xd9, 0x5d, 0x00, 0x30, 0xef, 0xfb, 0xbf, 0xfe, 0xa3, 0xb1, 0x5e,
+        0xe5, 0xf8, 0x3f, 0xb2, 0xaa, 0x26, 0x55, 0xf8, 0x68, 0x70, 0x41, 0x70, 0x15,
+        0x0f, 0x8d, 0xfd, 0x1e, 0x4c, 0x1b, 0x8a, 0x42, 0xb7, 0x19, 0xf4, 0x69, 0x18,
+        0x71, 0xae, 0x66, 0x23, 0x8a, 0x8a, 0x4d, 0x2f, 0xa3, 0x0d, 0xd9, 0x7f, 0xa6,
+        0xe3, 0x8c, 0x23, 0x11, 0x53, 0xe0, 0x59, 0x18, 0xc5, 0x75, 0x8a, 0xe2, 0x77,
+        0xf8, 0xb6, 0x94, 0x7f, 0x0c, 0x6a, 0xc0, 0xde, 0x74, 0x49, 0x64, 0xe2, 0xe9,
+        0x5c, 0x53, 0xb2, 0x04, 0xd8, 0xf7, 0x44, 0x0c, 0xab, 0x5f, 0x0d, 0x6d, 0x46,
+        0xe9, 0xe5, 0xc3, 0x76, 0x88, 0xb7, 0x96, 0x57, 0xac, 0xb6, 0x4d, 0xe1, 0x69,
+        0x1d, 0x6f, 0xfb, 0x4b, 0x88, 0x10, 0x6c, 0x42, 0xcb, 0x88, 0x3f, 0x5c, 0x00,
+        0x8f, 0xd0, 0x4e, 0xaf, 0x26, 0x28, 0x94, 0x71, 0x1f, 0x3d, 0x8f, 0x24, 0xe1,
+        0x70, 0x9e, 0xa7, 0x23, 0x5f, 0xec, 0x28, 0xcb, 0x85, 0xd1, 0x95, 0x98, 0x8a,
+        0x7e, 0x2a, 0x91, 0xf2, 0x27, 0x75, 0xf7, 0x19, 0xc0, 0x06, 0x98, 0x4d, 0x98,
+        0xfd, 0xd8, 0xaf, 0xd5, 0x90, 0x0f, 0xc4, 0x25, 0x53, 0xf8, 0xf5, 0x91, 0x36,
+        0x31, 0x05, 0xa5, 0xb0, 0xee, 0x6f, 0xc1, 0x70, 0x4d, 0x47, 0x0c, 0xd1, 0x91,
+        0x11, 0xaa, 0xad, 0x60, 0x1d, 0xba, 0xce, 0xb1, 0x27, 0x18, 0x5c, 0x59, 0x86,
+        0xe9, 0x66, 0x52, 0x58, 0xbe, 0xe9, 0x76, 0xac, 0x59, 0xe4, 0xe5, 0x5b, 0x05,
+        0x08, 0xf9, 0xc7, 0xda, 0xad, 0xfc, 0xfb, 0x52, 0x2a, 0xf7, 0xe4, 0x95, 0x25,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0xcf, 0x88, 0xd2, 0xa7, 0xa7, 0x11, 0xff, 0x5c,
+        0x00, 0x01, 0xf5, 0x01, 0x80, 0x80, 0x40, 0x00, 0x79, 0x97, 0x7f, 0x47, 0xb1,
+        0xc4, 0x67, 0xfb, 0x02, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5a];
+    const ZSTD_AS: [u8; 50] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x68, 0x4c, 0x00, 0x00, 0x08, 0x61, 0x01, 0x00,
+        0xfc, 0xff, 0x39, 0x10, 0x02, 0x02, 0x00, 0x10, 0x61, 0x02, 0x00, 0x10, 0x61,
+        0x02, 0x00, 0x10, 0x61, 0x02, 0x00, 0x10, 0x61, 0x02, 0x00, 0x10, 0x61, 0x02,
+        0x00, 0x10, 0x61, 0x03, 0x00, 0x10, 0x61, 0xf1, 0x13, 0x21, 0xb5];
+
+    fn limits(max_output: usize, max_ratio: usize) -> Limits {
+        Limits {
+            max_output: max_output,
+            max_ratio: max_ratio,
+            max_preallocation: 64
+        }
+    }
+
+    #[test]
+    fn test_max_output_len() {
+        assert_eq!(limits(1 << 30, 1000).max_output_len(40), 1 << 24);
+        assert_eq!(limits(1 << 30, 1000).max_output_len(1 << 20), 1000 << 20);
+        assert_eq!(limits(1 << 30, 1000).max_output_len(1 << 22), 1 << 30);
+        assert_eq!(limits(100, 1000).max_output_len(40), 100);
+        assert_eq!(limits(usize::MAX, usize::MAX).max_output_len(40), usize::MAX);
+    }
+
+    #[test]
+    fn test_output_buf() {
+        // a huge recorded size only gets the preallocation limit
+        let mut buf = limits(100, 1).output_buf(10, usize::MAX).unwrap();
+        for i in 0 .. 100 {
+            assert_eq!(buf.try_push(i as u8), Ok(()));
+        }
+        assert_eq!(buf.try_push(0), Err(GzError::LimitExceeded));
+        assert_eq!(buf.copy_back_pointer(0, 1), Err(GzError::LimitExceeded));
+        assert_eq!(buf.len(), 100);
+    }
+
+    #[test]
+    fn test_repetitive_input() {
+        // far past the default ratio, but under the grace size, so these
+        // decompress under the default limits
+        let expected: Vec<u8> = repeat(b'a').take(1 << 20).collect();
+        assert!(XZ_AS.len() * DEFAULT_MAX_RATIO < expected.len());
+        assert!(ZSTD_AS.len() * DEFAULT_MAX_RATIO < expected.len());
+        let out = decompress_xz(from_slice(&XZ_AS)).unwrap();
+        assert_eq!(out.as_slice(), expected.as_slice());
+        let out = decompress_zstd(from_slice(&ZSTD_AS)).unwrap();
+        assert_eq!(out.as_slice(), expected.as_slice());
+    }
+}
@@ -0,0 +1,358 @@
+#[doc="
+
+    Module: lz4
+
+    This decompresses LZ4 files, in either the frame format or the
+    older legacy format. Both split the data into blocks of LZ4
+    sequences, each of which is a run of literal bytes followed by a
+    match copied from earlier output. Frames say up front whether
+    blocks may refer back to earlier blocks, and may carry an xxHash
+    of each block, of the whole content, or both. Legacy files have
+    none of that, only compressed blocks of up to 8M each.
+
+"]
+use cvec::Buf;
+use error::{GzError, GzResult};
+use limits;
+use xxhash::xxh32;
+
+// These constants are defined by the LZ4 frame format
+const FRAME_MAGIC: u32 = 0x184d2204;
+const LEGACY_MAGIC: u32 = 0x184c2102;
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+// skippable frames may use any of 16 magic numbers
+const SKIPPABLE_MAGIC_MASK: u32 = 0xfffffff0;
+const MAGIC_LEN: usize = 4;
+const FRAME_VERSION: u8 = 0x40;
+const FRAME_VERSION_MASK: u8 = 0xc0;
+const FLAG_INDEPENDENT_BLOCKS: u8 = 0x20;
+const FLAG_BLOCK_CHECKSUM: u8 = 0x10;
+const FLAG_CONTENT_SIZE: u8 = 0x08;
+const FLAG_CONTENT_CHECKSUM: u8 = 0x04;
+const FLAG_RESERVED: u8 = 0x02;
+const FLAG_DICTIONARY_ID: u8 = 0x01;
+const BLOCK_SIZE_MASK: u8 = 0x70;
+const CONTENT_SIZE_LEN: usize = 8;
+const DICTIONARY_ID_LEN: usize = 4;
+const CHECKSUM_LEN: usize = 4;
+// a block size with this bit set is stored uncompressed
+const UNCOMPRESSED_BLOCK: u32 = 0x80000000;
+// legacy blocks hold 8M, which can grow a little if it won't compress
+const LEGACY_MAX_BLOCK_LEN: usize = 8 * 1024 * 1024 + 8 * 1024 * 1024 / 255 + 16;
+// literal and match lengths of 15 carry on in the following bytes
+const RUN_MASK: usize = 15;
+const MIN_MATCH: usize = 4;
+// lz4 manages about 2:1 on text, so this is a rough first allocation
+const EXPECTED_RATIO: usize = 2;
+
+/// Decompress the given LZ4 file
+/// Like lz4, this reads every frame in the file, skips skippable frames,
+/// and ignores anything after the last one.
+pub fn decompress_lz4(buffer: Buf) -> GzResult<Buf> {
+    let data = buffer.as_slice();
+    if data.len() < MAGIC_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    if !is_frame_start(data, 0) {
+        return Err(GzError::BadMagic);
+    }
+    let mut out_buf = try!(limits::output_buf(data.len(), data.len() * EXPECTED_RATIO));
+    let mut pos = 0;
+    while is_frame_start(data, pos) {
+        let magic = read_le32(data, pos);
+        pos += MAGIC_LEN;
+        pos = match magic {
+            FRAME_MAGIC => try!(decompress_frame(data, pos, &mut out_buf)),
+            LEGACY_MAGIC => try!(decompress_legacy(data, pos, &mut out_buf)),
+            _ => {
+                if pos + 4 > data.len() {
+                    return Err(GzError::TruncatedHeader);
+                }
+                let end = pos + 4 + read_le32(data, pos) as usize;
+                if end > data.len() {
+                    return Err(GzError::TruncatedData);
+                }
+                end
+            }
+        };
+    }
+    Ok(out_buf)
+}
+
+/// Whether a frame, legacy stream or skippable frame starts at pos
+fn is_frame_start(data: &[u8], pos: usize) -> bool {
+    if pos + MAGIC_LEN > data.len() {
+        return false;
+    }
+    let magic = read_le32(data, pos);
+    magic == FRAME_MAGIC || magic == LEGACY_MAGIC ||
+        magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC
+}
+
+/// Decompress the frame whose descriptor starts at pos onto the end of
+/// out_buf, returning where the frame ends
+fn decompress_frame(data: &[u8], pos: usize, out_buf: &mut Buf) -> GzResult<usize> {
+    let descriptor_start = pos;
+    let mut pos = pos;
+    if pos + 2 > data.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    let flags = data[pos];
+    let block_descriptor = data[pos + 1];
+    pos += 2;
+    if flags & FRAME_VERSION_MASK != FRAME_VERSION {
+        return Err(GzError::UnsupportedMethod);
+    }
+    if flags & FLAG_RESERVED != 0 || block_descriptor & !BLOCK_SIZE_MASK != 0 {
+        return Err(GzError::CorruptData);
+    }
+    // blocks hold 64K, 256K, 1M or 4M
+    let max_block_len = match (block_descriptor & BLOCK_SIZE_MASK) >> 4 {
+        size @ 4 ... 7 => 1 << (8 + 2 * size as usize),
+        _ => { return Err(GzError::CorruptData); }
+    };
+
+    let content_size_len = if flags & FLAG_CONTENT_SIZE != 0 { CONTENT_SIZE_LEN } else { 0 };
+    let dictionary_id_len = if flags & FLAG_DICTIONARY_ID != 0 { DICTIONARY_ID_LEN } else { 0 };
+    if pos + content_size_len + dictionary_id_len + 1 > data.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    let content_size = if content_size_len > 0 {
+        Some(read_le32(data, pos) as u64 | (read_le32(data, pos + 4) as u64) << 32)
+    } else {
+        None
+    };
+    pos += content_size_len;
+    // blocks compressed against a dictionary can't be read without it
+    if dictionary_id_len > 0 {
+        return Err(GzError::UnsupportedMethod);
+    }
+    // the descriptor is checked with the second byte of its hash
+    let header_checksum = (xxh32(&data[descriptor_start .. pos], 0) >> 8) as u8;
+    if data[pos] != header_checksum {
+        return Err(GzError::CrcMismatch);
+    }
+    pos += 1;
+
+    let frame_start = out_buf.len();
+    let independent = flags & FLAG_INDEPENDENT_BLOCKS != 0;
+    let block_checksums = flags & FLAG_BLOCK_CHECKSUM != 0;
+    loop {
+        if pos + 4 > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let block_header = read_le32(data, pos);
+        pos += 4;
+        // a zero size marks the end of the blocks
+        if block_header == 0 {
+            break;
+        }
+        let block_len = (block_header & !UNCOMPRESSED_BLOCK) as usize;
+        if block_len > max_block_len {
+            return Err(GzError::CorruptData);
+        }
+        let checksum_len = if block_checksums { CHECKSUM_LEN } else { 0 };
+        if pos + block_len + checksum_len > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let block = &data[pos .. pos + block_len];
+        pos += block_len;
+        if block_checksums {
+            if xxh32(block, 0) != read_le32(data, pos) {
+                return Err(GzError::CrcMismatch);
+            }
+            pos += CHECKSUM_LEN;
+        }
+        if block_header & UNCOMPRESSED_BLOCK != 0 {
+            for &byte in block.iter() {
+                try!(out_buf.try_push(byte));
+            }
+        } else {
+            let window_start = if independent { out_buf.len() } else { frame_start };
+            try!(decompress_block(block, window_start, out_buf));
+        }
+    }
+
+    let frame_len = out_buf.len() - frame_start;
+    if flags & FLAG_CONTENT_CHECKSUM != 0 {
+        if pos + CHECKSUM_LEN > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        if xxh32(&out_buf.as_slice()[frame_start ..], 0) != read_le32(data, pos) {
+            return Err(GzError::CrcMismatch);
+        }
+        pos += CHECKSUM_LEN;
+    }
+    match content_size {
+        Some(size) if size != frame_len as u64 => Err(GzError::SizeMismatch),
+        _ => Ok(pos)
+    }
+}
+
+/// Decompress the legacy stream whose first block starts at pos onto the
+/// end of out_buf, returning where the stream ends
+/// Legacy streams have no end marker, so they run until the end of the
+/// file or the start of another frame.
+fn decompress_legacy(data: &[u8], pos: usize, out_buf: &mut Buf) -> GzResult<usize> {
+    let mut pos = pos;
+    while pos + 4 <= data.len() && !is_frame_start(data, pos) {
+        let block_len = read_le32(data, pos) as usize;
+        pos += 4;
+        if block_len > LEGACY_MAX_BLOCK_LEN {
+            return Err(GzError::CorruptData);
+        }
+        if pos + block_len > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let window_start = out_buf.len();
+        try!(decompress_block(&data[pos .. pos + block_len], window_start, out_buf));
+        pos += block_len;
+    }
+    Ok(pos)
+}
+
+/// Decompress a block of LZ4 sequences onto the end of out_buf
+/// Matches may reach back as far as window_start.
+fn decompress_block(block: &[u8], window_start: usize, out_buf: &mut Buf) -> GzResult<()> {
+    let mut pos = 0;
+    loop {
+        if pos >= block.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let token = block[pos] as usize;
+        pos += 1;
+
+        let literal_len = try!(read_length(block, &mut pos, token >> 4));
+        if pos + literal_len > block.len() {
+            return Err(GzError::TruncatedData);
+        }
+        for &byte in block[pos .. pos + literal_len].iter() {
+            try!(out_buf.try_push(byte));
+        }
+        pos += literal_len;
+        // the last sequence is only literals
+        if pos == block.len() {
+            return Ok(());
+        }
+
+        if pos + 2 > block.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let offset = block[pos] as usize | (block[pos + 1] as usize) << 8;
+        pos += 2;
+        let match_len = try!(read_length(block, &mut pos, token & RUN_MASK)) + MIN_MATCH;
+        if offset == 0 || offset > out_buf.len() - window_start {
+            return Err(GzError::InvalidDistance);
+        }
+        try!(out_buf.copy_back_pointer(offset - 1, match_len));
+    }
+}
+
+/// Finish reading a length whose first four bits came from the token
+fn read_length(block: &[u8], pos: &mut usize, length: usize) -> GzResult<usize> {
+    let mut length = length;
+    if length == RUN_MASK {
+        loop {
+            if *pos >= block.len() {
+                return Err(GzError::TruncatedData);
+            }
+            let byte = block[*pos];
+            *pos += 1;
+            length += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(length)
+}
+
+fn read_le32(data: &[u8], pos: usize) -> u32 {
+    (0 .. 4).fold(0, |value, i| value | (data[pos + i] as u32) << (8 * i))
+}
+
+#[cfg(test)]
+mod lz4_tests {
+    use super::decompress_lz4;
+    use xxhash::xxh32;
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_lz4(from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    // printf 'hello, world\n' | lz4, which stores it uncompressed
+    const HELLO: [u8; 32] = [
+        0x04, 0x22, 0x4d, 0x18, 0x64, 0x40, 0xa7, 0x0d, 0x00, 0x00, 0x80, 0x68, 0x65,
+        0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x0a, 0x00, 0x00,
+        0x00, 0x00, 0x1a, 0xc4, 0xe0, 0xd9];
+
+    // the same LZ4 block in both formats
+    const LEGACY: [u8; 77] = [
+        0x02, 0x21, 0x4c, 0x18, 0x45, 0x00, 0x00, 0x00, 0xf6, 0x0c, 0x49, 0x74, 0x20,
+        0x77, 0x61, 0x73, 0x20, 0x74, 0x68, 0x65, 0x20, 0x62, 0x65, 0x73, 0x74, 0x20,
+        0x6f, 0x66, 0x20, 0x74, 0x69, 0x6d, 0x65, 0x73, 0x2c, 0x20, 0x69, 0x1a, 0x00,
+        0x3f, 0x77, 0x6f, 0x72, 0x1b, 0x00, 0x05, 0x30, 0x61, 0x67, 0x65, 0x34, 0x00,
+        0x69, 0x77, 0x69, 0x73, 0x64, 0x6f, 0x6d, 0x35, 0x00, 0x03, 0x1a, 0x00, 0xc0,
+        0x66, 0x6f, 0x6f, 0x6c, 0x69, 0x73, 0x68, 0x6e, 0x65, 0x73, 0x73, 0x0a];
+    const TEXT: &'static [u8] = b"It was the best of times, it was the worst of times, it was \
+                                  the age of wisdom, it was the age of foolishness\n";
+
+    #[test]
+    fn test_uncompressed_block() {
+        assert_eq!(decompress(&HELLO), Ok(b"hello, world\n".to_vec()));
+    }
+
+    #[test]
+    fn test_checksums() {
+        // lz4 --content-size -BD -BX, with linked blocks, a content size
+        // and both kinds of checksum
+        let raw = [0x04, 0x22, 0x4d, 0x18, 0x7c, 0x40, 0x6d, 0x00, 0x00, 0x00, 0x00, 0x00,
+                   0x00, 0x00, 0xaf, 0x45, 0x00, 0x00, 0x00, 0xf6, 0x0c, 0x49, 0x74, 0x20,
+                   0x77, 0x61, 0x73, 0x20, 0x74, 0x68, 0x65, 0x20, 0x62, 0x65, 0x73, 0x74,
+                   0x20, 0x6f, 0x66, 0x20, 0x74, 0x69, 0x6d, 0x65, 0x73, 0x2c, 0x20, 0x69,
+                   0x1a, 0x00, 0x3f, 0x77, 0x6f, 0x72, 0x1b, 0x00, 0x05, 0x30, 0x61, 0x67,
+                   0x65, 0x34, 0x00, 0x69, 0x77, 0x69, 0x73, 0x64, 0x6f, 0x6d, 0x35, 0x00,
+                   0x03, 0x1a, 0x00, 0xc0, 0x66, 0x6f, 0x6f, 0x6c, 0x69, 0x73, 0x68, 0x6e,
+                   0x65, 0x73, 0x73, 0x0a, 0x9e, 0x33, 0xb8, 0x1d, 0x00, 0x00, 0x00, 0x00,
+                   0xda, 0x7d, 0x90, 0x63];
+        assert_eq!(decompress(&raw), Ok(TEXT.to_vec()));
+
+        let mut bad_block = raw.to_vec();
+        bad_block[88] ^= 1;
+        assert_eq!(decompress(bad_block.as_slice()), Err(GzError::CrcMismatch));
+        let mut bad_size = raw.to_vec();
+        bad_size[6] = 0x6c;
+        bad_size[14] = (xxh32(&bad_size[4 .. 14], 0) >> 8) as u8;
+        assert_eq!(decompress(bad_size.as_slice()), Err(GzError::SizeMismatch));
+    }
+
+    #[test]
+    fn test_legacy() {
+        assert_eq!(decompress(&LEGACY), Ok(TEXT.to_vec()));
+        // a legacy stream ends where the next frame starts
+        let mut both = LEGACY.to_vec();
+        both.push_all(&HELLO);
+        let mut want = TEXT.to_vec();
+        want.push_all(b"hello, world\n");
+        assert_eq!(decompress(both.as_slice()), Ok(want));
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(&[0x04, 0x22]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(b"not lz4"), Err(GzError::BadMagic));
+        assert_eq!(decompress(&HELLO[.. 20]), Err(GzError::TruncatedData));
+        let mut bad_header = HELLO.to_vec();
+        bad_header[6] ^= 1;
+        assert_eq!(decompress(bad_header.as_slice()), Err(GzError::CrcMismatch));
+        let mut bad_checksum = HELLO.to_vec();
+        bad_checksum[31] ^= 1;
+        assert_eq!(decompress(bad_checksum.as_slice()), Err(GzError::CrcMismatch));
+        // a match from before the start of the output
+        let mut bad_offset = LEGACY.to_vec();
+        bad_offset[37] = 0x7f;
+        assert_eq!(decompress(bad_offset.as_slice()), Err(GzError::InvalidDistance));
+    }
+}
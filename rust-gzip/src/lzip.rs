@@ -0,0 +1,169 @@
+#[doc="
+
+    Module: lzip
+
+    This decompresses lzip files. A file is one or more members, each
+    a short header, LZMA data with the literal settings fixed and an
+    end marker, and a trailer holding the CRC32 and size of the data
+    and the size of the member itself, so damage to any part of a
+    member can be told from the rest.
+
+"]
+use cvec::Buf;
+use crc32;
+use error::{GzError, GzResult};
+use limits;
+use lzma;
+
+// These constants are defined by the lzip format
+const MAGIC: &'static [u8] = b"LZIP";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 6;
+const TRAILER_LEN: usize = 20;
+// lc = 3, lp = 0 and pb = 2 packed together, which lzip always uses
+const LZMA_PROPERTIES: u8 = 0x5d;
+// dictionary sizes are a power of two, less up to seven sixteenths
+const MIN_DICTIONARY_SIZE: usize = 1 << 12;
+const MAX_DICTIONARY_SIZE: usize = 1 << 29;
+// lzip manages about 4:1 on text, so this is a rough first allocation
+const EXPECTED_RATIO: usize = 4;
+
+/// Decompress the given lzip file
+/// Every member is decompressed, one after another, and anything after
+/// the last is ignored, as lzip does.
+pub fn decompress_lzip(buffer: Buf) -> GzResult<Buf> {
+    let data = buffer.as_slice();
+    if data.len() < HEADER_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    if !starts_with_magic(data, 0) {
+        return Err(GzError::BadMagic);
+    }
+    let mut out_buf = try!(limits::output_buf(data.len(), data.len() * EXPECTED_RATIO));
+    let mut pos = 0;
+    while starts_with_magic(data, pos) {
+        pos += try!(decompress_member(data, pos, &mut out_buf));
+    }
+    Ok(out_buf)
+}
+
+/// Decompress the member that starts at pos onto the end of out_buf,
+/// returning its size
+fn decompress_member(data: &[u8], pos: usize, out_buf: &mut Buf) -> GzResult<usize> {
+    if pos + HEADER_LEN > data.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    if data[pos + MAGIC.len()] != VERSION {
+        return Err(GzError::UnsupportedMethod);
+    }
+    // we keep all of the output, so the dictionary size is only checked
+    // to be one lzip could have written
+    let coded_size = data[pos + MAGIC.len() + 1];
+    let base_size = 1 << (coded_size & 0x1f) as usize;
+    let dictionary_size = base_size - (base_size / 16) * (coded_size >> 5) as usize;
+    if dictionary_size < MIN_DICTIONARY_SIZE || dictionary_size > MAX_DICTIONARY_SIZE {
+        return Err(GzError::CorruptData);
+    }
+
+    let member_start = out_buf.len();
+    let compressed_len = try!(lzma::decompress_lzma_until_end_marker(
+        &data[pos + HEADER_LEN ..], LZMA_PROPERTIES, out_buf));
+    let trailer = pos + HEADER_LEN + compressed_len;
+    if trailer + TRAILER_LEN > data.len() {
+        return Err(GzError::TruncatedData);
+    }
+    if crc32::sum(out_buf.limit_iter(member_start, out_buf.len())) !=
+       read_le(data, trailer, 4) as u32 {
+        return Err(GzError::CrcMismatch);
+    }
+    let member_len = HEADER_LEN + compressed_len + TRAILER_LEN;
+    if read_le(data, trailer + 4, 8) != (out_buf.len() - member_start) as u64 ||
+       read_le(data, trailer + 12, 8) != member_len as u64 {
+        return Err(GzError::SizeMismatch);
+    }
+    Ok(member_len)
+}
+
+fn starts_with_magic(data: &[u8], pos: usize) -> bool {
+    pos + MAGIC.len() <= data.len() && &data[pos .. pos + MAGIC.len()] == MAGIC
+}
+
+fn read_le(data: &[u8], pos: usize, len: usize) -> u64 {
+    (0 .. len).fold(0, |value, i| value | (data[pos + i] as u64) << (8 * i))
+}
+
+#[cfg(test)]
+mod lzip_tests {
+    use super::decompress_lzip;
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_lzip(from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    // printf 'hello, world\n' | lzip
+    const HELLO: [u8; 49] = [
+        0x4c, 0x5a, 0x49, 0x50, 0x01, 0x17, 0x00, 0x34, 0x19, 0x49, 0xee, 0x8d, 0xef,
+        0x8c, 0x6b, 0xca, 0x95, 0x59, 0x10, 0x04, 0x40, 0xed, 0xe4, 0x1b, 0xff, 0xfe,
+        0xe0, 0xf0, 0x00, 0x53, 0x74, 0x24, 0xf4, 0x0d, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    const TIMES: [u8; 94] = [
+        0x4c, 0x5a, 0x49, 0x50, 0x01, 0x17, 0x00, 0x24, 0x9d, 0x00, 0x07, 0x73, 0x43,
+        0x85, 0x19, 0xc1, 0xbc, 0x61, 0x9e, 0x16, 0x43, 0x3f, 0x49, 0xa5, 0x27, 0x15,
+        0xb4, 0xf5, 0x10, 0x18, 0x54, 0xbf, 0xcd, 0x91, 0x38, 0x3b, 0x33, 0x8b, 0x52,
+        0x7d, 0x4a, 0xe7, 0x72, 0xd2, 0xb1, 0xd8, 0x4a, 0x2a, 0x51, 0x00, 0x44, 0xb2,
+        0xe1, 0x69, 0x9f, 0x12, 0x41, 0xf5, 0x50, 0xc0, 0x17, 0xd2, 0xcd, 0xfc, 0x50,
+        0xd7, 0x80, 0x7f, 0xff, 0xff, 0xec, 0xfa, 0x90, 0x00, 0x57, 0xf2, 0x73, 0x69,
+        0x6d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5e, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00];
+    const TEXT: &'static [u8] = b"It was the best of times, it was the worst of times, it was \
+                                  the age of wisdom, it was the age of foolishness\n";
+
+    #[test]
+    fn test_members() {
+        assert_eq!(decompress(&HELLO), Ok(b"hello, world\n".to_vec()));
+        assert_eq!(decompress(&TIMES), Ok(TEXT.to_vec()));
+
+        // several members, then something that isn't one
+        let mut raw = HELLO.to_vec();
+        raw.push_all(&TIMES);
+        raw.push_all(b"trailing junk");
+        let mut expected = b"hello, world\n".to_vec();
+        expected.push_all(TEXT);
+        assert_eq!(decompress(raw.as_slice()), Ok(expected));
+    }
+
+    #[test]
+    fn test_trailer() {
+        let mut bad_crc = HELLO.to_vec();
+        bad_crc[29] ^= 1;
+        assert_eq!(decompress(bad_crc.as_slice()), Err(GzError::CrcMismatch));
+
+        let mut bad_data_size = HELLO.to_vec();
+        bad_data_size[33] = 0x0c;
+        assert_eq!(decompress(bad_data_size.as_slice()), Err(GzError::SizeMismatch));
+
+        let mut bad_member_size = HELLO.to_vec();
+        bad_member_size[41] = 0x32;
+        assert_eq!(decompress(bad_member_size.as_slice()), Err(GzError::SizeMismatch));
+
+        assert_eq!(decompress(&HELLO[.. 40]), Err(GzError::TruncatedData));
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(decompress(&HELLO[.. 3]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(b"LZMA\x01\x17\x00"), Err(GzError::BadMagic));
+
+        let mut version = HELLO.to_vec();
+        version[4] = 0;
+        assert_eq!(decompress(version.as_slice()), Err(GzError::UnsupportedMethod));
+
+        // a dictionary of 1K
+        let mut dictionary = HELLO.to_vec();
+        dictionary[5] = 0x0a;
+        assert_eq!(decompress(dictionary.as_slice()), Err(GzError::CorruptData));
+    }
+}
@@ -0,0 +1,616 @@
+#[doc="
+
+    Module: lzma
+
+    This decompresses LZMA, both in the .lzma files made by lzma and
+    xz --format=lzma, and in the LZMA2 chunks that xz blocks hold.
+    LZMA codes every bit with a binary range coder whose probabilities
+    adapt as it goes. Each step is either a literal byte, a match given
+    by a length and a distance, or a match that reuses one of the last
+    four distances, and a small state machine remembers which kinds of
+    step came last. LZMA2 splits the data into chunks that are either
+    stored or LZMA coded, and each chunk may reset the state, change
+    the literal settings, or start over with an empty dictionary.
+
+"]
+use std::iter::repeat;
+use cvec::Buf;
+use error::{GzError, GzResult};
+use limits;
+
+// These constants are defined by the LZMA SDK
+const HEADER_LEN: usize = 13;
+const UNKNOWN_SIZE: u64 = 0xffffffffffffffff;
+const MAX_PROPERTIES: u8 = 9 * 5 * 5;
+const STATES: usize = 12;
+// after a literal, states below this one follow another literal
+const LITERAL_STATES: usize = 7;
+const MAX_POS_STATES: usize = 16;
+const LITERAL_CODER_LEN: usize = 0x300;
+const PROBABILITY_BITS: u32 = 11;
+const PROBABILITY_INIT: u16 = 1 << (PROBABILITY_BITS - 1);
+const MOVE_BITS: u32 = 5;
+const TOP_VALUE: u32 = 1 << 24;
+const RANGE_CODER_INIT_LEN: usize = 5;
+const MIN_MATCH_LEN: usize = 2;
+const LOW_LEN_BITS: u32 = 3;
+const MID_LEN_BITS: u32 = 3;
+const HIGH_LEN_BITS: u32 = 8;
+// distances are coded by a slot, which depends on the length
+const LEN_TO_DIST_STATES: usize = 4;
+const DIST_SLOT_BITS: u32 = 6;
+// slots from this one on have extra bits, coded directly
+const END_DIST_MODEL_SLOT: u32 = 14;
+const FULL_DISTANCES: usize = 128;
+const ALIGN_BITS: u32 = 4;
+// the distance of a match that marks the end of the data
+const END_MARKER: u32 = 0xffffffff;
+// lzma manages about 4:1 on text, so this is a rough first allocation
+const EXPECTED_RATIO: usize = 4;
+
+// LZMA2 chunk control bytes
+const LZMA2_END: u8 = 0x00;
+const LZMA2_STORED_RESET: u8 = 0x01;
+const LZMA2_STORED: u8 = 0x02;
+const LZMA2_LZMA: u8 = 0x80;
+const LZMA2_RESET_STATE: u8 = 0x20;
+const LZMA2_NEW_PROPERTIES: u8 = 0x40;
+const LZMA2_RESET_DICTIONARY: u8 = 0x60;
+const LZMA2_RESET_MASK: u8 = 0x60;
+const LZMA2_MAX_LITERAL_BITS: u32 = 4;
+
+/// Decompress the given .lzma file
+/// The header gives the literal settings, the dictionary size and the
+/// size of the data, which may be unknown, in which case an end marker
+/// says where it stops.
+pub fn decompress_lzma(buffer: Buf) -> GzResult<Buf> {
+    let data = buffer.as_slice();
+    if data.len() < HEADER_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    let mut decoder = try!(LzmaDecoder::from_properties(data[0]));
+    // the dictionary size only matters to decoders with a fixed window
+    let size = (5 .. HEADER_LEN).rev().fold(0, |size, i| size << 8 | data[i] as u64);
+    let mut out_buf = try!(limits::output_buf(data.len(), data.len() * EXPECTED_RATIO));
+
+    let mut rc = try!(RangeDecoder::new(&data[HEADER_LEN ..]));
+    let end = if size == UNKNOWN_SIZE { None } else { Some(size as usize) };
+    let found_end_marker = try!(decoder.decode(&mut rc, &mut out_buf, 0, end));
+    rc.normalize();
+    if rc.overran() {
+        return Err(GzError::TruncatedData);
+    }
+    // with a known size the end marker is optional, but it can't come early
+    match end {
+        None if !found_end_marker => Err(GzError::CorruptData),
+        Some(_) if found_end_marker => Err(GzError::SizeMismatch),
+        _ => Ok(out_buf)
+    }
+}
+
+/// Decompress LZMA data that ends with an end marker, such as an lzip
+/// member's, onto the end of out_buf, returning how many bytes of data
+/// it took up
+/// There is no header, so properties packs the literal settings together
+/// as the first byte of a .lzma header would.
+pub fn decompress_lzma_until_end_marker(data: &[u8], properties: u8, out_buf: &mut Buf)
+        -> GzResult<usize> {
+    let mut decoder = try!(LzmaDecoder::from_properties(properties));
+    let mut rc = try!(RangeDecoder::new(data));
+    let dict_start = out_buf.len();
+    // without an end there's nothing else to stop at
+    try!(decoder.decode(&mut rc, out_buf, dict_start, None));
+    rc.normalize();
+    if rc.overran() {
+        return Err(GzError::TruncatedData);
+    }
+    Ok(rc.pos)
+}
+
+/// Decompress LZMA data that expands to out_len bytes, such as a 7z
+/// folder's, onto the end of out_buf
+/// As with a .lzma file of known size, the end marker may be left out.
+pub fn decompress_lzma_with_size(data: &[u8], properties: u8, out_len: usize, out_buf: &mut Buf)
+        -> GzResult<()> {
+    let mut decoder = try!(LzmaDecoder::from_properties(properties));
+    let mut rc = try!(RangeDecoder::new(data));
+    let dict_start = out_buf.len();
+    if try!(decoder.decode(&mut rc, out_buf, dict_start, Some(dict_start + out_len))) {
+        return Err(GzError::SizeMismatch);
+    }
+    rc.normalize();
+    if rc.overran() {
+        return Err(GzError::TruncatedData);
+    }
+    Ok(())
+}
+
+/// Decompress the LZMA2 data at the start of data onto the end of
+/// out_buf, returning how many bytes of data it took up
+pub fn decompress_lzma2(data: &[u8], out_buf: &mut Buf) -> GzResult<usize> {
+    let mut pos = 0;
+    let mut dict_start = out_buf.len();
+    let mut decoder: Option<LzmaDecoder> = None;
+    let mut needs_dictionary_reset = true;
+    loop {
+        if pos >= data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let control = data[pos];
+        pos += 1;
+        if control == LZMA2_END {
+            return Ok(pos);
+        }
+        if control == LZMA2_STORED_RESET || control >= LZMA2_LZMA | LZMA2_RESET_DICTIONARY {
+            dict_start = out_buf.len();
+            needs_dictionary_reset = false;
+        } else if needs_dictionary_reset {
+            return Err(GzError::CorruptData);
+        }
+
+        if control == LZMA2_STORED_RESET || control == LZMA2_STORED {
+            if pos + 2 > data.len() {
+                return Err(GzError::TruncatedData);
+            }
+            let len = read_be16(data, pos) + 1;
+            pos += 2;
+            if pos + len > data.len() {
+                return Err(GzError::TruncatedData);
+            }
+            for &byte in data[pos .. pos + len].iter() {
+                try!(out_buf.try_push(byte));
+            }
+            pos += len;
+            continue;
+        }
+        if control < LZMA2_LZMA {
+            return Err(GzError::CorruptData);
+        }
+
+        if pos + 4 > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let unpacked_len = (((control & 0x1f) as usize) << 16 | read_be16(data, pos)) + 1;
+        let packed_len = read_be16(data, pos + 2) + 1;
+        pos += 4;
+        let reset = control & LZMA2_RESET_MASK;
+        if reset >= LZMA2_NEW_PROPERTIES {
+            if pos >= data.len() {
+                return Err(GzError::TruncatedData);
+            }
+            let new_decoder = try!(LzmaDecoder::from_properties(data[pos]));
+            if new_decoder.lc + new_decoder.lp > LZMA2_MAX_LITERAL_BITS {
+                return Err(GzError::CorruptData);
+            }
+            decoder = Some(new_decoder);
+            pos += 1;
+        } else if reset == LZMA2_RESET_STATE {
+            decoder = match decoder {
+                Some(old) => Some(LzmaDecoder::new(old.lc, old.lp, old.pb)),
+                None => { return Err(GzError::CorruptData); }
+            };
+        }
+        let lzma = match decoder {
+            Some(ref mut lzma) => lzma,
+            None => { return Err(GzError::CorruptData); }
+        };
+
+        // every chunk has its own range coder, but the rest of the state
+        // carries on unless it was reset
+        if pos + packed_len > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let mut rc = try!(RangeDecoder::new(&data[pos .. pos + packed_len]));
+        let end = out_buf.len() + unpacked_len;
+        if try!(lzma.decode(&mut rc, out_buf, dict_start, Some(end))) {
+            return Err(GzError::CorruptData);
+        }
+        rc.normalize();
+        if rc.overran() || !rc.finished() {
+            return Err(GzError::CorruptData);
+        }
+        pos += packed_len;
+    }
+}
+
+/// Decodes bits using the probabilities it's given
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+    // set once a read goes past the end of data
+    overran: bool,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> GzResult<RangeDecoder<'a>> {
+        if data.len() < RANGE_CODER_INIT_LEN {
+            return Err(GzError::TruncatedData);
+        }
+        if data[0] != 0 {
+            return Err(GzError::CorruptData);
+        }
+        let code = (1 .. RANGE_CODER_INIT_LEN).fold(0, |code, i| code << 8 | data[i] as u32);
+        Ok(RangeDecoder {
+            data: data,
+            pos: RANGE_CODER_INIT_LEN,
+            range: 0xffffffff,
+            code: code,
+            overran: false,
+        })
+    }
+
+    fn normalize(&mut self) {
+        if self.range < TOP_VALUE {
+            let byte = match self.data.get(self.pos) {
+                Some(&byte) => byte,
+                None => {
+                    self.overran = true;
+                    0
+                }
+            };
+            self.pos += 1;
+            self.range <<= 8;
+            self.code = self.code << 8 | byte as u32;
+        }
+    }
+
+    /// Decode a bit, updating its probability of being 0
+    fn bit(&mut self, probability: &mut u16) -> usize {
+        self.normalize();
+        let bound = (self.range >> PROBABILITY_BITS) * *probability as u32;
+        if self.code < bound {
+            self.range = bound;
+            *probability += ((1 << PROBABILITY_BITS) - *probability) >> MOVE_BITS;
+            0
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *probability -= *probability >> MOVE_BITS;
+            1
+        }
+    }
+
+    /// Decode count bits, most significant first, using the probabilities
+    /// of a binary tree
+    fn bit_tree(&mut self, probabilities: &mut [u16], count: u32) -> usize {
+        let mut node = 1;
+        for _ in (0 .. count) {
+            node = node << 1 | self.bit(&mut probabilities[node]);
+        }
+        node - (1 << count as usize)
+    }
+
+    /// Decode count bits, least significant first
+    /// Unlike bit_tree, the probabilities start with the tree's first node.
+    fn reverse_bit_tree(&mut self, probabilities: &mut [u16], count: u32) -> usize {
+        let mut node = 1;
+        let mut value = 0;
+        for i in (0 .. count) {
+            let bit = self.bit(&mut probabilities[node - 1]);
+            node = node << 1 | bit;
+            value |= bit << i as usize;
+        }
+        value
+    }
+
+    /// Decode count bits that are equally likely to be 0 or 1
+    fn direct_bits(&mut self, count: u32) -> usize {
+        let mut value = 0;
+        for _ in (0 .. count) {
+            self.normalize();
+            self.range >>= 1;
+            let bit = if self.code >= self.range {
+                self.code -= self.range;
+                1
+            } else {
+                0
+            };
+            value = value << 1 | bit;
+        }
+        value
+    }
+
+    fn overran(&self) -> bool {
+        self.overran
+    }
+
+    /// Whether the encoder flushed here, having used all of its data
+    fn finished(&self) -> bool {
+        self.code == 0 && self.pos == self.data.len()
+    }
+}
+
+/// The probabilities for a match length
+struct LenDecoder {
+    choice: u16,
+    choice2: u16,
+    low: Vec<u16>,
+    mid: Vec<u16>,
+    high: Vec<u16>,
+}
+
+impl LenDecoder {
+    fn new() -> LenDecoder {
+        LenDecoder {
+            choice: PROBABILITY_INIT,
+            choice2: PROBABILITY_INIT,
+            low: probabilities(MAX_POS_STATES << LOW_LEN_BITS as usize),
+            mid: probabilities(MAX_POS_STATES << MID_LEN_BITS as usize),
+            high: probabilities(1 << HIGH_LEN_BITS as usize),
+        }
+    }
+
+    /// Lengths come in three ranges, the shorter two of which depend on
+    /// the position
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> usize {
+        if rc.bit(&mut self.choice) == 0 {
+            let start = pos_state << LOW_LEN_BITS as usize;
+            MIN_MATCH_LEN + rc.bit_tree(&mut self.low[start ..], LOW_LEN_BITS)
+        } else if rc.bit(&mut self.choice2) == 0 {
+            let start = pos_state << MID_LEN_BITS as usize;
+            MIN_MATCH_LEN + (1 << LOW_LEN_BITS as usize) +
+                rc.bit_tree(&mut self.mid[start ..], MID_LEN_BITS)
+        } else {
+            MIN_MATCH_LEN + (1 << LOW_LEN_BITS as usize) + (1 << MID_LEN_BITS as usize) +
+                rc.bit_tree(self.high.as_mut_slice(), HIGH_LEN_BITS)
+        }
+    }
+}
+
+/// Everything an LZMA decoder carries from one step to the next
+struct LzmaDecoder {
+    // how many high bits of the previous byte and low bits of the
+    // position pick the probabilities for a literal
+    lc: u32,
+    lp: u32,
+    // how many low bits of the position pick the probabilities for
+    // everything else
+    pb: u32,
+    literals: Vec<u16>,
+    is_match: Vec<u16>,
+    is_rep: Vec<u16>,
+    is_rep0: Vec<u16>,
+    is_rep1: Vec<u16>,
+    is_rep2: Vec<u16>,
+    is_rep0_long: Vec<u16>,
+    dist_slots: Vec<u16>,
+    dist_special: Vec<u16>,
+    align: Vec<u16>,
+    lengths: LenDecoder,
+    rep_lengths: LenDecoder,
+    state: usize,
+    // the last four distances, less one
+    reps: [usize; 4],
+}
+
+impl LzmaDecoder {
+    fn new(lc: u32, lp: u32, pb: u32) -> LzmaDecoder {
+        LzmaDecoder {
+            lc: lc,
+            lp: lp,
+            pb: pb,
+            literals: probabilities(LITERAL_CODER_LEN << (lc + lp) as usize),
+            is_match: probabilities(STATES * MAX_POS_STATES),
+            is_rep: probabilities(STATES),
+            is_rep0: probabilities(STATES),
+            is_rep1: probabilities(STATES),
+            is_rep2: probabilities(STATES),
+            is_rep0_long: probabilities(STATES * MAX_POS_STATES),
+            dist_slots: probabilities(LEN_TO_DIST_STATES << DIST_SLOT_BITS as usize),
+            dist_special: probabilities(FULL_DISTANCES - END_DIST_MODEL_SLOT as usize),
+            align: probabilities(1 << ALIGN_BITS as usize),
+            lengths: LenDecoder::new(),
+            rep_lengths: LenDecoder::new(),
+            state: 0,
+            reps: [0; 4],
+        }
+    }
+
+    /// Make a decoder from the byte that packs lc, lp and pb together
+    fn from_properties(properties: u8) -> GzResult<LzmaDecoder> {
+        if properties >= MAX_PROPERTIES {
+            return Err(GzError::CorruptData);
+        }
+        let properties = properties as u32;
+        Ok(LzmaDecoder::new(properties % 9, properties / 9 % 5, properties / 45))
+    }
+
+    /// Decode onto the end of out_buf until it holds end bytes, or until
+    /// the end marker if end is None, returning whether the end marker
+    /// was found
+    /// Matches can't reach back before dict_start.
+    fn decode(&mut self, rc: &mut RangeDecoder, out_buf: &mut Buf, dict_start: usize,
+              end: Option<usize>) -> GzResult<bool> {
+        let pos_mask = (1 << self.pb as usize) - 1;
+        loop {
+            if Some(out_buf.len()) == end {
+                return Ok(false);
+            }
+            // a corrupt stream can go on producing output from zeros
+            if rc.overran() {
+                return Err(GzError::TruncatedData);
+            }
+            let pos = out_buf.len() - dict_start;
+            let pos_state = pos & pos_mask;
+            let state = self.state;
+
+            if rc.bit(&mut self.is_match[state * MAX_POS_STATES + pos_state]) == 0 {
+                let byte = self.decode_literal(rc, out_buf, pos);
+                try!(out_buf.try_push(byte));
+                self.state = if state < 4 { 0 } else if state < 10 { state - 3 } else { state - 6 };
+                continue;
+            }
+
+            let len;
+            if rc.bit(&mut self.is_rep[state]) == 0 {
+                len = self.lengths.decode(rc, pos_state);
+                self.state = if state < LITERAL_STATES { 7 } else { 10 };
+                let distance = self.decode_distance(rc, len);
+                if distance == END_MARKER as usize {
+                    return Ok(true);
+                }
+                self.reps = [distance, self.reps[0], self.reps[1], self.reps[2]];
+            } else {
+                if rc.bit(&mut self.is_rep0[state]) == 0 {
+                    if rc.bit(&mut self.is_rep0_long[state * MAX_POS_STATES + pos_state]) == 0 {
+                        // a single byte from the last distance
+                        self.state = if state < LITERAL_STATES { 9 } else { 11 };
+                        try!(copy_match(out_buf, dict_start, end, self.reps[0], 1));
+                        continue;
+                    }
+                } else {
+                    let distance;
+                    if rc.bit(&mut self.is_rep1[state]) == 0 {
+                        distance = self.reps[1];
+                    } else {
+                        if rc.bit(&mut self.is_rep2[state]) == 0 {
+                            distance = self.reps[2];
+                        } else {
+                            distance = self.reps[3];
+                            self.reps[3] = self.reps[2];
+                        }
+                        self.reps[2] = self.reps[1];
+                    }
+                    self.reps[1] = self.reps[0];
+                    self.reps[0] = distance;
+                }
+                len = self.rep_lengths.decode(rc, pos_state);
+                self.state = if state < LITERAL_STATES { 8 } else { 11 };
+            }
+            try!(copy_match(out_buf, dict_start, end, self.reps[0], len));
+        }
+    }
+
+    /// Decode a literal, using the probabilities picked by the previous
+    /// byte and the position
+    /// Straight after a match, the byte that follows the match in the
+    /// dictionary is a good guess, so its bits are used too.
+    fn decode_literal(&mut self, rc: &mut RangeDecoder, out_buf: &Buf, pos: usize) -> u8 {
+        let previous = if pos > 0 { out_buf[out_buf.len() - 1] as usize } else { 0 };
+        let literal_state = (pos & ((1 << self.lp as usize) - 1)) << self.lc as usize |
+                            previous >> (8 - self.lc) as usize;
+        let start = LITERAL_CODER_LEN * literal_state;
+        let probabilities = &mut self.literals[start .. start + LITERAL_CODER_LEN];
+        let mut symbol = 1;
+        if self.state >= LITERAL_STATES {
+            let mut match_byte = out_buf[out_buf.len() - self.reps[0] - 1] as usize;
+            while symbol < 0x100 {
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                let bit = rc.bit(&mut probabilities[0x100 + (match_bit << 8) + symbol]);
+                symbol = symbol << 1 | bit;
+                if bit != match_bit {
+                    break;
+                }
+            }
+        }
+        while symbol < 0x100 {
+            symbol = symbol << 1 | rc.bit(&mut probabilities[symbol]);
+        }
+        symbol as u8
+    }
+
+    /// Decode the distance of a match, less one
+    /// A slot gives the top two bits and how many bits follow. Short
+    /// distances code those bits with probabilities, and long ones code
+    /// all but the lowest four directly.
+    fn decode_distance(&mut self, rc: &mut RangeDecoder, len: usize) -> usize {
+        let len_state = if len - MIN_MATCH_LEN < LEN_TO_DIST_STATES {
+            len - MIN_MATCH_LEN
+        } else {
+            LEN_TO_DIST_STATES - 1
+        };
+        let start = len_state << DIST_SLOT_BITS as usize;
+        let slot = rc.bit_tree(&mut self.dist_slots[start ..], DIST_SLOT_BITS);
+        if slot < 4 {
+            return slot;
+        }
+        let extra_bits = (slot as u32 >> 1) - 1;
+        let distance = (2 | (slot & 1)) << extra_bits as usize;
+        if (slot as u32) < END_DIST_MODEL_SLOT {
+            // each slot's tree sits just after the one before
+            let start = distance - slot;
+            distance + rc.reverse_bit_tree(&mut self.dist_special[start ..], extra_bits)
+        } else {
+            let high = rc.direct_bits(extra_bits - ALIGN_BITS) << ALIGN_BITS as usize;
+            distance + high + rc.reverse_bit_tree(self.align.as_mut_slice(), ALIGN_BITS)
+        }
+    }
+}
+
+/// Copy len bytes from distance + 1 back onto the end of out_buf
+fn copy_match(out_buf: &mut Buf, dict_start: usize, end: Option<usize>,
+              distance: usize, len: usize) -> GzResult<()> {
+    if distance >= out_buf.len() - dict_start {
+        return Err(GzError::InvalidDistance);
+    }
+    match end {
+        Some(end) if out_buf.len() + len > end => Err(GzError::CorruptData),
+        _ => {
+            try!(out_buf.copy_back_pointer(distance, len));
+            Ok(())
+        }
+    }
+}
+
+fn probabilities(len: usize) -> Vec<u16> {
+    repeat(PROBABILITY_INIT).take(len).collect()
+}
+
+fn read_be16(data: &[u8], pos: usize) -> usize {
+    (data[pos] as usize) << 8 | data[pos + 1] as usize
+}
+
+#[cfg(test)]
+mod lzma_tests {
+    use super::decompress_lzma;
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_lzma(from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    // printf 'It was the best of times, it was the worst of times\n' | lzma,
+    // which leaves the size unknown and writes an end marker
+    const TIMES: [u8; 58] = [
+        0x5d, 0x00, 0x00, 0x80, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0x00, 0x24, 0x9d, 0x00, 0x07, 0x73, 0x43, 0x85, 0x19, 0xc1, 0xbc, 0x61, 0x9e,
+        0x16, 0x43, 0x3f, 0x49, 0xa5, 0x27, 0x15, 0xb4, 0xf5, 0x10, 0x18, 0x54, 0xbf,
+        0xcd, 0x91, 0x38, 0x3b, 0x33, 0x8b, 0x52, 0x7c, 0x83, 0x65, 0x00, 0xa3, 0x1d,
+        0xff, 0xff, 0xfe, 0x04, 0xc0, 0x00];
+    const TEXT: &'static [u8] = b"It was the best of times, it was the worst of times\n";
+
+    fn with_size(size: u8) -> Vec<u8> {
+        let mut raw = TIMES.to_vec();
+        raw[5] = size;
+        for i in (6 .. 13) {
+            raw[i] = 0;
+        }
+        raw
+    }
+
+    #[test]
+    fn test_end_marker() {
+        assert_eq!(decompress(&TIMES), Ok(TEXT.to_vec()));
+    }
+
+    #[test]
+    fn test_known_size() {
+        assert_eq!(decompress(with_size(52).as_slice()), Ok(TEXT.to_vec()));
+        assert_eq!(decompress(with_size(20).as_slice()), Ok(TEXT[.. 20].to_vec()));
+        // the end marker turns up before the size is reached
+        assert_eq!(decompress(with_size(60).as_slice()), Err(GzError::SizeMismatch));
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(&TIMES[.. 10]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(&TIMES[.. 30]), Err(GzError::TruncatedData));
+        let mut bad_properties = TIMES.to_vec();
+        bad_properties[0] = 0xe1;
+        assert_eq!(decompress(bad_properties.as_slice()), Err(GzError::CorruptData));
+    }
+}
@@ -0,0 +1,207 @@
+#[doc="
+
+    Module: lzo
+
+    This decompresses blocks of LZO1X, the algorithm lzop uses. A block
+    is a run of instructions, each either a run of literal bytes or a
+    match copied from earlier output, with up to three more literals
+    packed into the match's last bits. What an instruction byte means
+    depends on what came before it: after a literal run, a small value
+    is a match a little over 2K back, while after a match with trailing
+    literals it's a two byte match close by. The block ends with a
+    match of length 3 and distance 0.
+
+"]
+use cvec::Buf;
+use error::{GzError, GzResult};
+
+// a first byte above this is a literal run, before any instruction
+const FIRST_LITERAL_RUN: u8 = 17;
+// instructions are told apart by these ranges of their first byte
+const M1_MAX: usize = 15;
+const M3_MIN: usize = 32;
+const M2_MIN: usize = 64;
+// the states after an instruction: how many literals it ended with, or
+// this after a literal run of four or more
+const STATE_LITERAL_RUN: usize = 4;
+const MIN_LITERAL_RUN: usize = 3;
+// one byte matches straight after a literal run start this far back
+const M1_LITERAL_RUN_DISTANCE: usize = 0x801;
+// far matches are at least this far back, except the end marker
+const M4_MIN_DISTANCE: usize = 0x4000;
+const END_MARKER_LEN: usize = 3;
+
+/// Decompress a block of LZO1X onto the end of out_buf
+/// Matches may only reach back within the block, which must expand to
+/// no more than max_len bytes.
+pub fn decompress_lzo1x(block: &[u8], out_buf: &mut Buf, max_len: usize) -> GzResult<()> {
+    let block_start = out_buf.len();
+    let mut pos = 0;
+    let mut state = 0;
+    if block.len() > 0 && block[0] > FIRST_LITERAL_RUN {
+        let literal_len = (block[0] - FIRST_LITERAL_RUN) as usize;
+        pos += 1;
+        try!(copy_literals(block, &mut pos, literal_len, out_buf, block_start + max_len));
+        state = if literal_len < STATE_LITERAL_RUN { literal_len } else { STATE_LITERAL_RUN };
+    }
+
+    loop {
+        let tag = try!(read_byte(block, &mut pos)) as usize;
+        let (match_len, distance, trailing) = if tag <= M1_MAX {
+            if state == 0 {
+                let literal_len = match tag {
+                    0 => try!(read_long_length(block, &mut pos, M1_MAX)),
+                    len => len
+                };
+                let literal_len = literal_len + MIN_LITERAL_RUN;
+                try!(copy_literals(block, &mut pos, literal_len, out_buf, block_start + max_len));
+                state = STATE_LITERAL_RUN;
+                continue;
+            }
+            let distance = (tag >> 2) + ((try!(read_byte(block, &mut pos)) as usize) << 2);
+            if state == STATE_LITERAL_RUN {
+                (3, distance + M1_LITERAL_RUN_DISTANCE, tag & 3)
+            } else {
+                (2, distance + 1, tag & 3)
+            }
+        } else if tag >= M2_MIN {
+            let distance = ((tag >> 2) & 7) + ((try!(read_byte(block, &mut pos)) as usize) << 3);
+            ((tag >> 5) + 1, distance + 1, tag & 3)
+        } else if tag >= M3_MIN {
+            let len = match tag & 31 {
+                0 => try!(read_long_length(block, &mut pos, 31)),
+                len => len
+            };
+            let distance = try!(read_le16(block, &mut pos));
+            (len + 2, (distance >> 2) + 1, distance & 3)
+        } else {
+            let len = match tag & 7 {
+                0 => try!(read_long_length(block, &mut pos, 7)),
+                len => len
+            };
+            let low_bits = try!(read_le16(block, &mut pos));
+            let distance = ((tag & 8) << 11) + (low_bits >> 2);
+            if distance == 0 {
+                if len + 2 != END_MARKER_LEN || pos != block.len() {
+                    return Err(GzError::CorruptData);
+                }
+                return Ok(());
+            }
+            (len + 2, distance + M4_MIN_DISTANCE, low_bits & 3)
+        };
+
+        if distance > out_buf.len() - block_start {
+            return Err(GzError::InvalidDistance);
+        }
+        if out_buf.len() + match_len > block_start + max_len {
+            return Err(GzError::SizeMismatch);
+        }
+        try!(out_buf.copy_back_pointer(distance - 1, match_len));
+        try!(copy_literals(block, &mut pos, trailing, out_buf, block_start + max_len));
+        state = trailing;
+    }
+}
+
+/// Copy len literal bytes from pos in block onto the end of out_buf,
+/// which may grow no longer than limit
+fn copy_literals(block: &[u8], pos: &mut usize, len: usize, out_buf: &mut Buf,
+                 limit: usize) -> GzResult<()> {
+    if *pos + len > block.len() {
+        return Err(GzError::TruncatedData);
+    }
+    if out_buf.len() + len > limit {
+        return Err(GzError::SizeMismatch);
+    }
+    for &byte in block[*pos .. *pos + len].iter() {
+        try!(out_buf.try_push(byte));
+    }
+    *pos += len;
+    Ok(())
+}
+
+/// Read a length too long for its instruction byte: each zero byte adds
+/// 255, and the first byte that isn't zero ends it
+fn read_long_length(block: &[u8], pos: &mut usize, base: usize) -> GzResult<usize> {
+    let mut len = base;
+    loop {
+        let byte = try!(read_byte(block, pos)) as usize;
+        if byte != 0 {
+            return Ok(len + byte);
+        }
+        len += 255;
+    }
+}
+
+fn read_byte(block: &[u8], pos: &mut usize) -> GzResult<u8> {
+    if *pos >= block.len() {
+        return Err(GzError::TruncatedData);
+    }
+    *pos += 1;
+    Ok(block[*pos - 1])
+}
+
+fn read_le16(block: &[u8], pos: &mut usize) -> GzResult<usize> {
+    let low = try!(read_byte(block, pos)) as usize;
+    let high = try!(read_byte(block, pos)) as usize;
+    Ok(low | high << 8)
+}
+
+#[cfg(test)]
+mod lzo_tests {
+    use std::iter::repeat;
+    use super::decompress_lzo1x;
+    use cvec::{CVec, Buf};
+    use error::GzError;
+
+    fn decompress(block: &[u8], max_len: usize) -> Result<Vec<u8>, GzError> {
+        let mut out_buf: Buf = CVec::with_capacity(max_len).unwrap();
+        decompress_lzo1x(block, &mut out_buf, max_len).map(|()| out_buf.iter().map(|&b| b).collect())
+    }
+
+    // a literal run, then near matches with and without trailing literals
+    const TIMES: [u8; 69] = [
+        0x2c, 0x49, 0x74, 0x20, 0x77, 0x61, 0x73, 0x20, 0x74, 0x68, 0x65, 0x20, 0x62,
+        0x65, 0x73, 0x74, 0x20, 0x6f, 0x66, 0x20, 0x74, 0x69, 0x6d, 0x65, 0x73, 0x2c,
+        0x20, 0x69, 0x28, 0x67, 0x00, 0x77, 0x6f, 0x72, 0x36, 0x6a, 0x00, 0x61, 0x67,
+        0x0c, 0x00, 0x40, 0x03, 0x03, 0x77, 0x69, 0x73, 0x64, 0x6f, 0x6d, 0x32, 0x64,
+        0x00, 0x09, 0x66, 0x6f, 0x6f, 0x6c, 0x69, 0x73, 0x68, 0x6e, 0x65, 0x73, 0x73,
+        0x0a, 0x11, 0x00, 0x00];
+    const TEXT: &'static [u8] = b"It was the best of times, it was the worst of times, it was \
+                                  the age of wisdom, it was the age of foolishness\n";
+
+    #[test]
+    fn test_matches() {
+        assert_eq!(decompress(&TIMES, TEXT.len()), Ok(TEXT.to_vec()));
+        assert_eq!(decompress(&TIMES, TEXT.len() - 1), Err(GzError::SizeMismatch));
+    }
+
+    #[test]
+    fn test_long_lengths() {
+        // a literal run of 300, a match of 20000 from 300 back, and a far
+        // match of 5 from just over 16K back
+        let mut block = vec![0x00, 0x00, 0x1b];
+        block.extend((0 .. 300).map(|i| (i % 251) as u8 + 1));
+        block.push(0x20);
+        block.extend(repeat(0).take(78));
+        block.push_all(&[0x4d, 0xac, 0x04]);
+        block.push_all(&[0x13, 0xd0, 0x01]);
+        block.push_all(&[0x11, 0x00, 0x00]);
+        let expected: Vec<u8> = (0 .. 20305).map(|i| (i % 300 % 251) as u8 + 1).collect();
+        assert_eq!(decompress(block.as_slice(), 20305), Ok(expected));
+
+        // a match from before the start of the block
+        let mut too_far = block.clone();
+        too_far[383] = 0xb0;
+        assert_eq!(decompress(too_far.as_slice(), 20305), Err(GzError::InvalidDistance));
+    }
+
+    #[test]
+    fn test_end_marker() {
+        assert_eq!(decompress(&[0x11, 0x00, 0x00], 0), Ok(Vec::new()));
+        assert_eq!(decompress(&[0x15, 0x61, 0x62, 0x63, 0x64, 0x11, 0x00], 4),
+                   Err(GzError::TruncatedData));
+        // anything after the end marker
+        assert_eq!(decompress(&[0x15, 0x61, 0x62, 0x63, 0x64, 0x11, 0x00, 0x00, 0x00], 4),
+                   Err(GzError::CorruptData));
+    }
+}
@@ -0,0 +1,418 @@
+#[doc="
+
+    Module: lzop
+
+    This reads lzop files. Each file compressed into one starts with
+    the magic number and a header giving its name, the method and
+    which checksums to expect, then holds blocks of up to 256K each,
+    compressed with LZO1X or stored if that didn't help. A block may
+    carry an Adler-32 or CRC32 of its data before and after
+    compression. lzop writes several files into one when asked, one
+    after another, so they are listed like the files of an archive.
+
+"]
+use std::usize;
+use adler32;
+use cvec::Buf;
+use crc32;
+use error::{GzError, GzResult};
+use limits;
+use lzo;
+
+// These constants are defined by lzop
+const MAGIC: &'static [u8] = b"\x89LZO\x00\r\n\x1a\n";
+// older versions leave out some of the header fields
+const MIN_VERSION: u16 = 0x0900;
+const VERSION_WITH_LEVEL: u16 = 0x0940;
+// the newest lzop we can read the files of
+const MAX_VERSION_NEEDED: u16 = 0x1040;
+const METHOD_LZO1X_1: u8 = 1;
+const METHOD_LZO1X_1_15: u8 = 2;
+const METHOD_LZO1X_999: u8 = 3;
+
+const FLAG_ADLER32_D: u32 = 0x0001;
+const FLAG_ADLER32_C: u32 = 0x0002;
+const FLAG_EXTRA_FIELD: u32 = 0x0040;
+const FLAG_CRC32_D: u32 = 0x0100;
+const FLAG_CRC32_C: u32 = 0x0200;
+const FLAG_MULTIPART: u32 = 0x0400;
+const FLAG_FILTER: u32 = 0x0800;
+const FLAG_HEADER_CRC32: u32 = 0x1000;
+// the rest of the low bits are unused, and the high ones say which OS
+// and character set the file came from
+const FLAG_RESERVED: u32 = 0x000fc000;
+// lzop never writes blocks bigger than this
+const MAX_BLOCK_LEN: usize = 64 * 1024 * 1024;
+
+/// A file in the lzop file
+#[derive(Show)]
+pub struct LzopEntry {
+    /// name of the file, with a nul after it for C
+    name: Vec<u8>,
+    /// which checksums its blocks carry
+    flags: u32,
+    /// where its first block starts, from the start of the buffer
+    offset: usize,
+    size: usize,
+}
+
+impl LzopEntry {
+    /// The name of the file, which is empty if it was compressed from
+    /// standard input
+    pub fn name(&self) -> &[u8] {
+        &self.name[.. self.name.len() - 1]
+    }
+
+    /// The name of the file, followed by a nul
+    pub fn c_name(&self) -> &[u8] {
+        &self.name[..]
+    }
+}
+
+/// A block of a file, as its header describes it
+struct Block {
+    /// length of the data once decompressed
+    len: usize,
+    /// where the data starts and ends, which is stored as it is if
+    /// that's len bytes
+    start: usize,
+    end: usize,
+    adler32: Option<u32>,
+    crc32: Option<u32>,
+    /// checksums of the data as it's stored
+    stored_adler32: Option<u32>,
+    stored_crc32: Option<u32>,
+}
+
+/// An lzop file, along with the files in it
+pub struct LzopArchive {
+    buffer: Buf,
+    entries: Vec<LzopEntry>,
+}
+
+impl LzopArchive {
+    /// Read the header of every file in buffer, and find where their
+    /// blocks are
+    /// Anything after the last file is ignored, as lzop does.
+    pub fn open(buffer: Buf) -> GzResult<LzopArchive> {
+        if buffer.len() < MAGIC.len() {
+            return Err(GzError::TruncatedHeader);
+        }
+        if !starts_with_magic(buffer.as_slice(), 0) {
+            return Err(GzError::BadMagic);
+        }
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while starts_with_magic(buffer.as_slice(), pos) {
+            let mut entry = try!(read_header(&buffer, pos + MAGIC.len()));
+            pos = entry.offset;
+            loop {
+                match try!(read_block(buffer.as_slice(), pos, entry.flags)) {
+                    Some(block) => {
+                        entry.size += block.len;
+                        pos = block.end;
+                    },
+                    None => { break; }
+                }
+            }
+            // skip the zero that ends the blocks
+            pos += 4;
+            entries.push(entry);
+        }
+        Ok(LzopArchive {
+            buffer: buffer,
+            entries: entries
+        })
+    }
+
+    /// The files in the lzop file
+    pub fn entries(&self) -> &Vec<LzopEntry> {
+        &self.entries
+    }
+
+    /// Decompress the file at index in entries
+    pub fn extract(&self, index: usize) -> GzResult<Buf> {
+        let entry = try_or!(self.entries.get(index), GzError::BadArchive);
+        let mut out_buf = try!(limits::output_buf(self.buffer.len(), entry.size));
+        try!(self.extract_onto(entry, &mut out_buf));
+        Ok(out_buf)
+    }
+
+    /// Decompress the blocks of entry onto the end of out_buf, checking
+    /// whichever checksums they carry
+    fn extract_onto(&self, entry: &LzopEntry, out_buf: &mut Buf) -> GzResult<()> {
+        let data = self.buffer.as_slice();
+        let mut pos = entry.offset;
+        while let Some(block) = try!(read_block(data, pos, entry.flags)) {
+            let stored = &data[block.start .. block.end];
+            if block.stored_adler32.map_or(false, |adler| adler32::sum(stored) != adler) ||
+               block.stored_crc32.map_or(false, |crc| {
+                   crc32::sum(self.buffer.limit_iter(block.start, block.end)) != crc
+               }) {
+                return Err(GzError::CrcMismatch);
+            }
+
+            let block_start = out_buf.len();
+            if stored.len() == block.len {
+                for &byte in stored.iter() {
+                    try!(out_buf.try_push(byte));
+                }
+            } else {
+                try!(lzo::decompress_lzo1x(stored, out_buf, block.len));
+                if out_buf.len() - block_start != block.len {
+                    return Err(GzError::SizeMismatch);
+                }
+            }
+
+            if block.adler32.map_or(false, |adler| {
+                   adler32::sum(&out_buf.as_slice()[block_start ..]) != adler
+               }) ||
+               block.crc32.map_or(false, |crc| {
+                   crc32::sum(out_buf.limit_iter(block_start, out_buf.len())) != crc
+               }) {
+                return Err(GzError::CrcMismatch);
+            }
+            pos = block.end;
+        }
+        Ok(())
+    }
+}
+
+/// Decompress the given lzop file
+/// Every file in it is decompressed, one after another, as lzop -dc does.
+pub fn decompress_lzop(buffer: Buf) -> GzResult<Buf> {
+    let input_len = buffer.len();
+    let archive = try!(LzopArchive::open(buffer));
+    let size = archive.entries().iter().fold(0, |size: usize, entry| {
+        size.checked_add(entry.size).unwrap_or(usize::MAX)
+    });
+    let mut out_buf = try!(limits::output_buf(input_len, size));
+    for entry in archive.entries().iter() {
+        try!(archive.extract_onto(entry, &mut out_buf));
+    }
+    Ok(out_buf)
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+fn starts_with_magic(data: &[u8], pos: usize) -> bool {
+    pos + MAGIC.len() <= data.len() && &data[pos .. pos + MAGIC.len()] == MAGIC
+}
+
+/// Read the header that starts at pos, just after the magic number,
+/// giving an entry whose offset is where its blocks start
+fn read_header(buffer: &Buf, pos: usize) -> GzResult<LzopEntry> {
+    let data = buffer.as_slice();
+    let header_start = pos;
+    let mut pos = pos;
+    let version = try_or!(read_be(data, &mut pos, 2), GzError::TruncatedHeader) as u16;
+    let _library_version = try_or!(read_be(data, &mut pos, 2), GzError::TruncatedHeader);
+    if version < MIN_VERSION {
+        return Err(GzError::UnsupportedMethod);
+    }
+    if version >= VERSION_WITH_LEVEL {
+        let version_needed = try_or!(read_be(data, &mut pos, 2), GzError::TruncatedHeader) as u16;
+        if version_needed > MAX_VERSION_NEEDED {
+            return Err(GzError::UnsupportedMethod);
+        }
+    }
+    let method = try_or!(read_be(data, &mut pos, 1), GzError::TruncatedHeader) as u8;
+    match method {
+        METHOD_LZO1X_1 | METHOD_LZO1X_1_15 | METHOD_LZO1X_999 => {},
+        _ => { return Err(GzError::UnsupportedMethod); }
+    }
+    if version >= VERSION_WITH_LEVEL {
+        let _level = try_or!(read_be(data, &mut pos, 1), GzError::TruncatedHeader);
+    }
+    let flags = try_or!(read_be(data, &mut pos, 4), GzError::TruncatedHeader);
+    if flags & FLAG_RESERVED != 0 {
+        return Err(GzError::CorruptData);
+    }
+    // filtered data and files split over several parts can't be
+    // read from the blocks alone
+    if flags & (FLAG_FILTER | FLAG_MULTIPART) != 0 {
+        return Err(GzError::UnsupportedMethod);
+    }
+    let _mode = try_or!(read_be(data, &mut pos, 4), GzError::TruncatedHeader);
+    let _mtime = try_or!(read_be(data, &mut pos, 4), GzError::TruncatedHeader);
+    if version >= VERSION_WITH_LEVEL {
+        let _mtime_high = try_or!(read_be(data, &mut pos, 4), GzError::TruncatedHeader);
+    }
+    let name_len = try_or!(read_be(data, &mut pos, 1), GzError::TruncatedHeader) as usize;
+    if pos + name_len > data.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    let mut name = data[pos .. pos + name_len].to_vec();
+    name.push(0);
+    pos += name_len;
+
+    let checksum = if flags & FLAG_HEADER_CRC32 != 0 {
+        crc32::sum(buffer.limit_iter(header_start, pos))
+    } else {
+        adler32::sum(&data[header_start .. pos])
+    };
+    if try_or!(read_be(data, &mut pos, 4), GzError::TruncatedHeader) != checksum {
+        return Err(GzError::CrcMismatch);
+    }
+    // nothing we need is kept in the extra field, so it and its
+    // checksum are skipped
+    if flags & FLAG_EXTRA_FIELD != 0 {
+        let extra_len = try_or!(read_be(data, &mut pos, 4), GzError::TruncatedHeader) as usize;
+        pos = match pos.checked_add(extra_len + 4) {
+            Some(end) if end <= data.len() => end,
+            _ => { return Err(GzError::TruncatedHeader); }
+        };
+    }
+
+    Ok(LzopEntry {
+        name: name,
+        flags: flags,
+        offset: pos,
+        size: 0
+    })
+}
+
+/// Read the header of the block at pos, or None if it's the zero that
+/// follows the last block
+fn read_block(data: &[u8], pos: usize, flags: u32) -> GzResult<Option<Block>> {
+    let mut pos = pos;
+    let len = try_or!(read_be(data, &mut pos, 4), GzError::TruncatedData) as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    let stored_len = try_or!(read_be(data, &mut pos, 4), GzError::TruncatedData) as usize;
+    if len > MAX_BLOCK_LEN || stored_len == 0 || stored_len > len {
+        return Err(GzError::CorruptData);
+    }
+    let mut block = Block {
+        len: len,
+        start: 0,
+        end: 0,
+        adler32: None,
+        crc32: None,
+        stored_adler32: None,
+        stored_crc32: None
+    };
+    if flags & FLAG_ADLER32_D != 0 {
+        block.adler32 = Some(try_or!(read_be(data, &mut pos, 4), GzError::TruncatedData));
+    }
+    if flags & FLAG_CRC32_D != 0 {
+        block.crc32 = Some(try_or!(read_be(data, &mut pos, 4), GzError::TruncatedData));
+    }
+    // stored blocks only have the checksums of their data
+    if stored_len < len {
+        if flags & FLAG_ADLER32_C != 0 {
+            block.stored_adler32 = Some(try_or!(read_be(data, &mut pos, 4), GzError::TruncatedData));
+        }
+        if flags & FLAG_CRC32_C != 0 {
+            block.stored_crc32 = Some(try_or!(read_be(data, &mut pos, 4), GzError::TruncatedData));
+        }
+    }
+    if pos + stored_len > data.len() {
+        return Err(GzError::TruncatedData);
+    }
+    block.start = pos;
+    block.end = pos + stored_len;
+    Ok(Some(block))
+}
+
+/// Read a big-endian number of len bytes, if the data doesn't end first
+fn read_be(data: &[u8], pos: &mut usize, len: usize) -> Option<u32> {
+    if *pos + len > data.len() {
+        return None;
+    }
+    let value = data[*pos .. *pos + len].iter().fold(0, |value, &byte| value << 8 | byte as u32);
+    *pos += len;
+    Some(value)
+}
+
+#[cfg(test)]
+mod lzop_tests {
+    use super::{LzopArchive, decompress_lzop};
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_lzop(from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    // printf 'hello, world\n' > hello.txt; lzop hello.txt, which stores it
+    // with an Adler-32
+    const HELLO: [u8; 76] = [
+        0x89, 0x4c, 0x5a, 0x4f, 0x00, 0x0d, 0x0a, 0x1a, 0x0a, 0x10, 0x30, 0x20, 0x80,
+        0x09, 0x40, 0x01, 0x05, 0x03, 0x00, 0x00, 0x01, 0x00, 0x00, 0x81, 0xa4, 0x65,
+        0x53, 0xf1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x09, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+        0x2e, 0x74, 0x78, 0x74, 0x69, 0x7e, 0x07, 0xad, 0x00, 0x00, 0x00, 0x0d, 0x00,
+        0x00, 0x00, 0x0d, 0x21, 0xe7, 0x04, 0x93, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c,
+        0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x0a, 0x00, 0x00, 0x00, 0x00];
+
+    // lzop --crc32 -C times.txt, with both checksums of the compressed block
+    const TIMES: [u8; 144] = [
+        0x89, 0x4c, 0x5a, 0x4f, 0x00, 0x0d, 0x0a, 0x1a, 0x0a, 0x10, 0x30, 0x20, 0x80,
+        0x09, 0x40, 0x01, 0x05, 0x03, 0x00, 0x03, 0x03, 0x00, 0x00, 0x81, 0xa4, 0x65,
+        0x53, 0xf1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x09, 0x74, 0x69, 0x6d, 0x65, 0x73,
+        0x2e, 0x74, 0x78, 0x74, 0x6a, 0x71, 0x07, 0xc0, 0x00, 0x00, 0x00, 0x6d, 0x00,
+        0x00, 0x00, 0x45, 0x24, 0x44, 0x26, 0x12, 0x69, 0x73, 0xf2, 0x57, 0xfe, 0x1c,
+        0x15, 0x0b, 0xf0, 0xed, 0xc3, 0xef, 0x2c, 0x49, 0x74, 0x20, 0x77, 0x61, 0x73,
+        0x20, 0x74, 0x68, 0x65, 0x20, 0x62, 0x65, 0x73, 0x74, 0x20, 0x6f, 0x66, 0x20,
+        0x74, 0x69, 0x6d, 0x65, 0x73, 0x2c, 0x20, 0x69, 0x28, 0x67, 0x00, 0x77, 0x6f,
+        0x72, 0x36, 0x6a, 0x00, 0x61, 0x67, 0x0c, 0x00, 0x40, 0x03, 0x03, 0x77, 0x69,
+        0x73, 0x64, 0x6f, 0x6d, 0x32, 0x64, 0x00, 0x09, 0x66, 0x6f, 0x6f, 0x6c, 0x69,
+        0x73, 0x68, 0x6e, 0x65, 0x73, 0x73, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00];
+    const TEXT: &'static [u8] = b"It was the best of times, it was the worst of times, it was \
+                                  the age of wisdom, it was the age of foolishness\n";
+
+    #[test]
+    fn test_stored_block() {
+        assert_eq!(decompress(&HELLO), Ok(b"hello, world\n".to_vec()));
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(decompress(&TIMES), Ok(TEXT.to_vec()));
+
+        // each of the CRC32 and Adler-32 of the data, then of the block
+        for &pos in [55, 59, 63, 67].iter() {
+            let mut corrupt = TIMES.to_vec();
+            corrupt[pos] ^= 1;
+            assert_eq!(decompress(corrupt.as_slice()), Err(GzError::CrcMismatch));
+        }
+        let mut bad_header = TIMES.to_vec();
+        bad_header[36] = b'T';
+        assert_eq!(decompress(bad_header.as_slice()), Err(GzError::CrcMismatch));
+    }
+
+    #[test]
+    fn test_several_files() {
+        let mut raw = HELLO.to_vec();
+        raw.push_all(&TIMES);
+        let archive = LzopArchive::open(from_slice(raw.as_slice())).unwrap();
+        let names: Vec<&[u8]> = archive.entries().iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, vec![b"hello.txt" as &[u8], b"times.txt"]);
+        assert_eq!(archive.extract(1).unwrap().iter().map(|&b| b).collect::<Vec<u8>>(),
+                   TEXT.to_vec());
+
+        // and decompressed as one, ignoring anything after the last
+        raw.push_all(b"trailing junk");
+        let mut expected = b"hello, world\n".to_vec();
+        expected.push_all(TEXT);
+        assert_eq!(decompress(raw.as_slice()), Ok(expected));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(decompress(&HELLO[.. 5]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Err(GzError::BadMagic));
+        assert_eq!(decompress(&HELLO[.. 30]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(&HELLO[.. 60]), Err(GzError::TruncatedData));
+        assert_eq!(decompress(&HELLO[.. 73]), Err(GzError::TruncatedData));
+
+        // an unknown method
+        let mut method = HELLO.to_vec();
+        method[15] = 0x80;
+        assert_eq!(decompress(method.as_slice()), Err(GzError::UnsupportedMethod));
+    }
+}
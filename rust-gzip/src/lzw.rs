@@ -0,0 +1,207 @@
+#[doc="
+
+    Module: lzw
+
+    This decompresses files made by UNIX compress (.Z). After a three
+    byte header they hold a single stream of LZW codes, packed least
+    significant bit first. Codes start out 9 bits wide and grow as the
+    dictionary fills, up to the limit given in the header. In block
+    mode, code 256 clears the dictionary and drops back to 9 bits.
+
+"]
+use std::iter::repeat;
+use cvec::Buf;
+use error::{GzError, GzResult};
+use limits;
+use gz_reader::GzBitReader;
+
+const LZW_MAGIC: [u8; 2] = [0x1f, 0x9d];
+const LZW_HEADER_LEN: usize = 3;
+const LZW_MAX_BITS_MASK: u8 = 0x1f;
+const LZW_BLOCK_MODE: u8 = 0x80;
+const INIT_BITS: u32 = 9;
+const MAX_BITS: u32 = 16;
+// in block mode, tells the decoder to start over with an empty dictionary
+const CLEAR_CODE: usize = 256;
+// compress manages about 3:1 on text, so this is a rough first allocation
+const EXPECTED_RATIO: usize = 3;
+// compress writes codes in groups of 8, so that a group of n bit codes
+// takes up exactly n bytes
+const GROUP_LEN: usize = 8;
+
+/// Decompress the given .Z file
+pub fn decompress_lzw(buffer: Buf) -> GzResult<Buf> {
+    if buffer.len() < LZW_HEADER_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    if buffer[0] != LZW_MAGIC[0] || buffer[1] != LZW_MAGIC[1] {
+        return Err(GzError::BadMagic);
+    }
+    let max_bits = (buffer[2] & LZW_MAX_BITS_MASK) as u32;
+    if max_bits < INIT_BITS || max_bits > MAX_BITS {
+        return Err(GzError::UnsupportedMethod);
+    }
+    let block_mode = buffer[2] & LZW_BLOCK_MODE != 0;
+    let first_free = if block_mode { CLEAR_CODE + 1 } else { CLEAR_CODE };
+    let table_len = 1 << max_bits as usize;
+
+    let capacity = (buffer.len() - LZW_HEADER_LEN) * EXPECTED_RATIO;
+    let mut out_buf = try!(limits::output_buf(buffer.len(), capacity));
+    let mut reader = match GzBitReader::at_bit(&buffer, LZW_HEADER_LEN * 8) {
+        Some(reader) => reader,
+        // compressing an empty file only writes the header
+        None => { return Ok(out_buf); }
+    };
+
+    // each code past 255 is an earlier code followed by one more byte
+    let mut prefixes: Vec<u16> = repeat(0).take(table_len).collect();
+    let mut suffixes: Vec<u8> = repeat(0).take(table_len).collect();
+    // a code's bytes come out last to first, so they are reversed here
+    let mut stack: Vec<u8> = Vec::new();
+
+    let mut bits = INIT_BITS;
+    let mut max_code = max_code_for(INIT_BITS, max_bits);
+    // how many codes have been read since the width last changed
+    let mut group_pos = 0;
+    let mut next_free = first_free;
+    let mut previous: Option<usize> = None;
+    let mut first_byte = 0u8;
+    loop {
+        if next_free > max_code {
+            skip_rest_of_group(&mut reader, bits, group_pos);
+            bits += 1;
+            max_code = max_code_for(bits, max_bits);
+            group_pos = 0;
+        }
+        // compress pads out the last byte, so fewer than bits bits left
+        // is the end of the stream
+        let code = match reader.read_bits(bits) {
+            Some(code) => code as usize,
+            None => { break; }
+        };
+        group_pos += 1;
+
+        let previous_code = match previous {
+            Some(previous_code) => previous_code,
+            None => {
+                // the first code after a fresh start must be a plain byte
+                if code >= CLEAR_CODE {
+                    return Err(GzError::InvalidCode);
+                }
+                first_byte = code as u8;
+                try!(out_buf.try_push(first_byte));
+                previous = Some(code);
+                continue;
+            }
+        };
+        if block_mode && code == CLEAR_CODE {
+            skip_rest_of_group(&mut reader, bits, group_pos);
+            bits = INIT_BITS;
+            max_code = max_code_for(INIT_BITS, max_bits);
+            group_pos = 0;
+            next_free = first_free;
+            previous = None;
+            continue;
+        }
+
+        let mut current = code;
+        if code >= next_free {
+            // the one code the decoder can't know yet is the one about to
+            // be added, which is the previous code followed by its own
+            // first byte
+            if code > next_free {
+                return Err(GzError::InvalidCode);
+            }
+            stack.push(first_byte);
+            current = previous_code;
+        }
+        while current > 255 {
+            stack.push(suffixes[current]);
+            current = prefixes[current] as usize;
+        }
+        first_byte = current as u8;
+        stack.push(first_byte);
+        while let Some(byte) = stack.pop() {
+            try!(out_buf.try_push(byte));
+        }
+
+        if next_free < table_len {
+            prefixes[next_free] = previous_code as u16;
+            suffixes[next_free] = first_byte;
+            next_free += 1;
+        }
+        previous = Some(code);
+    }
+    Ok(out_buf)
+}
+
+/// How big the next free code can get before codes must be widened
+/// At the widest setting that never happens, except that compress -b9
+/// still widens to 10 bits once its dictionary fills. Every decoder
+/// copes with that, so we do too.
+fn max_code_for(bits: u32, max_bits: u32) -> usize {
+    if bits == max_bits && bits != INIT_BITS {
+        1 << max_bits as usize
+    } else {
+        (1 << bits as usize) - 1
+    }
+}
+
+/// Skip the unused codes at the end of the group, which compress leaves
+/// behind whenever the code width changes
+fn skip_rest_of_group(reader: &mut GzBitReader, bits: u32, group_pos: usize) {
+    if group_pos % GROUP_LEN == 0 {
+        return;
+    }
+    for _ in (group_pos % GROUP_LEN .. GROUP_LEN) {
+        if reader.read_bits(bits).is_none() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod lzw_tests {
+    use super::decompress_lzw;
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_lzw(from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    #[test]
+    fn test_repeats() {
+        // printf 'TOBEORNOTTOBEORTOBEORNOT' | compress
+        let raw = [0x1f, 0x9d, 0x90, 0x54, 0x9e, 0x08, 0x29, 0xf2, 0x44, 0x8a, 0x93,
+                   0x27, 0x54, 0x02, 0x0e, 0x2c, 0xa8, 0x90, 0xa0, 0x41, 0x84];
+        assert_eq!(decompress(&raw), Ok(b"TOBEORNOTTOBEORTOBEORNOT".to_vec()));
+    }
+
+    #[test]
+    fn test_code_not_yet_in_dictionary() {
+        // printf 'aaaaaaa' | compress, where 257 and 258 each arrive just
+        // before they are added
+        let raw = [0x1f, 0x9d, 0x90, 0x61, 0x02, 0x0a, 0x0c, 0x03];
+        assert_eq!(decompress(&raw), Ok(b"aaaaaaa".to_vec()));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(decompress(&[0x1f, 0x9d, 0x90]), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_bad_header() {
+        assert_eq!(decompress(&[0x1f, 0x9d]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(&[0x1f, 0x8b, 0x90, 0x61]), Err(GzError::BadMagic));
+        assert_eq!(decompress(&[0x1f, 0x9d, 0x91, 0x61]), Err(GzError::UnsupportedMethod));
+    }
+
+    #[test]
+    fn test_invalid_code() {
+        // 'a', then code 300 when the next free code is 257
+        let raw = [0x1f, 0x9d, 0x90, 0x61, 0x58, 0x02];
+        assert_eq!(decompress(&raw), Err(GzError::InvalidCode));
+    }
+}
@@ -27,8 +27,11 @@ pub macro_rules! if_opt {
 #[macro_export]
 pub macro_rules! try_bail {
     ($expr: expr) => (match $expr {
-        Option::Some(v) => v,
-        Option::None => { bail!() },
+        Result::Ok(v) => v,
+        Result::Err(e) => {
+            set_last_error(e);
+            bail!()
+        },
     })
 }
 
@@ -51,3 +54,13 @@ pub macro_rules! try_ref_opt {
         }
     })
 }
+
+#[macro_export]
+pub macro_rules! try_or {
+    ($expr:expr, $err:expr) => (match $expr {
+        Option::Some(v) => v,
+        Option::None => {
+            return Result::Err($err);
+        }
+    })
+}
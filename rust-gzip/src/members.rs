@@ -0,0 +1,612 @@
+#[doc="
+
+    Module: members
+
+    This presents everything we can search inside as a list of
+    members, each a name and its decompressed contents, so that
+    matches can be reported against the file they were really in.
+    Zip, 7z, tar, ar and cpio archives have a member for every file
+    in them, as do lzop files and the payloads of RPM packages, while
+    a compressed file has a single member, unless it turns out to hold
+    a tar or cpio archive. Only gzip records a name for it, in its
+    header.
+
+    A member that is itself an archive or compressed file, like a jar
+    in a zip or the data.tar.xz of a Debian package, is opened in turn
+    and its members named after it, as in outer.zip!inner.jar!a.txt,
+    down to a depth the caller chooses. An archive that turns out to
+    hold itself is only opened once. Looking inside is only worth a
+    try, as a text file can start with a magic number by chance, so a
+    member that won't open or decompress is searched as it is.
+
+"]
+use ar::ArArchive;
+use cpio;
+use cpio::CpioArchive;
+use crc32;
+use cvec::Buf;
+use error::{GzError, GzResult};
+use format;
+use format::Format;
+use header;
+use lzop::LzopArchive;
+use rpm;
+use sevenzip::SevenZipArchive;
+use tar;
+use tar::TarArchive;
+use zip::ZipArchive;
+
+// the largest member we'll look inside, as each level of nesting keeps
+// its own copy in memory
+const MAX_NESTED_LEN: usize = 1 << 28;
+// what the names of nested members are joined with
+const NAME_SEPARATOR: u8 = b'!';
+
+/// Where the members come from
+enum Source {
+    /// a single compressed stream, until its member has been read
+    Compressed(Format, Option<Buf>),
+    /// a zip archive, and the index of the next entry to read
+    Zip(ZipArchive, usize),
+    /// a 7z archive, and the index of the next entry to read
+    SevenZip(SevenZipArchive, usize),
+    /// a tar archive, and the index of the next entry to read
+    Tar(TarArchive, usize),
+    /// an lzop file, and the index of the next file in it to read
+    Lzop(LzopArchive, usize),
+    /// an ar archive, and the index of the next entry to read
+    Ar(ArArchive, usize),
+    /// a cpio archive, perhaps the payload of an RPM package, and the
+    /// index of the next entry to read
+    Cpio(CpioArchive, usize),
+}
+
+pub struct Members {
+    source: Source,
+    /// what the source reads through a view: the decompressed stream a
+    /// tar or cpio source was found in, or an RPM package whose payload
+    /// isn't compressed
+    source_data: Option<Buf>,
+    /// the members of the current member, if it's an archive or
+    /// compressed file, which are read before moving on, along with
+    /// the current member's name and contents, which they read through
+    /// a view
+    nested: Option<(Vec<u8>, Buf, Box<Members>)>,
+    /// how many more levels of members inside members to open
+    max_depth: usize,
+    /// the length and CRC32 of the buffer these are the members of, and
+    /// of every buffer it was found inside
+    ancestors: Vec<(usize, u32)>,
+    /// name of the current member followed by a nul, just the nul if
+    /// it has no name
+    name: Vec<u8>,
+    /// contents of the current member, if it could be decompressed
+    data: Option<Buf>,
+}
+
+impl Members {
+    /// Work out what buffer holds, and get ready to read its members
+    /// Members that are archives or compressed files are opened too, up
+    /// to max_depth inside one another.
+    pub fn open(buffer: Buf, max_depth: usize) -> GzResult<Members> {
+        let ancestors = if max_depth > 0 {
+            vec![fingerprint(&buffer)]
+        } else {
+            Vec::new()
+        };
+        Members::open_inside(buffer, max_depth, ancestors)
+    }
+
+    /// Get ready to read the members of buffer, which was found inside
+    /// the ancestors given
+    fn open_inside(buffer: Buf, max_depth: usize, ancestors: Vec<(usize, u32)>)
+            -> GzResult<Members> {
+        let mut source_data = None;
+        let source = match format::detect(&buffer) {
+            Some(Format::Zip) => Source::Zip(try!(ZipArchive::open(buffer)), 0),
+            Some(Format::SevenZip) => Source::SevenZip(try!(SevenZipArchive::open(buffer)), 0),
+            Some(Format::Tar) => Source::Tar(try!(TarArchive::open(buffer)), 0),
+            Some(Format::Lzop) => Source::Lzop(try!(LzopArchive::open(buffer)), 0),
+            Some(Format::Ar) => Source::Ar(try!(ArArchive::open(buffer)), 0),
+            Some(Format::Cpio) => Source::Cpio(try!(CpioArchive::open(buffer)), 0),
+            Some(Format::Rpm) => {
+                let payload = try!(rpm::decompress_payload(&buffer));
+                source_data = Some(buffer);
+                Source::Cpio(try!(CpioArchive::open(payload)), 0)
+            },
+            Some(format) => Source::Compressed(format, Some(buffer)),
+            None => { return Err(GzError::BadMagic); }
+        };
+        Ok(Members {
+            source: source,
+            source_data: source_data,
+            nested: None,
+            max_depth: max_depth,
+            ancestors: ancestors,
+            name: vec![0],
+            data: None
+        })
+    }
+
+    /// Move on to the next member
+    /// Returns None once there are no more members. A member that can't
+    /// be decompressed gives an error, but the ones after it may still
+    /// be fine.
+    pub fn next(&mut self) -> Option<GzResult<()>> {
+        self.data = None;
+        let (nested_result, nested_name, fall_back) = match self.nested {
+            Some((ref outer_name, _, ref mut nested)) => {
+                let result = nested.next();
+                // a lone stream that won't decompress probably wasn't
+                // compressed at all, unless it went past the limits
+                let fall_back = match result {
+                    Some(Err(err)) => err != GzError::LimitExceeded && nested.is_compressed(),
+                    _ => false
+                };
+                (result, join_names(outer_name.as_slice(), nested.c_name()), fall_back)
+            },
+            None => (None, Vec::new(), false)
+        };
+        if fall_back {
+            let (mut outer_name, data, _) = self.nested.take().unwrap();
+            outer_name.push(0);
+            self.name = outer_name;
+            self.data = Some(data);
+            return Some(Ok(()));
+        }
+        match nested_result {
+            Some(result) => {
+                self.name = nested_name;
+                return Some(result);
+            },
+            None => { self.nested = None; }
+        }
+
+        let (mut name, result) = match self.source {
+            Source::Compressed(format, ref mut buffer) => {
+                let buffer = match buffer.take() {
+                    Some(buffer) => buffer,
+                    None => { return None; }
+                };
+                match format::decompress(format, view(&buffer)) {
+                    // a stream that won't decompress is likely to be text
+                    // that starts with the magic number by chance, like
+                    // zlib's two bytes or bzip2's "BZh", so it's searched
+                    // as it is, unless it went past the limits
+                    Err(err) if err != GzError::LimitExceeded => (Vec::new(), Ok(buffer)),
+                    result => {
+                        let name = if format == Format::Gzip {
+                            match header::parse_header(&buffer) {
+                                Ok(header) => header.fname.map(|name| name.into_bytes()),
+                                Err(_) => None
+                            }
+                        } else {
+                            None
+                        };
+                        (name.unwrap_or(Vec::new()), result)
+                    }
+                }
+            },
+            Source::Zip(ref zip, ref mut index) => {
+                if *index >= zip.entries().len() {
+                    return None;
+                }
+                let name = zip.entries()[*index].name().to_vec();
+                let result = zip.extract(*index);
+                *index += 1;
+                (name, result)
+            },
+            Source::SevenZip(ref mut sevenzip, ref mut index) => {
+                if *index >= sevenzip.entries().len() {
+                    return None;
+                }
+                let name = sevenzip.entries()[*index].name().to_vec();
+                let result = sevenzip.extract(*index);
+                *index += 1;
+                (name, result)
+            },
+            Source::Tar(ref tar, ref mut index) => {
+                if *index >= tar.entries().len() {
+                    return None;
+                }
+                let name = tar.entries()[*index].name().to_vec();
+                let result = tar.extract(*index);
+                *index += 1;
+                (name, result)
+            },
+            Source::Lzop(ref lzop, ref mut index) => {
+                if *index >= lzop.entries().len() {
+                    return None;
+                }
+                let name = lzop.entries()[*index].name().to_vec();
+                let result = lzop.extract(*index);
+                *index += 1;
+                (name, result)
+            },
+            Source::Ar(ref ar, ref mut index) => {
+                if *index >= ar.entries().len() {
+                    return None;
+                }
+                let name = ar.entries()[*index].name().to_vec();
+                let result = ar.extract(*index);
+                *index += 1;
+                (name, result)
+            },
+            Source::Cpio(ref cpio, ref mut index) => {
+                if *index >= cpio.entries().len() {
+                    return None;
+                }
+                let name = cpio.entries()[*index].name().to_vec();
+                let result = cpio.extract(*index);
+                *index += 1;
+                (name, result)
+            }
+        };
+        name.push(0);
+        self.name = name;
+        let data = match result {
+            Ok(data) => data,
+            Err(err) => { return Some(Err(err)); }
+        };
+
+        // a .tar.gz, .tar.bz2 or .tar.lzo is searched file by file instead,
+        // unless the tar archive won't open
+        if self.is_compressed() && tar::is_tar(&data) {
+            match TarArchive::open(view(&data)) {
+                Ok(archive) => {
+                    self.source = Source::Tar(archive, 0);
+                    self.source_data = Some(data);
+                    return self.next();
+                },
+                Err(_) => {}
+            }
+        }
+        // and so is a .cpio.gz, like an initramfs
+        if self.is_compressed() && cpio::is_cpio(&data) {
+            match CpioArchive::open(view(&data)) {
+                Ok(archive) => {
+                    self.source = Source::Cpio(archive, 0);
+                    self.source_data = Some(data);
+                    return self.next();
+                },
+                Err(_) => {}
+            }
+        }
+        match self.nested_fingerprint(&data) {
+            Some(fingerprint) => {
+                let mut ancestors = self.ancestors.clone();
+                ancestors.push(fingerprint);
+                match Members::open_inside(view(&data), self.max_depth - 1, ancestors) {
+                    Ok(nested) => {
+                        let mut outer_name = self.name.clone();
+                        outer_name.pop();
+                        self.nested = Some((outer_name, data, Box::new(nested)));
+                        return self.next();
+                    },
+                    Err(_) => {}
+                }
+            },
+            None => {}
+        }
+        self.data = Some(data);
+        Some(Ok(()))
+    }
+
+    /// The name of the current member followed by a nul, which is all
+    /// there is if it has no name
+    pub fn c_name(&self) -> &[u8] {
+        &self.name[..]
+    }
+
+    /// The contents of the current member
+    pub fn data(&self) -> Option<&Buf> {
+        match self.nested {
+            Some((_, _, ref nested)) => nested.data(),
+            None => self.data.as_ref()
+        }
+    }
+
+    fn is_compressed(&self) -> bool {
+        match self.source {
+            Source::Compressed(_, _) => true,
+            Source::Lzop(ref lzop, _) => lzop.entries().len() == 1,
+            _ => false
+        }
+    }
+
+    /// The length and CRC32 of data, if it's an archive or compressed
+    /// file to open in turn
+    /// It isn't if we're as deep as we may go, if it's too big to keep
+    /// a copy of, or if it's the same as a buffer we're already inside.
+    fn nested_fingerprint(&self, data: &Buf) -> Option<(usize, u32)> {
+        if self.max_depth == 0 || data.len() > MAX_NESTED_LEN {
+            return None;
+        }
+        // zlib's two byte header is too easily matched by chance to be
+        // worth trying on every member
+        match format::detect(data) {
+            Some(Format::Zlib) | None => { return None; },
+            Some(_) => {}
+        }
+        let fingerprint = fingerprint(data);
+        if self.ancestors.contains(&fingerprint) {
+            None
+        } else {
+            Some(fingerprint)
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+/// A buffer that reads the contents of buffer without owning them, for
+/// an archive that buffer is kept alongside
+fn view(buffer: &Buf) -> Buf {
+    unsafe { buffer.view(0, buffer.len()) }.unwrap()
+}
+
+fn fingerprint(buffer: &Buf) -> (usize, u32) {
+    (buffer.len(), crc32::sum(buffer.iter()))
+}
+
+/// The name of a member inside another, followed by a nul
+/// A member with no name of its own, like the contents of a gzip file
+/// that doesn't record one, just takes the outer name.
+fn join_names(outer_name: &[u8], c_name: &[u8]) -> Vec<u8> {
+    let mut name = outer_name.to_vec();
+    if c_name.len() > 1 {
+        name.push(NAME_SEPARATOR);
+    }
+    name.push_all(c_name);
+    name
+}
+
+#[cfg(test)]
+mod members_tests {
+    use std::iter::repeat;
+    use super::{Members, fingerprint};
+    use crc32;
+    use cvec::from_slice;
+    use error::{GzError, GzResult};
+
+    // printf 'hello\n' > greeting; gzip greeting
+    static NAMED_GZIP: &'static [u8] = &[
+        0x1f, 0x8b, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x67, 0x72,
+        0x65, 0x65, 0x74, 0x69, 0x6e, 0x67, 0x00, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0xe7, 0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00];
+
+    // "a.txt" holding "one\n" and "b.txt" holding "two\n", both stored
+    static ARCHIVE: &'static [u8] = &[
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x21, 0x46, 0x9f, 0xa8, 0x17, 0xf8, 0x04, 0x00, 0x00, 0x00, 0x04, 0x00,
+        0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x6f,
+        0x6e, 0x65, 0x0a, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x21, 0x46, 0x74, 0x08, 0x17, 0x96, 0x04, 0x00, 0x00,
+        0x00, 0x04, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74,
+        0x78, 0x74, 0x74, 0x77, 0x6f, 0x0a, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03,
+        0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x46, 0x9f, 0xa8,
+        0x17, 0xf8, 0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x05, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x01,
+        0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21,
+        0x46, 0x74, 0x08, 0x17, 0x96, 0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00,
+        0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x80, 0x01, 0x27, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74, 0x78, 0x74,
+        0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00,
+        0x66, 0x00, 0x00, 0x00, 0x4e, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    // "src/main.c" holding "int main;\n" and "README" holding "read me\n",
+    // in a tar archive that was then gzipped
+    static TAR_GZ: &'static [u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xed, 0xd3,
+        0x31, 0x0e, 0x83, 0x30, 0x0c, 0x85, 0x61, 0xcf, 0x3d, 0x45, 0x4e, 0xd0,
+        0x3a, 0x55, 0x9a, 0x0e, 0x9d, 0x90, 0x60, 0xec, 0xd2, 0x1b, 0x44, 0x94,
+        0x81, 0x01, 0x86, 0x00, 0xf7, 0x27, 0x64, 0x41, 0x62, 0xe9, 0x04, 0x52,
+        0xc5, 0xff, 0x2d, 0xcf, 0xf2, 0xe2, 0xc1, 0x7a, 0x43, 0xac, 0x6f, 0x5d,
+        0x68, 0xfb, 0x6b, 0x2d, 0xbb, 0xd1, 0xc4, 0x3b, 0x97, 0x33, 0xd9, 0xa6,
+        0xaa, 0xbd, 0xaf, 0x73, 0xde, 0x3f, 0xbd, 0x7d, 0x88, 0x51, 0x39, 0xc0,
+        0x34, 0x8c, 0x21, 0xa6, 0x93, 0x72, 0x4e, 0x6d, 0x3f, 0x9a, 0xe5, 0xff,
+        0xaf, 0x8b, 0xe0, 0x84, 0x3e, 0x55, 0x51, 0xbe, 0xab, 0x7d, 0x6f, 0xfc,
+        0xee, 0xbf, 0x6e, 0xfa, 0xef, 0xbd, 0x75, 0xf4, 0xff, 0x08, 0xb1, 0x09,
+        0x5f, 0xd3, 0x35, 0xb4, 0x1f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xe0, 0x4f, 0xcd, 0x3d, 0xd4, 0x7c, 0xfb, 0x00,
+        0x28, 0x00, 0x00];
+
+    /// Read every member, as (name, contents) pairs
+    fn read_all(mut members: Members) -> Vec<(Vec<u8>, GzResult<Vec<u8>>)> {
+        let mut result = Vec::new();
+        loop {
+            let contents = match members.next() {
+                None => { break; },
+                Some(Ok(())) => Ok(members.data().unwrap().iter().map(|&b| b).collect()),
+                Some(Err(err)) => Err(err)
+            };
+            result.push((members.c_name().to_vec(), contents));
+        }
+        result
+    }
+
+    fn read_members(raw: &[u8]) -> GzResult<Vec<(Vec<u8>, GzResult<Vec<u8>>)>> {
+        Members::open(from_slice(raw), 4).map(|members| read_all(members))
+    }
+
+    fn read_to_depth(raw: &[u8], max_depth: usize) -> Vec<(Vec<u8>, GzResult<Vec<u8>>)> {
+        read_all(Members::open(from_slice(raw), max_depth).unwrap())
+    }
+
+    /// An ar archive holding the given files
+    fn ar_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut raw = b"!<arch>\n".to_vec();
+        for &(name, contents) in files.iter() {
+            let header = format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                                 name, 0, 0, 0, 644, contents.len());
+            raw.push_all(header.as_bytes());
+            raw.push_all(contents);
+            if raw.len() % 2 != 0 {
+                raw.push(b'\n');
+            }
+        }
+        raw
+    }
+
+    /// A gzip file holding contents in a single stored block
+    fn gzip_stored(contents: &[u8]) -> Vec<u8> {
+        let len = contents.len();
+        let mut raw = vec![0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0x03, 0x01,
+                           len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8];
+        raw.push_all(contents);
+        let crc = crc32::sum(from_slice(contents).iter());
+        for &value in [crc, len as u32].iter() {
+            raw.push_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8,
+                           (value >> 24) as u8]);
+        }
+        raw
+    }
+
+    /// A tar header for a regular file of the given size, with nothing
+    /// after it
+    fn tar_header(name: &str, size: usize) -> Vec<u8> {
+        let mut header: Vec<u8> = repeat(0).take(512).collect();
+        for (i, &byte) in name.as_bytes().iter().enumerate() {
+            header[i] = byte;
+        }
+        for (i, &byte) in format!("{:011o}", size).as_bytes().iter().enumerate() {
+            header[124 + i] = byte;
+        }
+        header[156] = b'0';
+        for (i, &byte) in b"ustar\000".iter().enumerate() {
+            header[257 + i] = byte;
+        }
+        let sum = header.iter().fold(8 * b' ' as usize, |sum, &b| sum + b as usize);
+        for (i, &byte) in format!("{:06o}\0 ", sum).as_bytes().iter().enumerate() {
+            header[148 + i] = byte;
+        }
+        header
+    }
+
+    #[test]
+    fn test_gzip_name() {
+        assert_eq!(read_members(NAMED_GZIP),
+                   Ok(vec![(b"greeting\0".to_vec(), Ok(b"hello\n".to_vec()))]));
+    }
+
+    #[test]
+    fn test_gzip_without_name() {
+        let mut unnamed = NAMED_GZIP.to_vec();
+        // clear FNAME and drop the name
+        unnamed[3] = 0;
+        let unnamed: Vec<u8> = unnamed[.. 10].iter().chain(unnamed[19 ..].iter())
+                                             .map(|&b| b).collect();
+        assert_eq!(read_members(unnamed.as_slice()),
+                   Ok(vec![(b"\0".to_vec(), Ok(b"hello\n".to_vec()))]));
+    }
+
+    #[test]
+    fn test_zip_entries() {
+        assert_eq!(read_members(ARCHIVE),
+                   Ok(vec![(b"a.txt\0".to_vec(), Ok(b"one\n".to_vec())),
+                           (b"b.txt\0".to_vec(), Ok(b"two\n".to_vec()))]));
+    }
+
+    #[test]
+    fn test_bad_member_is_skipped() {
+        let mut corrupt = ARCHIVE.to_vec();
+        corrupt[35] = b'O';
+        assert_eq!(read_members(corrupt.as_slice()),
+                   Ok(vec![(b"a.txt\0".to_vec(), Err(GzError::CrcMismatch)),
+                           (b"b.txt\0".to_vec(), Ok(b"two\n".to_vec()))]));
+    }
+
+    #[test]
+    fn test_tar_gz_entries() {
+        assert_eq!(read_members(TAR_GZ),
+                   Ok(vec![(b"src/main.c\0".to_vec(), Ok(b"int main;\n".to_vec())),
+                           (b"README\0".to_vec(), Ok(b"read me\n".to_vec()))]));
+    }
+
+    #[test]
+    fn test_deb_entries() {
+        // an ar archive laid out like a Debian package
+        let deb = ar_archive(&[("debian-binary", &b"2.0\n"[..]), ("data.tar.gz", TAR_GZ),
+                               ("bad.gz", &NAMED_GZIP[.. 30])]);
+        assert_eq!(read_members(deb.as_slice()),
+                   Ok(vec![(b"debian-binary\0".to_vec(), Ok(b"2.0\n".to_vec())),
+                           (b"data.tar.gz!src/main.c\0".to_vec(), Ok(b"int main;\n".to_vec())),
+                           (b"data.tar.gz!README\0".to_vec(), Ok(b"read me\n".to_vec())),
+                           (b"bad.gz\0".to_vec(), Ok(NAMED_GZIP[.. 30].to_vec()))]));
+    }
+
+    #[test]
+    fn test_nested_depth() {
+        let inner = ar_archive(&[("data.tar.gz", TAR_GZ)]);
+        let outer = ar_archive(&[("a.zip", ARCHIVE), ("inner.a", inner.as_slice())]);
+        assert_eq!(read_to_depth(outer.as_slice(), 2),
+                   vec![(b"a.zip!a.txt\0".to_vec(), Ok(b"one\n".to_vec())),
+                        (b"a.zip!b.txt\0".to_vec(), Ok(b"two\n".to_vec())),
+                        (b"inner.a!data.tar.gz!src/main.c\0".to_vec(), Ok(b"int main;\n".to_vec())),
+                        (b"inner.a!data.tar.gz!README\0".to_vec(), Ok(b"read me\n".to_vec()))]);
+        assert_eq!(read_to_depth(outer.as_slice(), 1),
+                   vec![(b"a.zip!a.txt\0".to_vec(), Ok(b"one\n".to_vec())),
+                        (b"a.zip!b.txt\0".to_vec(), Ok(b"two\n".to_vec())),
+                        (b"inner.a!data.tar.gz\0".to_vec(), Ok(TAR_GZ.to_vec()))]);
+        assert_eq!(read_to_depth(outer.as_slice(), 0),
+                   vec![(b"a.zip\0".to_vec(), Ok(ARCHIVE.to_vec())),
+                        (b"inner.a\0".to_vec(), Ok(inner))]);
+    }
+
+    #[test]
+    fn test_nested_cycle() {
+        // as if the archive had been found inside data.tar.gz, so that
+        // opening it again would go round forever
+        let deb = ar_archive(&[("data.tar.gz", TAR_GZ)]);
+        let ancestors = vec![fingerprint(&from_slice(TAR_GZ))];
+        let members = Members::open_inside(from_slice(deb.as_slice()), 4, ancestors).unwrap();
+        assert_eq!(read_all(members), vec![(b"data.tar.gz\0".to_vec(), Ok(TAR_GZ.to_vec()))]);
+    }
+
+    #[test]
+    fn test_magic_without_archive() {
+        // members that only start like an archive or a compressed file
+        let fake_ar = b"!<arch>\nis how ar archives start\n";
+        let fake_bzip2 = b"BZh91AY&SY is how bzip2 files start\n";
+        let outer = ar_archive(&[("ar.txt", &fake_ar[..]), ("bzip2.txt", &fake_bzip2[..])]);
+        assert_eq!(read_members(outer.as_slice()),
+                   Ok(vec![(b"ar.txt\0".to_vec(), Ok(fake_ar.to_vec())),
+                           (b"bzip2.txt\0".to_vec(), Ok(fake_bzip2.to_vec()))]));
+    }
+
+    #[test]
+    fn test_stream_without_archive() {
+        // decompressed data that only starts like a cpio or tar archive is
+        // searched as a single stream
+        let fake_cpio = b"070701 is the magic number of newc cpio archives\n";
+        assert_eq!(read_members(gzip_stored(fake_cpio).as_slice()),
+                   Ok(vec![(b"\0".to_vec(), Ok(fake_cpio.to_vec()))]));
+        let truncated_tar = tar_header("a.txt", 1000);
+        assert_eq!(read_members(gzip_stored(truncated_tar.as_slice()).as_slice()),
+                   Ok(vec![(b"\0".to_vec(), Ok(truncated_tar))]));
+    }
+
+    #[test]
+    fn test_text_like_zlib() {
+        // "x^" passes for a zlib header, and "2" for a block header
+        let text = b"x^2 + y^2 = r^2\n";
+        assert_eq!(read_members(text), Ok(vec![(b"\0".to_vec(), Ok(text.to_vec()))]));
+    }
+
+    #[test]
+    fn test_text_like_compressed() {
+        let text = b"BZh is how bzip2 files start\n";
+        assert_eq!(read_members(text), Ok(vec![(b"\0".to_vec(), Ok(text.to_vec()))]));
+        // a damaged gzip file is searched without the name in its header
+        assert_eq!(read_members(&NAMED_GZIP[.. 30]),
+                   Ok(vec![(b"\0".to_vec(), Ok(NAMED_GZIP[.. 30].to_vec()))]));
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(read_members(b"just some text").err(), Some(GzError::BadMagic));
+    }
+}
@@ -0,0 +1,32 @@
+// C header for the incremental decoder in rust-gzip
+
+#ifndef __RGZIP_STREAM_H
+#define __RGZIP_STREAM_H
+
+typedef struct gzip_stream gzip_stream;
+
+/* Returns NULL if the decoder can't be allocated */
+gzip_stream * gzip_stream_new(void);
+
+/* All of these return -1 on failure, and decompress_error_code() in
+ * rgzip.h tells why */
+
+/* Returns 0 on success, -1 on failure */
+int gzip_stream_feed(gzip_stream * stream,
+    const void * buf,
+    int buf_len);
+
+/* Returns the number of bytes written to out, 0 once everything fed so
+ * far has been decompressed, -1 if the input is corrupt */
+int gzip_stream_read(gzip_stream * stream,
+    void * out,
+    int out_len);
+
+/* Call after the last feed and read. Returns 0 if the input ended
+ * cleanly after a complete gzip member, -1 if it was cut short */
+int gzip_stream_finish(const gzip_stream * stream);
+
+void gzip_stream_free(gzip_stream * stream);
+
+
+#endif
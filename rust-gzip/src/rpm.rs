@@ -0,0 +1,233 @@
+#[doc="
+
+    Module: rpm
+
+    This finds the payload of RPM packages. A package starts with a
+    fixed size lead, left over from RPM's first format, then two
+    headers of the same shape: the signature, padded out to eight
+    bytes, and the header proper. Each is an index of tagged entries
+    followed by a store of their values. The payload comes straight
+    after, a cpio archive compressed as the header's payload
+    compressor tag says, or with gzip if it doesn't say.
+
+"]
+use std::str;
+use cvec::Buf;
+use error::{GzError, GzResult};
+use format;
+use format::Format;
+
+// These constants are defined by the RPM package format
+const LEAD_MAGIC: &'static [u8] = &[0xed, 0xab, 0xee, 0xdb];
+const LEAD_LEN: usize = 96;
+const HEADER_MAGIC: &'static [u8] = &[0x8e, 0xad, 0xe8, 0x01];
+// magic, four reserved bytes, then the entry count and store size
+const HEADER_INTRO_LEN: usize = 16;
+const INDEX_ENTRY_LEN: usize = 16;
+const SIGNATURE_ALIGNMENT: usize = 8;
+
+const TAG_PAYLOAD_FORMAT: u32 = 1124;
+const TAG_PAYLOAD_COMPRESSOR: u32 = 1125;
+const TYPE_STRING: u32 = 6;
+
+const CPIO_FORMAT: &'static [u8] = b"cpio";
+const DEFAULT_COMPRESSOR: &'static [u8] = b"gzip";
+
+/// The index and store of one of the package's headers
+struct Header<'a> {
+    index: &'a [u8],
+    store: &'a [u8],
+}
+
+/// Decompress the payload of the RPM package in buffer, giving the
+/// cpio archive of its files
+/// A payload that isn't compressed is read in place, and must not be
+/// used once buffer is freed.
+pub fn decompress_payload(buffer: &Buf) -> GzResult<Buf> {
+    let (payload_start, compressor) = try!(read_headers(buffer.as_slice()));
+    let payload = try_or!(unsafe { buffer.view(payload_start, buffer.len()) },
+                          GzError::AllocationFailed);
+    match compressor {
+        Some(format) => format::decompress(format, payload),
+        None => Ok(payload)
+    }
+}
+
+/// Whether buffer starts with an RPM lead
+pub fn is_rpm(buffer: &Buf) -> bool {
+    buffer.as_slice().starts_with(LEAD_MAGIC)
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+/// Skip the lead and both headers, returning where the payload starts
+/// and what it's compressed with, if anything
+fn read_headers(data: &[u8]) -> GzResult<(usize, Option<Format>)> {
+    if data.len() < LEAD_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    if !data.starts_with(LEAD_MAGIC) {
+        return Err(GzError::BadMagic);
+    }
+    let (_, signature_end) = try!(read_header(data, LEAD_LEN));
+    let header_start = (signature_end + SIGNATURE_ALIGNMENT - 1)
+                       / SIGNATURE_ALIGNMENT * SIGNATURE_ALIGNMENT;
+    let (header, payload_start) = try!(read_header(data, header_start));
+
+    match try!(find_string(&header, TAG_PAYLOAD_FORMAT)) {
+        Some(name) if name != CPIO_FORMAT => { return Err(GzError::UnsupportedMethod); },
+        _ => {}
+    }
+    let compressor = try!(find_string(&header, TAG_PAYLOAD_COMPRESSOR));
+    let compressor = match str::from_utf8(compressor.unwrap_or(DEFAULT_COMPRESSOR)) {
+        Ok("gzip") => Some(Format::Gzip),
+        Ok("bzip2") => Some(Format::Bzip2),
+        Ok("xz") => Some(Format::Xz),
+        Ok("lzma") => Some(Format::Lzma),
+        Ok("zstd") => Some(Format::Zstd),
+        // rpm writes this for a payload it was told not to compress
+        Ok("identity") => None,
+        _ => { return Err(GzError::UnsupportedMethod); }
+    };
+    Ok((payload_start, compressor))
+}
+
+/// Read the header that starts at pos, returning it and where it ends
+fn read_header(data: &[u8], pos: usize) -> GzResult<(Header, usize)> {
+    if pos + HEADER_INTRO_LEN > data.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    if !data[pos ..].starts_with(HEADER_MAGIC) {
+        return Err(GzError::BadArchive);
+    }
+    let count = read_be32(data, pos + 8) as usize;
+    let store_len = read_be32(data, pos + 12) as usize;
+    let index_start = pos + HEADER_INTRO_LEN;
+    let store_start = try_or!(count.checked_mul(INDEX_ENTRY_LEN)
+                                   .and_then(|len| len.checked_add(index_start)),
+                              GzError::BadArchive);
+    let end = match store_start.checked_add(store_len) {
+        Some(end) if end <= data.len() => end,
+        _ => { return Err(GzError::TruncatedHeader); }
+    };
+    Ok((Header {
+        index: &data[index_start .. store_start],
+        store: &data[store_start .. end]
+    }, end))
+}
+
+/// The value of the string entry with the given tag, if there is one
+fn find_string<'a>(header: &Header<'a>, tag: u32) -> GzResult<Option<&'a [u8]>> {
+    for entry in header.index.chunks(INDEX_ENTRY_LEN) {
+        if read_be32(entry, 0) != tag {
+            continue;
+        }
+        let offset = read_be32(entry, 8) as usize;
+        if read_be32(entry, 4) != TYPE_STRING || offset >= header.store.len() {
+            return Err(GzError::BadArchive);
+        }
+        let value = &header.store[offset ..];
+        let len = try_or!(value.iter().position(|&b| b == 0), GzError::BadArchive);
+        return Ok(Some(&value[.. len]));
+    }
+    Ok(None)
+}
+
+fn read_be32(data: &[u8], pos: usize) -> u32 {
+    (0 .. 4).fold(0, |value, i| value << 8 | data[pos + i] as u32)
+}
+
+#[cfg(test)]
+mod rpm_tests {
+    use std::iter::repeat;
+    use super::{decompress_payload, is_rpm};
+    use cvec::from_slice;
+    use error::GzError;
+
+    // printf 'hello\n' | gzip -n
+    const HELLO_GZ: [u8; 26] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48, 0xcd,
+        0xc9, 0xc9, 0xe7, 0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00];
+
+    /// A header holding the given string entries
+    fn header(entries: &[(u32, &str)]) -> Vec<u8> {
+        let mut index = Vec::new();
+        let mut store = Vec::new();
+        for &(tag, value) in entries.iter() {
+            for &field in [tag, 6, store.len() as u32, 1].iter() {
+                index.push_all(&be32(field));
+            }
+            store.push_all(value.as_bytes());
+            store.push(0);
+        }
+        let mut raw = vec![0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+        raw.push_all(&be32(entries.len() as u32));
+        raw.push_all(&be32(store.len() as u32));
+        raw.push_all(index.as_slice());
+        raw.push_all(store.as_slice());
+        raw
+    }
+
+    fn be32(value: u32) -> [u8; 4] {
+        [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+    }
+
+    /// A package with a lead, a signature padded to eight bytes, the
+    /// header and the payload
+    fn package(entries: &[(u32, &str)], payload: &[u8]) -> Vec<u8> {
+        let mut raw = vec![0xed, 0xab, 0xee, 0xdb];
+        raw.extend(repeat(0).take(92));
+        raw.push_all(header(&[(1000, "sha")]).as_slice());
+        while raw.len() % 8 != 0 {
+            raw.push(0);
+        }
+        raw.push_all(header(entries).as_slice());
+        raw.push_all(payload);
+        raw
+    }
+
+    fn payload(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_payload(&from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    #[test]
+    fn test_compressors() {
+        let raw = package(&[(1000, "name"), (1124, "cpio"), (1125, "gzip")], &HELLO_GZ);
+        assert!(is_rpm(&from_slice(raw.as_slice())));
+        assert_eq!(payload(raw.as_slice()), Ok(b"hello\n".to_vec()));
+        // gzip is assumed when no compressor is given
+        assert_eq!(payload(package(&[(1000, "name")], &HELLO_GZ).as_slice()),
+                   Ok(b"hello\n".to_vec()));
+        assert_eq!(payload(package(&[(1125, "identity")], b"070701").as_slice()),
+                   Ok(b"070701".to_vec()));
+
+        assert_eq!(payload(package(&[(1125, "lzop")], &HELLO_GZ).as_slice()),
+                   Err(GzError::UnsupportedMethod));
+        assert_eq!(payload(package(&[(1124, "drpm")], &HELLO_GZ).as_slice()),
+                   Err(GzError::UnsupportedMethod));
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        let raw = package(&[(1125, "gzip")], &HELLO_GZ);
+        assert_eq!(payload(&raw[.. 90]), Err(GzError::TruncatedHeader));
+
+        let mut bad_lead = raw.clone();
+        bad_lead[0] = 0xee;
+        assert_eq!(payload(bad_lead.as_slice()), Err(GzError::BadMagic));
+
+        // the signature is at 96 and the header at 136
+        let mut bad_magic = raw.clone();
+        bad_magic[136] = 0;
+        assert_eq!(payload(bad_magic.as_slice()), Err(GzError::BadArchive));
+
+        // a string entry whose value starts past the end of the store
+        let mut bad_string = raw.clone();
+        bad_string[163] = 0x20;
+        assert_eq!(payload(bad_string.as_slice()), Err(GzError::BadArchive));
+
+        assert_eq!(payload(&raw[.. 160]), Err(GzError::TruncatedHeader));
+    }
+}
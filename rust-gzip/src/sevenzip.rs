@@ -0,0 +1,995 @@
+#[doc="
+
+    Module: sevenzip
+
+    This reads 7z archives. A short signature header at the start
+    points to the header at the end, which may itself be compressed
+    like a file would be. The header lists folders, each a run of
+    packed data and the coder that unpacks it, and then the files,
+    whose contents lie one after another in the output of a folder.
+    Folders that are stored, or compressed with LZMA, LZMA2 or
+    deflate, can be read, and every folder and file is checked
+    against the CRC32 the header gives for it.
+
+"]
+use std::iter::repeat;
+use cvec::{CVec, Buf};
+use crc32;
+use error::{GzError, GzResult};
+use limits;
+use gz_reader::GzBitReader;
+use inflate::inflate;
+use lzma;
+
+// These constants are defined by the 7z format
+const SIGNATURE: &'static [u8] = b"7z\xbc\xaf\x27\x1c";
+const SIGNATURE_HEADER_LEN: usize = 32;
+const MAJOR_VERSION: u8 = 0;
+
+// the properties the header is made of
+const ID_END: u8 = 0x00;
+const ID_HEADER: u8 = 0x01;
+const ID_ARCHIVE_PROPERTIES: u8 = 0x02;
+const ID_ADDITIONAL_STREAMS_INFO: u8 = 0x03;
+const ID_MAIN_STREAMS_INFO: u8 = 0x04;
+const ID_FILES_INFO: u8 = 0x05;
+const ID_PACK_INFO: u8 = 0x06;
+const ID_UNPACK_INFO: u8 = 0x07;
+const ID_SUBSTREAMS_INFO: u8 = 0x08;
+const ID_SIZE: u8 = 0x09;
+const ID_CRC: u8 = 0x0a;
+const ID_FOLDER: u8 = 0x0b;
+const ID_CODERS_UNPACK_SIZE: u8 = 0x0c;
+const ID_NUM_UNPACK_STREAM: u8 = 0x0d;
+const ID_EMPTY_STREAM: u8 = 0x0e;
+const ID_EMPTY_FILE: u8 = 0x0f;
+const ID_NAME: u8 = 0x11;
+const ID_ENCODED_HEADER: u8 = 0x17;
+
+// the flags byte in front of each coder
+const CODER_ID_LEN_MASK: u8 = 0x0f;
+const CODER_IS_COMPLEX: u8 = 0x10;
+const CODER_HAS_PROPERTIES: u8 = 0x20;
+const CODER_HAS_ALTERNATIVES: u8 = 0x80;
+
+const CODER_COPY: &'static [u8] = &[0x00];
+const CODER_LZMA2: &'static [u8] = &[0x21];
+const CODER_LZMA: &'static [u8] = &[0x03, 0x01, 0x01];
+const CODER_DEFLATE: &'static [u8] = &[0x04, 0x01, 0x08];
+const CODER_AES: &'static [u8] = &[0x06, 0xf1, 0x07, 0x01];
+// the literal settings and the dictionary size
+const LZMA_PROPERTIES_LEN: usize = 5;
+
+const REPLACEMENT_CHARACTER: u32 = 0xfffd;
+
+/// A file in the archive, as the header describes it
+#[derive(Show)]
+pub struct SevenZipEntry {
+    /// path of the file within the archive, with a nul after it for C
+    name: Vec<u8>,
+    /// the folder its contents are in, or None if it's empty
+    folder: Option<usize>,
+    /// where its contents start in the folder's output
+    offset: usize,
+    size: usize,
+    crc: Option<u32>,
+}
+
+impl SevenZipEntry {
+    /// The path of the file within the archive
+    pub fn name(&self) -> &[u8] {
+        &self.name[.. self.name.len() - 1]
+    }
+
+    /// The path of the file, followed by a nul
+    pub fn c_name(&self) -> &[u8] {
+        &self.name[..]
+    }
+}
+
+/// One step of unpacking a folder
+struct Coder {
+    id: Vec<u8>,
+    properties: Vec<u8>,
+}
+
+/// A run of packed data and the coders that unpack it
+struct Folder {
+    coders: Vec<Coder>,
+    /// how many outputs the coders have between them, and which of
+    /// them feed another coder rather than being the folder's output
+    out_count: usize,
+    bound_outputs: Vec<usize>,
+    /// how many packed streams the coders read, and where each starts
+    /// after the signature header, along with its length
+    packed_count: usize,
+    packed: Vec<(usize, usize)>,
+    /// the length of every coder output, then of the folder's output
+    unpack_sizes: Vec<usize>,
+    unpack_size: usize,
+    crc: Option<u32>,
+}
+
+/// One of the files a folder's output is split into
+struct Substream {
+    size: usize,
+    crc: Option<u32>,
+}
+
+/// The folders, and the files each one's output is split into
+struct StreamsInfo {
+    folders: Vec<Folder>,
+    substreams: Vec<Vec<Substream>>,
+}
+
+/// A 7z archive, along with the files it lists
+/// Directories are left out, as they hold nothing to search
+pub struct SevenZipArchive {
+    buffer: Buf,
+    folders: Vec<Folder>,
+    entries: Vec<SevenZipEntry>,
+    /// the folder unpacked last, as whole folders have to be unpacked
+    /// and the files in one are usually read one after another
+    unpacked: Option<(usize, GzResult<Buf>)>,
+}
+
+impl SevenZipArchive {
+    /// Read the header of the archive in buffer, unpacking it first if
+    /// it was compressed
+    pub fn open(buffer: Buf) -> GzResult<SevenZipArchive> {
+        let (folders, entries) = match try!(read_signature_header(&buffer)) {
+            Some(header) => {
+                let header = if header[0] == ID_ENCODED_HEADER {
+                    try!(unpack_header(&header[1 ..], &buffer))
+                } else {
+                    header
+                };
+                let mut reader = HeaderReader::new(header.as_slice());
+                try!(reader.expect(ID_HEADER));
+                try!(read_header(&mut reader))
+            },
+            // an empty archive has no header at all
+            None => (Vec::new(), Vec::new())
+        };
+        Ok(SevenZipArchive {
+            buffer: buffer,
+            folders: folders,
+            entries: entries,
+            unpacked: None
+        })
+    }
+
+    /// The files in the archive
+    pub fn entries(&self) -> &Vec<SevenZipEntry> {
+        &self.entries
+    }
+
+    /// Decompress the file at index in entries
+    /// This unpacks the whole folder the file is in, unless it was the
+    /// last one unpacked.
+    pub fn extract(&mut self, index: usize) -> GzResult<Buf> {
+        let (folder, offset, size, crc) = match self.entries.get(index) {
+            Some(entry) => (entry.folder, entry.offset, entry.size, entry.crc),
+            None => { return Err(GzError::BadArchive); }
+        };
+        let folder = match folder {
+            Some(folder) => folder,
+            None => { return CVec::with_capacity(0).ok_or(GzError::AllocationFailed); }
+        };
+        let cached = match self.unpacked {
+            Some((cached, _)) => cached == folder,
+            None => false
+        };
+        if !cached {
+            let result = unpack_folder(&self.buffer, &self.folders[folder]);
+            self.unpacked = Some((folder, result));
+        }
+        let unpacked = match self.unpacked {
+            Some((_, Ok(ref unpacked))) => unpacked,
+            Some((_, Err(err))) => { return Err(err); },
+            None => { return Err(GzError::BadArchive); }
+        };
+
+        if offset + size > unpacked.len() {
+            return Err(GzError::SizeMismatch);
+        }
+        let mut out = try_or!(CVec::with_capacity(size), GzError::AllocationFailed);
+        for &byte in unpacked.limit_iter(offset, offset + size) {
+            try!(out.try_push(byte));
+        }
+        if crc.map_or(false, |crc| crc32::sum(out.iter()) != crc) {
+            return Err(GzError::CrcMismatch);
+        }
+        Ok(out)
+    }
+}
+
+/// Reads the numbers, bit vectors and so on the header is made of
+/// Running out of header is a damaged archive rather than truncated
+/// data, as the header's CRC has already been checked.
+struct HeaderReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn new(data: &'a [u8]) -> HeaderReader<'a> {
+        HeaderReader {
+            data: data,
+            pos: 0
+        }
+    }
+
+    fn read_byte(&mut self) -> GzResult<u8> {
+        if self.pos >= self.data.len() {
+            return Err(GzError::BadArchive);
+        }
+        self.pos += 1;
+        Ok(self.data[self.pos - 1])
+    }
+
+    fn read_bytes(&mut self, len: usize) -> GzResult<&'a [u8]> {
+        if len > self.data.len() - self.pos {
+            return Err(GzError::BadArchive);
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len .. self.pos])
+    }
+
+    fn read_le(&mut self, len: usize) -> GzResult<u64> {
+        let bytes = try!(self.read_bytes(len));
+        Ok(read_le(bytes, 0, len))
+    }
+
+    /// Read a number of up to 64 bits, whose first byte says how many
+    /// more bytes follow by how many of its high bits are set
+    fn read_number(&mut self) -> GzResult<u64> {
+        let first = try!(self.read_byte());
+        let mut value = 0u64;
+        for i in 0 .. 8 {
+            let mask = 0x80u8 >> i;
+            if first & mask == 0 {
+                return Ok(value | ((first & (mask - 1)) as u64) << (8 * i));
+            }
+            value |= (try!(self.read_byte()) as u64) << (8 * i);
+        }
+        Ok(value)
+    }
+
+    fn read_size(&mut self) -> GzResult<usize> {
+        Ok(try!(self.read_number()) as usize)
+    }
+
+    /// Read how many of something there are, which can't be more than
+    /// the header has room to describe
+    fn read_count(&mut self) -> GzResult<usize> {
+        let count = try!(self.read_number());
+        if count > self.data.len() as u64 {
+            return Err(GzError::BadArchive);
+        }
+        Ok(count as usize)
+    }
+
+    /// Read count bits, high bit first
+    fn read_bits(&mut self, count: usize) -> GzResult<Vec<bool>> {
+        let mut bits = Vec::new();
+        let mut byte = 0;
+        for i in 0 .. count {
+            if i % 8 == 0 {
+                byte = try!(self.read_byte());
+            }
+            bits.push(byte & (0x80 >> (i % 8)) != 0);
+        }
+        Ok(bits)
+    }
+
+    /// Read count CRCs, any of which may be left out
+    fn read_digests(&mut self, count: usize) -> GzResult<Vec<Option<u32>>> {
+        let defined = if try!(self.read_byte()) != 0 {
+            repeat(true).take(count).collect()
+        } else {
+            try!(self.read_bits(count))
+        };
+        let mut digests = Vec::new();
+        for &is_defined in defined.iter() {
+            digests.push(if is_defined { Some(try!(self.read_le(4)) as u32) } else { None });
+        }
+        Ok(digests)
+    }
+
+    fn expect(&mut self, id: u8) -> GzResult<()> {
+        if try!(self.read_byte()) != id {
+            return Err(GzError::BadArchive);
+        }
+        Ok(())
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+/// Check the signature header, and find the header it points to,
+/// which is None for an empty archive
+fn read_signature_header(buffer: &Buf) -> GzResult<Option<Vec<u8>>> {
+    if buffer.len() < SIGNATURE_HEADER_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    let data = buffer.as_slice();
+    if &data[.. SIGNATURE.len()] != SIGNATURE {
+        return Err(GzError::BadMagic);
+    }
+    if data[SIGNATURE.len()] != MAJOR_VERSION {
+        return Err(GzError::UnsupportedMethod);
+    }
+    if crc32::sum(buffer.limit_iter(12, SIGNATURE_HEADER_LEN)) != read_le(data, 8, 4) as u32 {
+        return Err(GzError::CrcMismatch);
+    }
+    let header_offset = read_le(data, 12, 8) as usize;
+    let header_len = read_le(data, 20, 8) as usize;
+    if header_len == 0 {
+        return Ok(None);
+    }
+    let header_end = match header_offset.checked_add(header_len)
+                                        .and_then(|end| end.checked_add(SIGNATURE_HEADER_LEN)) {
+        Some(end) if end <= data.len() => end,
+        _ => { return Err(GzError::TruncatedData); }
+    };
+    let header_start = header_end - header_len;
+    if crc32::sum(buffer.limit_iter(header_start, header_end)) != read_le(data, 28, 4) as u32 {
+        return Err(GzError::CrcMismatch);
+    }
+    Ok(Some(data[header_start .. header_end].to_vec()))
+}
+
+/// Read the rest of the header, just after its ID, giving the folders
+/// and the entries for the files in them
+fn read_header(reader: &mut HeaderReader) -> GzResult<(Vec<Folder>, Vec<SevenZipEntry>)> {
+    let mut id = try!(reader.read_byte());
+    if id == ID_ARCHIVE_PROPERTIES {
+        while try!(reader.read_byte()) != ID_END {
+            let len = try!(reader.read_size());
+            try!(reader.read_bytes(len));
+        }
+        id = try!(reader.read_byte());
+    }
+    // the additional streams are only used for data kept outside the
+    // header, which 7-Zip never writes
+    if id == ID_ADDITIONAL_STREAMS_INFO {
+        try!(read_streams_info(reader));
+        id = try!(reader.read_byte());
+    }
+    let streams = if id == ID_MAIN_STREAMS_INFO {
+        let streams = try!(read_streams_info(reader));
+        id = try!(reader.read_byte());
+        streams
+    } else {
+        StreamsInfo {
+            folders: Vec::new(),
+            substreams: Vec::new()
+        }
+    };
+    let entries = if id == ID_FILES_INFO {
+        let entries = try!(read_files_info(reader, &streams.substreams));
+        id = try!(reader.read_byte());
+        entries
+    } else {
+        Vec::new()
+    };
+    if id != ID_END {
+        return Err(GzError::BadArchive);
+    }
+    Ok((streams.folders, entries))
+}
+
+/// Read the description of a compressed header, just after its ID,
+/// and unpack it
+fn unpack_header(data: &[u8], buffer: &Buf) -> GzResult<Vec<u8>> {
+    let mut reader = HeaderReader::new(data);
+    let streams = try!(read_streams_info(&mut reader));
+    let folder = try_or!(streams.folders.get(0), GzError::BadArchive);
+    let header = try!(unpack_folder(buffer, folder));
+    if header.len() == 0 {
+        return Err(GzError::BadArchive);
+    }
+    Ok(header.iter().map(|&b| b).collect())
+}
+
+/// Read where the packed streams are, the folders that unpack them,
+/// and the files each folder's output holds
+fn read_streams_info(reader: &mut HeaderReader) -> GzResult<StreamsInfo> {
+    let mut pack_pos = 0;
+    let mut pack_lens = Vec::new();
+    let mut folders = Vec::new();
+    let mut substreams = None;
+    loop {
+        match try!(reader.read_byte()) {
+            ID_PACK_INFO => {
+                let (pos, lens) = try!(read_pack_info(reader));
+                pack_pos = pos;
+                pack_lens = lens;
+            },
+            ID_UNPACK_INFO => { folders = try!(read_unpack_info(reader)); },
+            ID_SUBSTREAMS_INFO => {
+                substreams = Some(try!(read_substreams_info(reader, &folders)));
+            },
+            ID_END => { break; },
+            _ => { return Err(GzError::BadArchive); }
+        }
+    }
+
+    // each folder's packed streams follow on from the last folder's
+    let mut offset = pack_pos;
+    let mut next = 0;
+    for folder in folders.iter_mut() {
+        for _ in 0 .. folder.packed_count {
+            let len = *try_or!(pack_lens.get(next), GzError::BadArchive);
+            folder.packed.push((offset, len));
+            offset = try_or!(offset.checked_add(len), GzError::BadArchive);
+            next += 1;
+        }
+    }
+    // without substreams, each folder holds a single file
+    let substreams = match substreams {
+        Some(substreams) => substreams,
+        None => folders.iter().map(|folder| {
+            vec![Substream { size: folder.unpack_size, crc: folder.crc }]
+        }).collect()
+    };
+    Ok(StreamsInfo {
+        folders: folders,
+        substreams: substreams
+    })
+}
+
+/// Read where the packed streams start, after the signature header,
+/// and their lengths
+fn read_pack_info(reader: &mut HeaderReader) -> GzResult<(usize, Vec<usize>)> {
+    let pack_pos = try!(reader.read_size());
+    let count = try!(reader.read_count());
+    let mut lens = Vec::new();
+    loop {
+        match try!(reader.read_byte()) {
+            ID_SIZE => {
+                for _ in 0 .. count {
+                    lens.push(try!(reader.read_size()));
+                }
+            },
+            // the CRCs of the packed data are left to the unpacked data's
+            ID_CRC => { try!(reader.read_digests(count)); },
+            ID_END => { break; },
+            _ => { return Err(GzError::BadArchive); }
+        }
+    }
+    if lens.len() != count {
+        return Err(GzError::BadArchive);
+    }
+    Ok((pack_pos, lens))
+}
+
+/// Read the folders, along with how long their output is
+fn read_unpack_info(reader: &mut HeaderReader) -> GzResult<Vec<Folder>> {
+    try!(reader.expect(ID_FOLDER));
+    let count = try!(reader.read_count());
+    // folders kept outside the header aren't something 7-Zip writes
+    if try!(reader.read_byte()) != 0 {
+        return Err(GzError::BadArchive);
+    }
+    let mut folders = Vec::new();
+    for _ in 0 .. count {
+        folders.push(try!(read_folder(reader)));
+    }
+
+    try!(reader.expect(ID_CODERS_UNPACK_SIZE));
+    for folder in folders.iter_mut() {
+        for _ in 0 .. folder.out_count {
+            folder.unpack_sizes.push(try!(reader.read_size()));
+        }
+        // the one output that isn't bound to another coder's input
+        let output = try_or!((0 .. folder.out_count).find(|i| !folder.bound_outputs.contains(i)),
+                             GzError::BadArchive);
+        folder.unpack_size = folder.unpack_sizes[output];
+    }
+    loop {
+        match try!(reader.read_byte()) {
+            ID_CRC => {
+                let digests = try!(reader.read_digests(count));
+                for (folder, &crc) in folders.iter_mut().zip(digests.iter()) {
+                    folder.crc = crc;
+                }
+            },
+            ID_END => { break; },
+            _ => { return Err(GzError::BadArchive); }
+        }
+    }
+    Ok(folders)
+}
+
+/// Read a folder's coders and how they're connected
+fn read_folder(reader: &mut HeaderReader) -> GzResult<Folder> {
+    let coder_count = try!(reader.read_count());
+    let mut coders = Vec::new();
+    let mut in_count = 0;
+    let mut out_count = 0;
+    for _ in 0 .. coder_count {
+        let flags = try!(reader.read_byte());
+        if flags & CODER_HAS_ALTERNATIVES != 0 {
+            return Err(GzError::UnsupportedMethod);
+        }
+        let id = try!(reader.read_bytes((flags & CODER_ID_LEN_MASK) as usize)).to_vec();
+        if flags & CODER_IS_COMPLEX != 0 {
+            in_count += try!(reader.read_count());
+            out_count += try!(reader.read_count());
+        } else {
+            in_count += 1;
+            out_count += 1;
+        }
+        let properties = if flags & CODER_HAS_PROPERTIES != 0 {
+            let len = try!(reader.read_count());
+            try!(reader.read_bytes(len)).to_vec()
+        } else {
+            Vec::new()
+        };
+        coders.push(Coder {
+            id: id,
+            properties: properties
+        });
+    }
+    if out_count == 0 || in_count + 1 < out_count {
+        return Err(GzError::BadArchive);
+    }
+
+    // every output but one feeds another coder's input, and the inputs
+    // left over read the packed streams
+    let mut bound_outputs = Vec::new();
+    for _ in 0 .. out_count - 1 {
+        let _input = try!(reader.read_count());
+        bound_outputs.push(try!(reader.read_count()));
+    }
+    let packed_count = in_count + 1 - out_count;
+    if packed_count > 1 {
+        for _ in 0 .. packed_count {
+            try!(reader.read_count());
+        }
+    }
+    Ok(Folder {
+        coders: coders,
+        out_count: out_count,
+        bound_outputs: bound_outputs,
+        packed_count: packed_count,
+        packed: Vec::new(),
+        unpack_sizes: Vec::new(),
+        unpack_size: 0,
+        crc: None
+    })
+}
+
+/// Read how each folder's output is split into files, and their CRCs
+fn read_substreams_info(reader: &mut HeaderReader, folders: &Vec<Folder>)
+        -> GzResult<Vec<Vec<Substream>>> {
+    let mut counts: Vec<usize> = folders.iter().map(|_| 1).collect();
+    let mut id = try!(reader.read_byte());
+    if id == ID_NUM_UNPACK_STREAM {
+        for count in counts.iter_mut() {
+            *count = try!(reader.read_count());
+        }
+        id = try!(reader.read_byte());
+    }
+
+    // only the sizes of all but the last file in a folder are given, as
+    // the last one takes up the rest
+    let has_sizes = id == ID_SIZE;
+    let mut substreams = Vec::new();
+    for (folder, &count) in folders.iter().zip(counts.iter()) {
+        let mut streams = Vec::new();
+        if count > 0 {
+            let mut rest = folder.unpack_size;
+            for _ in 1 .. count {
+                if !has_sizes {
+                    return Err(GzError::BadArchive);
+                }
+                let size = try!(reader.read_size());
+                if size > rest {
+                    return Err(GzError::BadArchive);
+                }
+                rest -= size;
+                streams.push(Substream { size: size, crc: None });
+            }
+            // a folder with one file already has its CRC
+            let crc = if count == 1 { folder.crc } else { None };
+            streams.push(Substream { size: rest, crc: crc });
+        }
+        substreams.push(streams);
+    }
+    if has_sizes {
+        id = try!(reader.read_byte());
+    }
+
+    loop {
+        match id {
+            ID_CRC => {
+                let unknown = folders.iter().zip(substreams.iter())
+                                     .filter(|&(folder, streams)| {
+                                         streams.len() != 1 || folder.crc.is_none()
+                                     })
+                                     .fold(0, |total, (_, streams)| total + streams.len());
+                let digests = try!(reader.read_digests(unknown));
+                let mut digests = digests.iter();
+                for (folder, streams) in folders.iter().zip(substreams.iter_mut()) {
+                    if streams.len() == 1 && folder.crc.is_some() {
+                        continue;
+                    }
+                    for stream in streams.iter_mut() {
+                        stream.crc = *try_or!(digests.next(), GzError::BadArchive);
+                    }
+                }
+            },
+            ID_END => { break; },
+            _ => { return Err(GzError::BadArchive); }
+        }
+        id = try!(reader.read_byte());
+    }
+    Ok(substreams)
+}
+
+/// Read the names of the files, and which are empty or directories,
+/// and match them up with the files in the folders
+fn read_files_info(reader: &mut HeaderReader, substreams: &Vec<Vec<Substream>>)
+        -> GzResult<Vec<SevenZipEntry>> {
+    let count = try!(reader.read_count());
+    let mut names: Vec<Vec<u8>> = repeat(Vec::new()).take(count).collect();
+    let mut empty_streams: Vec<bool> = repeat(false).take(count).collect();
+    let mut empty_files: Vec<bool> = Vec::new();
+    loop {
+        let id = try!(reader.read_byte());
+        if id == ID_END {
+            break;
+        }
+        let len = try!(reader.read_size());
+        let mut property = HeaderReader::new(try!(reader.read_bytes(len)));
+        match id {
+            ID_EMPTY_STREAM => { empty_streams = try!(property.read_bits(count)); },
+            ID_EMPTY_FILE => {
+                let empty_count = empty_streams.iter().filter(|&&empty| empty).count();
+                empty_files = try!(property.read_bits(empty_count));
+            },
+            ID_NAME => {
+                // names kept outside the header aren't something 7-Zip writes
+                if try!(property.read_byte()) != 0 {
+                    return Err(GzError::BadArchive);
+                }
+                for name in names.iter_mut() {
+                    let mut units = Vec::new();
+                    loop {
+                        let unit = try!(property.read_le(2)) as u16;
+                        if unit == 0 {
+                            break;
+                        }
+                        units.push(unit);
+                    }
+                    *name = utf16_to_utf8(units.as_slice());
+                }
+            },
+            // times, attributes and the like don't matter to a search
+            _ => {}
+        }
+    }
+
+    let mut streams = Vec::new();
+    for (folder, folder_streams) in substreams.iter().enumerate() {
+        let mut offset = 0;
+        for stream in folder_streams.iter() {
+            streams.push((folder, offset, stream));
+            offset += stream.size;
+        }
+    }
+    let mut streams = streams.into_iter();
+    let mut empty_files = empty_files.into_iter();
+    let mut entries = Vec::new();
+    for (mut name, is_empty) in names.into_iter().zip(empty_streams.into_iter()) {
+        name.push(0);
+        if is_empty {
+            // anything empty that isn't a file is a directory
+            if empty_files.next() == Some(true) {
+                entries.push(SevenZipEntry {
+                    name: name,
+                    folder: None,
+                    offset: 0,
+                    size: 0,
+                    crc: None
+                });
+            }
+            continue;
+        }
+        let (folder, offset, stream) = try_or!(streams.next(), GzError::BadArchive);
+        entries.push(SevenZipEntry {
+            name: name,
+            folder: Some(folder),
+            offset: offset,
+            size: stream.size,
+            crc: stream.crc
+        });
+    }
+    Ok(entries)
+}
+
+/// Unpack a folder's packed stream with its coder, checking the result
+/// against the folder's CRC if it has one
+fn unpack_folder(buffer: &Buf, folder: &Folder) -> GzResult<Buf> {
+    // chains of coders, such as a BCJ filter in front of LZMA, can't be
+    // read
+    if folder.coders.len() != 1 || folder.packed.len() != 1 {
+        return Err(GzError::UnsupportedMethod);
+    }
+    let coder = &folder.coders[0];
+    let (offset, len) = folder.packed[0];
+    let end = match offset.checked_add(len)
+                          .and_then(|end| end.checked_add(SIGNATURE_HEADER_LEN)) {
+        Some(end) if end <= buffer.len() => end,
+        _ => { return Err(GzError::TruncatedData); }
+    };
+    let start = end - len;
+    let packed = &buffer.as_slice()[start .. end];
+
+    let mut out_buf = try!(limits::output_buf(packed.len(), folder.unpack_size));
+    let id = coder.id.as_slice();
+    if id == CODER_COPY {
+        for &byte in packed.iter() {
+            try!(out_buf.try_push(byte));
+        }
+    } else if id == CODER_LZMA {
+        if coder.properties.len() != LZMA_PROPERTIES_LEN {
+            return Err(GzError::CorruptData);
+        }
+        try!(lzma::decompress_lzma_with_size(packed, coder.properties[0], folder.unpack_size,
+                                             &mut out_buf));
+    } else if id == CODER_LZMA2 {
+        try!(lzma::decompress_lzma2(packed, &mut out_buf));
+    } else if id == CODER_DEFLATE {
+        let mut reader = try_or!(GzBitReader::new(buffer.limit_iter(start, end)),
+                                 GzError::TruncatedData);
+        try!(inflate(&mut reader, &mut out_buf));
+    } else if id == CODER_AES {
+        return Err(GzError::Encrypted);
+    } else {
+        return Err(GzError::UnsupportedMethod);
+    }
+
+    if out_buf.len() != folder.unpack_size {
+        return Err(GzError::SizeMismatch);
+    }
+    if folder.crc.map_or(false, |crc| crc32::sum(out_buf.iter()) != crc) {
+        return Err(GzError::CrcMismatch);
+    }
+    Ok(out_buf)
+}
+
+/// Turn a name from UTF-16 into UTF-8, replacing any surrogate that
+/// isn't part of a pair
+fn utf16_to_utf8(units: &[u16]) -> Vec<u8> {
+    let mut utf8 = Vec::new();
+    let mut i = 0;
+    while i < units.len() {
+        let unit = units[i] as u32;
+        i += 1;
+        let code = if unit >= 0xd800 && unit < 0xdc00 && i < units.len() &&
+                      units[i] >= 0xdc00 && units[i] < 0xe000 {
+            i += 1;
+            0x10000 + ((unit - 0xd800) << 10) + (units[i - 1] as u32 - 0xdc00)
+        } else if unit >= 0xd800 && unit < 0xe000 {
+            REPLACEMENT_CHARACTER
+        } else {
+            unit
+        };
+        if code < 0x80 {
+            utf8.push(code as u8);
+        } else if code < 0x800 {
+            utf8.push_all(&[0xc0 | (code >> 6) as u8, 0x80 | (code & 0x3f) as u8]);
+        } else if code < 0x10000 {
+            utf8.push_all(&[0xe0 | (code >> 12) as u8, 0x80 | (code >> 6 & 0x3f) as u8,
+                            0x80 | (code & 0x3f) as u8]);
+        } else {
+            utf8.push_all(&[0xf0 | (code >> 18) as u8, 0x80 | (code >> 12 & 0x3f) as u8,
+                            0x80 | (code >> 6 & 0x3f) as u8, 0x80 | (code & 0x3f) as u8]);
+        }
+    }
+    utf8
+}
+
+/// Read a little endian number of up to 8 bytes
+fn read_le(data: &[u8], pos: usize, len: usize) -> u64 {
+    (0 .. len).fold(0, |value, i| value | (data[pos + i] as u64) << (8 * i))
+}
+
+#[cfg(test)]
+mod sevenzip_tests {
+    use super::SevenZipArchive;
+    use cvec::from_slice;
+    use error::GzError;
+
+    // "docs/a.txt" holding "hello\n", "b.txt" holding "hello hello hello\n",
+    // an empty "empty.txt" and the directory "docs", each file stored in
+    // a folder of its own
+    static STORED: &'static [u8] = &[
+        0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c, 0x00, 0x03, 0xdf, 0x1c, 0x12, 0x3c,
+        0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf1, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x7c, 0x72, 0xa0, 0xe6, 0x68, 0x65, 0x6c, 0x6c,
+        0x6f, 0x0a, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x68, 0x65, 0x6c, 0x6c,
+        0x6f, 0x20, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x0a, 0x01, 0x04, 0x06, 0x00,
+        0x02, 0x09, 0x06, 0x12, 0x00, 0x07, 0x0b, 0x02, 0x00, 0x01, 0x01, 0x00,
+        0x01, 0x01, 0x00, 0x0c, 0x06, 0x12, 0x00, 0x08, 0x0a, 0x01, 0x20, 0x30,
+        0x3a, 0x36, 0x3b, 0x7c, 0x8a, 0xdf, 0x00, 0x00, 0x05, 0x04, 0x0e, 0x01,
+        0x30, 0x0f, 0x01, 0x80, 0x11, 0x41, 0x00, 0x64, 0x00, 0x6f, 0x00, 0x63,
+        0x00, 0x73, 0x00, 0x2f, 0x00, 0x61, 0x00, 0x2e, 0x00, 0x74, 0x00, 0x78,
+        0x00, 0x74, 0x00, 0x00, 0x00, 0x62, 0x00, 0x2e, 0x00, 0x74, 0x00, 0x78,
+        0x00, 0x74, 0x00, 0x00, 0x00, 0x65, 0x00, 0x6d, 0x00, 0x70, 0x00, 0x74,
+        0x00, 0x79, 0x00, 0x2e, 0x00, 0x74, 0x00, 0x78, 0x00, 0x74, 0x00, 0x00,
+        0x00, 0x64, 0x00, 0x6f, 0x00, 0x63, 0x00, 0x73, 0x00, 0x00, 0x00, 0x14,
+        0x22, 0x01, 0x00, 0x0e, 0xbd, 0xe5, 0x7e, 0xdb, 0x5e, 0xdd, 0x01, 0x0e,
+        0xbd, 0xe5, 0x7e, 0xdb, 0x5e, 0xdd, 0x01, 0x0e, 0xbd, 0xe5, 0x7e, 0xdb,
+        0x5e, 0xdd, 0x01, 0xef, 0xc0, 0xe4, 0x7e, 0xdb, 0x5e, 0xdd, 0x01, 0x12,
+        0x22, 0x01, 0x00, 0x0e, 0xbd, 0xe5, 0x7e, 0xdb, 0x5e, 0xdd, 0x01, 0x0e,
+        0xbd, 0xe5, 0x7e, 0xdb, 0x5e, 0xdd, 0x01, 0x0e, 0xbd, 0xe5, 0x7e, 0xdb,
+        0x5e, 0xdd, 0x01, 0xef, 0xc0, 0xe4, 0x7e, 0xdb, 0x5e, 0xdd, 0x01, 0x13,
+        0x22, 0x01, 0x00, 0x6f, 0xf9, 0xe5, 0x7e, 0xdb, 0x5e, 0xdd, 0x01, 0x6f,
+        0xf9, 0xe5, 0x7e, 0xdb, 0x5e, 0xdd, 0x01, 0x0e, 0xbd, 0xe5, 0x7e, 0xdb,
+        0x5e, 0xdd, 0x01, 0x6f, 0xf9, 0xe5, 0x7e, 0xdb, 0x5e, 0xdd, 0x01, 0x15,
+        0x12, 0x01, 0x00, 0x20, 0x80, 0xa4, 0x81, 0x20, 0x80, 0xa4, 0x81, 0x20,
+        0x80, 0xa4, 0x81, 0x10, 0x80, 0xed, 0x41, 0x00, 0x00];
+
+    // the same files in a single LZMA2 folder, with the header also
+    // compressed with LZMA2
+    static COMPRESSED: &'static [u8] = &[
+        0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c, 0x00, 0x03, 0x60, 0xb6, 0xe7, 0x74,
+        0xa2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x2b, 0xd2, 0xd2, 0x17, 0xe0, 0x00, 0x17, 0x00,
+        0x0f, 0x5d, 0x00, 0x34, 0x19, 0x49, 0xee, 0x8d, 0xdd, 0x41, 0xc0, 0x35,
+        0xb1, 0x69, 0xe3, 0xa7, 0x00, 0x00, 0x00, 0xe0, 0x00, 0xf1, 0x00, 0x83,
+        0x5d, 0x00, 0x00, 0x81, 0x33, 0x07, 0xae, 0x0f, 0xcf, 0x6e, 0xee, 0x8c,
+        0x07, 0xc8, 0x43, 0x7f, 0x41, 0x88, 0x9b, 0x83, 0xa7, 0x1b, 0xb4, 0x68,
+        0x36, 0x38, 0xf9, 0x64, 0x2b, 0x88, 0x52, 0xaa, 0x6e, 0x68, 0x1f, 0x68,
+        0x7f, 0xe0, 0x58, 0x24, 0xee, 0x6e, 0xeb, 0x3a, 0xd8, 0x4e, 0x20, 0xb8,
+        0x89, 0xd7, 0x21, 0xd8, 0x3c, 0x65, 0xbb, 0x01, 0xc9, 0xd9, 0x27, 0xce,
+        0x55, 0x16, 0xe6, 0x91, 0x30, 0xdc, 0xb8, 0x0e, 0xfc, 0xdf, 0x0b, 0x2a,
+        0xa9, 0x43, 0x5b, 0xac, 0xab, 0x09, 0x0c, 0xba, 0x62, 0x01, 0x36, 0x4b,
+        0x84, 0x88, 0x98, 0x13, 0xb7, 0xcf, 0x1b, 0xa9, 0xda, 0xd3, 0x1d, 0xf6,
+        0x58, 0x4a, 0x25, 0xe9, 0x66, 0x07, 0xe5, 0x01, 0x43, 0xfd, 0xd8, 0x2b,
+        0x3d, 0x7a, 0x34, 0xca, 0x20, 0xa6, 0x41, 0xba, 0x88, 0xeb, 0xd4, 0x09,
+        0x10, 0x01, 0x02, 0xc1, 0x87, 0xe1, 0x67, 0x4e, 0x8e, 0x60, 0x00, 0x00,
+        0x00, 0x00, 0x17, 0x06, 0x17, 0x01, 0x09, 0x80, 0x8b, 0x00, 0x07, 0x0b,
+        0x01, 0x00, 0x01, 0x21, 0x21, 0x01, 0x16, 0x0c, 0x80, 0xf2, 0x0a, 0x01,
+        0xfe, 0x0e, 0xb0, 0xbe, 0x00, 0x00];
+
+    // "b.txt" holding "hello hello hello\n" in a folder of its own, laid
+    // out as 7z a -m0=LZMA -mhc=off writes it, with lc=3, lp=0, pb=2 and
+    // a 64K dictionary
+    static LZMA: &'static [u8] = &[
+        0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c, 0x00, 0x04, 0x7e, 0x6e, 0x84, 0x6c,
+        0x13, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xce, 0x41, 0x8a, 0x10, 0x00, 0x34, 0x19, 0x49,
+        0xee, 0x8d, 0xe9, 0x4f, 0x7e, 0x21, 0xb6, 0x20, 0xb7, 0xff, 0xff, 0xba,
+        0x34, 0x00, 0x00, 0x01, 0x04, 0x06, 0x00, 0x01, 0x09, 0x13, 0x00, 0x07,
+        0x0b, 0x01, 0x00, 0x01, 0x23, 0x03, 0x01, 0x01, 0x05, 0x5d, 0x00, 0x00,
+        0x01, 0x00, 0x0c, 0x12, 0x0a, 0x01, 0x3b, 0x7c, 0x8a, 0xdf, 0x00, 0x00,
+        0x05, 0x01, 0x11, 0x0d, 0x00, 0x62, 0x00, 0x2e, 0x00, 0x74, 0x00, 0x78,
+        0x00, 0x74, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    // the same with 7z a -m0=Deflate -mhc=off
+    static DEFLATE: &'static [u8] = &[
+        0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c, 0x00, 0x04, 0x4c, 0x81, 0x52, 0x01,
+        0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2e, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x26, 0xe2, 0xc6, 0x4c, 0xcb, 0x48, 0xcd, 0xc9,
+        0xc9, 0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00, 0x01, 0x04, 0x06, 0x00, 0x01,
+        0x09, 0x0b, 0x00, 0x07, 0x0b, 0x01, 0x00, 0x01, 0x03, 0x04, 0x01, 0x08,
+        0x0c, 0x12, 0x0a, 0x01, 0x3b, 0x7c, 0x8a, 0xdf, 0x00, 0x00, 0x05, 0x01,
+        0x11, 0x0d, 0x00, 0x62, 0x00, 0x2e, 0x00, 0x74, 0x00, 0x78, 0x00, 0x74,
+        0x00, 0x00, 0x00, 0x00, 0x00];
+
+    // the same LZMA data, in a folder that also runs it through the x86
+    // BCJ filter, as 7z a -m0=BCJ -m1=LZMA does
+    static BCJ_LZMA: &'static [u8] = &[
+        0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c, 0x00, 0x04, 0x23, 0x19, 0x5e, 0x4b,
+        0x13, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xc2, 0x52, 0x04, 0xf5, 0x00, 0x34, 0x19, 0x49,
+        0xee, 0x8d, 0xe9, 0x4f, 0x7e, 0x21, 0xb6, 0x20, 0xb7, 0xff, 0xff, 0xba,
+        0x34, 0x00, 0x00, 0x01, 0x04, 0x06, 0x00, 0x01, 0x09, 0x13, 0x00, 0x07,
+        0x0b, 0x01, 0x00, 0x02, 0x04, 0x03, 0x03, 0x01, 0x03, 0x23, 0x03, 0x01,
+        0x01, 0x05, 0x5d, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x0c, 0x12, 0x12,
+        0x0a, 0x01, 0x3b, 0x7c, 0x8a, 0xdf, 0x00, 0x00, 0x05, 0x01, 0x11, 0x0d,
+        0x00, 0x62, 0x00, 0x2e, 0x00, 0x74, 0x00, 0x78, 0x00, 0x74, 0x00, 0x00,
+        0x00, 0x00, 0x00];
+
+    // the LZMA archive with the dictionary size cut short in the coder's
+    // properties
+    static SHORT_PROPERTIES: &'static [u8] = &[
+        0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c, 0x00, 0x04, 0x22, 0x8f, 0x2b, 0xf4,
+        0x13, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x33, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x11, 0xf7, 0x15, 0x37, 0x00, 0x34, 0x19, 0x49,
+        0xee, 0x8d, 0xe9, 0x4f, 0x7e, 0x21, 0xb6, 0x20, 0xb7, 0xff, 0xff, 0xba,
+        0x34, 0x00, 0x00, 0x01, 0x04, 0x06, 0x00, 0x01, 0x09, 0x13, 0x00, 0x07,
+        0x0b, 0x01, 0x00, 0x01, 0x23, 0x03, 0x01, 0x01, 0x04, 0x5d, 0x00, 0x00,
+        0x01, 0x0c, 0x12, 0x0a, 0x01, 0x3b, 0x7c, 0x8a, 0xdf, 0x00, 0x00, 0x05,
+        0x01, 0x11, 0x0d, 0x00, 0x62, 0x00, 0x2e, 0x00, 0x74, 0x00, 0x78, 0x00,
+        0x74, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    fn extract(archive: &mut SevenZipArchive, index: usize) -> Result<Vec<u8>, GzError> {
+        let out = try!(archive.extract(index));
+        Ok(out.iter().map(|&b| b).collect())
+    }
+
+    #[test]
+    fn test_entries() {
+        for raw in [STORED, COMPRESSED].iter() {
+            let archive = SevenZipArchive::open(from_slice(*raw)).unwrap();
+            let names: Vec<&[u8]> = archive.entries().iter().map(|e| e.name()).collect();
+            assert_eq!(names, vec![&b"docs/a.txt"[..], &b"b.txt"[..], &b"empty.txt"[..]]);
+            assert_eq!(archive.entries()[1].c_name(), &b"b.txt\0"[..]);
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        for raw in [STORED, COMPRESSED].iter() {
+            let mut archive = SevenZipArchive::open(from_slice(*raw)).unwrap();
+            assert_eq!(extract(&mut archive, 1), Ok(b"hello hello hello\n".to_vec()));
+            assert_eq!(extract(&mut archive, 0), Ok(b"hello\n".to_vec()));
+            assert_eq!(extract(&mut archive, 2), Ok(Vec::new()));
+            assert_eq!(extract(&mut archive, 3), Err(GzError::BadArchive));
+        }
+    }
+
+    #[test]
+    fn test_lzma_and_deflate() {
+        for raw in [LZMA, DEFLATE].iter() {
+            let mut archive = SevenZipArchive::open(from_slice(*raw)).unwrap();
+            assert_eq!(archive.entries()[0].name(), &b"b.txt"[..]);
+            assert_eq!(extract(&mut archive, 0), Ok(b"hello hello hello\n".to_vec()));
+        }
+        let mut archive = SevenZipArchive::open(from_slice(SHORT_PROPERTIES)).unwrap();
+        assert_eq!(extract(&mut archive, 0), Err(GzError::CorruptData));
+    }
+
+    #[test]
+    fn test_coder_chain() {
+        let mut archive = SevenZipArchive::open(from_slice(BCJ_LZMA)).unwrap();
+        assert_eq!(archive.entries()[0].name(), &b"b.txt"[..]);
+        assert_eq!(extract(&mut archive, 0), Err(GzError::UnsupportedMethod));
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let mut corrupt = STORED.to_vec();
+        corrupt[32] = b'j';
+        let mut archive = SevenZipArchive::open(from_slice(corrupt.as_slice())).unwrap();
+        assert_eq!(extract(&mut archive, 0), Err(GzError::CrcMismatch));
+        assert_eq!(extract(&mut archive, 1), Ok(b"hello hello hello\n".to_vec()));
+
+        let mut corrupt_header = COMPRESSED.to_vec();
+        corrupt_header[200] ^= 1;
+        assert_eq!(SevenZipArchive::open(from_slice(corrupt_header.as_slice())).err(),
+                   Some(GzError::CrcMismatch));
+    }
+
+    #[test]
+    fn test_signature_header() {
+        assert_eq!(SevenZipArchive::open(from_slice(&STORED[.. 20])).err(),
+                   Some(GzError::TruncatedHeader));
+        assert_eq!(SevenZipArchive::open(from_slice(&STORED[.. 200])).err(),
+                   Some(GzError::TruncatedData));
+
+        let mut bad_magic = STORED.to_vec();
+        bad_magic[1] = b'Z';
+        assert_eq!(SevenZipArchive::open(from_slice(bad_magic.as_slice())).err(),
+                   Some(GzError::BadMagic));
+
+        let mut bad_version = STORED.to_vec();
+        bad_version[6] = 1;
+        assert_eq!(SevenZipArchive::open(from_slice(bad_version.as_slice())).err(),
+                   Some(GzError::UnsupportedMethod));
+
+        let mut bad_offset = STORED.to_vec();
+        bad_offset[12] ^= 1;
+        assert_eq!(SevenZipArchive::open(from_slice(bad_offset.as_slice())).err(),
+                   Some(GzError::CrcMismatch));
+    }
+}
@@ -0,0 +1,285 @@
+#[doc="
+
+    Module: snappy
+
+    This decompresses Snappy files in the framing format, which is what
+    snzip, python-snappy and most data pipelines write to .sz files. A
+    stream starts with an identifier chunk and then holds chunks of at
+    most 64K of output each, either stored or as a raw Snappy block.
+    Each carries a masked CRC32C of the data it expands to.
+
+"]
+use cvec::Buf;
+use crc32c;
+use error::{GzError, GzResult};
+use limits;
+
+// These constants are defined by the Snappy framing format
+const STREAM_IDENTIFIER: &'static [u8] = b"\xff\x06\x00\x00sNaPpY";
+const CHUNK_HEADER_LEN: usize = 4;
+const CHECKSUM_LEN: usize = 4;
+const CHUNK_COMPRESSED: u8 = 0x00;
+const CHUNK_UNCOMPRESSED: u8 = 0x01;
+const CHUNK_STREAM_IDENTIFIER: u8 = 0xff;
+// chunks up to here must be understood, those after it may be skipped
+const LAST_UNSKIPPABLE_CHUNK: u8 = 0x7f;
+const MAX_CHUNK_DATA_LEN: usize = 65536;
+// the stored CRC is rotated and offset, so that a CRC of data holding
+// CRCs doesn't look like one
+const CHECKSUM_MASK_DELTA: u32 = 0xa282ead8;
+// the tag's low bits say whether a literal or which kind of copy follows
+const TAG_LITERAL: u8 = 0;
+const TAG_COPY_1: u8 = 1;
+const TAG_COPY_2: u8 = 2;
+// literal lengths above this are held in the next 1 to 4 bytes
+const MAX_INLINE_LITERAL_LEN: usize = 60;
+// Snappy manages about 2:1 on text, so this is a rough first allocation
+const EXPECTED_RATIO: usize = 2;
+
+/// Decompress the given Snappy framed file
+/// Concatenated streams are read as one, since each starts with another
+/// stream identifier, which is allowed anywhere.
+pub fn decompress_snappy(buffer: Buf) -> GzResult<Buf> {
+    let data = buffer.as_slice();
+    if data.len() < STREAM_IDENTIFIER.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    if &data[.. STREAM_IDENTIFIER.len()] != STREAM_IDENTIFIER {
+        return Err(GzError::BadMagic);
+    }
+    let mut out_buf = try!(limits::output_buf(data.len(), data.len() * EXPECTED_RATIO));
+
+    let mut pos = 0;
+    while pos < data.len() {
+        if pos + CHUNK_HEADER_LEN > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let chunk_type = data[pos];
+        let chunk_len = (1 .. 4).fold(0, |len, i| len | (data[pos + i] as usize) << (8 * (i - 1)));
+        pos += CHUNK_HEADER_LEN;
+        if pos + chunk_len > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let chunk = &data[pos .. pos + chunk_len];
+        pos += chunk_len;
+
+        match chunk_type {
+            CHUNK_COMPRESSED | CHUNK_UNCOMPRESSED => {
+                if chunk_len < CHECKSUM_LEN {
+                    return Err(GzError::TruncatedData);
+                }
+                let checksum = read_le32(chunk, 0);
+                let chunk_data = &chunk[CHECKSUM_LEN ..];
+                let chunk_start = out_buf.len();
+                if chunk_type == CHUNK_COMPRESSED {
+                    try!(decompress_block(chunk_data, &mut out_buf));
+                } else {
+                    if chunk_data.len() > MAX_CHUNK_DATA_LEN {
+                        return Err(GzError::CorruptData);
+                    }
+                    for &byte in chunk_data.iter() {
+                        try!(out_buf.try_push(byte));
+                    }
+                }
+                let crc = crc32c::sum(out_buf.limit_iter(chunk_start, out_buf.len()));
+                if mask_checksum(crc) != checksum {
+                    return Err(GzError::CrcMismatch);
+                }
+            }
+            CHUNK_STREAM_IDENTIFIER => {
+                if chunk != &STREAM_IDENTIFIER[CHUNK_HEADER_LEN ..] {
+                    return Err(GzError::BadMagic);
+                }
+            }
+            // a chunk type from a later version of the format
+            0x02 ... LAST_UNSKIPPABLE_CHUNK => {
+                return Err(GzError::UnsupportedMethod);
+            }
+            // padding and other skippable chunks
+            _ => {}
+        }
+    }
+    Ok(out_buf)
+}
+
+/// Decompress a raw Snappy block onto the end of out_buf
+/// Copies may only reach back within the block, which never expands to
+/// more than a chunk's worth.
+fn decompress_block(block: &[u8], out_buf: &mut Buf) -> GzResult<()> {
+    let mut pos = 0;
+    let expected_len = try!(read_varint(block, &mut pos));
+    if expected_len > MAX_CHUNK_DATA_LEN {
+        return Err(GzError::CorruptData);
+    }
+    let block_start = out_buf.len();
+    while pos < block.len() {
+        let tag = block[pos];
+        pos += 1;
+        let produced = out_buf.len() - block_start;
+        if tag & 3 == TAG_LITERAL {
+            let mut literal_len = (tag >> 2) as usize;
+            if literal_len >= MAX_INLINE_LITERAL_LEN {
+                let extra_len = literal_len - MAX_INLINE_LITERAL_LEN + 1;
+                if pos + extra_len > block.len() {
+                    return Err(GzError::TruncatedData);
+                }
+                literal_len = (0 .. extra_len).fold(0, |len, i| {
+                    len | (block[pos + i] as usize) << (8 * i)
+                });
+                pos += extra_len;
+            }
+            let literal_len = literal_len + 1;
+            if pos + literal_len > block.len() {
+                return Err(GzError::TruncatedData);
+            }
+            if produced + literal_len > expected_len {
+                return Err(GzError::SizeMismatch);
+            }
+            for &byte in block[pos .. pos + literal_len].iter() {
+                try!(out_buf.try_push(byte));
+            }
+            pos += literal_len;
+            continue;
+        }
+
+        let (copy_len, offset_len) = match tag & 3 {
+            TAG_COPY_1 => (4 + ((tag >> 2) & 7) as usize, 1),
+            TAG_COPY_2 => (((tag >> 2) as usize) + 1, 2),
+            _ => (((tag >> 2) as usize) + 1, 4),
+        };
+        if pos + offset_len > block.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let mut offset = (0 .. offset_len).fold(0, |offset, i| {
+            offset | (block[pos + i] as usize) << (8 * i)
+        });
+        // one byte copies keep the top three bits of the offset in the tag
+        if tag & 3 == TAG_COPY_1 {
+            offset |= ((tag >> 5) as usize) << 8;
+        }
+        pos += offset_len;
+        if offset == 0 || offset > produced {
+            return Err(GzError::InvalidDistance);
+        }
+        if produced + copy_len > expected_len {
+            return Err(GzError::SizeMismatch);
+        }
+        try!(out_buf.copy_back_pointer(offset - 1, copy_len));
+    }
+    if out_buf.len() - block_start != expected_len {
+        return Err(GzError::SizeMismatch);
+    }
+    Ok(())
+}
+
+/// Read the little-endian base 128 length at the start of a block
+fn read_varint(block: &[u8], pos: &mut usize) -> GzResult<usize> {
+    let mut value = 0;
+    // a 32 bit length takes at most five bytes
+    for shift in 0 .. 5 {
+        if *pos >= block.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let byte = block[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as usize) << (7 * shift);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(GzError::CorruptData)
+}
+
+fn mask_checksum(crc: u32) -> u32 {
+    ((crc >> 15) | (crc << 17)) + CHECKSUM_MASK_DELTA
+}
+
+fn read_le32(data: &[u8], pos: usize) -> u32 {
+    (0 .. 4).fold(0, |value, i| value | (data[pos + i] as u32) << (8 * i))
+}
+
+#[cfg(test)]
+mod snappy_tests {
+    use super::decompress_snappy;
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_snappy(from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    const STREAM_IDENTIFIER: [u8; 10] = [0xff, 0x06, 0x00, 0x00, 0x73, 0x4e, 0x61, 0x50, 0x70, 0x59];
+
+    // printf 'hello, world\n' | snzip, which stores it uncompressed
+    const HELLO: [u8; 31] = [
+        0xff, 0x06, 0x00, 0x00, 0x73, 0x4e, 0x61, 0x50, 0x70, 0x59, 0x01, 0x11, 0x00,
+        0x00, 0x4e, 0xda, 0x8f, 0xd5, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77,
+        0x6f, 0x72, 0x6c, 0x64, 0x0a];
+
+    const TEXT_SZ: [u8; 87] = [
+        0xff, 0x06, 0x00, 0x00, 0x73, 0x4e, 0x61, 0x50, 0x70, 0x59, 0x00, 0x49, 0x00,
+        0x00, 0xce, 0x03, 0x86, 0x41, 0x6d, 0x68, 0x49, 0x74, 0x20, 0x77, 0x61, 0x73,
+        0x20, 0x74, 0x68, 0x65, 0x20, 0x62, 0x65, 0x73, 0x74, 0x20, 0x6f, 0x66, 0x20,
+        0x74, 0x69, 0x6d, 0x65, 0x73, 0x2c, 0x20, 0x69, 0x19, 0x1a, 0x08, 0x77, 0x6f,
+        0x72, 0x5e, 0x1b, 0x00, 0x08, 0x61, 0x67, 0x65, 0x01, 0x34, 0x14, 0x77, 0x69,
+        0x73, 0x64, 0x6f, 0x6d, 0x32, 0x35, 0x00, 0x0d, 0x1a, 0x2c, 0x66, 0x6f, 0x6f,
+        0x6c, 0x69, 0x73, 0x68, 0x6e, 0x65, 0x73, 0x73, 0x0a];
+    const TEXT: &'static [u8] = b"It was the best of times, it was the worst of times, it was \
+                                  the age of wisdom, it was the age of foolishness\n";
+
+    #[test]
+    fn test_uncompressed_chunk() {
+        assert_eq!(decompress(&HELLO), Ok(b"hello, world\n".to_vec()));
+    }
+
+    #[test]
+    fn test_compressed_chunk() {
+        assert_eq!(decompress(&TEXT_SZ), Ok(TEXT.to_vec()));
+
+        // "abcd" then a copy of 8 from 4 back, with two and four byte offsets
+        let mut raw = STREAM_IDENTIFIER.to_vec();
+        raw.push_all(&[0x00, 0x0d, 0x00, 0x00, 0xa8, 0x8d, 0x5f, 0x03, 0x0c, 0x0c, 0x61,
+                       0x62, 0x63, 0x64, 0x1e, 0x04, 0x00]);
+        raw.push_all(&[0x00, 0x0f, 0x00, 0x00, 0xa8, 0x8d, 0x5f, 0x03, 0x0c, 0x0c, 0x61,
+                       0x62, 0x63, 0x64, 0x1f, 0x04, 0x00, 0x00, 0x00]);
+        assert_eq!(decompress(&raw), Ok(b"abcdabcdabcdabcdabcdabcd".to_vec()));
+
+        // a copy from before the start of the block
+        let mut bad_offset = raw.clone();
+        bad_offset[25] = 0x05;
+        assert_eq!(decompress(&bad_offset), Err(GzError::InvalidDistance));
+
+        // a block that's shorter than its length says
+        let mut bad_len = raw.clone();
+        bad_len[18] = 0x0d;
+        assert_eq!(decompress(&bad_len), Err(GzError::SizeMismatch));
+    }
+
+    #[test]
+    fn test_skipped_chunks() {
+        // padding, another stream identifier and a second stream's chunk
+        let mut raw = HELLO.to_vec();
+        raw.push_all(&[0xfe, 0x02, 0x00, 0x00, 0x00, 0x00]);
+        raw.push_all(&HELLO);
+        assert_eq!(decompress(&raw), Ok(b"hello, world\nhello, world\n".to_vec()));
+
+        let mut reserved = HELLO.to_vec();
+        reserved.push_all(&[0x02, 0x00, 0x00, 0x00]);
+        assert_eq!(decompress(&reserved), Err(GzError::UnsupportedMethod));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(decompress(&HELLO[.. 4]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(b"sNaPpY and some more"), Err(GzError::BadMagic));
+        assert_eq!(decompress(&HELLO[.. 30]), Err(GzError::TruncatedData));
+
+        let mut bad_crc = HELLO.to_vec();
+        bad_crc[18] = 0x48;
+        assert_eq!(decompress(&bad_crc), Err(GzError::CrcMismatch));
+
+        let mut bad_identifier = HELLO.to_vec();
+        bad_identifier.push_all(&[0xff, 0x06, 0x00, 0x00, 0x73, 0x4e, 0x61, 0x50, 0x70, 0x5a]);
+        assert_eq!(decompress(&bad_identifier), Err(GzError::BadMagic));
+    }
+}
@@ -0,0 +1,406 @@
+#[doc="
+
+    Module: stream
+
+    This provides incremental gzip decompression, for input that
+    arrives a piece at a time (a pipe, or a file too big to map at
+    once). Compressed bytes are fed in as they arrive and decompressed
+    bytes are pulled out as they are produced. Only the last 32 KiB
+    of output are kept between calls, as that is as far back as a
+    deflate back pointer can reach.
+
+"]
+extern crate core;
+
+use std::cmp;
+use cvec::{CVec, Buf};
+use crc32::Crc32;
+use error::{GzError, GzResult};
+use gz_reader::GzBitReader;
+use header;
+use huffman::HuffmanTable;
+use inflate;
+use inflate::Block;
+use self::core::num::Int;
+
+// how far back a deflate back pointer can reach
+const WINDOW_SIZE: usize = 32768;
+const GZIP_FOOTER_LEN: usize = 8;
+const INITIAL_CAPACITY: usize = 4 * WINDOW_SIZE;
+
+/// Where we are in the gzip stream
+enum State {
+    /// waiting for the header of the next member
+    Header,
+    /// at the start of a deflate block
+    BlockHeader,
+    /// somewhere inside a deflate block
+    Block(Block),
+    /// waiting for the CRC32/ISIZE trailer of the current member
+    Trailer,
+    /// the last member has ended, anything after it is ignored
+    Done,
+}
+
+/// What happened on a single step of decoding
+enum Progress {
+    /// made some progress, keep going
+    Continue,
+    /// can't go any further without more input
+    NeedInput,
+    /// the stream has ended
+    Finished,
+}
+
+pub struct GzStream {
+    /// compressed bytes that have been fed in and not consumed yet
+    input: Buf,
+    /// position in input of the next bit to read
+    in_bit: usize,
+    /// decompressed output, including the window behind it
+    out: Buf,
+    /// how much of out has been handed back already
+    out_read: usize,
+    /// running CRC of the current member, covering out up to crc_pos
+    crc: Crc32,
+    crc_pos: usize,
+    /// how much output the current member has produced so far
+    member_len: usize,
+    /// how many members have been decoded completely
+    members: usize,
+    /// whether the block being decoded is the last one of the member
+    last_block: bool,
+    fixed_tree: HuffmanTable,
+    state: State,
+    /// the error that stopped decoding, if any
+    failed: Option<GzError>,
+}
+
+impl GzStream {
+    /// Create a stream decoder, expecting a gzip header first
+    pub fn new() -> GzResult<GzStream> {
+        Ok(GzStream {
+            input: try_or!(CVec::with_capacity(INITIAL_CAPACITY), GzError::AllocationFailed),
+            in_bit: 0,
+            out: try_or!(CVec::with_capacity(INITIAL_CAPACITY), GzError::AllocationFailed),
+            out_read: 0,
+            crc: Crc32::new(),
+            crc_pos: 0,
+            member_len: 0,
+            members: 0,
+            last_block: false,
+            fixed_tree: try!(inflate::build_fixed_huffman_table()),
+            state: State::Header,
+            failed: None,
+        })
+    }
+
+    /// Append the next piece of compressed input
+    pub fn feed(&mut self, data: &[u8]) -> GzResult<()> {
+        // drop the input we are completely done with first
+        let consumed = self.in_bit / 8;
+        if consumed > 0 {
+            self.input.discard_front(consumed);
+            self.in_bit -= consumed * 8;
+        }
+        for &byte in data.iter() {
+            try!(self.input.try_push(byte));
+        }
+        Ok(())
+    }
+
+    /// Decompress into dst as much as the input fed so far allows
+    /// Returns the number of bytes written, which is 0 once everything
+    /// available has been read, or why the input is corrupt
+    pub fn read(&mut self, dst: &mut [u8]) -> GzResult<usize> {
+        match self.failed {
+            Some(err) => { return Err(err); },
+            None => {}
+        }
+        match self.decode(dst.len()) {
+            Err(err) => {
+                self.failed = Some(err);
+                return Err(err);
+            },
+            Ok(()) => {}
+        }
+        let count = cmp::min(dst.len(), self.out.len() - self.out_read);
+        for i in (0 .. count) {
+            dst[i] = self.out[self.out_read + i];
+        }
+        self.out_read += count;
+        self.compact_output();
+        Ok(count)
+    }
+
+    /// Check that the input fed so far makes up one or more complete
+    /// gzip members. Call after the last feed, once read has returned 0.
+    pub fn finish(&self) -> GzResult<()> {
+        match self.failed {
+            Some(err) => { return Err(err); },
+            None => {}
+        }
+        match self.state {
+            State::Done => Ok(()),
+            State::Header if self.members > 0 && self.in_bit / 8 == self.input.len() => Ok(()),
+            State::Header if self.members == 0 => Err(GzError::TruncatedHeader),
+            _ => Err(GzError::TruncatedData)
+        }
+    }
+
+    /// Decode until want bytes are waiting to be read, or we run out
+    /// of input
+    fn decode(&mut self, want: usize) -> GzResult<()> {
+        while self.out.len() - self.out_read < want {
+            let progress = match self.state {
+                State::Header => self.read_header(),
+                State::BlockHeader => self.read_block_header(),
+                State::Block(_) => self.inflate_block(self.out_read + want),
+                State::Trailer => self.read_trailer(),
+                State::Done => Ok(Progress::Finished),
+            };
+            match try!(progress) {
+                Progress::Continue => {},
+                Progress::NeedInput | Progress::Finished => { break; }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse the header of the next member, if all of it is here
+    fn read_header(&mut self) -> GzResult<Progress> {
+        let start = self.in_bit / 8;
+        let available = self.input.len() - start;
+        // anything other than another member after the first one is
+        // padding or garbage, which gzip ignores too
+        for i in (0 .. cmp::min(available, 2)) {
+            if self.input[start + i] != [0x1f, 0x8b][i] {
+                if self.members > 0 {
+                    self.state = State::Done;
+                    return Ok(Progress::Finished);
+                }
+                return Err(GzError::BadMagic);
+            }
+        }
+        if available > 2 && self.input[start + 2] != 8 {
+            return Err(GzError::UnsupportedMethod);
+        }
+        let header = match header::parse_header_at(&self.input, start) {
+            Ok(header) => header,
+            Err(GzError::TruncatedHeader) => { return Ok(Progress::NeedInput); }
+            Err(err) => { return Err(err); }
+        };
+        self.in_bit += header.header_len * 8;
+        self.state = State::BlockHeader;
+        Ok(Progress::Continue)
+    }
+
+    /// Read the header of the next deflate block
+    fn read_block_header(&mut self) -> GzResult<Progress> {
+        let mut reader = match GzBitReader::at_bit(&self.input, self.in_bit) {
+            Some(reader) => reader,
+            None => { return Ok(Progress::NeedInput); }
+        };
+        match inflate::read_block_header(&mut reader) {
+            Ok((last_block, block)) => {
+                self.in_bit = reader.bit_position();
+                self.last_block = last_block;
+                self.state = State::Block(block);
+                Ok(Progress::Continue)
+            },
+            Err(_) if reader.exhausted() => Ok(Progress::NeedInput),
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Decode the current block until out holds want_len bytes, the
+    /// block ends, or the input runs out
+    fn inflate_block(&mut self, want_len: usize) -> GzResult<Progress> {
+        let mut reader = match GzBitReader::at_bit(&self.input, self.in_bit) {
+            Some(reader) => reader,
+            None => { return Ok(Progress::NeedInput); }
+        };
+        let mut block_done = false;
+        match self.state {
+            State::Block(Block::Stored(ref mut remaining)) => {
+                while *remaining > 0 && self.out.len() < want_len {
+                    let byte = match reader.read_bits(8) {
+                        Some(byte) => byte,
+                        None => { break; }
+                    };
+                    try!(self.out.try_push(byte as u8));
+                    self.in_bit = reader.bit_position();
+                    *remaining -= 1;
+                }
+                block_done = *remaining == 0;
+            },
+            State::Block(Block::Fixed) => {
+                block_done = try!(inflate_symbols(&mut reader, &mut self.in_bit,
+                                                  &self.fixed_tree, None,
+                                                  &mut self.out, want_len));
+            },
+            State::Block(Block::Dynamic(ref literals_tree, ref distances_tree)) => {
+                block_done = try!(inflate_symbols(&mut reader, &mut self.in_bit,
+                                                  literals_tree, Some(distances_tree),
+                                                  &mut self.out, want_len));
+            },
+            _ => {}
+        }
+        if !block_done {
+            return Ok(if reader.exhausted() { Progress::NeedInput } else { Progress::Continue });
+        }
+        if self.last_block {
+            // the trailer starts on the next byte boundary
+            self.in_bit = (self.in_bit + 7) / 8 * 8;
+            self.state = State::Trailer;
+        } else {
+            self.state = State::BlockHeader;
+        }
+        Ok(Progress::Continue)
+    }
+
+    /// Check the CRC32 and ISIZE of the member that just ended
+    fn read_trailer(&mut self) -> GzResult<Progress> {
+        let start = self.in_bit / 8;
+        if self.input.len() - start < GZIP_FOOTER_LEN {
+            return Ok(Progress::NeedInput);
+        }
+        let crc: u32 = Int::from_le(try_or!(self.input.get_wide::<u32>(start),
+                                            GzError::TruncatedData));
+        let size: u32 = Int::from_le(try_or!(self.input.get_wide::<u32>(start + 4),
+                                             GzError::TruncatedData));
+        if self.update_crc() != crc {
+            return Err(GzError::CrcMismatch);
+        }
+        if self.member_len as u32 != size {
+            return Err(GzError::SizeMismatch);
+        }
+        self.crc = Crc32::new();
+        self.member_len = 0;
+        self.members += 1;
+        self.in_bit += GZIP_FOOTER_LEN * 8;
+        self.state = State::Header;
+        Ok(Progress::Continue)
+    }
+
+    /// Run everything decoded since the last call through the CRC
+    /// Returns the CRC of the member so far
+    fn update_crc(&mut self) -> u32 {
+        let crc = self.crc.sum(self.out.limit_iter(self.crc_pos, self.out.len()));
+        self.member_len += self.out.len() - self.crc_pos;
+        self.crc_pos = self.out.len();
+        crc
+    }
+
+    /// Forget output that has been read, keeping the window that later
+    /// back pointers may still refer to
+    fn compact_output(&mut self) {
+        if self.out_read < 2 * WINDOW_SIZE {
+            return;
+        }
+        self.update_crc();
+        let discard = self.out_read - WINDOW_SIZE;
+        self.out.discard_front(discard);
+        self.out_read -= discard;
+        self.crc_pos -= discard;
+    }
+}
+
+/// Decode symbols of a Huffman coded block until the block ends (Ok(true))
+/// or out holds want_len bytes or the input runs out (Ok(false)).
+/// in_bit follows the end of the last complete symbol.
+fn inflate_symbols(reader: &mut GzBitReader, in_bit: &mut usize,
+                   literals_root: &HuffmanTable,
+                   distances_root: Option<&HuffmanTable>,
+                   out: &mut Buf, want_len: usize) -> GzResult<bool> {
+    while out.len() < want_len {
+        match inflate::inflate_symbol(reader, literals_root, distances_root, out) {
+            Ok(end_of_block) => {
+                *in_bit = reader.bit_position();
+                if end_of_block {
+                    return Ok(true);
+                }
+            },
+            Err(_) if reader.exhausted() => { return Ok(false); }
+            Err(err) => { return Err(err); }
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::GzStream;
+    use error::{GzError, GzResult};
+
+    // printf 'hello\n' | gzip -n; printf 'world\n' | gzip -n
+    static TWO_MEMBERS: &'static [u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48,
+        0xcd, 0xc9, 0xc9, 0xe7, 0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00,
+        0x00, 0x00, 0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+        0x2b, 0xcf, 0x2f, 0xca, 0x49, 0xe1, 0x02, 0x00, 0xa8, 0x61, 0x38, 0xdd,
+        0x06, 0x00, 0x00, 0x00];
+
+    /// Feed input in pieces of chunk_len bytes, reading out_len bytes at a time
+    fn stream_bytes(raw: &[u8], chunk_len: usize, out_len: usize)
+            -> GzResult<(Vec<u8>, GzResult<()>)> {
+        let mut stream = GzStream::new().unwrap();
+        let mut result = Vec::new();
+        let mut out: Vec<u8> = ::std::iter::repeat(0).take(out_len).collect();
+        for chunk in raw.chunks(chunk_len) {
+            try!(stream.feed(chunk));
+            loop {
+                let count = try!(stream.read(out.as_mut_slice()));
+                if count == 0 {
+                    break;
+                }
+                for &byte in out[.. count].iter() {
+                    result.push(byte);
+                }
+            }
+        }
+        Ok((result, stream.finish()))
+    }
+
+    #[test]
+    fn test_whole_input() {
+        assert_eq!(stream_bytes(TWO_MEMBERS, 64, 64),
+                   Ok((b"hello\nworld\n".to_vec(), Ok(()))));
+    }
+
+    #[test]
+    fn test_byte_at_a_time() {
+        assert_eq!(stream_bytes(TWO_MEMBERS, 1, 1),
+                   Ok((b"hello\nworld\n".to_vec(), Ok(()))));
+        assert_eq!(stream_bytes(TWO_MEMBERS, 1, 5),
+                   Ok((b"hello\nworld\n".to_vec(), Ok(()))));
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert_eq!(stream_bytes(&TWO_MEMBERS[.. 40], 3, 4),
+                   Ok((b"hello\nwor".to_vec(), Err(GzError::TruncatedData))));
+        assert_eq!(stream_bytes(&TWO_MEMBERS[.. 48], 3, 4),
+                   Ok((b"hello\nworld\n".to_vec(), Err(GzError::TruncatedData))));
+        assert_eq!(stream_bytes(&TWO_MEMBERS[.. 20], 3, 4),
+                   Ok((b"hello\n".to_vec(), Err(GzError::TruncatedData))));
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let mut corrupt = TWO_MEMBERS.to_vec();
+        corrupt[18] ^= 0xff;
+        assert_eq!(stream_bytes(corrupt.as_slice(), 7, 4), Err(GzError::CrcMismatch));
+    }
+
+    #[test]
+    fn test_not_gzip() {
+        assert_eq!(stream_bytes(b"plain text", 4, 4), Err(GzError::BadMagic));
+    }
+
+    #[test]
+    fn test_header_only() {
+        assert_eq!(stream_bytes(&TWO_MEMBERS[.. 5], 2, 4),
+                   Ok((Vec::new(), Err(GzError::TruncatedHeader))));
+    }
+}
@@ -0,0 +1,388 @@
+#[doc="
+
+    Module: tar
+
+    This reads tar archives, which are a run of 512 byte blocks: a
+    header block for each file, then the file's contents padded out
+    to a whole block. A name too long for the header comes in an
+    entry of its own just before it, either a GNU long name entry or
+    a pax extended header, which can also give a larger size.
+
+"]
+use cvec::Buf;
+use error::{GzError, GzResult};
+
+// These constants are defined by POSIX (ustar) and GNU tar
+const BLOCK_LEN: usize = 512;
+const NAME_OFFSET: usize = 0;
+const NAME_LEN: usize = 100;
+const SIZE_OFFSET: usize = 124;
+const SIZE_LEN: usize = 12;
+const CHECKSUM_OFFSET: usize = 148;
+const CHECKSUM_LEN: usize = 8;
+const TYPE_OFFSET: usize = 156;
+const MAGIC_OFFSET: usize = 257;
+const MAGIC_LEN: usize = 6;
+const PREFIX_OFFSET: usize = 345;
+const PREFIX_LEN: usize = 155;
+// GNU tar writes "ustar  \0" instead, and uses the prefix for other things
+const USTAR_MAGIC: &'static [u8] = b"ustar\0";
+
+const TYPE_REGULAR: u8 = b'0';
+const TYPE_OLD_REGULAR: u8 = 0;
+const TYPE_CONTIGUOUS: u8 = b'7';
+const TYPE_GNU_LONG_NAME: u8 = b'L';
+const TYPE_GNU_LONG_LINK: u8 = b'K';
+const TYPE_PAX_HEADER: u8 = b'x';
+const TYPE_PAX_GLOBAL_HEADER: u8 = b'g';
+
+const PAX_PATH: &'static [u8] = b"path";
+const PAX_SIZE: &'static [u8] = b"size";
+
+/// A file in the archive
+#[derive(Show)]
+pub struct TarEntry {
+    /// path of the file within the archive, with a nul after it for C
+    name: Vec<u8>,
+    /// where its contents start, from the start of the buffer
+    offset: usize,
+    size: usize,
+}
+
+impl TarEntry {
+    /// The path of the file within the archive
+    pub fn name(&self) -> &[u8] {
+        &self.name[.. self.name.len() - 1]
+    }
+
+    /// The path of the file, followed by a nul
+    pub fn c_name(&self) -> &[u8] {
+        &self.name[..]
+    }
+}
+
+/// A tar archive, along with the files in it
+/// Only regular files are listed, as directories, links and devices
+/// hold nothing to search
+pub struct TarArchive {
+    buffer: Buf,
+    entries: Vec<TarEntry>,
+}
+
+impl TarArchive {
+    /// Read every header of the archive in buffer
+    pub fn open(buffer: Buf) -> GzResult<TarArchive> {
+        let mut entries = Vec::new();
+        // given by a GNU long name entry or pax header, for the next file
+        let mut next_name: Option<Vec<u8>> = None;
+        let mut next_size: Option<usize> = None;
+        let mut pos = 0;
+        while pos + BLOCK_LEN <= buffer.len() {
+            let header = &buffer.as_slice()[pos .. pos + BLOCK_LEN];
+            // the archive ends with blocks of zeros
+            if is_zero_block(header) {
+                break;
+            }
+            if !checksum_matches(header) {
+                return Err(GzError::BadArchive);
+            }
+            let entry_type = header[TYPE_OFFSET];
+            let describes_next = match entry_type {
+                TYPE_GNU_LONG_NAME | TYPE_GNU_LONG_LINK |
+                TYPE_PAX_HEADER | TYPE_PAX_GLOBAL_HEADER => true,
+                _ => false
+            };
+            let mut size = try!(parse_number(&header[SIZE_OFFSET .. SIZE_OFFSET + SIZE_LEN]));
+            if !describes_next {
+                size = next_size.take().unwrap_or(size);
+            }
+            let start = pos + BLOCK_LEN;
+            let end = match start.checked_add(size) {
+                Some(end) if end <= buffer.len() => end,
+                _ => { return Err(GzError::TruncatedData); }
+            };
+            let data = &buffer.as_slice()[start .. end];
+
+            match entry_type {
+                TYPE_REGULAR | TYPE_OLD_REGULAR | TYPE_CONTIGUOUS => {
+                    let mut name = next_name.take().unwrap_or_else(|| header_name(header));
+                    // very old tars mark directories with a slash instead
+                    if !name.ends_with(b"/") {
+                        name.push(0);
+                        entries.push(TarEntry {
+                            name: name,
+                            offset: start,
+                            size: size
+                        });
+                    }
+                },
+                TYPE_GNU_LONG_NAME => {
+                    next_name = Some(until_nul(data).to_vec());
+                },
+                TYPE_PAX_HEADER => {
+                    try!(read_pax_header(data, &mut next_name, &mut next_size));
+                },
+                TYPE_GNU_LONG_LINK | TYPE_PAX_GLOBAL_HEADER => {},
+                _ => {
+                    next_name = None;
+                }
+            }
+            // contents are padded out to a whole block
+            pos = start + (size + BLOCK_LEN - 1) / BLOCK_LEN * BLOCK_LEN;
+        }
+        Ok(TarArchive {
+            buffer: buffer,
+            entries: entries
+        })
+    }
+
+    /// The files in the archive
+    pub fn entries(&self) -> &Vec<TarEntry> {
+        &self.entries
+    }
+
+    /// The contents of the file at index in entries, read in place
+    /// They must not be used once the archive is freed.
+    pub fn extract(&self, index: usize) -> GzResult<Buf> {
+        let entry = try_or!(self.entries.get(index), GzError::BadArchive);
+        Ok(try_or!(unsafe { self.buffer.view(entry.offset, entry.offset + entry.size) },
+                   GzError::AllocationFailed))
+    }
+}
+
+/// Whether buffer starts with a tar header
+/// Old archives have no magic number, but the header checksum is
+/// enough to tell.
+pub fn is_tar(buffer: &Buf) -> bool {
+    if buffer.len() < BLOCK_LEN {
+        return false;
+    }
+    let header = &buffer.as_slice()[.. BLOCK_LEN];
+    !is_zero_block(header) && checksum_matches(header)
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+fn is_zero_block(block: &[u8]) -> bool {
+    block.iter().all(|&b| b == 0)
+}
+
+/// The checksum is the sum of the header's bytes, counting its own field
+/// as spaces. Some old tars summed them as signed chars.
+fn checksum_matches(header: &[u8]) -> bool {
+    let field = &header[CHECKSUM_OFFSET .. CHECKSUM_OFFSET + CHECKSUM_LEN];
+    let expected = match parse_number(field) {
+        Ok(expected) => expected as i64,
+        Err(_) => { return false; }
+    };
+    let mut unsigned: i64 = 0;
+    let mut signed: i64 = 0;
+    for (i, &byte) in header.iter().enumerate() {
+        let byte = if i >= CHECKSUM_OFFSET && i < CHECKSUM_OFFSET + CHECKSUM_LEN {
+            b' '
+        } else {
+            byte
+        };
+        unsigned += byte as i64;
+        signed += byte as i8 as i64;
+    }
+    expected == unsigned || expected == signed
+}
+
+/// Parse a numeric header field
+/// These are octal, padded with spaces and nuls, except that GNU tar
+/// writes numbers too big for that in base 256, flagged by the top bit.
+fn parse_number(field: &[u8]) -> GzResult<usize> {
+    let mut value: usize = 0;
+    if field.len() > 0 && field[0] & 0x80 != 0 {
+        for (i, &byte) in field.iter().enumerate() {
+            let byte = if i == 0 { byte & 0x7f } else { byte };
+            value = try_or!(value.checked_mul(256), GzError::BadArchive) + byte as usize;
+        }
+        return Ok(value);
+    }
+    for &byte in field.iter().skip_while(|&&b| b == b' ') {
+        match byte {
+            b'0' ... b'7' => {
+                value = try_or!(value.checked_mul(8), GzError::BadArchive)
+                        + (byte - b'0') as usize;
+            },
+            b' ' | 0 => { break; },
+            _ => { return Err(GzError::BadArchive); }
+        }
+    }
+    Ok(value)
+}
+
+/// The part of field before the first nul
+fn until_nul(field: &[u8]) -> &[u8] {
+    match field.iter().position(|&b| b == 0) {
+        Some(len) => &field[.. len],
+        None => field
+    }
+}
+
+/// The name in a header, joined to the ustar prefix if there is one
+fn header_name(header: &[u8]) -> Vec<u8> {
+    let name = until_nul(&header[NAME_OFFSET .. NAME_OFFSET + NAME_LEN]);
+    let prefix = until_nul(&header[PREFIX_OFFSET .. PREFIX_OFFSET + PREFIX_LEN]);
+    let mut full_name = Vec::new();
+    if &header[MAGIC_OFFSET .. MAGIC_OFFSET + MAGIC_LEN] == USTAR_MAGIC && prefix.len() > 0 {
+        full_name.push_all(prefix);
+        full_name.push(b'/');
+    }
+    full_name.push_all(name);
+    full_name
+}
+
+/// Pick the path and size out of a pax extended header, whose records
+/// look like "<length> <key>=<value>\n", the length counting everything
+fn read_pax_header(data: &[u8], name: &mut Option<Vec<u8>>, size: &mut Option<usize>)
+        -> GzResult<()> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let rest = &data[pos ..];
+        let space = try_or!(rest.iter().position(|&b| b == b' '), GzError::BadArchive);
+        let mut len: usize = 0;
+        for &digit in rest[.. space].iter() {
+            if digit < b'0' || digit > b'9' {
+                return Err(GzError::BadArchive);
+            }
+            len = try_or!(len.checked_mul(10), GzError::BadArchive) + (digit - b'0') as usize;
+        }
+        if len <= space + 1 || len > rest.len() || rest[len - 1] != b'\n' {
+            return Err(GzError::BadArchive);
+        }
+        let record = &rest[space + 1 .. len - 1];
+        let equals = try_or!(record.iter().position(|&b| b == b'='), GzError::BadArchive);
+        let (key, value) = (&record[.. equals], &record[equals + 1 ..]);
+        if key == PAX_PATH {
+            *name = Some(value.to_vec());
+        } else if key == PAX_SIZE {
+            let mut value_size: usize = 0;
+            for &digit in value.iter() {
+                if digit < b'0' || digit > b'9' {
+                    return Err(GzError::BadArchive);
+                }
+                value_size = try_or!(value_size.checked_mul(10), GzError::BadArchive)
+                             + (digit - b'0') as usize;
+            }
+            *size = Some(value_size);
+        }
+        pos += len;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tar_tests {
+    use super::{TarArchive, is_tar};
+    use cvec::{from_slice, Buf};
+    use error::GzError;
+
+    /// A header block followed by the padded contents
+    fn entry(name: &str, entry_type: u8, contents: &[u8]) -> Vec<u8> {
+        let mut header: Vec<u8> = ::std::iter::repeat(0).take(512).collect();
+        for (i, &byte) in name.as_bytes().iter().enumerate() {
+            header[i] = byte;
+        }
+        let size = format!("{:011o}", contents.len());
+        for (i, &byte) in size.as_bytes().iter().enumerate() {
+            header[124 + i] = byte;
+        }
+        header[156] = entry_type;
+        for (i, &byte) in b"ustar\x0000".iter().enumerate() {
+            header[257 + i] = byte;
+        }
+        for i in (148 .. 156) {
+            header[i] = b' ';
+        }
+        let sum = header.iter().fold(0, |sum, &b| sum + b as usize);
+        let checksum = format!("{:06o}\0", sum);
+        for (i, &byte) in checksum.as_bytes().iter().enumerate() {
+            header[148 + i] = byte;
+        }
+        header.push_all(contents);
+        while header.len() % 512 != 0 {
+            header.push(0);
+        }
+        header
+    }
+
+    fn archive(entries: &[Vec<u8>]) -> Buf {
+        let mut raw = Vec::new();
+        for entry in entries.iter() {
+            raw.push_all(entry.as_slice());
+        }
+        raw.push_all(&[0; 1024]);
+        from_slice(raw.as_slice())
+    }
+
+    fn contents(tar: &TarArchive) -> Vec<(Vec<u8>, Vec<u8>)> {
+        (0 .. tar.entries().len()).map(|i| {
+            (tar.entries()[i].name().to_vec(),
+             tar.extract(i).unwrap().iter().map(|&b| b).collect())
+        }).collect()
+    }
+
+    #[test]
+    fn test_extract_in_place() {
+        let buffer = archive(&[entry("a.txt", b'0', b"contents\n")]);
+        let start = buffer.as_slice().as_ptr() as usize;
+        let tar = TarArchive::open(buffer).unwrap();
+        let out = tar.extract(0).unwrap();
+        assert_eq!(out.as_slice(), b"contents\n");
+        assert_eq!(out.as_slice().as_ptr() as usize, start + 512);
+    }
+
+    #[test]
+    fn test_regular_files() {
+        let buffer = archive(&[entry("src/", b'5', b""),
+                               entry("src/main.c", b'0', b"int main;\n"),
+                               entry("link", b'2', b""),
+                               entry("empty", b'0', b"")]);
+        assert!(is_tar(&buffer));
+        let tar = TarArchive::open(buffer).unwrap();
+        assert_eq!(contents(&tar), vec![(b"src/main.c".to_vec(), b"int main;\n".to_vec()),
+                                         (b"empty".to_vec(), Vec::new())]);
+        assert_eq!(tar.entries()[1].c_name(), &b"empty\0"[..]);
+    }
+
+    #[test]
+    fn test_gnu_long_name() {
+        let long_name: String = ::std::iter::repeat("dir/").take(40).collect::<String>() + "f.txt";
+        let mut name_data = long_name.as_bytes().to_vec();
+        name_data.push(0);
+        let tar = TarArchive::open(archive(&[entry("././@LongLink", b'L', name_data.as_slice()),
+                                             entry("dir/dir/dir/", b'0', b"long\n"),
+                                             entry("short", b'0', b"short\n")])).unwrap();
+        assert_eq!(contents(&tar), vec![(long_name.as_bytes().to_vec(), b"long\n".to_vec()),
+                                        (b"short".to_vec(), b"short\n".to_vec())]);
+    }
+
+    #[test]
+    fn test_pax_header() {
+        let pax = b"20 path=a/b/c/d.txt\n24 mtime=1420070400.123\n";
+        let tar = TarArchive::open(archive(&[entry("PaxHeaders/d.txt", b'x', pax),
+                                             entry("d.txt", b'0', b"pax\n")])).unwrap();
+        assert_eq!(contents(&tar), vec![(b"a/b/c/d.txt".to_vec(), b"pax\n".to_vec())]);
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut raw = entry("a", b'0', b"a\n");
+        raw[0] = b'b';
+        let buffer = from_slice(raw.as_slice());
+        assert!(!is_tar(&buffer));
+        assert_eq!(TarArchive::open(buffer).err(), Some(GzError::BadArchive));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let raw = entry("a", b'0', &[b'a'; 600]);
+        assert_eq!(TarArchive::open(from_slice(&raw[.. 1000])).err(),
+                   Some(GzError::TruncatedData));
+    }
+}
@@ -0,0 +1,148 @@
+#[doc="
+
+    Module: xxhash
+
+    This is the xxHash checksum, which zstd and LZ4 use to check the
+    contents of a frame. zstd uses the 64 bit hash and LZ4 the 32 bit
+    one, which works the same way on lanes half as wide.
+
+"]
+extern crate core;
+
+use self::core::num::Int;
+
+const PRIME32_1: u32 = 0x9e3779b1;
+const PRIME32_2: u32 = 0x85ebca77;
+const PRIME32_3: u32 = 0xc2b2ae3d;
+const PRIME32_4: u32 = 0x27d4eb2f;
+const PRIME32_5: u32 = 0x165667b1;
+const PRIME64_1: u64 = 0x9e3779b185ebca87;
+const PRIME64_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME64_3: u64 = 0x165667b19e3779f9;
+const PRIME64_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME64_5: u64 = 0x27d4eb2f165667c5;
+// the input is mixed into four lanes at a time
+const STRIPE32_LEN: usize = 16;
+const STRIPE64_LEN: usize = 32;
+
+/// The 32 bit xxHash of data
+pub fn xxh32(data: &[u8], seed: u32) -> u32 {
+    let len = data.len();
+    let mut pos = 0;
+    let mut hash = if len >= STRIPE32_LEN {
+        let mut lanes = [seed + PRIME32_1 + PRIME32_2, seed + PRIME32_2, seed, seed - PRIME32_1];
+        while pos + STRIPE32_LEN <= len {
+            for i in (0 .. 4) {
+                lanes[i] = round32(lanes[i], read_u32(data, pos + i * 4));
+            }
+            pos += STRIPE32_LEN;
+        }
+        lanes[0].rotate_left(1) + lanes[1].rotate_left(7)
+            + lanes[2].rotate_left(12) + lanes[3].rotate_left(18)
+    } else {
+        seed + PRIME32_5
+    };
+    hash += len as u32;
+
+    while pos + 4 <= len {
+        hash += read_u32(data, pos) * PRIME32_3;
+        hash = hash.rotate_left(17) * PRIME32_4;
+        pos += 4;
+    }
+    while pos < len {
+        hash += data[pos] as u32 * PRIME32_5;
+        hash = hash.rotate_left(11) * PRIME32_1;
+        pos += 1;
+    }
+
+    hash ^= hash >> 15;
+    hash *= PRIME32_2;
+    hash ^= hash >> 13;
+    hash *= PRIME32_3;
+    hash ^ (hash >> 16)
+}
+
+/// The 64 bit xxHash of data
+pub fn xxh64(data: &[u8], seed: u64) -> u64 {
+    let len = data.len();
+    let mut pos = 0;
+    let mut hash = if len >= STRIPE64_LEN {
+        let mut lanes = [seed + PRIME64_1 + PRIME64_2, seed + PRIME64_2, seed, seed - PRIME64_1];
+        while pos + STRIPE64_LEN <= len {
+            for i in (0 .. 4) {
+                lanes[i] = round64(lanes[i], read_u64(data, pos + i * 8));
+            }
+            pos += STRIPE64_LEN;
+        }
+        let mut hash = lanes[0].rotate_left(1) + lanes[1].rotate_left(7)
+                       + lanes[2].rotate_left(12) + lanes[3].rotate_left(18);
+        for &lane in lanes.iter() {
+            hash = (hash ^ round64(0, lane)) * PRIME64_1 + PRIME64_4;
+        }
+        hash
+    } else {
+        seed + PRIME64_5
+    };
+    hash += len as u64;
+
+    while pos + 8 <= len {
+        hash ^= round64(0, read_u64(data, pos));
+        hash = hash.rotate_left(27) * PRIME64_1 + PRIME64_4;
+        pos += 8;
+    }
+    if pos + 4 <= len {
+        hash ^= read_u32(data, pos) as u64 * PRIME64_1;
+        hash = hash.rotate_left(23) * PRIME64_2 + PRIME64_3;
+        pos += 4;
+    }
+    while pos < len {
+        hash ^= data[pos] as u64 * PRIME64_5;
+        hash = hash.rotate_left(11) * PRIME64_1;
+        pos += 1;
+    }
+
+    hash ^= hash >> 33;
+    hash *= PRIME64_2;
+    hash ^= hash >> 29;
+    hash *= PRIME64_3;
+    hash ^ (hash >> 32)
+}
+
+/// Mix 4 bytes of input into a lane
+fn round32(lane: u32, input: u32) -> u32 {
+    (lane + input * PRIME32_2).rotate_left(13) * PRIME32_1
+}
+
+/// Mix 8 bytes of input into a lane
+fn round64(lane: u64, input: u64) -> u64 {
+    (lane + input * PRIME64_2).rotate_left(31) * PRIME64_1
+}
+
+fn read_u64(data: &[u8], pos: usize) -> u64 {
+    (0 .. 8).fold(0, |value, i| value | (data[pos + i] as u64) << (8 * i))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    (0 .. 4).fold(0, |value, i| value | (data[pos + i] as u32) << (8 * i))
+}
+
+#[cfg(test)]
+mod xxhash_tests {
+    use super::{xxh32, xxh64};
+
+    #[test]
+    fn test_xxh32() {
+        assert_eq!(xxh32(b"", 0), 0x02cc5d05);
+        assert_eq!(xxh32(b"a", 0), 0x550d7456);
+        assert_eq!(xxh32(b"abc", 0), 0x32d153ff);
+        assert_eq!(xxh32(b"Nobody inspects the spammish repetition", 0), 0xe2293b2f);
+    }
+
+    #[test]
+    fn test_xxh64() {
+        assert_eq!(xxh64(b"", 0), 0xef46db3751d8e999);
+        assert_eq!(xxh64(b"a", 0), 0xd24ec4f1a98c6e5b);
+        assert_eq!(xxh64(b"abc", 0), 0x44bc2cf5ad770999);
+        assert_eq!(xxh64(b"Nobody inspects the spammish repetition", 0), 0xfbcea83c8a378bf1);
+    }
+}
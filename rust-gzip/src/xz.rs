@@ -0,0 +1,408 @@
+#[doc="
+
+    Module: xz
+
+    This decompresses xz files. A file holds one or more streams, each
+    of which is a header, a run of blocks, an index listing the size of
+    every block, and a footer that repeats the header's flags. Each
+    block has its own header saying how it was compressed: LZMA2, after
+    up to three of the filters in the filter module, which are undone
+    once it's decompressed. The block is followed by a check of its
+    contents, usually a CRC64. The headers, index and footer are each
+    covered by a CRC32.
+
+"]
+use cvec::Buf;
+use error::{GzError, GzResult};
+use limits;
+use crc32;
+use crc64;
+use lzma;
+use filter::Filter;
+
+// These constants are defined by the .xz file format
+const STREAM_MAGIC: &'static [u8] = b"\xfd7zXZ\0";
+const FOOTER_MAGIC: &'static [u8] = b"YZ";
+const STREAM_HEADER_LEN: usize = 12;
+const STREAM_FOOTER_LEN: usize = 12;
+const STREAM_FLAGS_LEN: usize = 2;
+const CRC32_LEN: usize = 4;
+// everything in a stream is padded to a multiple of this
+const ALIGNMENT: usize = 4;
+const INDEX_INDICATOR: u8 = 0x00;
+const BLOCK_FLAGS_RESERVED: u8 = 0x3c;
+const BLOCK_FLAGS_FILTERS: u8 = 0x03;
+const BLOCK_FLAGS_COMPRESSED_SIZE: u8 = 0x40;
+const BLOCK_FLAGS_UNCOMPRESSED_SIZE: u8 = 0x80;
+const FILTER_LZMA2: u64 = 0x21;
+const LZMA2_MAX_DICTIONARY: u8 = 40;
+const CHECK_MASK: u8 = 0x0f;
+const CHECK_CRC32: u8 = 0x01;
+const CHECK_CRC64: u8 = 0x04;
+// how long each of the 16 kinds of check is
+const CHECK_LENS: [usize; 16] = [0, 4, 4, 4, 8, 8, 8, 16, 16, 16, 32, 32, 32, 64, 64, 64];
+const MAX_VARINT_LEN: usize = 9;
+// xz manages about 4:1 on text, so this is a rough first allocation
+const EXPECTED_RATIO: usize = 4;
+
+/// Decompress the given xz file
+/// Like xz, this reads every stream in the file and skips the padding
+/// between them, but ignores anything after the last one.
+pub fn decompress_xz(buffer: Buf) -> GzResult<Buf> {
+    if buffer.len() < STREAM_HEADER_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    if !is_stream_start(buffer.as_slice(), 0) {
+        return Err(GzError::BadMagic);
+    }
+    let mut out_buf = try!(limits::output_buf(buffer.len(), buffer.len() * EXPECTED_RATIO));
+    let mut pos = 0;
+    loop {
+        pos = try!(decompress_stream(&buffer, pos, &mut out_buf));
+        let data = buffer.as_slice();
+        while pos + ALIGNMENT <= data.len() && data[pos .. pos + ALIGNMENT].iter().all(|&b| b == 0) {
+            pos += ALIGNMENT;
+        }
+        if !is_stream_start(data, pos) {
+            break;
+        }
+    }
+    Ok(out_buf)
+}
+
+fn is_stream_start(data: &[u8], pos: usize) -> bool {
+    pos + STREAM_MAGIC.len() <= data.len() &&
+        &data[pos .. pos + STREAM_MAGIC.len()] == STREAM_MAGIC
+}
+
+/// Decompress the stream starting at pos onto the end of out_buf,
+/// returning where it ends
+fn decompress_stream(buffer: &Buf, pos: usize, out_buf: &mut Buf) -> GzResult<usize> {
+    let data = buffer.as_slice();
+    let stream_start = pos;
+    if pos + STREAM_HEADER_LEN > data.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    let flags_start = pos + STREAM_MAGIC.len();
+    let flags = &data[flags_start .. flags_start + STREAM_FLAGS_LEN];
+    if !check_crc32(buffer, flags_start, flags_start + STREAM_FLAGS_LEN,
+                     flags_start + STREAM_FLAGS_LEN) {
+        return Err(GzError::CrcMismatch);
+    }
+    if flags[0] != 0 || flags[1] & !CHECK_MASK != 0 {
+        return Err(GzError::UnsupportedMethod);
+    }
+    let check = flags[1];
+    let mut pos = pos + STREAM_HEADER_LEN;
+
+    // the unpadded and uncompressed size of each block, to check the
+    // index against
+    let mut blocks: Vec<(u64, u64)> = Vec::new();
+    loop {
+        if pos >= data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        if data[pos] == INDEX_INDICATOR {
+            break;
+        }
+        let (block_end, sizes) = try!(decompress_block(buffer, pos, stream_start, check, out_buf));
+        pos = block_end;
+        blocks.push(sizes);
+    }
+
+    let index_start = pos;
+    pos += 1;
+    if try!(read_varint(data, &mut pos)) != blocks.len() as u64 {
+        return Err(GzError::CorruptData);
+    }
+    for &(unpadded_len, uncompressed_len) in blocks.iter() {
+        if try!(read_varint(data, &mut pos)) != unpadded_len ||
+           try!(read_varint(data, &mut pos)) != uncompressed_len {
+            return Err(GzError::CorruptData);
+        }
+    }
+    pos = try!(skip_padding(data, pos, stream_start));
+    if !check_crc32(buffer, index_start, pos, pos) {
+        return Err(GzError::CrcMismatch);
+    }
+    pos += CRC32_LEN;
+    let index_len = pos - index_start;
+
+    if pos + STREAM_FOOTER_LEN > data.len() {
+        return Err(GzError::TruncatedData);
+    }
+    // unlike everywhere else, the footer's CRC comes before what it covers
+    if !check_crc32(buffer, pos + CRC32_LEN, pos + STREAM_FOOTER_LEN - FOOTER_MAGIC.len(), pos) {
+        return Err(GzError::CrcMismatch);
+    }
+    // the footer gives the index size in multiples of 4, less one
+    let backward_len = (read_le(data, pos + CRC32_LEN, 4) as usize + 1) * ALIGNMENT;
+    let footer_flags = &data[pos + 8 .. pos + 8 + STREAM_FLAGS_LEN];
+    let footer_magic = &data[pos + 10 .. pos + STREAM_FOOTER_LEN];
+    if backward_len != index_len || footer_flags != flags || footer_magic != FOOTER_MAGIC {
+        return Err(GzError::CorruptData);
+    }
+    Ok(pos + STREAM_FOOTER_LEN)
+}
+
+/// Decompress the block starting at pos onto the end of out_buf,
+/// returning where it ends, along with its unpadded and uncompressed
+/// sizes as the index gives them
+fn decompress_block(buffer: &Buf, pos: usize, stream_start: usize, check: u8, out_buf: &mut Buf)
+        -> GzResult<(usize, (u64, u64))> {
+    let data = buffer.as_slice();
+    let header_len = (data[pos] as usize + 1) * 4;
+    if pos + header_len > data.len() {
+        return Err(GzError::TruncatedData);
+    }
+    let header_end = pos + header_len - CRC32_LEN;
+    if !check_crc32(buffer, pos, header_end, header_end) {
+        return Err(GzError::CrcMismatch);
+    }
+    let flags = data[pos + 1];
+    if flags & BLOCK_FLAGS_RESERVED != 0 {
+        return Err(GzError::UnsupportedMethod);
+    }
+    // the header's fields can't run into its CRC
+    let header = &data[.. header_end];
+    let mut field = pos + 2;
+    let compressed_len = if flags & BLOCK_FLAGS_COMPRESSED_SIZE != 0 {
+        Some(try!(read_varint(header, &mut field)))
+    } else {
+        None
+    };
+    let uncompressed_len = if flags & BLOCK_FLAGS_UNCOMPRESSED_SIZE != 0 {
+        Some(try!(read_varint(header, &mut field)))
+    } else {
+        None
+    };
+    // filters such as BCJ may come before LZMA2, which has to be last
+    let mut filters = Vec::new();
+    for _ in (0 .. flags & BLOCK_FLAGS_FILTERS) {
+        let id = try!(read_varint(header, &mut field));
+        let props_len = try!(read_varint(header, &mut field));
+        if id == FILTER_LZMA2 {
+            return Err(GzError::CorruptData);
+        }
+        if props_len > (header_end - field) as u64 {
+            return Err(GzError::TruncatedData);
+        }
+        let props_end = field + props_len as usize;
+        filters.push(try!(Filter::from_xz(id, &header[field .. props_end])));
+        field = props_end;
+    }
+    if try!(read_varint(header, &mut field)) != FILTER_LZMA2 {
+        return Err(GzError::UnsupportedMethod);
+    }
+    if try!(read_varint(header, &mut field)) != 1 || field >= header_end ||
+       header[field] > LZMA2_MAX_DICTIONARY {
+        return Err(GzError::CorruptData);
+    }
+    field += 1;
+    if header[field ..].iter().any(|&b| b != 0) {
+        return Err(GzError::CorruptData);
+    }
+
+    let mut pos = pos + header_len;
+    let out_start = out_buf.len();
+    let compressed = match compressed_len {
+        Some(len) if pos as u64 + len > data.len() as u64 => {
+            return Err(GzError::TruncatedData);
+        }
+        Some(len) => &data[pos .. pos + len as usize],
+        None => &data[pos ..]
+    };
+    let used = try!(lzma::decompress_lzma2(compressed, out_buf));
+    if used != compressed.len() && compressed_len.is_some() {
+        return Err(GzError::CorruptData);
+    }
+    for filter in filters.iter().rev() {
+        filter.unfilter(&mut out_buf.as_mut_slice()[out_start ..]);
+    }
+    let decompressed_len = (out_buf.len() - out_start) as u64;
+    match uncompressed_len {
+        Some(len) if len != decompressed_len => { return Err(GzError::SizeMismatch); }
+        _ => {}
+    }
+    pos = try!(skip_padding(data, pos + used, stream_start));
+
+    let check_len = CHECK_LENS[check as usize];
+    if pos + check_len > data.len() {
+        return Err(GzError::TruncatedData);
+    }
+    // SHA-256 and the other checks are skipped, like xz does with check
+    // types it doesn't know
+    let check_ok = match check {
+        CHECK_CRC32 => crc32::sum(out_buf.limit_iter(out_start, out_buf.len())) as u64 ==
+                       read_le(data, pos, check_len),
+        CHECK_CRC64 => crc64::sum(out_buf.limit_iter(out_start, out_buf.len())) ==
+                       read_le(data, pos, check_len),
+        _ => true
+    };
+    if !check_ok {
+        return Err(GzError::CrcMismatch);
+    }
+    let unpadded_len = (header_len + used + check_len) as u64;
+    Ok((pos + check_len, (unpadded_len, decompressed_len)))
+}
+
+/// Skip the zeros that pad pos out to a multiple of 4 from the start of
+/// the stream
+fn skip_padding(data: &[u8], pos: usize, stream_start: usize) -> GzResult<usize> {
+    let mut pos = pos;
+    while (pos - stream_start) % ALIGNMENT != 0 {
+        if pos >= data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        if data[pos] != 0 {
+            return Err(GzError::CorruptData);
+        }
+        pos += 1;
+    }
+    Ok(pos)
+}
+
+/// Whether the CRC32 of buffer from start to end matches the one
+/// stored at crc_pos
+fn check_crc32(buffer: &Buf, start: usize, end: usize, crc_pos: usize) -> bool {
+    crc_pos + CRC32_LEN <= buffer.len() &&
+        crc32::sum(buffer.limit_iter(start, end)) as u64 == read_le(buffer.as_slice(), crc_pos, CRC32_LEN)
+}
+
+/// Read a number stored 7 bits at a time, least significant first, with
+/// the top bit of each byte set if more follow
+fn read_varint(data: &[u8], pos: &mut usize) -> GzResult<u64> {
+    let mut value = 0u64;
+    for i in (0 .. MAX_VARINT_LEN) {
+        if *pos >= data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            // a number can't be padded out with extra zero bytes
+            if i > 0 && byte == 0 {
+                return Err(GzError::CorruptData);
+            }
+            return Ok(value);
+        }
+    }
+    Err(GzError::CorruptData)
+}
+
+/// Read a little endian number of up to 8 bytes
+fn read_le(data: &[u8], pos: usize, len: usize) -> u64 {
+    (0 .. len).fold(0, |value, i| value | (data[pos + i] as u64) << (8 * i))
+}
+
+#[cfg(test)]
+mod xz_tests {
+    use super::decompress_xz;
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_xz(from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    // printf 'hello, world\n' | xz, which stores it in an uncompressed
+    // LZMA2 chunk with a CRC64
+    const HELLO: [u8; 80] = [
+        0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46, 0x04,
+        0xc0, 0x11, 0x0d, 0x21, 0x01, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x88, 0x88, 0xcd, 0x68, 0x01, 0x00, 0x0c, 0x68, 0x65, 0x6c, 0x6c,
+        0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x0a, 0x00, 0x00, 0x00, 0x00,
+        0x7b, 0x46, 0x5a, 0x81, 0xc9, 0x12, 0xb8, 0xea, 0x00, 0x01, 0x2d, 0x0d, 0x79,
+        0x93, 0x1d, 0x7e, 0x1f, 0xb6, 0xf3, 0x7d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04,
+        0x59, 0x5a];
+
+    #[test]
+    fn test_stored_chunk() {
+        assert_eq!(decompress(&HELLO), Ok(b"hello, world\n".to_vec()));
+    }
+
+    #[test]
+    fn test_lzma_chunk() {
+        let raw = [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46,
+                   0x04, 0xc0, 0x46, 0x6d, 0x21, 0x01, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00,
+                   0x00, 0x00, 0x00, 0x00, 0x8b, 0x33, 0xf8, 0x24, 0xe0, 0x00, 0x6c, 0x00,
+                   0x3e, 0x5d, 0x00, 0x24, 0x9d, 0x00, 0x07, 0x73, 0x43, 0x85, 0x19, 0xc1,
+                   0xbc, 0x61, 0x9e, 0x16, 0x43, 0x3f, 0x49, 0xa5, 0x27, 0x15, 0xb4, 0xf5,
+                   0x10, 0x18, 0x54, 0xbf, 0xcd, 0x91, 0x38, 0x3b, 0x33, 0x8b, 0x52, 0x7d,
+                   0x4a, 0xe7, 0x72, 0xd2, 0xb1, 0xd8, 0x4a, 0x2a, 0x51, 0x00, 0x44, 0xb2,
+                   0xe1, 0x69, 0x9f, 0x12, 0x41, 0xf5, 0x50, 0xc0, 0x17, 0xd2, 0xcd, 0xfc,
+                   0x50, 0xa4, 0x75, 0x00, 0x00, 0x00, 0x00, 0x00, 0xdb, 0x0b, 0x16, 0x8b,
+                   0x20, 0x3e, 0xb0, 0x3b, 0x00, 0x01, 0x62, 0x6d, 0xeb, 0xa1, 0x4e, 0x44,
+                   0x1f, 0xb6, 0xf3, 0x7d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5a];
+        let text = b"It was the best of times, it was the worst of times, it was the age of \
+                     wisdom, it was the age of foolishness\n";
+        assert_eq!(decompress(&raw), Ok(text.to_vec()));
+    }
+
+    #[test]
+    fn test_multiple_streams() {
+        let mut raw = HELLO.to_vec();
+        raw.push_all(&[0, 0, 0, 0]);
+        raw.push_all(&HELLO);
+        raw.push_all(b"junk");
+        assert_eq!(decompress(raw.as_slice()), Ok(b"hello, world\nhello, world\n".to_vec()));
+    }
+
+    #[test]
+    fn test_bad_checks() {
+        let mut bad_block = HELLO.to_vec();
+        bad_block[52] ^= 1;
+        assert_eq!(decompress(bad_block.as_slice()), Err(GzError::CrcMismatch));
+        let mut bad_header = HELLO.to_vec();
+        bad_header[28] ^= 1;
+        assert_eq!(decompress(bad_header.as_slice()), Err(GzError::CrcMismatch));
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(&HELLO[.. 8]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(b"not an xz file"), Err(GzError::BadMagic));
+        assert_eq!(decompress(&HELLO[.. 40]), Err(GzError::TruncatedData));
+    }
+
+    #[test]
+    fn test_x86_filter() {
+        // xz --x86 --lzma2 of a call 0x10 bytes on, after some nops
+        let raw = [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46,
+                   0x04, 0xc1, 0x13, 0x0e, 0x04, 0x00, 0x21, 0x01, 0x16, 0x00, 0x00, 0x00,
+                   0x00, 0x00, 0x00, 0x00, 0x85, 0x3d, 0x0e, 0xeb, 0xe0, 0x00, 0x0d, 0x00,
+                   0x0b, 0x5d, 0x00, 0x48, 0x6a, 0xb6, 0x03, 0xa0, 0x0c, 0x8d, 0x88, 0x0f,
+                   0x20, 0x00, 0x00, 0x00, 0x86, 0x7d, 0xae, 0x4d, 0xd6, 0xce, 0xd9, 0xa0,
+                   0x00, 0x01, 0x2f, 0x0e, 0x41, 0xa0, 0x22, 0xd5, 0x1f, 0xb6, 0xf3, 0x7d,
+                   0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5a];
+        let code = [0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90,
+                    0xe8, 0x10, 0x00, 0x00, 0x00, 0x90];
+        assert_eq!(decompress(&raw), Ok(code.to_vec()));
+    }
+
+    #[test]
+    fn test_delta_filter() {
+        // printf 'aabbccddeeff\n' | xz --delta=dist=2 --lzma2
+        let raw = [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46,
+                   0x04, 0xc1, 0x10, 0x0d, 0x03, 0x01, 0x01, 0x21, 0x01, 0x16, 0x00, 0x00,
+                   0x00, 0x00, 0x00, 0x00, 0x6a, 0x52, 0x6f, 0x20, 0xe0, 0x00, 0x0c, 0x00,
+                   0x08, 0x5d, 0x00, 0x30, 0xe0, 0x03, 0x5d, 0x48, 0x00, 0x00, 0x00, 0x00,
+                   0x09, 0xde, 0x51, 0x43, 0xea, 0xda, 0x4f, 0xea, 0x00, 0x01, 0x2c, 0x0d,
+                   0x38, 0xa2, 0x06, 0x67, 0x1f, 0xb6, 0xf3, 0x7d, 0x01, 0x00, 0x00, 0x00,
+                   0x00, 0x04, 0x59, 0x5a];
+        assert_eq!(decompress(&raw), Ok(b"aabbccddeeff\n".to_vec()));
+    }
+
+    #[test]
+    fn test_unsupported_filter() {
+        // printf 'hi\n' | xz --riscv --lzma2
+        let raw = [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46,
+                   0x04, 0xc1, 0x07, 0x03, 0x0b, 0x00, 0x21, 0x01, 0x16, 0x00, 0x00, 0x00,
+                   0x00, 0x00, 0x00, 0x00, 0xfd, 0x71, 0xa8, 0xa1, 0x01, 0x00, 0x02, 0x68,
+                   0x69, 0x0a, 0x00, 0x00, 0xfb, 0x7b, 0xe8, 0xe8, 0x2a, 0x58, 0x43, 0x4f,
+                   0x00, 0x01, 0x23, 0x03, 0xf0, 0x93, 0x26, 0x07, 0x1f, 0xb6, 0xf3, 0x7d,
+                   0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5a];
+        assert_eq!(decompress(&raw), Err(GzError::UnsupportedMethod));
+    }
+}
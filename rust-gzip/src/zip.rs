@@ -0,0 +1,456 @@
+#[doc="
+
+    Module: zip
+
+    This reads zip archives. The central directory at the end of the
+    archive lists every entry along with where its data starts, and
+    that data is either stored as is or compressed with deflate, which
+    the inflate module already handles. Each entry is checked against
+    the CRC32 the central directory gives for it.
+
+    Archives over 4G or with more than 65535 entries are zip64 ones:
+    the fields that overflow are set to all ones, and the real values
+    are in a zip64 record before the end of the central directory and
+    in an extra field of each central header.
+
+"]
+extern crate core;
+
+use cvec::Buf;
+use crc32;
+use error::{GzError, GzResult};
+use limits;
+use gz_reader::GzBitReader;
+use inflate::inflate;
+use self::core::num::Int;
+
+// These constants are defined by the zip APPNOTE
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06054b50;
+const LOCAL_HEADER_LEN: usize = 30;
+const CENTRAL_HEADER_LEN: usize = 46;
+const END_OF_CENTRAL_DIR_LEN: usize = 22;
+const ZIP64_END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const ZIP64_END_OF_CENTRAL_DIR_LEN: usize = 56;
+const ZIP64_LOCATOR_LEN: usize = 20;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+// a 32 bit size or offset that's in the zip64 extra field instead
+const ZIP64_MARKER: usize = 0xffffffff;
+// the end of central directory record is followed by a comment of
+// at most this many bytes
+const MAX_COMMENT_LEN: usize = 65535;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+const FLAG_ENCRYPTED: u16 = 0x0001;
+
+/// A file in the archive, as the central directory describes it
+#[derive(Show)]
+pub struct ZipEntry {
+    /// path of the file within the archive, with a nul after it for C
+    name: Vec<u8>,
+    method: u16,
+    flags: u16,
+    crc: u32,
+    compressed_size: usize,
+    uncompressed_size: usize,
+    /// where the local header is, from the start of the buffer
+    local_header: usize,
+}
+
+impl ZipEntry {
+    /// The path of the file within the archive
+    pub fn name(&self) -> &[u8] {
+        &self.name[.. self.name.len() - 1]
+    }
+
+    /// The path of the file, followed by a nul
+    pub fn c_name(&self) -> &[u8] {
+        &self.name[..]
+    }
+}
+
+/// A zip archive, along with the files it lists
+/// Directories are left out, as they hold nothing to search
+pub struct ZipArchive {
+    buffer: Buf,
+    entries: Vec<ZipEntry>,
+}
+
+impl ZipArchive {
+    /// Read the central directory of the archive in buffer
+    pub fn open(buffer: Buf) -> GzResult<ZipArchive> {
+        let end = try!(find_end_of_central_dir(&buffer));
+        // the central directory comes right before whichever record
+        // describes it
+        let (count, dir_len, dir_offset, dir_end) =
+            match try!(find_zip64_end_of_central_dir(&buffer, end)) {
+                Some(zip64_end) => (try!(read_u64(&buffer, zip64_end + 32)) as usize,
+                                    try!(read_u64(&buffer, zip64_end + 40)) as usize,
+                                    try!(read_u64(&buffer, zip64_end + 48)) as usize,
+                                    zip64_end),
+                None => (try!(read_u16(&buffer, end + 10)) as usize,
+                         try!(read_u32(&buffer, end + 12)) as usize,
+                         try!(read_u32(&buffer, end + 16)) as usize,
+                         end)
+            };
+        if dir_len > dir_end {
+            return Err(GzError::BadArchive);
+        }
+        // the offsets are off by however much was put in front of the
+        // archive, as with self-extracting ones
+        let dir_start = dir_end - dir_len;
+        if dir_offset > dir_start {
+            return Err(GzError::BadArchive);
+        }
+        let prefix_len = dir_start - dir_offset;
+
+        let mut entries = Vec::new();
+        let mut pos = dir_start;
+        for _ in (0 .. count) {
+            let (entry, next) = try!(read_central_header(&buffer, pos, prefix_len));
+            if !entry.name().ends_with(b"/") {
+                entries.push(entry);
+            }
+            pos = next;
+        }
+        Ok(ZipArchive {
+            buffer: buffer,
+            entries: entries
+        })
+    }
+
+    /// The files in the archive
+    pub fn entries(&self) -> &Vec<ZipEntry> {
+        &self.entries
+    }
+
+    /// Decompress the file at index in entries
+    pub fn extract(&self, index: usize) -> GzResult<Buf> {
+        let entry = try_or!(self.entries.get(index), GzError::BadArchive);
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(GzError::Encrypted);
+        }
+        let header = entry.local_header;
+        if try!(read_u32(&self.buffer, header)) != LOCAL_HEADER_SIGNATURE {
+            return Err(GzError::BadArchive);
+        }
+        // the local header repeats the name, and may have different extra
+        // fields than the central directory
+        let name_len = try!(read_u16(&self.buffer, header + 26)) as usize;
+        let extra_len = try!(read_u16(&self.buffer, header + 28)) as usize;
+        let start = header + LOCAL_HEADER_LEN + name_len + extra_len;
+        if start > self.buffer.len() || entry.compressed_size > self.buffer.len() - start {
+            return Err(GzError::TruncatedData);
+        }
+        let end = start + entry.compressed_size;
+
+        let mut out = try!(limits::output_buf(entry.compressed_size, entry.uncompressed_size));
+        match entry.method {
+            METHOD_STORED => {
+                for &byte in self.buffer.limit_iter(start, end) {
+                    try!(out.try_push(byte));
+                }
+            },
+            METHOD_DEFLATED => {
+                let mut reader = try_or!(GzBitReader::new(self.buffer.limit_iter(start, end)),
+                                         GzError::TruncatedData);
+                try!(inflate(&mut reader, &mut out));
+            },
+            _ => { return Err(GzError::UnsupportedMethod); }
+        }
+
+        if out.len() != entry.uncompressed_size {
+            return Err(GzError::SizeMismatch);
+        }
+        if crc32::sum(out.iter()) != entry.crc {
+            return Err(GzError::CrcMismatch);
+        }
+        Ok(out)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+//                       Helper functions                          //
+/////////////////////////////////////////////////////////////////////
+
+fn read_u16(buffer: &Buf, index: usize) -> GzResult<u16> {
+    Ok(Int::from_le(try_or!(buffer.get_wide::<u16>(index), GzError::BadArchive)))
+}
+
+fn read_u32(buffer: &Buf, index: usize) -> GzResult<u32> {
+    Ok(Int::from_le(try_or!(buffer.get_wide::<u32>(index), GzError::BadArchive)))
+}
+
+fn read_u64(buffer: &Buf, index: usize) -> GzResult<u64> {
+    Ok(Int::from_le(try_or!(buffer.get_wide::<u64>(index), GzError::BadArchive)))
+}
+
+fn has_signature(buffer: &Buf, index: usize, signature: u32) -> bool {
+    buffer.get_wide::<u32>(index).map_or(false, |value| Int::from_le(value) == signature)
+}
+
+/// Find the end of central directory record, searching backwards past
+/// the archive comment
+fn find_end_of_central_dir(buffer: &Buf) -> GzResult<usize> {
+    if buffer.len() < END_OF_CENTRAL_DIR_LEN {
+        return Err(GzError::BadArchive);
+    }
+    let last = buffer.len() - END_OF_CENTRAL_DIR_LEN;
+    let first = if last > MAX_COMMENT_LEN { last - MAX_COMMENT_LEN } else { 0 };
+    let mut pos = last;
+    loop {
+        if try!(read_u32(buffer, pos)) == END_OF_CENTRAL_DIR_SIGNATURE {
+            return Ok(pos);
+        }
+        if pos == first {
+            return Err(GzError::BadArchive);
+        }
+        pos -= 1;
+    }
+}
+
+/// Find the zip64 end of central directory record, if the locator in
+/// front of the end of central directory record says there is one
+fn find_zip64_end_of_central_dir(buffer: &Buf, end: usize) -> GzResult<Option<usize>> {
+    if end < ZIP64_LOCATOR_LEN || !has_signature(buffer, end - ZIP64_LOCATOR_LEN,
+                                                 ZIP64_LOCATOR_SIGNATURE) {
+        return Ok(None);
+    }
+    let locator = end - ZIP64_LOCATOR_LEN;
+    let offset = try!(read_u64(buffer, locator + 8)) as usize;
+    if offset < locator && has_signature(buffer, offset, ZIP64_END_OF_CENTRAL_DIR_SIGNATURE) {
+        return Ok(Some(offset));
+    }
+    // the offset is wrong if something was put in front of the archive,
+    // but the record is nearly always right before the locator
+    if locator >= ZIP64_END_OF_CENTRAL_DIR_LEN &&
+       has_signature(buffer, locator - ZIP64_END_OF_CENTRAL_DIR_LEN,
+                     ZIP64_END_OF_CENTRAL_DIR_SIGNATURE) {
+        return Ok(Some(locator - ZIP64_END_OF_CENTRAL_DIR_LEN));
+    }
+    Err(GzError::BadArchive)
+}
+
+/// Read the central directory header at pos
+/// Returns the entry, and where the next header starts
+fn read_central_header(buffer: &Buf, pos: usize, prefix_len: usize)
+        -> GzResult<(ZipEntry, usize)> {
+    if try!(read_u32(buffer, pos)) != CENTRAL_HEADER_SIGNATURE {
+        return Err(GzError::BadArchive);
+    }
+    let name_len = try!(read_u16(buffer, pos + 28)) as usize;
+    let extra_len = try!(read_u16(buffer, pos + 30)) as usize;
+    let comment_len = try!(read_u16(buffer, pos + 32)) as usize;
+    let name_start = pos + CENTRAL_HEADER_LEN;
+    if name_start + name_len > buffer.len() {
+        return Err(GzError::BadArchive);
+    }
+    let mut name: Vec<u8> = buffer.limit_iter(name_start, name_start + name_len)
+                                  .map(|&b| b).collect();
+    name.push(0);
+
+    let mut entry = ZipEntry {
+        name: name,
+        flags: try!(read_u16(buffer, pos + 8)),
+        method: try!(read_u16(buffer, pos + 10)),
+        crc: try!(read_u32(buffer, pos + 16)),
+        compressed_size: try!(read_u32(buffer, pos + 20)) as usize,
+        uncompressed_size: try!(read_u32(buffer, pos + 24)) as usize,
+        local_header: try!(read_u32(buffer, pos + 42)) as usize,
+    };
+    let extra_start = name_start + name_len;
+    try!(read_zip64_extra(buffer, extra_start, extra_start + extra_len, &mut entry));
+    if entry.local_header > buffer.len() - prefix_len {
+        return Err(GzError::BadArchive);
+    }
+    entry.local_header += prefix_len;
+    Ok((entry, extra_start + extra_len + comment_len))
+}
+
+/// Look through the extra fields between start and end for a zip64
+/// one, and take the sizes and offset that didn't fit in the central
+/// header from it
+fn read_zip64_extra(buffer: &Buf, start: usize, end: usize, entry: &mut ZipEntry)
+        -> GzResult<()> {
+    let mut pos = start;
+    while pos + 4 <= end {
+        let id = try!(read_u16(buffer, pos));
+        let len = try!(read_u16(buffer, pos + 2)) as usize;
+        let field_end = pos + 4 + len;
+        if field_end > end {
+            return Err(GzError::BadArchive);
+        }
+        if id == ZIP64_EXTRA_ID {
+            // only the values that overflowed are there, in this order
+            let mut value = pos + 4;
+            try!(read_zip64_value(buffer, &mut value, field_end, &mut entry.uncompressed_size));
+            try!(read_zip64_value(buffer, &mut value, field_end, &mut entry.compressed_size));
+            try!(read_zip64_value(buffer, &mut value, field_end, &mut entry.local_header));
+        }
+        pos = field_end;
+    }
+    Ok(())
+}
+
+/// Replace field with the next value of a zip64 extra field if it's
+/// marked as being there
+fn read_zip64_value(buffer: &Buf, pos: &mut usize, end: usize, field: &mut usize)
+        -> GzResult<()> {
+    if *field != ZIP64_MARKER {
+        return Ok(());
+    }
+    if *pos + 8 > end {
+        return Err(GzError::BadArchive);
+    }
+    *field = try!(read_u64(buffer, *pos)) as usize;
+    *pos += 8;
+    Ok(())
+}
+
+#[cfg(test)]
+mod zip_tests {
+    use super::ZipArchive;
+    use cvec::from_slice;
+    use error::GzError;
+
+    // a directory "docs/", "docs/a.txt" stored and "b.txt" deflated
+    static ARCHIVE: &'static [u8] = &[
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x21, 0x46, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x64, 0x6f, 0x63, 0x73, 0x2f, 0x50,
+        0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21,
+        0x46, 0xe2, 0x9c, 0x53, 0xa5, 0x07, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00,
+        0x00, 0x0a, 0x00, 0x00, 0x00, 0x64, 0x6f, 0x63, 0x73, 0x2f, 0x61, 0x2e,
+        0x74, 0x78, 0x74, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x0a, 0x50, 0x4b,
+        0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x46,
+        0x3b, 0x7c, 0x8a, 0xdf, 0x0b, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00,
+        0x05, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74, 0x78, 0x74, 0xcb, 0x48, 0xcd,
+        0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00, 0x50, 0x4b, 0x01, 0x02,
+        0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x46,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x80, 0x01, 0x00, 0x00, 0x00, 0x00, 0x64, 0x6f, 0x63, 0x73, 0x2f, 0x50,
+        0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x21, 0x46, 0xe2, 0x9c, 0x53, 0xa5, 0x07, 0x00, 0x00, 0x00, 0x07,
+        0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x80, 0x01, 0x23, 0x00, 0x00, 0x00, 0x64, 0x6f, 0x63,
+        0x73, 0x2f, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x01, 0x02, 0x14,
+        0x03, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x46, 0x3b,
+        0x7c, 0x8a, 0xdf, 0x0b, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00, 0x05,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+        0x01, 0x52, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b,
+        0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x03, 0x00, 0x9e, 0x00,
+        0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    // "a.txt" stored and "b.txt" deflated, with every size and offset
+    // in zip64 extra fields and a zip64 end of central directory record
+    static ZIP64_ARCHIVE: &'static [u8] = &[
+        0x50, 0x4b, 0x03, 0x04, 0x2d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x21, 0x46, 0xe2, 0x9c, 0x53, 0xa5, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0x05, 0x00, 0x14, 0x00, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x01,
+        0x00, 0x10, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x73, 0x74, 0x6f, 0x72, 0x65,
+        0x64, 0x0a, 0x50, 0x4b, 0x03, 0x04, 0x2d, 0x00, 0x00, 0x00, 0x08, 0x00,
+        0x00, 0x00, 0x21, 0x46, 0x3b, 0x7c, 0x8a, 0xdf, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0x05, 0x00, 0x14, 0x00, 0x62, 0x2e, 0x74, 0x78,
+        0x74, 0x01, 0x00, 0x10, 0x00, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xcb, 0x48, 0xcd,
+        0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00, 0x50, 0x4b, 0x01, 0x02,
+        0x2d, 0x03, 0x2d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x46,
+        0xe2, 0x9c, 0x53, 0xa5, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0x05, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xa4, 0x81, 0xff, 0xff, 0xff, 0xff, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x01,
+        0x00, 0x18, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x50, 0x4b, 0x01, 0x02, 0x2d, 0x03, 0x2d, 0x00, 0x00,
+        0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x46, 0x3b, 0x7c, 0x8a, 0xdf, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x05, 0x00, 0x1c, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xa4, 0x81, 0xff, 0xff, 0xff,
+        0xff, 0x62, 0x2e, 0x74, 0x78, 0x74, 0x01, 0x00, 0x18, 0x00, 0x12, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x3e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x4b,
+        0x06, 0x06, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2d, 0x00,
+        0x2d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x9e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x4b, 0x06, 0x07, 0x00, 0x00,
+        0x00, 0x00, 0x1e, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+        0x00, 0x00, 0x50, 0x4b, 0x05, 0x06, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00];
+
+    fn extract(zip: &ZipArchive, index: usize) -> Result<Vec<u8>, GzError> {
+        let out = try!(zip.extract(index));
+        Ok(out.iter().map(|&b| b).collect())
+    }
+
+    #[test]
+    fn test_entries() {
+        let zip = ZipArchive::open(from_slice(ARCHIVE)).unwrap();
+        let names: Vec<&[u8]> = zip.entries().iter().map(|e| e.name()).collect();
+        assert_eq!(names, vec![&b"docs/a.txt"[..], &b"b.txt"[..]]);
+        assert_eq!(zip.entries()[1].c_name(), &b"b.txt\0"[..]);
+    }
+
+    #[test]
+    fn test_extract() {
+        let zip = ZipArchive::open(from_slice(ARCHIVE)).unwrap();
+        assert_eq!(extract(&zip, 0), Ok(b"stored\n".to_vec()));
+        assert_eq!(extract(&zip, 1), Ok(b"hello hello hello\n".to_vec()));
+        assert_eq!(extract(&zip, 2), Err(GzError::BadArchive));
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let mut corrupt = ARCHIVE.to_vec();
+        corrupt[75] ^= 0x20;
+        let zip = ZipArchive::open(from_slice(corrupt.as_slice())).unwrap();
+        assert_eq!(extract(&zip, 0), Err(GzError::CrcMismatch));
+        assert_eq!(extract(&zip, 1), Ok(b"hello hello hello\n".to_vec()));
+    }
+
+    #[test]
+    fn test_prefixed_archive() {
+        let mut prefixed = b"#!/bin/sh\n".to_vec();
+        prefixed.push_all(ARCHIVE);
+        let zip = ZipArchive::open(from_slice(prefixed.as_slice())).unwrap();
+        assert_eq!(extract(&zip, 1), Ok(b"hello hello hello\n".to_vec()));
+    }
+
+    #[test]
+    fn test_zip64() {
+        let zip = ZipArchive::open(from_slice(ZIP64_ARCHIVE)).unwrap();
+        let names: Vec<&[u8]> = zip.entries().iter().map(|e| e.name()).collect();
+        assert_eq!(names, vec![&b"a.txt"[..], &b"b.txt"[..]]);
+        assert_eq!(extract(&zip, 0), Ok(b"stored\n".to_vec()));
+        assert_eq!(extract(&zip, 1), Ok(b"hello hello hello\n".to_vec()));
+
+        // the zip64 record is found next to its locator when the offset
+        // to it is off
+        let mut prefixed = b"#!/bin/sh\n".to_vec();
+        prefixed.push_all(ZIP64_ARCHIVE);
+        let zip = ZipArchive::open(from_slice(prefixed.as_slice())).unwrap();
+        assert_eq!(extract(&zip, 1), Ok(b"hello hello hello\n".to_vec()));
+
+        // a locator pointing at nothing
+        let mut no_record = ZIP64_ARCHIVE.to_vec();
+        no_record[286] = 0;
+        assert_eq!(ZipArchive::open(from_slice(no_record.as_slice())).err(),
+                   Some(GzError::BadArchive));
+
+        // an extra field too short for the values marked as being in it
+        let mut short_extra = ZIP64_ARCHIVE.to_vec();
+        short_extra[181] = 0x10;
+        assert_eq!(ZipArchive::open(from_slice(short_extra.as_slice())).err(),
+                   Some(GzError::BadArchive));
+    }
+
+    #[test]
+    fn test_not_a_zip() {
+        assert_eq!(ZipArchive::open(from_slice(&[0; 64])).err(), Some(GzError::BadArchive));
+        assert_eq!(ZipArchive::open(from_slice(&ARCHIVE[.. 200])).err(),
+                   Some(GzError::BadArchive));
+    }
+}
@@ -0,0 +1,215 @@
+#[doc="
+
+    Module: zlib
+
+    This reads zlib streams (RFC 1950), the thin wrapper around deflate
+    that git objects, .zz dumps and plenty of caches use. The two byte
+    header is checked, an optional preset dictionary is primed into the
+    window, the deflate data goes through the inflate module and the
+    Adler-32 trailer is checked against the output.
+
+"]
+use std::usize;
+use cvec::Buf;
+use adler32;
+use error::{GzError, GzResult};
+use limits::Limits;
+use gz_reader::GzBitReader;
+use inflate::inflate;
+
+const HEADER_LEN: usize = 2;
+const DICTID_LEN: usize = 4;
+const TRAILER_LEN: usize = 4;
+// CM, the low nibble of CMF, is 8 for deflate, the only method defined
+const DEFLATE: u8 = 8;
+// CINFO above 7 would mean a window larger than deflate allows
+const MAX_CINFO: u8 = 7;
+const FDICT: u8 = 0x20;
+// deflate with a 32K window
+const DEFAULT_CMF: u8 = 0x78;
+// deflate block types, from the BTYPE bits of a block header
+const STORED: u8 = 0;
+const RESERVED: u8 = 3;
+// deflate manages about 3:1 on text, so this is a rough first allocation
+const EXPECTED_RATIO: usize = 3;
+
+/// Decompress a zlib stream that doesn't need a preset dictionary
+pub fn decompress_zlib(buffer: Buf) -> GzResult<Buf> {
+    decompress_zlib_with_dictionary(buffer, &[])
+}
+
+/// Decompress a zlib stream, priming the window with dictionary if the
+/// stream asks for one. The dictionary must be the one whose Adler-32
+/// is recorded in the header
+pub fn decompress_zlib_with_dictionary(buffer: Buf, dictionary: &[u8]) -> GzResult<Buf> {
+    if buffer.len() < HEADER_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    let cmf = buffer[0];
+    let flg = buffer[1];
+    if !check_passes(cmf, flg) {
+        return Err(GzError::BadMagic);
+    }
+    if cmf & 0x0f != DEFLATE || cmf >> 4 > MAX_CINFO {
+        return Err(GzError::UnsupportedMethod);
+    }
+
+    let mut start = HEADER_LEN;
+    let limits = Limits::current();
+    let mut out_buf = try!(limits.output_buf(buffer.len(),
+                                             dictionary.len() + buffer.len() * EXPECTED_RATIO));
+    if flg & FDICT != 0 {
+        if buffer.len() < start + DICTID_LEN {
+            return Err(GzError::TruncatedHeader);
+        }
+        if dictionary.is_empty() || read_be32(&buffer, start) != adler32::sum(dictionary) {
+            return Err(GzError::MissingDictionary);
+        }
+        start += DICTID_LEN;
+        // back references can reach into the dictionary as if it had
+        // been decompressed just before the stream
+        for &byte in dictionary.iter() {
+            try!(out_buf.try_push(byte));
+        }
+    }
+    let dictionary_len = out_buf.len();
+    // the dictionary doesn't count towards the output
+    let max_len = limits.max_output_len(buffer.len());
+    out_buf.set_max_len(max_len.checked_add(dictionary_len).unwrap_or(usize::MAX));
+
+    let trailer = {
+        let mut reader = try_or!(GzBitReader::new(buffer.limit_iter(start, buffer.len())),
+                                 GzError::TruncatedData);
+        try!(inflate(&mut reader, &mut out_buf));
+        reader.align_to_byte();
+        reader.byte_index()
+    };
+    out_buf.discard_front(dictionary_len);
+
+    // like gzip, ignore anything after the trailer
+    if trailer + TRAILER_LEN > buffer.len() {
+        return Err(GzError::TruncatedData);
+    }
+    if read_be32(&buffer, trailer) != adler32::sum(out_buf.as_slice()) {
+        return Err(GzError::CrcMismatch);
+    }
+    Ok(out_buf)
+}
+
+/// Whether buffer looks like a zlib stream that can be read without
+/// help. Two bytes make a weak magic number, so only the headers zlib
+/// itself writes are accepted: a 32K window and no preset dictionary,
+/// which nobody could hand us while searching files anyway. Text like
+/// "x^2" still passes that, so the first deflate block header has to
+/// make sense too.
+pub fn is_zlib(buffer: &Buf) -> bool {
+    match (buffer.get(0), buffer.get(1)) {
+        (Some(&cmf), Some(&flg)) => cmf == DEFAULT_CMF && flg & FDICT == 0 && check_passes(cmf, flg)
+            && block_header_passes(buffer),
+        _ => false
+    }
+}
+
+/// Whether the header check bits in flg are right
+fn check_passes(cmf: u8, flg: u8) -> bool {
+    ((cmf as u32) << 8 | flg as u32) % 31 == 0
+}
+
+/// Whether the deflate block after the header, if there is one yet,
+/// could be real: not of the reserved type, and if stored, with its
+/// length followed by the length's complement
+fn block_header_passes(buffer: &Buf) -> bool {
+    let start = HEADER_LEN;
+    let first = match buffer.get(start) {
+        Some(&first) => first,
+        None => return true
+    };
+    match (first >> 1) & 3 {
+        STORED => match (buffer.get(start + 1), buffer.get(start + 2),
+                         buffer.get(start + 3), buffer.get(start + 4)) {
+            (Some(&len_lo), Some(&len_hi), Some(&nlen_lo), Some(&nlen_hi)) =>
+                len_lo == !nlen_lo && len_hi == !nlen_hi,
+            _ => false
+        },
+        RESERVED => false,
+        _ => true
+    }
+}
+
+/// Read the big endian u32 at index pos, zlib being the odd one out
+fn read_be32(buffer: &Buf, pos: usize) -> u32 {
+    (0 .. 4).fold(0, |value, i| value << 8 | buffer[pos + i] as u32)
+}
+
+#[cfg(test)]
+mod zlib_tests {
+    use super::{decompress_zlib, decompress_zlib_with_dictionary, is_zlib};
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8], dictionary: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_zlib_with_dictionary(from_slice(raw), dictionary)
+            .map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    // python: zlib.compress(b"hello, hello, hello zlib\n")
+    const HELLO: [u8; 23] = [
+        0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xc8, 0x40, 0xa2, 0x14, 0xaa,
+        0x72, 0x32, 0x93, 0xb8, 0x00, 0x74, 0x01, 0x08, 0xb0];
+    // the same, made with zdict=DICTIONARY
+    const HELLO_WITH_DICTIONARY: [u8; 19] = [
+        0x78, 0xbb, 0xcd, 0xdb, 0x0c, 0x6d, 0xcb, 0x80, 0x28, 0x40, 0xa6, 0xc0, 0x8a, 0xb8,
+        0x00, 0x74, 0x01, 0x08, 0xb0];
+    const DICTIONARY: &'static [u8] = b"hello, world of zlib dictionaries";
+    const TEXT: &'static [u8] = b"hello, hello, hello zlib\n";
+
+    #[test]
+    fn test_decompress() {
+        let out = decompress_zlib(from_slice(&HELLO)).unwrap();
+        assert_eq!(out.as_slice(), TEXT);
+        // trailing garbage is ignored
+        let mut raw = HELLO.to_vec();
+        raw.push_all(b"junk");
+        assert_eq!(decompress(raw.as_slice(), &[]), Ok(TEXT.to_vec()));
+    }
+
+    #[test]
+    fn test_dictionary() {
+        assert_eq!(decompress(&HELLO_WITH_DICTIONARY, DICTIONARY), Ok(TEXT.to_vec()));
+        assert_eq!(decompress(&HELLO_WITH_DICTIONARY, &[]), Err(GzError::MissingDictionary));
+        assert_eq!(decompress(&HELLO_WITH_DICTIONARY, b"some other dictionary"),
+                   Err(GzError::MissingDictionary));
+        // a dictionary the stream doesn't ask for is just ignored
+        assert_eq!(decompress(&HELLO, DICTIONARY), Ok(TEXT.to_vec()));
+    }
+
+    #[test]
+    fn test_is_zlib() {
+        assert!(is_zlib(&from_slice(&HELLO)));
+        assert!(is_zlib(&from_slice(&[0x78, 0x01])));
+        assert!(is_zlib(&from_slice(&[0x78, 0xda])));
+        // streams needing a dictionary, and text that happens to pass the check
+        assert!(!is_zlib(&from_slice(&HELLO_WITH_DICTIONARY)));
+        assert!(!is_zlib(&from_slice(b"x ")));
+        assert!(!is_zlib(&from_slice(b"xy")));
+        assert!(!is_zlib(&from_slice(b"x")));
+        // "x^" passes the header check, but what follows isn't deflate
+        assert!(!is_zlib(&from_slice(b"x^ 2")));
+        assert!(!is_zlib(&from_slice(b"x^7 + y^7\n")));
+        assert!(!is_zlib(&from_slice(b"x^ with more text than a stored block has")));
+        // a stored block, as level 0 writes
+        assert!(is_zlib(&from_slice(&[0x78, 0x01, 0x01, 0x02, 0x00, 0xfd, 0xff, b'h', b'i'])));
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(&HELLO[.. 1], &[]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(&[0x78, 0x9d, 0x01], &[]), Err(GzError::BadMagic));
+        // CM 15 is reserved
+        assert_eq!(decompress(&[0x7f, 0x07, 0x01], &[]), Err(GzError::UnsupportedMethod));
+        assert_eq!(decompress(&HELLO[.. 20], &[]), Err(GzError::TruncatedData));
+        let mut bad_adler = HELLO.to_vec();
+        bad_adler[22] ^= 1;
+        assert_eq!(decompress(bad_adler.as_slice(), &[]), Err(GzError::CrcMismatch));
+    }
+}
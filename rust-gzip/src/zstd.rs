@@ -0,0 +1,942 @@
+#[doc="
+
+    Module: zstd
+
+    This decompresses zstd files. A file is a run of frames, each of
+    which is split into blocks that are stored raw, as one repeated
+    byte, or compressed. A compressed block holds its literals, coded
+    with Huffman codes in one or four streams, followed by sequences
+    that say how many literals to copy and where to copy a match from.
+    The lengths and offsets of the sequences are coded with finite
+    state entropy (FSE) tables, read backwards from the end of the
+    block. Tables and recent offsets carry over from block to block
+    within a frame, and a frame may end with part of its xxHash.
+
+"]
+use std::iter::repeat;
+use cvec::Buf;
+use error::{GzError, GzResult};
+use limits;
+use xxhash::xxh64;
+
+// These constants are defined by RFC 8878
+const FRAME_MAGIC: u32 = 0xfd2fb528;
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+// skippable frames may use any of 16 magic numbers
+const SKIPPABLE_MAGIC_MASK: u32 = 0xfffffff0;
+const MAGIC_LEN: usize = 4;
+const BLOCK_HEADER_LEN: usize = 3;
+const CHECKSUM_LEN: usize = 4;
+const MAX_BLOCK_LEN: usize = 128 * 1024;
+const RAW_BLOCK: u32 = 0;
+const RLE_BLOCK: u32 = 1;
+const COMPRESSED_BLOCK: u32 = 2;
+const RAW_LITERALS: u8 = 0;
+const RLE_LITERALS: u8 = 1;
+const COMPRESSED_LITERALS: u8 = 2;
+const MAX_HUFFMAN_BITS: u32 = 11;
+const MAX_HUFFMAN_WEIGHT_LOG: u32 = 6;
+const MAX_LITERAL_SYMBOL: usize = 255;
+const JUMP_TABLE_LEN: usize = 6;
+const MAX_LITERAL_LENGTH_LOG: u32 = 9;
+const MAX_OFFSET_LOG: u32 = 8;
+const MAX_MATCH_LENGTH_LOG: u32 = 9;
+// the offsets every frame starts out with for repeats
+const INITIAL_OFFSETS: [usize; 3] = [1, 4, 8];
+// zstd manages about 3:1 on text, so this is a rough first allocation
+const EXPECTED_RATIO: usize = 3;
+
+// The distributions used by tables in predefined mode, where -1 marks
+// a symbol less likely than 1 in the size of the table
+const LITERAL_LENGTH_LOG: u32 = 6;
+const LITERAL_LENGTH_DISTRIBUTION: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1];
+const MATCH_LENGTH_LOG: u32 = 6;
+const MATCH_LENGTH_DISTRIBUTION: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1,
+    -1, -1, -1, -1, -1];
+const OFFSET_LOG: u32 = 5;
+const OFFSET_DISTRIBUTION: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1];
+
+// Each length code stands for a base value plus this many extra bits
+const LITERAL_LENGTH_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    16, 18, 20, 22, 24, 28, 32, 40, 48, 64, 128, 256, 512, 1024, 2048, 4096,
+    8192, 16384, 32768, 65536];
+const LITERAL_LENGTH_EXTRA_BITS: [u32; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15, 16];
+const MATCH_LENGTH_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
+    19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34,
+    35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027, 2051,
+    4099, 8195, 16387, 32771, 65539];
+const MATCH_LENGTH_EXTRA_BITS: [u32; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16];
+// offset codes are just the number of extra bits
+const MAX_OFFSET_CODE: usize = 31;
+
+/// Decompress the given zstd file
+/// Like zstd, this reads every frame in the file, skips skippable
+/// frames, and ignores anything after the last one.
+pub fn decompress_zstd(buffer: Buf) -> GzResult<Buf> {
+    let data = buffer.as_slice();
+    if data.len() < MAGIC_LEN {
+        return Err(GzError::TruncatedHeader);
+    }
+    if !is_frame_start(data, 0) {
+        return Err(GzError::BadMagic);
+    }
+    let mut out_buf = try!(limits::output_buf(data.len(), data.len() * EXPECTED_RATIO));
+    let mut pos = 0;
+    while is_frame_start(data, pos) {
+        let magic = read_le(data, pos, MAGIC_LEN) as u32;
+        pos += MAGIC_LEN;
+        if magic == FRAME_MAGIC {
+            pos = try!(decompress_frame(data, pos, &mut out_buf));
+        } else {
+            if pos + 4 > data.len() {
+                return Err(GzError::TruncatedHeader);
+            }
+            pos += 4 + read_le(data, pos, 4) as usize;
+            if pos > data.len() {
+                return Err(GzError::TruncatedData);
+            }
+        }
+    }
+    Ok(out_buf)
+}
+
+/// Whether a zstd frame or a skippable frame starts at pos
+fn is_frame_start(data: &[u8], pos: usize) -> bool {
+    if pos + MAGIC_LEN > data.len() {
+        return false;
+    }
+    let magic = read_le(data, pos, MAGIC_LEN) as u32;
+    magic == FRAME_MAGIC || magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC
+}
+
+/// What a frame's blocks need to remember from earlier blocks
+struct FrameState {
+    // where the frame's output starts, since matches can't reach before it
+    start: usize,
+    huffman: Option<HuffmanTable>,
+    literal_lengths: Option<FseTable>,
+    offsets: Option<FseTable>,
+    match_lengths: Option<FseTable>,
+    recent_offsets: [usize; 3],
+}
+
+/// Decompress the frame whose header starts at pos onto the end of
+/// out_buf, returning where the frame ends
+fn decompress_frame(data: &[u8], pos: usize, out_buf: &mut Buf) -> GzResult<usize> {
+    let mut pos = pos;
+    if pos >= data.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    let descriptor = data[pos];
+    pos += 1;
+    let content_size_flag = descriptor >> 6;
+    let single_segment = descriptor & 0x20 != 0;
+    let has_checksum = descriptor & 0x04 != 0;
+    if descriptor & 0x08 != 0 {
+        return Err(GzError::CorruptData);
+    }
+    let dictionary_id_len = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+    let content_size_len = match content_size_flag {
+        0 => if single_segment { 1 } else { 0 },
+        1 => 2,
+        2 => 4,
+        _ => 8
+    };
+    // the window size only matters to decoders that stream their output
+    let window_descriptor_len = if single_segment { 0 } else { 1 };
+    if pos + window_descriptor_len + dictionary_id_len + content_size_len > data.len() {
+        return Err(GzError::TruncatedHeader);
+    }
+    pos += window_descriptor_len;
+    if read_le(data, pos, dictionary_id_len) != 0 {
+        return Err(GzError::UnsupportedMethod);
+    }
+    pos += dictionary_id_len;
+    let content_size = match content_size_len {
+        0 => None,
+        2 => Some(read_le(data, pos, 2) + 256),
+        len => Some(read_le(data, pos, len))
+    };
+    pos += content_size_len;
+
+    let mut state = FrameState {
+        start: out_buf.len(),
+        huffman: None,
+        literal_lengths: None,
+        offsets: None,
+        match_lengths: None,
+        recent_offsets: INITIAL_OFFSETS,
+    };
+    loop {
+        if pos + BLOCK_HEADER_LEN > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let header = read_le(data, pos, BLOCK_HEADER_LEN) as u32;
+        pos += BLOCK_HEADER_LEN;
+        let last_block = header & 1 != 0;
+        let block_len = (header >> 3) as usize;
+        if block_len > MAX_BLOCK_LEN {
+            return Err(GzError::CorruptData);
+        }
+        match (header >> 1) & 3 {
+            RAW_BLOCK => {
+                if pos + block_len > data.len() {
+                    return Err(GzError::TruncatedData);
+                }
+                for &byte in data[pos .. pos + block_len].iter() {
+                    try!(out_buf.try_push(byte));
+                }
+                pos += block_len;
+            }
+            RLE_BLOCK => {
+                // block_len is how many times the byte repeats
+                if pos >= data.len() {
+                    return Err(GzError::TruncatedData);
+                }
+                for _ in (0 .. block_len) {
+                    try!(out_buf.try_push(data[pos]));
+                }
+                pos += 1;
+            }
+            COMPRESSED_BLOCK => {
+                if pos + block_len > data.len() {
+                    return Err(GzError::TruncatedData);
+                }
+                try!(decompress_block(&data[pos .. pos + block_len], &mut state, out_buf));
+                pos += block_len;
+            }
+            _ => { return Err(GzError::CorruptData); }
+        }
+        if last_block {
+            break;
+        }
+    }
+
+    let frame_len = out_buf.len() - state.start;
+    if has_checksum {
+        if pos + CHECKSUM_LEN > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        // only the low 32 bits of the hash are kept
+        let checksum = read_le(data, pos, CHECKSUM_LEN) as u32;
+        pos += CHECKSUM_LEN;
+        let frame_out = &out_buf.as_slice()[state.start ..];
+        if xxh64(frame_out, 0) as u32 != checksum {
+            return Err(GzError::CrcMismatch);
+        }
+    }
+    match content_size {
+        Some(size) if size != frame_len as u64 => Err(GzError::SizeMismatch),
+        _ => Ok(pos)
+    }
+}
+
+/// Decompress a compressed block onto the end of out_buf
+fn decompress_block(block: &[u8], state: &mut FrameState, out_buf: &mut Buf) -> GzResult<()> {
+    let (literals, pos) = try!(read_literals(block, state));
+    if pos >= block.len() {
+        return Err(GzError::TruncatedData);
+    }
+
+    // the number of sequences takes one to three bytes
+    let mut pos = pos;
+    let first = block[pos] as usize;
+    pos += 1;
+    let sequence_count = if first < 128 {
+        first
+    } else if first < 255 {
+        if pos >= block.len() {
+            return Err(GzError::TruncatedData);
+        }
+        pos += 1;
+        ((first - 128) << 8) + block[pos - 1] as usize
+    } else {
+        if pos + 2 > block.len() {
+            return Err(GzError::TruncatedData);
+        }
+        pos += 2;
+        read_le(block, pos - 2, 2) as usize + 0x7f00
+    };
+    if sequence_count == 0 {
+        for &byte in literals.iter() {
+            try!(out_buf.try_push(byte));
+        }
+        return Ok(());
+    }
+
+    if pos >= block.len() {
+        return Err(GzError::TruncatedData);
+    }
+    let modes = block[pos];
+    pos += 1;
+    if modes & 3 != 0 {
+        return Err(GzError::CorruptData);
+    }
+    let literal_lengths = try!(read_sequence_table(block, &mut pos, modes >> 6,
+                                                   &state.literal_lengths,
+                                                   &LITERAL_LENGTH_DISTRIBUTION,
+                                                   LITERAL_LENGTH_LOG, MAX_LITERAL_LENGTH_LOG));
+    let offsets = try!(read_sequence_table(block, &mut pos, (modes >> 4) & 3,
+                                           &state.offsets,
+                                           &OFFSET_DISTRIBUTION,
+                                           OFFSET_LOG, MAX_OFFSET_LOG));
+    let match_lengths = try!(read_sequence_table(block, &mut pos, (modes >> 2) & 3,
+                                                 &state.match_lengths,
+                                                 &MATCH_LENGTH_DISTRIBUTION,
+                                                 MATCH_LENGTH_LOG, MAX_MATCH_LENGTH_LOG));
+
+    let mut reader = try!(BackwardReader::new(&block[pos ..]));
+    let mut literal_length_state = reader.read(literal_lengths.log) as usize;
+    let mut offset_state = reader.read(offsets.log) as usize;
+    let mut match_length_state = reader.read(match_lengths.log) as usize;
+    let mut literal_pos = 0;
+    for i in (0 .. sequence_count) {
+        let literal_length_code = literal_lengths.entries[literal_length_state].symbol as usize;
+        let offset_code = offsets.entries[offset_state].symbol as usize;
+        let match_length_code = match_lengths.entries[match_length_state].symbol as usize;
+        if literal_length_code >= LITERAL_LENGTH_BASE.len() ||
+           offset_code > MAX_OFFSET_CODE ||
+           match_length_code >= MATCH_LENGTH_BASE.len() {
+            return Err(GzError::CorruptData);
+        }
+
+        // the extra bits come in the opposite order to the states
+        let offset_value = (1 << offset_code) + reader.read(offset_code as u32) as usize;
+        let match_length = (MATCH_LENGTH_BASE[match_length_code] as usize) +
+                           reader.read(MATCH_LENGTH_EXTRA_BITS[match_length_code]) as usize;
+        let literal_length = (LITERAL_LENGTH_BASE[literal_length_code] as usize) +
+                             reader.read(LITERAL_LENGTH_EXTRA_BITS[literal_length_code]) as usize;
+        let offset = try!(resolve_offset(&mut state.recent_offsets, offset_value, literal_length));
+
+        if literal_pos + literal_length > literals.len() {
+            return Err(GzError::CorruptData);
+        }
+        for &byte in literals[literal_pos .. literal_pos + literal_length].iter() {
+            try!(out_buf.try_push(byte));
+        }
+        literal_pos += literal_length;
+        if offset > out_buf.len() - state.start {
+            return Err(GzError::InvalidDistance);
+        }
+        try!(out_buf.copy_back_pointer(offset - 1, match_length));
+
+        if i + 1 < sequence_count {
+            literal_length_state = literal_lengths.next_state(literal_length_state, &mut reader);
+            match_length_state = match_lengths.next_state(match_length_state, &mut reader);
+            offset_state = offsets.next_state(offset_state, &mut reader);
+        }
+    }
+    if !reader.finished() {
+        return Err(GzError::CorruptData);
+    }
+    for &byte in literals[literal_pos ..].iter() {
+        try!(out_buf.try_push(byte));
+    }
+
+    state.literal_lengths = Some(literal_lengths);
+    state.offsets = Some(offsets);
+    state.match_lengths = Some(match_lengths);
+    Ok(())
+}
+
+/// Work out the real offset of a match, keeping the three most recent
+/// offsets up to date
+/// Values of 1 to 3 pick one of the recent offsets, shifted along by one
+/// when there are no literals before the match.
+fn resolve_offset(recent: &mut [usize; 3], offset_value: usize, literal_length: usize)
+        -> GzResult<usize> {
+    if offset_value > 3 {
+        let offset = offset_value - 3;
+        recent[2] = recent[1];
+        recent[1] = recent[0];
+        recent[0] = offset;
+        return Ok(offset);
+    }
+    let index = if literal_length == 0 { offset_value } else { offset_value - 1 };
+    let offset = match index {
+        0 => { return Ok(recent[0]); }
+        3 => recent[0] - 1,
+        _ => recent[index]
+    };
+    if offset == 0 {
+        return Err(GzError::InvalidDistance);
+    }
+    if index != 1 {
+        recent[2] = recent[1];
+    }
+    recent[1] = recent[0];
+    recent[0] = offset;
+    Ok(offset)
+}
+
+/// Read the literals section at the start of a compressed block,
+/// returning the literals and the length of the section
+fn read_literals(block: &[u8], state: &mut FrameState) -> GzResult<(Vec<u8>, usize)> {
+    if block.is_empty() {
+        return Err(GzError::TruncatedData);
+    }
+    let literals_type = block[0] & 3;
+    let size_format = (block[0] >> 2) & 3;
+    if literals_type == RAW_LITERALS || literals_type == RLE_LITERALS {
+        let header_len = match size_format {
+            0 | 2 => 1,
+            1 => 2,
+            _ => 3
+        };
+        if header_len > block.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let size = if header_len == 1 {
+            (block[0] >> 3) as usize
+        } else {
+            (read_le(block, 0, header_len) >> 4) as usize
+        };
+        if literals_type == RAW_LITERALS {
+            if header_len + size > block.len() {
+                return Err(GzError::TruncatedData);
+            }
+            return Ok((block[header_len .. header_len + size].to_vec(), header_len + size));
+        }
+        if header_len >= block.len() {
+            return Err(GzError::TruncatedData);
+        }
+        return Ok((repeat(block[header_len]).take(size).collect(), header_len + 1));
+    }
+
+    // compressed literals give both their sizes, in 10, 14 or 18 bits
+    let (header_len, size_bits) = match size_format {
+        0 | 1 => (3, 10),
+        2 => (4, 14),
+        _ => (5, 18)
+    };
+    if header_len > block.len() {
+        return Err(GzError::TruncatedData);
+    }
+    let header = read_le(block, 0, header_len);
+    let size_mask = (1 << size_bits) - 1;
+    let size = ((header >> 4) & size_mask) as usize;
+    let compressed_len = ((header >> (4 + size_bits)) & size_mask) as usize;
+    if header_len + compressed_len > block.len() {
+        return Err(GzError::TruncatedData);
+    }
+    let mut compressed = &block[header_len .. header_len + compressed_len];
+    if literals_type == COMPRESSED_LITERALS {
+        let (table, table_len) = try!(read_huffman_table(compressed));
+        state.huffman = Some(table);
+        compressed = &compressed[table_len ..];
+    }
+    // otherwise the block reuses the previous block's table
+    let table = match state.huffman {
+        Some(ref table) => table,
+        None => { return Err(GzError::CorruptData); }
+    };
+
+    let mut literals = Vec::with_capacity(size);
+    if size_format == 0 {
+        try!(table.decode_stream(compressed, size, &mut literals));
+    } else {
+        // four streams, each a quarter of the literals except the last,
+        // which gets what's left
+        if compressed.len() < JUMP_TABLE_LEN {
+            return Err(GzError::TruncatedData);
+        }
+        let quarter = (size + 3) / 4;
+        if 3 * quarter > size {
+            return Err(GzError::CorruptData);
+        }
+        let mut start = JUMP_TABLE_LEN;
+        for i in (0 .. 4) {
+            let (end, count) = if i < 3 {
+                (start + read_le(compressed, 2 * i, 2) as usize, quarter)
+            } else {
+                (compressed.len(), size - 3 * quarter)
+            };
+            if end > compressed.len() {
+                return Err(GzError::TruncatedData);
+            }
+            try!(table.decode_stream(&compressed[start .. end], count, &mut literals));
+            start = end;
+        }
+    }
+    Ok((literals, header_len + compressed_len))
+}
+
+/// A Huffman decoding table, indexed by the next max_bits bits
+struct HuffmanTable {
+    max_bits: u32,
+    // the symbol and the length of its code
+    entries: Vec<(u8, u8)>,
+}
+
+impl HuffmanTable {
+    /// Decode count literals from a single stream
+    fn decode_stream(&self, stream: &[u8], count: usize, literals: &mut Vec<u8>) -> GzResult<()> {
+        let mut reader = try!(BackwardReader::new(stream));
+        for _ in (0 .. count) {
+            let (symbol, bits) = self.entries[reader.peek(self.max_bits) as usize];
+            reader.consume(bits as u32);
+            literals.push(symbol);
+        }
+        // a stream holds exactly the codes it needs
+        if !reader.finished() {
+            return Err(GzError::CorruptData);
+        }
+        Ok(())
+    }
+}
+
+/// Read a Huffman tree description, returning the table and the length
+/// of the description
+/// The tree is given as a weight for every symbol but the last, whose
+/// weight is whatever brings the total up to a power of two. Weights
+/// are either packed four bits at a time or compressed with FSE.
+fn read_huffman_table(data: &[u8]) -> GzResult<(HuffmanTable, usize)> {
+    if data.is_empty() {
+        return Err(GzError::TruncatedData);
+    }
+    let header = data[0] as usize;
+    let mut weights: Vec<u8> = Vec::new();
+    let description_len;
+    if header < 128 {
+        description_len = 1 + header;
+        if description_len > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        let compressed = &data[1 .. description_len];
+        let (table, table_len) = try!(read_fse_table(compressed, MAX_HUFFMAN_WEIGHT_LOG));
+        let mut reader = try!(BackwardReader::new(&compressed[table_len ..]));
+        // two states take turns over the same stream, and the weights
+        // end when the stream runs out
+        let mut states = [reader.read(table.log) as usize, reader.read(table.log) as usize];
+        let mut turn = 0;
+        loop {
+            if weights.len() >= MAX_LITERAL_SYMBOL {
+                return Err(GzError::CorruptData);
+            }
+            weights.push(table.entries[states[turn]].symbol);
+            states[turn] = table.next_state(states[turn], &mut reader);
+            turn = 1 - turn;
+            if reader.overflowed() {
+                weights.push(table.entries[states[turn]].symbol);
+                break;
+            }
+        }
+    } else {
+        let count = header - 127;
+        description_len = 1 + (count + 1) / 2;
+        if description_len > data.len() {
+            return Err(GzError::TruncatedData);
+        }
+        for i in (0 .. count) {
+            let byte = data[1 + i / 2];
+            weights.push(if i % 2 == 0 { byte >> 4 } else { byte & 0xf });
+        }
+    }
+    // the last state can add one more weight than the loop allows for,
+    // and there's no room left for the implicit one after 255
+    if weights.len() > MAX_LITERAL_SYMBOL {
+        return Err(GzError::CorruptData);
+    }
+
+    let mut total = 0u32;
+    for &weight in weights.iter() {
+        if weight as u32 > MAX_HUFFMAN_BITS {
+            return Err(GzError::BadHuffmanTable);
+        }
+        if weight > 0 {
+            total += 1 << (weight - 1) as u32;
+        }
+    }
+    if total == 0 {
+        return Err(GzError::BadHuffmanTable);
+    }
+    let max_bits = highest_bit(total) + 1;
+    let left = (1 << max_bits) - total;
+    if max_bits > MAX_HUFFMAN_BITS || left & (left - 1) != 0 {
+        return Err(GzError::BadHuffmanTable);
+    }
+    weights.push((highest_bit(left) + 1) as u8);
+
+    // the longest codes come first, and within a length symbols are in
+    // order, so each weight fills the table on from the one before it
+    let mut entries: Vec<(u8, u8)> = repeat((0, 0)).take(1 << max_bits as usize).collect();
+    let mut next = 0;
+    for weight in (1 .. max_bits + 1) {
+        let bits = (max_bits + 1 - weight) as u8;
+        let span = 1 << (weight - 1) as usize;
+        for (symbol, _) in weights.iter().enumerate().filter(|&(_, &w)| w as u32 == weight) {
+            for entry in entries[next .. next + span].iter_mut() {
+                *entry = (symbol as u8, bits);
+            }
+            next += span;
+        }
+    }
+    Ok((HuffmanTable { max_bits: max_bits, entries: entries }, description_len))
+}
+
+/// One state of an FSE table
+/// Decoding the state gives symbol, and the next state is baseline plus
+/// the next bits bits of the stream.
+#[derive(Copy, Clone)]
+struct FseEntry {
+    symbol: u8,
+    bits: u8,
+    baseline: u16,
+}
+
+/// An FSE decoding table with 2^log states
+#[derive(Clone)]
+struct FseTable {
+    log: u32,
+    entries: Vec<FseEntry>,
+}
+
+impl FseTable {
+    fn next_state(&self, state: usize, reader: &mut BackwardReader) -> usize {
+        let entry = self.entries[state];
+        entry.baseline as usize + reader.read(entry.bits as u32) as usize
+    }
+}
+
+/// Read the table for literal lengths, offsets or match lengths, given
+/// its two bit mode from the sequences header
+fn read_sequence_table(block: &[u8], pos: &mut usize, mode: u8, previous: &Option<FseTable>,
+                       distribution: &[i16], log: u32, max_log: u32) -> GzResult<FseTable> {
+    match mode {
+        // predefined
+        0 => build_fse_table(distribution, log),
+        // every sequence has the same code
+        1 => {
+            if *pos >= block.len() {
+                return Err(GzError::TruncatedData);
+            }
+            let entry = FseEntry { symbol: block[*pos], bits: 0, baseline: 0 };
+            *pos += 1;
+            Ok(FseTable { log: 0, entries: vec![entry] })
+        }
+        2 => {
+            let (table, table_len) = try!(read_fse_table(&block[*pos ..], max_log));
+            *pos += table_len;
+            Ok(table)
+        }
+        // repeat the previous block's table
+        _ => match *previous {
+            Some(ref table) => Ok(table.clone()),
+            None => Err(GzError::CorruptData)
+        }
+    }
+}
+
+/// Read an FSE table description, returning the table and the length of
+/// the description
+/// The description gives how often each symbol turns up, out of the size
+/// of the table. Each count is written in only as many bits as the
+/// counts still to come could need, and runs of zeros are shortened.
+fn read_fse_table(data: &[u8], max_log: u32) -> GzResult<(FseTable, usize)> {
+    let mut bit_pos = 0;
+    let log = read_forward(data, &mut bit_pos, 4) + 5;
+    if log > max_log {
+        return Err(GzError::CorruptData);
+    }
+    let mut distribution: Vec<i16> = Vec::new();
+    let mut remaining = (1 << log) + 1;
+    let mut threshold = 1 << log;
+    let mut bits = log + 1;
+    let mut previous_zero = false;
+    while remaining > 1 {
+        if previous_zero {
+            // two bit repeat counts, where 3 means carry on
+            loop {
+                let repeat_count = read_forward(data, &mut bit_pos, 2);
+                for _ in (0 .. repeat_count) {
+                    distribution.push(0);
+                }
+                if repeat_count != 3 {
+                    break;
+                }
+            }
+        }
+        if distribution.len() > MAX_LITERAL_SYMBOL || bit_pos > data.len() * 8 {
+            return Err(GzError::CorruptData);
+        }
+        // small values take one bit fewer than large ones
+        let max = 2 * threshold - 1 - remaining;
+        let mut count = peek_forward(data, bit_pos, bits - 1) & (threshold - 1);
+        if count < max {
+            bit_pos += (bits - 1) as usize;
+        } else {
+            count = peek_forward(data, bit_pos, bits) & (2 * threshold - 1);
+            if count >= threshold {
+                count -= max;
+            }
+            bit_pos += bits as usize;
+        }
+        let probability = count as i16 - 1;
+        let used = if probability < 0 { 1 } else { probability as u32 };
+        if used >= remaining {
+            return Err(GzError::CorruptData);
+        }
+        remaining -= used;
+        distribution.push(probability);
+        previous_zero = probability == 0;
+        while remaining < threshold {
+            bits -= 1;
+            threshold >>= 1;
+        }
+    }
+    let table_len = (bit_pos + 7) / 8;
+    if remaining != 1 || table_len > data.len() {
+        return Err(GzError::CorruptData);
+    }
+    Ok((try!(build_fse_table(distribution.as_slice(), log)), table_len))
+}
+
+/// Build the decoding table for a distribution
+/// Symbols less likely than 1 in the size of the table get a state each
+/// at the end, and the rest are spread through the table in a fixed
+/// order that the encoder also follows.
+fn build_fse_table(distribution: &[i16], log: u32) -> GzResult<FseTable> {
+    let size = 1 << log as usize;
+    let mut entries: Vec<FseEntry> = repeat(FseEntry { symbol: 0, bits: 0, baseline: 0 })
+                                     .take(size).collect();
+    let mut next_count: Vec<u32> = Vec::with_capacity(distribution.len());
+    let mut high = size;
+    for (symbol, &probability) in distribution.iter().enumerate() {
+        if probability == -1 {
+            if high == 0 {
+                return Err(GzError::CorruptData);
+            }
+            high -= 1;
+            entries[high].symbol = symbol as u8;
+            next_count.push(1);
+        } else {
+            next_count.push(probability as u32);
+        }
+    }
+
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mask = size - 1;
+    let mut pos = 0;
+    for (symbol, &probability) in distribution.iter().enumerate() {
+        for _ in (0 .. if probability > 0 { probability } else { 0 }) {
+            entries[pos].symbol = symbol as u8;
+            loop {
+                pos = (pos + step) & mask;
+                if pos < high {
+                    break;
+                }
+            }
+        }
+    }
+    if pos != 0 {
+        return Err(GzError::CorruptData);
+    }
+
+    for entry in entries.iter_mut() {
+        let count = next_count[entry.symbol as usize];
+        next_count[entry.symbol as usize] += 1;
+        let bits = log - highest_bit(count);
+        entry.bits = bits as u8;
+        entry.baseline = ((count << bits as usize) - size as u32) as u16;
+    }
+    Ok(FseTable { log: log, entries: entries })
+}
+
+/// Reads a stream that was written forwards from its end
+/// The last byte has a marker bit above the last bit written, and
+/// reading past the start gives zeros.
+struct BackwardReader<'a> {
+    data: &'a [u8],
+    // how many bits are left to read, negative once the start is passed
+    bits_left: isize,
+}
+
+impl<'a> BackwardReader<'a> {
+    fn new(data: &'a [u8]) -> GzResult<BackwardReader<'a>> {
+        let last = match data.last() {
+            Some(&last) if last != 0 => last,
+            Some(_) => { return Err(GzError::CorruptData); }
+            None => { return Err(GzError::TruncatedData); }
+        };
+        let bits_left = (data.len() - 1) * 8 + highest_bit(last as u32) as usize;
+        Ok(BackwardReader { data: data, bits_left: bits_left as isize })
+    }
+
+    /// The next count bits, without moving past them
+    fn peek(&self, count: u32) -> u64 {
+        let end = self.bits_left;
+        let start = end - count as isize;
+        let low = if start < 0 { 0 } else { start };
+        if end <= low {
+            return 0;
+        }
+        let first = (low / 8) as usize;
+        let mut word = 0u64;
+        for i in (0 .. 8) {
+            if first + i < self.data.len() {
+                word |= (self.data[first + i] as u64) << (8 * i);
+            }
+        }
+        let width = (end - low) as usize;
+        let value = (word >> (low % 8) as usize) & ((1 << width) - 1);
+        value << (low - start) as usize
+    }
+
+    fn consume(&mut self, count: u32) {
+        self.bits_left -= count as isize;
+    }
+
+    fn read(&mut self, count: u32) -> u64 {
+        let value = self.peek(count);
+        self.consume(count);
+        value
+    }
+
+    /// Whether more bits have been read than there were
+    fn overflowed(&self) -> bool {
+        self.bits_left < 0
+    }
+
+    /// Whether every bit has been read, and no more
+    fn finished(&self) -> bool {
+        self.bits_left == 0
+    }
+}
+
+/// The next count bits of a stream read forwards, least significant bit
+/// first, with zeros past the end
+fn peek_forward(data: &[u8], bit_pos: usize, count: u32) -> u32 {
+    let mut value = 0u32;
+    for i in (0 .. count as usize) {
+        let byte_index = (bit_pos + i) / 8;
+        if byte_index < data.len() && data[byte_index] & (1 << (bit_pos + i) % 8) != 0 {
+            value |= 1 << i;
+        }
+    }
+    value
+}
+
+fn read_forward(data: &[u8], bit_pos: &mut usize, count: u32) -> u32 {
+    let value = peek_forward(data, *bit_pos, count);
+    *bit_pos += count as usize;
+    value
+}
+
+/// The position of the highest set bit of a non-zero value
+fn highest_bit(value: u32) -> u32 {
+    31 - value.leading_zeros()
+}
+
+/// Read a little endian number of up to 8 bytes
+fn read_le(data: &[u8], pos: usize, len: usize) -> u64 {
+    (0 .. len).fold(0, |value, i| value | (data[pos + i] as u64) << (8 * i))
+}
+
+#[cfg(test)]
+mod zstd_tests {
+    use std::iter::repeat;
+    use super::{decompress_zstd, read_huffman_table};
+    use cvec::from_slice;
+    use error::GzError;
+
+    fn decompress(raw: &[u8]) -> Result<Vec<u8>, GzError> {
+        decompress_zstd(from_slice(raw)).map(|out| out.iter().map(|&b| b).collect())
+    }
+
+    // printf 'hello, world\n' | zstd, which stores it in a raw block
+    const RAW: [u8; 26] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x58, 0x69, 0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c,
+        0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x0a, 0x4c, 0x1f, 0xf9, 0xf1];
+
+    #[test]
+    fn test_raw_block() {
+        assert_eq!(decompress(&RAW), Ok(b"hello, world\n".to_vec()));
+    }
+
+    #[test]
+    fn test_compressed_block() {
+        // the same text, with Huffman coded literals and FSE coded
+        // sequences, from zstd -19
+        let raw = [0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x68, 0x85, 0x02, 0x00, 0x02, 0x85, 0x0f,
+                   0x11, 0xb0, 0xeb, 0x3c, 0x21, 0x3d, 0x45, 0x81, 0x6a, 0x56, 0xb2, 0x79,
+                   0xe0, 0x62, 0x04, 0xf2, 0xe5, 0x31, 0x20, 0xba, 0x4d, 0xcf, 0x64, 0x2b,
+                   0x6b, 0x35, 0x37, 0x25, 0xb9, 0xf2, 0x51, 0x61, 0x96, 0x9d, 0x0b, 0xf5,
+                   0x26, 0x66, 0x55, 0x6c, 0x7a, 0xe1, 0x95, 0x9f, 0x67, 0x34, 0x86, 0xfb,
+                   0xd0, 0xa9, 0xd6, 0x5d, 0xf9, 0xae, 0x93, 0x3c, 0x91, 0x7b, 0x0f, 0x78,
+                   0x27, 0x02, 0x05, 0x00, 0x3f, 0x95, 0xca, 0x95, 0xdc, 0xb9, 0xf4, 0xf7,
+                   0x09, 0xc6, 0x0d, 0xcf, 0x31, 0xd6, 0x8b, 0x25, 0x61];
+        let text = b"It was the best of times, it was the worst of times, it was the age of \
+                     wisdom, it was the age of foolishness, it was the epoch of belief, it \
+                     was the epoch of incredulity\n";
+        assert_eq!(decompress(&raw), Ok(text.to_vec()));
+    }
+
+    #[test]
+    fn test_rle_blocks() {
+        // 300000 a's, as one compressed block followed by RLE blocks
+        let raw = [0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x58, 0x54, 0x00, 0x00, 0x10, 0x61, 0x61,
+                   0x01, 0x00, 0xfb, 0xff, 0x39, 0xc0, 0x02, 0x02, 0x00, 0x10, 0x61, 0x03,
+                   0x9f, 0x04, 0x61, 0x8d, 0x5f, 0x04, 0xa6];
+        assert_eq!(decompress(&raw), Ok(repeat(b'a').take(300000).collect()));
+    }
+
+    #[test]
+    fn test_multiple_frames() {
+        // 'one\n' with a checksum, a skippable frame, then 'two\n' without
+        let raw = [0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x58, 0x21, 0x00, 0x00, 0x6f, 0x6e, 0x65,
+                   0x0a, 0xdf, 0x16, 0x68, 0x09, 0x5e, 0x2a, 0x4d, 0x18, 0x03, 0x00, 0x00,
+                   0x00, 0x78, 0x79, 0x7a, 0x28, 0xb5, 0x2f, 0xfd, 0x00, 0x58, 0x21, 0x00,
+                   0x00, 0x74, 0x77, 0x6f, 0x0a];
+        assert_eq!(decompress(&raw), Ok(b"one\ntwo\n".to_vec()));
+        // anything after the last frame is ignored
+        let mut trailing = raw.to_vec();
+        trailing.push_all(b"junk");
+        assert_eq!(decompress(trailing.as_slice()), Ok(b"one\ntwo\n".to_vec()));
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut raw = RAW.to_vec();
+        raw[25] ^= 1;
+        assert_eq!(decompress(raw.as_slice()), Err(GzError::CrcMismatch));
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(&[0x28, 0xb5]), Err(GzError::TruncatedHeader));
+        assert_eq!(decompress(b"not zstd"), Err(GzError::BadMagic));
+        assert_eq!(decompress(&RAW[.. 20]), Err(GzError::TruncatedData));
+        // a dictionary id
+        assert_eq!(decompress(&[0x28, 0xb5, 0x2f, 0xfd, 0x01, 0x58, 0x07, 0x01, 0x00, 0x00]),
+                   Err(GzError::UnsupportedMethod));
+    }
+
+    #[test]
+    fn test_too_many_weights() {
+        // FSE compressed weights from a table with two equally likely
+        // symbols, so that each state takes a bit, and a stream with just
+        // enough bits for the second state to give a 256th weight
+        let mut description = vec![36, 0x10, 0x3f];
+        description.extend(repeat(0).take(33));
+        description.push(0x01);
+        assert_eq!(read_huffman_table(description.as_slice()).err(),
+                   Some(GzError::CorruptData));
+    }
+}
@@ -12,9 +12,24 @@ typedef enum {
     AG_COMPRESS,
     AG_ZIP,
     AG_XZ,
+    AG_TAR,
+    AG_BZIP2,
+    AG_ZSTD,
+    AG_LZ4,
+    AG_LZMA,
+    AG_ZLIB,
+    AG_BROTLI,
+    AG_SNAPPY,
+    AG_LZOP,
+    AG_LZIP,
+    AG_SEVENZIP,
+    AG_AR,
+    AG_CPIO,
+    AG_RPM,
 } ag_compression_type;
 
-ag_compression_type is_zipped(const void *buf, const int buf_len);
+ag_compression_type is_zipped(const void *buf, const size_t buf_len);
+ag_compression_type is_zipped_by_name(const char *path);
 
-void *decompress(const ag_compression_type zip_type, const void *buf, const int buf_len, const char *dir_full_path, int *new_buf_len);
+void *decompress(const ag_compression_type zip_type, const void *buf, const size_t buf_len, const char *dir_full_path, size_t *new_buf_len);
 #endif
@@ -55,6 +55,10 @@ int main(int argc, char **argv) {
 
     parse_options(argc, argv, &base_paths, &paths);
     log_debug("PCRE Version: %s", pcre_version());
+    /* the limits are shared by every thread, so set them before any start */
+    decompress_set_limits((size_t)opts.zip_max_size << 20,
+                          (size_t)opts.zip_max_ratio,
+                          (size_t)opts.zip_max_prealloc << 20);
 
 #ifdef _WIN32
     {
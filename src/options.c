@@ -95,6 +95,15 @@ Search Options:\n\
   -v --invert-match\n\
   -w --word-regexp        Only match whole words\n\
   -z --search-zip         Search contents of compressed (e.g., gzip) files\n\
+     --zip-depth NUM      Search archives inside archives up to NUM deep\n\
+                          (Default: 4)\n\
+     --zip-max-size MB    Skip files that decompress to more than MB megabytes\n\
+                          (Default: 1024, 0 for no limit)\n\
+     --zip-max-ratio NUM  Skip files that decompress to more than NUM times\n\
+                          their size, past the first 16MB (Default: 1000)\n\
+     --zip-max-prealloc MB\n\
+                          Allocate at most MB megabytes for output before\n\
+                          decompressing (Default: 64)\n\
 \n");
     printf("File Types:\n\
 The search can be restricted to certain types of files. Example:\n\
@@ -119,6 +128,10 @@ void init_options(void) {
 #endif
     opts.max_matches_per_file = 0;
     opts.max_search_depth = DEFAULT_MAX_SEARCH_DEPTH;
+    opts.zip_depth = DEFAULT_ZIP_DEPTH;
+    opts.zip_max_size = DEFAULT_ZIP_MAX_SIZE;
+    opts.zip_max_ratio = DEFAULT_ZIP_MAX_RATIO;
+    opts.zip_max_prealloc = DEFAULT_ZIP_MAX_PREALLOC;
     opts.path_sep = '\n';
     opts.print_break = TRUE;
     opts.print_path = PATH_PRINT_DEFAULT;
@@ -265,6 +278,10 @@ void parse_options(int argc, char **argv, char **base_paths[], char **paths[]) {
         { "vimgrep", no_argument, &opts.vimgrep, 1 },
         { "word-regexp", no_argument, NULL, 'w' },
         { "workers", required_argument, NULL, 0 },
+        { "zip-depth", required_argument, NULL, 0 },
+        { "zip-max-prealloc", required_argument, NULL, 0 },
+        { "zip-max-ratio", required_argument, NULL, 0 },
+        { "zip-max-size", required_argument, NULL, 0 },
     };
 
     lang_count = get_lang_count();
@@ -478,6 +495,30 @@ void parse_options(int argc, char **argv, char **base_paths[], char **paths[]) {
                 } else if (strcmp(longopts[opt_index].name, "workers") == 0) {
                     opts.workers = atoi(optarg);
                     break;
+                } else if (strcmp(longopts[opt_index].name, "zip-depth") == 0) {
+                    opts.zip_depth = atoi(optarg);
+                    if (opts.zip_depth < 0) {
+                        die("Invalid zip depth: %s", optarg);
+                    }
+                    break;
+                } else if (strcmp(longopts[opt_index].name, "zip-max-prealloc") == 0) {
+                    opts.zip_max_prealloc = atoi(optarg);
+                    if (opts.zip_max_prealloc < 0) {
+                        die("Invalid zip preallocation limit: %s", optarg);
+                    }
+                    break;
+                } else if (strcmp(longopts[opt_index].name, "zip-max-ratio") == 0) {
+                    opts.zip_max_ratio = atoi(optarg);
+                    if (opts.zip_max_ratio < 0) {
+                        die("Invalid zip ratio limit: %s", optarg);
+                    }
+                    break;
+                } else if (strcmp(longopts[opt_index].name, "zip-max-size") == 0) {
+                    opts.zip_max_size = atoi(optarg);
+                    if (opts.zip_max_size < 0) {
+                        die("Invalid zip size limit: %s", optarg);
+                    }
+                    break;
                 } else if (strcmp(longopts[opt_index].name, "color-line-number") == 0) {
                     free(opts.color_line_number);
                     ag_asprintf(&opts.color_line_number, "\033[%sm", optarg);
@@ -10,6 +10,10 @@
 #define DEFAULT_BEFORE_LEN 2
 #define DEFAULT_CONTEXT_LEN 2
 #define DEFAULT_MAX_SEARCH_DEPTH 25
+#define DEFAULT_ZIP_DEPTH 4
+#define DEFAULT_ZIP_MAX_SIZE 1024 /* in MB */
+#define DEFAULT_ZIP_MAX_RATIO 1000
+#define DEFAULT_ZIP_MAX_PREALLOC 64 /* in MB */
 enum case_behavior {
     CASE_DEFAULT, /* Changes to CASE_SMART at the end of option parsing */
     CASE_SENSITIVE,
@@ -68,6 +72,10 @@ typedef struct {
     int skip_vcs_ignores;
     int search_binary_files;
     int search_zip_files;
+    int zip_depth; /* how deep to open archives found inside archives */
+    int zip_max_size; /* most MB one file may decompress to, 0 for no limit */
+    int zip_max_ratio; /* most output per byte of input, 0 for no limit */
+    int zip_max_prealloc; /* most MB allocated on the word of a header */
     int search_hidden_files;
     int search_stream; /* true if tail -F blah | ag */
     int stats;
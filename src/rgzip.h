@@ -3,9 +3,139 @@
 #ifndef __RGZIP_H
 #define __RGZIP_H
 
+#include <stddef.h>
+
+/* Error codes returned by decompress_error_code() */
+#define RGZIP_OK 0
+#define RGZIP_BAD_MAGIC 1
+#define RGZIP_UNSUPPORTED_METHOD 2
+#define RGZIP_TRUNCATED_HEADER 3
+#define RGZIP_TRUNCATED_DATA 4
+#define RGZIP_BAD_BLOCK_TYPE 5
+#define RGZIP_BAD_STORED_LENGTH 6
+#define RGZIP_BAD_HUFFMAN_TABLE 7
+#define RGZIP_INVALID_CODE 8
+#define RGZIP_INVALID_DISTANCE 9
+#define RGZIP_CRC_MISMATCH 10
+#define RGZIP_SIZE_MISMATCH 11
+#define RGZIP_ALLOCATION_FAILED 12
+#define RGZIP_BAD_ARCHIVE 13
+#define RGZIP_ENCRYPTED 14
+#define RGZIP_CORRUPT_DATA 15
+#define RGZIP_MISSING_DICTIONARY 16
+#define RGZIP_LIMIT_EXCEEDED 17
+
+/* What a buffer holds, as returned by decompress_detect_format() */
+#define RGZIP_FORMAT_NONE 0
+#define RGZIP_FORMAT_GZIP 1
+#define RGZIP_FORMAT_COMPRESS 2
+#define RGZIP_FORMAT_BZIP2 3
+#define RGZIP_FORMAT_ZSTD 4
+#define RGZIP_FORMAT_LZ4 5
+#define RGZIP_FORMAT_XZ 6
+#define RGZIP_FORMAT_LZMA 7
+#define RGZIP_FORMAT_SNAPPY 8
+#define RGZIP_FORMAT_LZOP 9
+#define RGZIP_FORMAT_LZIP 10
+#define RGZIP_FORMAT_ZLIB 11
+#define RGZIP_FORMAT_ZIP 12
+#define RGZIP_FORMAT_SEVENZIP 13
+#define RGZIP_FORMAT_TAR 14
+#define RGZIP_FORMAT_AR 15
+#define RGZIP_FORMAT_CPIO 16
+#define RGZIP_FORMAT_RPM 17
+
 void * decompress_gzip_to_heap(const void * buf,
-    int buf_len,
-    int * new_buf_len);
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses a file made by UNIX compress (.Z), like the above */
+void * decompress_lzw_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses a bzip2 file, like the above */
+void * decompress_bzip2_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses a zstd file, like the above */
+void * decompress_zstd_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses an LZ4 file, in the frame or legacy format, like the above */
+void * decompress_lz4_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses an xz file, like the above */
+void * decompress_xz_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses a .lzma file, the format xz replaced, like the above */
+void * decompress_lzma_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses a zlib stream, like the above */
+void * decompress_zlib_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses a zlib stream made with the preset dictionary dict, which
+ * is only used if the stream asks for it. Fails with
+ * RGZIP_MISSING_DICTIONARY if the stream needs a different one */
+void * decompress_zlib_dict_to_heap(const void * buf,
+    size_t buf_len,
+    const void * dict,
+    size_t dict_len,
+    size_t * new_buf_len);
+
+/* Decompresses a brotli stream, like decompress_gzip_to_heap. brotli has
+ * no magic number, so only call this on files known to hold it */
+void * decompress_brotli_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses a Snappy framed (.sz) file, like decompress_gzip_to_heap */
+void * decompress_snappy_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses an lzop file, like decompress_gzip_to_heap. If it holds
+ * several files they are decompressed one after another */
+void * decompress_lzop_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Decompresses an lzip file, like decompress_gzip_to_heap, checking the
+ * CRC32 and sizes in the trailer of each member */
+void * decompress_lzip_to_heap(const void * buf,
+    size_t buf_len,
+    size_t * new_buf_len);
+
+/* Works out from its magic number what buf holds, returning one of the
+ * RGZIP_FORMAT codes above, or RGZIP_FORMAT_NONE if it's nothing that can
+ * be read. Brotli has no magic number, so it is never found this way */
+int decompress_detect_format(const void * buf,
+    size_t buf_len);
+
+/* Limits how much any one buffer may decompress to, for every thread:
+ * max_output bytes in all, max_ratio times the compressed size once past
+ * the first 16 MiB, and max_prealloc bytes allocated up front on the word
+ * of a header. 0 means no limit. Decompression that would go past them
+ * fails with RGZIP_LIMIT_EXCEEDED. Call before any thread decompresses */
+void decompress_set_limits(size_t max_output,
+    size_t max_ratio,
+    size_t max_prealloc);
+
+/* Why the last call on this thread failed, RGZIP_OK if it didn't */
+int decompress_error_code(void);
+
+/* A static description of an error code, not to be freed */
+const char * decompress_error_message(int code);
 
 
 #endif
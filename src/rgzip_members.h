@@ -0,0 +1,40 @@
+// C header for reading the members of compressed files, zip and tar archives
+
+#ifndef __RGZIP_MEMBERS_H
+#define __RGZIP_MEMBERS_H
+
+#include <stddef.h>
+
+typedef struct archive_members archive_members;
+
+/* Gets ready to read the members of the compressed file (gzip, .Z,
+ * bzip2, zstd, lz4, xz, lzma, lzip, snappy or zlib), lzop file, zip,
+ * 7z, tar, ar or cpio archive or RPM package in buf, which must stay
+ * valid until the members are freed. Members that are archives or
+ * compressed files themselves are opened in turn, up to max_depth
+ * inside one another. Returns NULL on failure, and
+ * decompress_error_code() in rgzip.h tells why */
+archive_members * archive_members_open(const void * buf,
+    size_t buf_len, size_t max_depth);
+
+/* Moves on to the next member. Returns 1 if there is one, 0 once all have
+ * been read, or -1 if it can't be decompressed. Its name is still set
+ * then, and the members after it can still be read */
+int archive_members_next(archive_members * members);
+
+/* The name of the current member: the path of a file in a zip, 7z, tar,
+ * ar or cpio archive or an RPM's payload, or the original file name in a
+ * gzip header. A compressed tar or cpio archive has a member for each
+ * file in it. The members of a nested archive are named after it, like
+ * inner.jar!com/Foo.properties. Empty if it has none */
+const char * archive_member_name(const archive_members * members);
+
+/* The decompressed contents of the current member, owned by members and
+ * valid until the next call to archive_members_next */
+const void * archive_member_data(const archive_members * members,
+    size_t * data_len);
+
+void archive_members_free(archive_members * members);
+
+
+#endif
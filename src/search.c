@@ -6,6 +6,14 @@ void search_buf(const char *buf, const size_t buf_len,
     int binary = -1; /* 1 = yes, 0 = no, -1 = don't know */
     size_t buf_offset = 0;
 
+    /* This is checked here rather than when the file is opened, so that an
+     * archive over 2GB still gets to rust-gzip, whose size_t interface reads
+     * it, and only the members too large for pcre are skipped */
+    if (!opts.literal && buf_len > INT_MAX) {
+        log_err("Skipping %s: pcre_exec() can't handle files larger than %i bytes.", dir_full_path, INT_MAX);
+        return;
+    }
+
     if (opts.search_stream) {
         binary = 0;
     } else if (!opts.search_binary_files) {
@@ -168,6 +176,153 @@ void search_buf(const char *buf, const size_t buf_len,
     }
 }
 
+/* Search decompressed stream output one line at a time, like search_stream
+ * does for plain input. A partial line at the end of chunk is kept in line
+ * until the rest of it arrives. */
+static void search_stream_chunk(const char *chunk, const size_t chunk_len, char **line, size_t *line_len,
+                                size_t *line_cap, size_t *line_num, const char *path) {
+    size_t i;
+
+    for (i = 0; i < chunk_len; i++) {
+        if (*line_len + 2 > *line_cap) {
+            *line_cap = *line_cap ? *line_cap * 2 : 128;
+            *line = ag_realloc(*line, *line_cap);
+        }
+        (*line)[(*line_len)++] = chunk[i];
+        if (chunk[i] == '\n') {
+            (*line)[*line_len] = '\0';
+            opts.stream_line_num = (*line_num)++;
+            search_buf(*line, *line_len, path);
+            *line_len = 0;
+        }
+    }
+}
+
+/* Decompress gzip input as it arrives, without holding all of it in memory.
+ * start holds the first start_len bytes, already read from stream */
+static void search_gzip_stream(FILE *stream, const char *path, const char *start, size_t start_len) {
+    char *in_buf = ag_malloc(STREAM_CHUNK_SIZE);
+    char *out_buf = ag_malloc(STREAM_CHUNK_SIZE);
+    char *line = NULL;
+    size_t line_len = 0;
+    size_t line_cap = 0;
+    size_t line_num = 1;
+    const char *in = start;
+    size_t in_len = start_len;
+    int out_len = 0;
+    gzip_stream *gz = gzip_stream_new();
+
+    if (gz == NULL) {
+        log_err("Unable to allocate a gzip decoder for %s", path);
+        goto cleanup;
+    }
+
+    while (in_len > 0) {
+        if (gzip_stream_feed(gz, in, (int)in_len) != 0) {
+            out_len = -1;
+            break;
+        }
+        while ((out_len = gzip_stream_read(gz, out_buf, STREAM_CHUNK_SIZE)) > 0) {
+            search_stream_chunk(out_buf, out_len, &line, &line_len, &line_cap, &line_num, path);
+        }
+        if (out_len < 0) {
+            break;
+        }
+        in = in_buf;
+        in_len = fread(in_buf, 1, STREAM_CHUNK_SIZE, stream);
+    }
+
+    if (out_len < 0 || gzip_stream_finish(gz) != 0) {
+        log_err("Cannot decompress zipped stream %s: %s", path,
+                decompress_error_message(decompress_error_code()));
+    } else if (line_len > 0) {
+        /* last line had no newline */
+        line[line_len] = '\0';
+        opts.stream_line_num = line_num;
+        search_buf(line, line_len, path);
+    }
+
+cleanup:
+    if (gz != NULL) {
+        gzip_stream_free(gz);
+    }
+    free(line);
+    free(in_buf);
+    free(out_buf);
+}
+
+/* Log why the archive at path, or the member name in it, couldn't be read.
+ * name is NULL for the archive itself or a member without a name, like the
+ * contents of most gzip files. Going past the decompression limits doesn't
+ * mean the file is corrupt, so that says which options would let it through
+ * instead. An archive that won't open for any other reason is searched as
+ * it is */
+static void log_member_error(const char *path, const char *name) {
+    int code = decompress_error_code();
+
+    if (code == RGZIP_LIMIT_EXCEEDED && name == NULL) {
+        log_err("Skipping %s: %s (see --zip-max-size and --zip-max-ratio)", path,
+                decompress_error_message(code));
+    } else if (code == RGZIP_LIMIT_EXCEEDED) {
+        log_err("Skipping %s in %s: %s (see --zip-max-size and --zip-max-ratio)", name, path,
+                decompress_error_message(code));
+    } else if (name == NULL) {
+        log_err("Cannot decompress %s: %s", path, decompress_error_message(code));
+    } else {
+        log_err("Cannot decompress %s in %s: %s", name, path, decompress_error_message(code));
+    }
+}
+
+/* Search each member of a compressed file, zip, 7z, tar, ar or cpio
+ * archive or RPM package on its own, reporting matches as
+ * archive.zip!path/in/archive, bundle.tar.gz!path/in/archive,
+ * pkg.deb!data.tar.gz!./path/in/package, pkg.rpm!./path/in/package or
+ * file.gz!original-name. Archives inside archives are opened down to
+ * --zip-depth, as in outer.zip!inner.jar!path/in/jar. The '!' matches
+ * what rust-gzip joins the names of nested members with. A file that
+ * only looks like an archive, such as text starting with "BZh" or a
+ * file that happens to pass for a tar header, is searched as it is */
+static void search_members(const char *buf, const size_t buf_len, const char *path) {
+    archive_members *members = archive_members_open(buf, buf_len, opts.zip_depth);
+    int status;
+
+    if (members == NULL) {
+        if (decompress_error_code() == RGZIP_LIMIT_EXCEEDED) {
+            log_member_error(path, NULL);
+        } else {
+            log_debug("%s isn't an archive after all: %s", path,
+                      decompress_error_message(decompress_error_code()));
+            search_buf(buf, buf_len, path);
+        }
+        return;
+    }
+
+    while ((status = archive_members_next(members)) != 0) {
+        const char *name = archive_member_name(members);
+        char *member_path = NULL;
+        size_t member_len = 0;
+        const char *member_buf;
+
+        if (status < 0) {
+            log_member_error(path, name[0] == '\0' ? NULL : name);
+            continue;
+        }
+        member_buf = archive_member_data(members, &member_len);
+        if (member_len == 0) {
+            continue;
+        }
+        if (name[0] == '\0') {
+            search_buf(member_buf, member_len, path);
+        } else {
+            ag_asprintf(&member_path, "%s!%s", path, name);
+            search_buf(member_buf, member_len, member_path);
+            free(member_path);
+        }
+    }
+
+    archive_members_free(members);
+}
+
 /* TODO: this will only match single lines. multi-line regexes silently don't match */
 void search_stream(FILE *stream, const char *path) {
     char *line = NULL;
@@ -175,9 +330,20 @@ void search_stream(FILE *stream, const char *path) {
     size_t line_cap = 0;
     size_t i;
 
-    for (i = 1; (line_len = getline(&line, &line_cap, stream)) > 0; i++) {
+    /* Only one byte can be pushed back, so the first line is read to look for
+     * gzip's two byte magic, and handed to the decoder if it's there */
+    line_len = getline(&line, &line_cap, stream);
+    if (opts.search_zip_files && line_len >= 2 &&
+        (unsigned char)line[0] == 0x1F && (unsigned char)line[1] == 0x8B) {
+        search_gzip_stream(stream, path, line, line_len);
+        free(line);
+        return;
+    }
+
+    for (i = 1; line_len > 0; i++) {
         opts.stream_line_num = i;
         search_buf(line, line_len, path);
+        line_len = getline(&line, &line_cap, stream);
     }
 
     free(line);
@@ -229,11 +395,6 @@ void search_file(const char *file_full_path) {
         goto cleanup;
     }
 
-    if (!opts.literal && f_len > INT_MAX) {
-        log_err("Skipping %s: pcre_exec() can't handle files larger than %i bytes.", file_full_path, INT_MAX);
-        goto cleanup;
-    }
-
 #ifdef _WIN32
     {
         HANDLE hmmap = CreateFileMapping(
@@ -266,12 +427,27 @@ void search_file(const char *file_full_path) {
 #endif
 
     if (opts.search_zip_files) {
-        ag_compression_type zip_type = is_zipped(buf, f_len);
+        ag_compression_type zip_type = is_zipped_by_name(file_full_path);
+        if (zip_type == AG_NO_COMPRESSION) {
+            zip_type = is_zipped(buf, f_len);
+        }
+        /* rust-gzip reads every format it can detect, and finds the tar archive in a .tar.gz, .tar.bz2,
+         * .tar.xz and so on. brotli can't be detected, so it's just decompressed below */
+        if (zip_type != AG_NO_COMPRESSION && zip_type != AG_BROTLI) {
+            search_members(buf, f_len, file_full_path);
+            goto cleanup;
+        }
         if (zip_type != AG_NO_COMPRESSION) {
-            int _buf_len = (int)f_len;
+            size_t _buf_len = f_len;
             char *_buf = decompress(zip_type, buf, f_len, file_full_path, &_buf_len);
-            if (_buf == NULL || _buf_len == 0) {
-                log_err("Cannot decompress zipped file %s", file_full_path);
+            if (_buf == NULL) {
+                log_err("Cannot decompress zipped file %s: %s", file_full_path,
+                        decompress_error_message(decompress_error_code()));
+                goto cleanup;
+            }
+            if (_buf_len == 0) {
+                /* nothing to search */
+                free(_buf);
                 goto cleanup;
             }
             search_buf(_buf, _buf_len, file_full_path);
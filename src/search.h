@@ -27,9 +27,15 @@
 #include "log.h"
 #include "options.h"
 #include "print.h"
+#include "rgzip.h"
+#include "rgzip_stream.h"
+#include "rgzip_members.h"
 #include "util.h"
 #include "uthash.h"
 
+/* How much compressed stream input is read, and decompressed output searched, at a time */
+#define STREAM_CHUNK_SIZE 65536
+
 size_t alpha_skip_lookup[256];
 size_t *find_skip_lookup;
 
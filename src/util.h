@@ -45,7 +45,22 @@ typedef enum {
     AG_NO_COMPRESSION,
     AG_GZIP,
     AG_COMPRESS,
-    AG_ZIP
+    AG_ZIP,
+    AG_XZ,
+    AG_TAR,
+    AG_BZIP2,
+    AG_ZSTD,
+    AG_LZ4,
+    AG_LZMA,
+    AG_ZLIB,
+    AG_BROTLI,
+    AG_SNAPPY,
+    AG_LZOP,
+    AG_LZIP,
+    AG_SEVENZIP,
+    AG_AR,
+    AG_CPIO,
+    AG_RPM
 } ag_compression_type;
 
 ag_stats stats;
@@ -72,8 +87,9 @@ strncmp_fp get_strstr(enum case_behavior opts);
 size_t invert_matches(const char *buf, const size_t buf_len, match_t matches[], size_t matches_len);
 void compile_study(pcre **re, pcre_extra **re_extra, char *q, const int pcre_opts, const int study_opts);
 
-void *decompress(const ag_compression_type zip_type, const void *buf, const int buf_len, const char *dir_full_path, int *new_buf_len);
-ag_compression_type is_zipped(const void *buf, const int buf_len);
+void *decompress(const ag_compression_type zip_type, const void *buf, const size_t buf_len, const char *dir_full_path, size_t *new_buf_len);
+ag_compression_type is_zipped(const void *buf, const size_t buf_len);
+ag_compression_type is_zipped_by_name(const char *path);
 
 int is_binary(const void *buf, const size_t buf_len);
 int is_regex(const char *query);
@@ -0,0 +1,65 @@
+Setup:
+
+  $ . $TESTDIR/setup.sh
+  $ mkdir zip tgz gz nested big ratio bzh src
+  $ printf 'first line\nhello zip\n' > notes.txt
+  $ zip -q zip/a.zip notes.txt
+  $ echo 'hello tar' > src/main.c
+  $ tar czf tgz/bundle.tar.gz src/main.c
+  $ echo 'hello gzip' > orig.txt
+  $ gzip orig.txt
+  $ mv orig.txt.gz gz/renamed.gz
+  $ zip -qj nested/outer.zip tgz/bundle.tar.gz
+  $ yes 'hello big' | head -n 200000 | gzip > big/big.gz
+  $ yes 'hello' | head -n 3000000 | gzip > ratio/many.gz
+  $ echo 'BZh is how bzip2 files start' > bzh/notes.txt
+
+Matches in a zip are reported against the file in it:
+
+  $ ag -z hello zip
+  zip/a.zip!notes.txt:2:hello zip
+
+And so are matches in a tar.gz:
+
+  $ ag -z hello tgz
+  tgz/bundle.tar.gz!src/main.c:1:hello tar
+
+A gzip file is named after the original name in its header:
+
+  $ ag -z hello gz
+  gz/renamed.gz!orig.txt:1:hello gzip
+
+Archives inside archives are opened too, down to --zip-depth:
+
+  $ ag -z hello nested
+  nested/outer.zip!bundle.tar.gz!src/main.c:1:hello tar
+  $ ag -z --zip-depth 0 hello nested
+  [1]
+
+Files that decompress past --zip-max-size are skipped:
+
+  $ ag -z --zip-max-size 1 hello big
+  ERR: Skipping big/big.gz: decompresses to more than the size or ratio limit (see --zip-max-size and --zip-max-ratio)
+  [1]
+  $ ag -z -c --zip-max-size 3 hello big
+  big/big.gz:200000
+
+So are files past --zip-max-ratio, once they're bigger than 16MB:
+
+  $ ag -z --zip-max-ratio 100 hello ratio
+  ERR: Skipping ratio/many.gz: decompresses to more than the size or ratio limit (see --zip-max-size and --zip-max-ratio)
+  [1]
+  $ ag -z -c hello ratio
+  ratio/many.gz:3000000
+
+A file that only starts like a compressed file is searched as it is:
+
+  $ ag -z bzip2 bzh
+  bzh/notes.txt:1:BZh is how bzip2 files start
+
+Gzipped input on stdin is decompressed:
+
+  $ unalias ag
+  $ alias ag="$TESTDIR/../ag --nocolor --workers=1"
+  $ ag -z hello < gz/renamed.gz
+  hello gzip